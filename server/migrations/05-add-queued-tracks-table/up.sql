@@ -0,0 +1,8 @@
+CREATE TABLE queued_tracks (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT,
+  artist_name TEXT,
+  album_name TEXT,
+  duration FLOAT,
+  created_at DATETIME
+);
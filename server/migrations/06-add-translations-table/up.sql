@@ -0,0 +1,13 @@
+CREATE TABLE translations (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  track_id INTEGER,
+  language TEXT,
+  plain_lyrics TEXT,
+  synced_lyrics TEXT,
+  created_at DATETIME,
+  updated_at DATETIME,
+  FOREIGN KEY (track_id) REFERENCES tracks (id),
+  UNIQUE(track_id, language)
+);
+
+CREATE INDEX idx_translations_track_id ON translations (track_id);
@@ -0,0 +1,17 @@
+ALTER TABLE queued_tracks ADD COLUMN retry_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE queued_tracks ADD COLUMN next_attempt_at DATETIME;
+
+CREATE INDEX idx_queued_tracks_next_attempt_at ON queued_tracks (next_attempt_at);
+
+CREATE TABLE dead_letter_tracks (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT,
+  artist_name TEXT,
+  album_name TEXT,
+  duration FLOAT,
+  retry_count INTEGER,
+  last_error TEXT,
+  created_at DATETIME
+);
+
+CREATE INDEX idx_dead_letter_tracks_created_at ON dead_letter_tracks (created_at);
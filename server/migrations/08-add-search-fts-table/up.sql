@@ -0,0 +1,54 @@
+-- Full-text index over the track metadata and the lyrics text, ranked with BM25 when searching by keyword.
+-- Rows are keyed by track id and only hold the latest lyrics of each track.
+CREATE VIRTUAL TABLE search_fts USING fts5(
+  name_lower,
+  artist_name_lower,
+  album_name_lower,
+  lyrics
+);
+
+INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+SELECT
+  tracks.id,
+  tracks.name_lower,
+  tracks.artist_name_lower,
+  tracks.album_name_lower,
+  COALESCE(lyrics.plain_lyrics, lyrics.synced_lyrics, '')
+FROM tracks
+LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id;
+
+CREATE TRIGGER search_fts_tracks_ai AFTER INSERT ON tracks
+BEGIN
+  INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+  VALUES (new.id, new.name_lower, new.artist_name_lower, new.album_name_lower, '');
+END;
+
+CREATE TRIGGER search_fts_tracks_au AFTER UPDATE OF name_lower, artist_name_lower, album_name_lower ON tracks
+BEGIN
+  DELETE FROM search_fts WHERE rowid = old.id;
+  INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+  SELECT new.id, new.name_lower, new.artist_name_lower, new.album_name_lower, COALESCE(lyrics.plain_lyrics, lyrics.synced_lyrics, '')
+  FROM (SELECT 1) LEFT JOIN lyrics ON lyrics.id = new.last_lyrics_id;
+END;
+
+CREATE TRIGGER search_fts_tracks_ad AFTER DELETE ON tracks
+BEGIN
+  DELETE FROM search_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER search_fts_lyrics_ai AFTER INSERT ON lyrics
+BEGIN
+  DELETE FROM search_fts WHERE rowid = new.track_id;
+  INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+  SELECT id, name_lower, artist_name_lower, album_name_lower, COALESCE(new.plain_lyrics, new.synced_lyrics, '')
+  FROM tracks WHERE id = new.track_id;
+END;
+
+CREATE TRIGGER search_fts_lyrics_au AFTER UPDATE OF plain_lyrics, synced_lyrics ON lyrics
+WHEN new.id = (SELECT last_lyrics_id FROM tracks WHERE id = new.track_id)
+BEGIN
+  DELETE FROM search_fts WHERE rowid = new.track_id;
+  INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+  SELECT id, name_lower, artist_name_lower, album_name_lower, COALESCE(new.plain_lyrics, new.synced_lyrics, '')
+  FROM tracks WHERE id = new.track_id;
+END;
@@ -0,0 +1,19 @@
+-- lyrics_content_hash() is registered by the server before the migrations run
+ALTER TABLE lyrics ADD COLUMN content_hash TEXT;
+
+UPDATE lyrics SET content_hash = lyrics_content_hash(plain_lyrics, synced_lyrics, instrumental);
+
+CREATE INDEX idx_lyrics_track_id_content_hash ON lyrics (track_id, content_hash);
+
+-- A duplicate publish points the track back to existing lyrics rather than inserting new ones,
+-- so the search index follows last_lyrics_id instead of lyrics inserts
+DROP TRIGGER search_fts_lyrics_ai;
+DROP TRIGGER search_fts_tracks_au;
+
+CREATE TRIGGER search_fts_tracks_au AFTER UPDATE OF name_lower, artist_name_lower, album_name_lower, last_lyrics_id ON tracks
+BEGIN
+  DELETE FROM search_fts WHERE rowid = old.id;
+  INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+  SELECT new.id, new.name_lower, new.artist_name_lower, new.album_name_lower, COALESCE(lyrics.plain_lyrics, lyrics.synced_lyrics, '')
+  FROM (SELECT 1) LEFT JOIN lyrics ON lyrics.id = new.last_lyrics_id;
+END;
@@ -0,0 +1,7 @@
+ALTER TABLE flags ADD COLUMN reason TEXT;
+ALTER TABLE flags ADD COLUMN reviewed_at DATETIME;
+
+UPDATE flags SET reason = 'other';
+
+CREATE INDEX idx_flags_lyrics_id ON flags (lyrics_id);
+CREATE INDEX idx_flags_reviewed_at ON flags (reviewed_at);
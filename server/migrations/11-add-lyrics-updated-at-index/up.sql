@@ -0,0 +1,17 @@
+UPDATE lyrics SET updated_at = COALESCE(created_at, CURRENT_TIMESTAMP) WHERE updated_at IS NULL;
+
+-- The changes feed compares updated_at with timestamps written by the server, so rewrite the
+-- ones stored in another format (e.g. by `datetime('now')`) the way the server writes them
+UPDATE lyrics
+SET updated_at = COALESCE(
+  CASE
+    WHEN strftime('%f', updated_at) = strftime('%S', updated_at) || '.000'
+      THEN datetime(updated_at) || '+00:00'
+    ELSE strftime('%Y-%m-%d %H:%M:%f', updated_at) || '+00:00'
+  END,
+  updated_at
+)
+WHERE updated_at NOT LIKE '%+00:00';
+
+-- Used by the changes feed, which pages through lyrics by (updated_at, id)
+CREATE INDEX idx_lyrics_updated_at_id ON lyrics (updated_at, id);
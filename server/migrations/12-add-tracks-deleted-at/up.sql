@@ -0,0 +1 @@
+ALTER TABLE tracks ADD COLUMN deleted_at DATETIME;
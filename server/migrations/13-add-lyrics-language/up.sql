@@ -0,0 +1,5 @@
+-- detect_language() is registered by the server before the migrations run
+ALTER TABLE lyrics ADD COLUMN language TEXT;
+
+-- Lyrics too short to tell the language are left without one
+UPDATE lyrics SET language = detect_language(plain_lyrics) WHERE instrumental = 0 AND plain_lyrics IS NOT NULL;
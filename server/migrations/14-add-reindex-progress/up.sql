@@ -0,0 +1,6 @@
+-- Position of an interrupted reindex, so that the next run resumes from there. There is at most one row.
+CREATE TABLE reindex_progress (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  last_lyrics_id INTEGER NOT NULL,
+  updated_at DATETIME
+);
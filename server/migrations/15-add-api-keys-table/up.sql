@@ -0,0 +1,10 @@
+-- Keys of the clients allowed to use the API when API keys are enabled. Only a SHA-256 of each key
+-- is stored, the key itself is shown once when it is created.
+CREATE TABLE api_keys (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  key_hash TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  daily_quota INTEGER NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  revoked_at DATETIME
+);
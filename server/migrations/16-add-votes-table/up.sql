@@ -0,0 +1,13 @@
+-- Up (1) and down (-1) votes on lyrics. The voter is a hash identifying the client, so that a client
+-- voting again on the same lyrics changes its vote instead of adding one.
+CREATE TABLE votes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  lyrics_id INTEGER NOT NULL,
+  voter TEXT NOT NULL,
+  value INTEGER NOT NULL CHECK (value IN (-1, 1)),
+  created_at DATETIME,
+  updated_at DATETIME,
+  FOREIGN KEY (lyrics_id) REFERENCES lyrics (id)
+);
+
+CREATE INDEX idx_votes_lyrics_id_voter ON votes (lyrics_id, voter);
@@ -0,0 +1,8 @@
+-- Other names artists are credited under, like "Beatles" for "The Beatles". Both names are stored
+-- in the same canonical form as tracks.artist_name_lower.
+CREATE TABLE artist_aliases (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  alias_lower TEXT NOT NULL UNIQUE,
+  artist_name_lower TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+-- Where the lyrics were taken from, like a URL given by the publisher or the name of a provider
+ALTER TABLE lyrics ADD COLUMN attribution TEXT;
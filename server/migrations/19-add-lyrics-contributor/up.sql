@@ -0,0 +1,6 @@
+-- Who published the lyrics: a hash identifying the client, like the voter of a vote, and the handle
+-- the publisher chose to be credited under on the contributors leaderboard, if any
+ALTER TABLE lyrics ADD COLUMN contributor TEXT;
+ALTER TABLE lyrics ADD COLUMN contributor_handle TEXT;
+
+CREATE INDEX idx_lyrics_contributor ON lyrics (contributor);
@@ -0,0 +1,7 @@
+-- A voter has a single vote on each lyrics, which voting again changes. Of the votes the same voter
+-- already cast on the same lyrics, only the latest is kept.
+DELETE FROM votes
+WHERE id NOT IN (SELECT MAX(id) FROM votes GROUP BY lyrics_id, voter);
+
+DROP INDEX idx_votes_lyrics_id_voter;
+CREATE UNIQUE INDEX idx_votes_lyrics_id_voter ON votes (lyrics_id, voter);
@@ -0,0 +1,17 @@
+-- Secrets of this install, generated when the database is created
+CREATE TABLE secrets (
+  name TEXT PRIMARY KEY,
+  value BLOB NOT NULL
+);
+
+-- Keys the hashes identifying the clients, the contributors of the lyrics and the voters of the
+-- votes, so that they cannot be reversed by hashing every IP address without it. It must not leave
+-- the server along with a copy of the database.
+INSERT INTO secrets (name, value) VALUES ('client_id_salt', randomblob(32));
+
+-- The identifiers stored so far were unkeyed hashes, which are keyed like the new ones
+UPDATE votes
+SET voter = keyed_client_id((SELECT value FROM secrets WHERE name = 'client_id_salt'), voter);
+UPDATE lyrics
+SET contributor = keyed_client_id((SELECT value FROM secrets WHERE name = 'client_id_salt'), contributor)
+WHERE contributor IS NOT NULL;
@@ -0,0 +1,16 @@
+-- A handle belongs to the contributor who first published under it, whatever its case, so that the
+-- leaderboard lists each handle once. The lyrics other contributors published under a handle taken
+-- before are left anonymous.
+CREATE INDEX idx_lyrics_contributor_handle ON lyrics (contributor_handle COLLATE NOCASE);
+
+UPDATE lyrics
+SET contributor_handle = NULL
+WHERE
+  contributor_handle IS NOT NULL
+  AND contributor IS NOT (
+    SELECT first.contributor
+    FROM lyrics AS first
+    WHERE first.contributor_handle = lyrics.contributor_handle COLLATE NOCASE
+    ORDER BY first.id
+    LIMIT 1
+  );
@@ -0,0 +1,109 @@
+use axum::{
+  extract::{Request, State},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use anyhow::Result;
+use chrono::{Days, Utc};
+use rand::RngCore;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::{
+  path::PathBuf,
+  sync::{atomic::{AtomicU64, Ordering}, Arc},
+  time::Duration,
+};
+use crate::{
+  db::migrate,
+  entities::api_key::ApiKey,
+  errors::ApiError,
+  repositories::api_key_repository,
+  AppState,
+};
+
+/// Header carrying the API key of a request
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const KEY_PREFIX: &str = "lrclib_";
+
+/// Returns a new random key, to be handed to the client once
+pub fn generate_key() -> String {
+  let mut bytes = [0u8; 24];
+  rand::thread_rng().fill_bytes(&mut bytes);
+  format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+pub fn hash_key(key: &str) -> String {
+  hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Stores a new key with the given quota in the database, and returns the key itself
+pub fn create_api_key(database: &PathBuf, name: &str, daily_quota: u64, busy_timeout: Duration) -> Result<String> {
+  let mut conn = Connection::open(database)?;
+  conn.busy_timeout(busy_timeout)?;
+  migrate(&mut conn)?;
+
+  let key = generate_key();
+  api_key_repository::add_one(&hash_key(&key), name, daily_quota, &mut conn)?;
+  Ok(key)
+}
+
+/// Revokes the keys with the given name, returning whether there were any. Servers keep accepting a
+/// revoked key until it expires from their key cache.
+pub fn revoke_api_key(database: &PathBuf, name: &str, busy_timeout: Duration) -> Result<bool> {
+  let mut conn = Connection::open(database)?;
+  conn.busy_timeout(busy_timeout)?;
+  migrate(&mut conn)?;
+
+  api_key_repository::revoke_by_name(name, &mut conn)
+}
+
+/// Rejects requests without a valid API key, and requests of keys that used up their quota for the
+/// current UTC day, when API keys are enabled. Otherwise every request is let through.
+pub async fn require_api_key(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+  if !state.api_keys_enabled {
+    return next.run(request).await;
+  }
+
+  let Some(key) = request.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok()) else {
+    return ApiError::InvalidApiKeyError.into_response();
+  };
+
+  let api_key = match find_api_key(&state, hash_key(key.trim())).await {
+    Ok(Some(api_key)) => api_key,
+    Ok(None) => return ApiError::InvalidApiKeyError.into_response(),
+    Err(err) => return err.into_response(),
+  };
+
+  if api_key.daily_quota > 0 {
+    let now = Utc::now();
+    let today = now.date_naive();
+    let usage = state.api_key_usage_cache
+      .get_with(format!("{}:{}", api_key.id, today), async { Arc::new(AtomicU64::new(0)) })
+      .await;
+
+    if usage.fetch_add(1, Ordering::Relaxed) >= api_key.daily_quota {
+      let tomorrow = (today + Days::new(1)).and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc();
+      let retry_after = (tomorrow - now).num_seconds().max(1) as u64;
+      return ApiError::RateLimitedError(retry_after).into_response();
+    }
+  }
+
+  next.run(request).await
+}
+
+/// Looks the key up in the cache, then in the database. Unknown keys are cached too, so that they
+/// don't hit the database on every request.
+pub(crate) async fn find_api_key(state: &Arc<AppState>, key_hash: String) -> Result<Option<ApiKey>, ApiError> {
+  if let Some(api_key) = state.api_key_cache.get(&key_hash).await {
+    return Ok(api_key);
+  }
+
+  let api_key = {
+    let mut conn = state.pool.get()?;
+    api_key_repository::get_by_hash(&key_hash, &mut conn)?
+  };
+  state.api_key_cache.insert(key_hash, api_key.clone()).await;
+
+  Ok(api_key)
+}
@@ -0,0 +1,80 @@
+use axum::http::{header, HeaderMap};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Publish tokens issued to trusted clients let them publish without solving a proof-of-work
+/// challenge. A token is `<payload>.<signature>`, both base64url encoded, where the payload holds
+/// the claims as JSON and the signature is an HMAC-SHA256 of the encoded payload.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateClass {
+  /// Subject to the regular publish rate limit
+  #[default]
+  Standard,
+  /// Not rate limited at all
+  Unlimited,
+  /// Not rate limited, and allowed to use the operator endpoints such as the export
+  Operator,
+}
+
+impl RateClass {
+  pub fn is_rate_limited(&self) -> bool {
+    *self == RateClass::Standard
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenClaims {
+  /// Expiry as a Unix timestamp, in seconds
+  pub exp: i64,
+  #[serde(default)]
+  pub class: RateClass,
+}
+
+pub fn issue_token(secret: &str, expires_in: Duration, class: RateClass) -> String {
+  let claims = TokenClaims {
+    exp: Utc::now().timestamp() + expires_in.as_secs() as i64,
+    class,
+  };
+  let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("claims are serializable"));
+  let signature = URL_SAFE_NO_PAD.encode(sign(secret, &payload).finalize().into_bytes());
+  format!("{}.{}", payload, signature)
+}
+
+/// Returns the claims of a token that is correctly signed and not expired yet
+pub fn verify_token(secret: &str, token: &str) -> Option<TokenClaims> {
+  let (payload, signature) = token.split_once('.')?;
+  let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+  sign(secret, payload).verify_slice(&signature).ok()?;
+
+  let claims: TokenClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+  (claims.exp > Utc::now().timestamp()).then_some(claims)
+}
+
+/// Verifies the `Authorization: Bearer` token of a request. Without a configured secret, or
+/// with a missing, invalid or expired token, the request is treated as anonymous.
+pub fn bearer_claims(headers: &HeaderMap, secret: Option<&str>) -> Option<TokenClaims> {
+  let secret = secret?;
+  let token = headers
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))?;
+  verify_token(secret, token.trim())
+}
+
+/// Whether the request carries a valid token of the operator class
+pub fn is_operator(headers: &HeaderMap, secret: Option<&str>) -> bool {
+  bearer_claims(headers, secret).is_some_and(|claims| claims.class == RateClass::Operator)
+}
+
+fn sign(secret: &str, payload: &str) -> HmacSha256 {
+  let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+  mac.update(payload.as_bytes());
+  mac
+}
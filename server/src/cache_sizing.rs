@@ -0,0 +1,89 @@
+use std::{hash::Hash, mem::size_of};
+use moka::future::{Cache, CacheBuilder};
+use crate::config::{CacheSizing, Config};
+
+/// Rough memory taken by each cache entry besides its key and value, for the bookkeeping of moka
+const ENTRY_OVERHEAD: usize = 128;
+
+/// A cache sized by the memory budget, and its share of the budget
+#[derive(Clone, Copy)]
+pub enum SizedCache {
+  Get,
+  Search,
+  Challenge,
+  MissingTrack,
+  Idempotency,
+}
+
+impl SizedCache {
+  fn name(&self) -> &'static str {
+    match self {
+      SizedCache::Get => "get",
+      SizedCache::Search => "search",
+      SizedCache::Challenge => "challenge",
+      SizedCache::MissingTrack => "missing_track",
+      SizedCache::Idempotency => "idempotency",
+    }
+  }
+
+  /// Lookups are by far the most requested, then searches. The other caches hold small entries.
+  fn budget_share(&self) -> f64 {
+    match self {
+      SizedCache::Get => 0.6,
+      SizedCache::Search => 0.3,
+      SizedCache::Challenge => 0.04,
+      SizedCache::MissingTrack => 0.03,
+      SizedCache::Idempotency => 0.03,
+    }
+  }
+
+  fn entries(&self, config: &Config) -> u64 {
+    match self {
+      SizedCache::Get => config.get_cache_capacity,
+      SizedCache::Search => config.search_cache_capacity,
+      SizedCache::Challenge => config.challenge_cache_capacity,
+      SizedCache::MissingTrack => config.missing_track_cache_capacity,
+      SizedCache::Idempotency => config.idempotency_cache_capacity,
+    }
+  }
+}
+
+/// Caps a cache at its configured number of entries or, with `auto` sizing, at its share of the
+/// memory budget, weighing each entry with `weigh`. The capacity is logged, so that operators can
+/// check the split.
+pub fn with_capacity<K, V>(
+  builder: CacheBuilder<K, V, Cache<K, V>>,
+  cache: SizedCache,
+  config: &Config,
+  weigh: fn(&K, &V) -> usize,
+) -> CacheBuilder<K, V, Cache<K, V>>
+where
+  K: Eq + Hash + Send + Sync + 'static,
+  V: Clone + Send + Sync + 'static,
+{
+  match config.cache_sizing {
+    CacheSizing::Entries => {
+      let max_entries = cache.entries(config);
+      tracing::info!(message = "cache capacity", cache = cache.name(), max_entries);
+      builder.max_capacity(max_entries)
+    },
+    CacheSizing::Auto => {
+      let budget_bytes = config.cache_memory_budget * 1024 * 1024;
+      let max_bytes = (budget_bytes as f64 * cache.budget_share()) as u64;
+      tracing::info!(message = "cache capacity", cache = cache.name(), max_bytes);
+      builder
+        .weigher(move |key, value| (weigh(key, value) + ENTRY_OVERHEAD).try_into().unwrap_or(u32::MAX))
+        .max_capacity(max_bytes)
+    },
+  }
+}
+
+/// Weighs the entries of the caches of serialized responses
+pub fn weigh_string(key: &str, value: &str) -> usize {
+  key.len() + value.len()
+}
+
+/// Weighs the entries whose value has no heap allocations
+pub fn weigh_key<V>(key: &str, _value: &V) -> usize {
+  key.len() + size_of::<V>()
+}
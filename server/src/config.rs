@@ -0,0 +1,593 @@
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::Deserialize;
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr}, path::{Path, PathBuf}, str::FromStr};
+use crate::utils::TrustedProxies;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+  /// Human readable lines
+  #[default]
+  Compact,
+  /// One JSON object per line, for log pipelines
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "compact" => Ok(LogFormat::Compact),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!("unknown log format {}, expected compact or json", value)),
+    }
+  }
+}
+
+/// Hash function of the proof-of-work challenges
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PowAlgorithm {
+  #[default]
+  Sha256,
+  /// Memory-hard, so that solving challenges can't be cheaply parallelized on GPUs
+  Argon2id,
+}
+
+impl FromStr for PowAlgorithm {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "sha256" => Ok(PowAlgorithm::Sha256),
+      "argon2id" => Ok(PowAlgorithm::Argon2id),
+      _ => Err(format!("unknown proof-of-work algorithm {}, expected sha256 or argon2id", value)),
+    }
+  }
+}
+
+/// How the capacity of the caches is bounded
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheSizing {
+  /// Each cache holds up to its `*_cache_capacity` entries, whatever their size
+  #[default]
+  Entries,
+  /// The caches share `cache_memory_budget`, each entry weighing its size
+  Auto,
+}
+
+/// How the scheduled vacuum reclaims the free pages of the database
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VacuumMode {
+  #[default]
+  Off,
+  /// `PRAGMA incremental_vacuum` in small batches, each holding the write lock briefly. The first
+  /// run switches the database to `auto_vacuum = INCREMENTAL`, which takes a full vacuum.
+  Incremental,
+  /// A full `VACUUM`, which also defragments the database but holds the write lock throughout
+  Full,
+}
+
+/// What to do with requests that carry no user agent, when filtering user agents
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingUserAgent {
+  #[default]
+  Allow,
+  Deny,
+}
+
+/// The number of queue workers, either fixed or one per available CPU core
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "RawWorkersCount")]
+pub enum WorkersCount {
+  #[default]
+  Auto,
+  Fixed(u8),
+}
+
+impl WorkersCount {
+  pub fn resolve(&self) -> usize {
+    match self {
+      WorkersCount::Auto => std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1),
+      WorkersCount::Fixed(count) => *count as usize,
+    }
+  }
+}
+
+impl FromStr for WorkersCount {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "auto" => Ok(WorkersCount::Auto),
+      _ => value
+        .parse()
+        .map(WorkersCount::Fixed)
+        .map_err(|_| format!("invalid workers count {}, expected a number or auto", value)),
+    }
+  }
+}
+
+/// Accepts both `workers_count = 2` and `workers_count = "auto"` in the config file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawWorkersCount {
+  Fixed(u8),
+  Named(String),
+}
+
+impl TryFrom<RawWorkersCount> for WorkersCount {
+  type Error = String;
+
+  fn try_from(raw: RawWorkersCount) -> Result<Self, Self::Error> {
+    match raw {
+      RawWorkersCount::Fixed(count) => Ok(WorkersCount::Fixed(count)),
+      RawWorkersCount::Named(name) => name.parse(),
+    }
+  }
+}
+
+/// Server configuration, loaded from a TOML file. Absent fields keep their default value.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// IPv4 or IPv6 address of the interface to listen on, all IPv4 interfaces by default
+  pub bind_address: IpAddr,
+  pub port: u16,
+  /// Path of a Unix domain socket to listen on instead of the TCP address and port, for a reverse
+  /// proxy on the same host. Only supported on Unix platforms.
+  pub unix_socket: Option<PathBuf>,
+  /// PEM files of the certificate chain and its private key, to serve HTTPS directly instead of
+  /// plain HTTP. Both must be set together.
+  pub tls_cert_file: Option<PathBuf>,
+  pub tls_key_file: Option<PathBuf>,
+  pub database: Option<PathBuf>,
+  pub log_format: LogFormat,
+  pub workers_count: WorkersCount,
+  pub min_pow_difficulty: u8,
+  pub pow_algorithm: PowAlgorithm,
+  /// Cost parameters of argon2id challenges: memory in KiB, number of passes and lanes
+  pub pow_argon2_memory_cost: u32,
+  pub pow_argon2_time_cost: u32,
+  pub pow_argon2_parallelism: u32,
+  /// Replaces `min_pow_difficulty` for argon2id challenges, which need far fewer hashes to be as
+  /// slow to solve
+  pub pow_argon2_min_difficulty: u8,
+  /// Challenges per minute per client IP, 0 disables the limit
+  pub challenge_rate_limit: u32,
+  /// Publishes per minute per client IP, 0 disables the limit
+  pub publish_rate_limit: u32,
+  /// Longest search parameter accepted, in characters
+  pub search_max_query_length: usize,
+  /// Most tracks a single `/api/search/stream` response returns
+  pub search_stream_max_rows: usize,
+  /// Largest publish request body accepted, in bytes
+  pub publish_body_limit: usize,
+  /// Fewest lines of text published lyrics must have, unless instrumental. 0 disables the check.
+  pub publish_min_lines: usize,
+  /// Fewest characters of text published lyrics must have, unless instrumental. 0 disables the check.
+  pub publish_min_chars: usize,
+  /// Share of the words of published lyrics, from 0 to 1, at which lyrics that also mention the
+  /// artist are rejected as the track and artist names repeated. 0 disables the check.
+  pub publish_metadata_echo_threshold: f64,
+  /// Seconds a provider fetch can take before it counts as a failure
+  pub provider_timeout: u64,
+  /// Fetch timeouts in seconds overriding `provider_timeout`, by provider name
+  pub provider_timeouts: HashMap<String, u64>,
+  /// Seconds a route handler can take to respond before the request fails with a 504, 0 disables it.
+  /// The streaming routes are never timed out.
+  pub request_timeout: u64,
+  /// Timeouts in seconds overriding `request_timeout`, by route path like `/api/publish`
+  pub route_timeouts: HashMap<String, u64>,
+  /// Consecutive failures after which a provider is disabled for `provider_cooldown`, 0 disables it
+  pub provider_failure_threshold: u32,
+  /// Seconds a failing provider stays disabled
+  pub provider_cooldown: u64,
+  /// Most provider fetches running at once across all the queue workers, to bound the outbound
+  /// connections whatever the number of workers
+  pub provider_max_in_flight: usize,
+  /// Queue tracks missing from the database to be fetched from the providers. When disabled, no
+  /// queue workers are started and a metadata miss is just a 404.
+  pub enable_queue: bool,
+  /// Also queue the tracks missing on `/api/exists`, which library scans call for whole libraries
+  pub exists_queue_missing: bool,
+  /// Keep a bloom filter of the track names in memory, so that lookups for tracks the database
+  /// definitely doesn't have skip the database
+  pub track_filter: bool,
+  /// Seconds between the rebuilds of the track filter from the database
+  pub track_filter_rebuild_interval: u64,
+  /// False positive rate the track filter is sized for, between 0 and 1
+  pub track_filter_false_positive_rate: f64,
+  /// Seconds to wait for in-flight queue jobs on shutdown
+  pub queue_grace_period: u64,
+  /// Number of missing tracks held in memory before spilling to the database
+  pub queue_capacity: usize,
+  /// Milliseconds a connection waits for a locked database
+  pub db_busy_timeout: u64,
+  /// Number of pooled database connections, by default one per queue worker plus four per CPU core
+  /// for the requests, and at least 8
+  pub db_pool_size: Option<u32>,
+  /// Connections the pool keeps open while idle, all of them by default
+  pub db_pool_min_idle: Option<u32>,
+  /// Milliseconds a request waits for a free database connection before getting a 503
+  pub db_pool_timeout: u64,
+  /// Milliseconds of waiting for a free connection after which the wait counts as slow in the metrics
+  pub db_pool_slow_wait: u64,
+  /// Seconds every connection has to stay in use before a warning about the saturated pool is
+  /// logged, 0 disables the warning
+  pub db_pool_saturation_warning: u64,
+  /// Page cache size of each database connection, in KiB
+  pub db_cache_size: u32,
+  /// Milliseconds after which a database query is logged as slow, 0 disables the logging
+  pub db_slow_query_threshold: u64,
+  pub publish_token_secret: Option<String>,
+  /// Unreviewed flags after which lyrics are evicted from the cache, 0 disables the eviction
+  pub flag_eviction_threshold: u32,
+  /// How long a client's flag of a track is remembered, so flagging it again only counts once
+  pub flag_dedup_ttl: u64,
+  /// Flags per minute accepted for each track, from all clients together, 0 disables the limit
+  pub flag_track_rate_limit: u32,
+  /// Concurrent connections to the live feed of published lyrics
+  pub live_max_connections: usize,
+  /// Require a valid API key, created with `lrclib create-api-key`, on every API request but the health probes
+  pub api_keys_enabled: bool,
+  pub cache_sizing: CacheSizing,
+  /// Memory shared by the caches with `auto` cache sizing, in MiB
+  pub cache_memory_budget: u64,
+  /// Cache TTLs and idle times are in seconds
+  pub challenge_cache_ttl: u64,
+  pub challenge_cache_capacity: u64,
+  pub get_cache_ttl: u64,
+  pub get_cache_capacity: u64,
+  pub search_cache_ttl: u64,
+  pub search_cache_tti: u64,
+  pub search_cache_capacity: u64,
+  /// How long a missing track is remembered after being queued, so repeated lookups don't queue it again
+  pub missing_track_cache_ttl: u64,
+  pub missing_track_cache_capacity: u64,
+  /// How long the response of a publish is replayed to retries with the same idempotency key
+  pub idempotency_cache_ttl: u64,
+  pub idempotency_cache_capacity: u64,
+  /// How long API keys are cached, so a revoked key keeps working for up to this long
+  pub api_key_cache_ttl: u64,
+  /// How long artist aliases are cached. Changes made through the admin API apply right away.
+  pub artist_alias_cache_ttl: u64,
+  /// How long the contributors leaderboards are cached, so new publishes show up after up to this long
+  pub leaderboard_cache_ttl: u64,
+  /// Origins allowed to call the API from a browser, like `https://example.com`. Any origin is
+  /// allowed when unset.
+  pub cors_allowed_origins: Option<Vec<String>>,
+  /// Any method is allowed when unset
+  pub cors_allowed_methods: Option<Vec<String>>,
+  /// The headers LRCLIB clients use are allowed when unset
+  pub cors_allowed_headers: Option<Vec<String>>,
+  /// Only possible together with `cors_allowed_origins`
+  pub cors_allow_credentials: bool,
+  /// Clients whose user agent contains any of these are refused with a 403
+  pub user_agent_denylist: Vec<String>,
+  /// When set, only clients whose user agent contains one of these are served
+  pub user_agent_allowlist: Option<Vec<String>>,
+  /// Match the user agent lists case-sensitively, instead of ignoring the case
+  pub user_agent_case_sensitive: bool,
+  /// Whether clients sending no user agent are served, when filtering user agents
+  pub missing_user_agent: MissingUserAgent,
+  /// Networks (like `10.0.0.0/8`) or addresses of the reverse proxies allowed to set the client IP
+  /// with `X-Forwarded-For`. The header of any other peer is ignored.
+  pub trusted_proxies: Vec<String>,
+  /// Vacuum the database once a day during a low-traffic window, off by default
+  pub vacuum_mode: VacuumMode,
+  /// UTC hours the vacuum window starts and ends at. It can wrap around midnight, like 22 to 4.
+  pub vacuum_window_start: u8,
+  pub vacuum_window_end: u8,
+  /// The vacuum waits (or stops between batches) while more lyrics than this were published in
+  /// the last 10 minutes
+  pub vacuum_max_recent_lyrics: usize,
+  /// Free pages reclaimed by each incremental vacuum batch
+  pub vacuum_batch_pages: u32,
+  /// Seconds between the runs of the pruning of the audit tables, 0 to never prune them
+  pub prune_interval: u64,
+  /// Days the dead-lettered tracks are kept for, 0 to keep them
+  pub dead_letter_retention_days: u64,
+  /// Days the reviewed flags are kept for after their review, 0 to keep them. Unreviewed flags are
+  /// always kept.
+  pub flag_retention_days: u64,
+  /// Also delete the votes on the lyrics of deleted tracks, which no longer count for anything
+  pub prune_deleted_track_votes: bool,
+  /// Rows deleted by each statement of the pruning, each holding the write lock briefly
+  pub prune_batch_size: usize,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      port: 3300,
+      unix_socket: None,
+      tls_cert_file: None,
+      tls_key_file: None,
+      database: None,
+      log_format: LogFormat::Compact,
+      workers_count: WorkersCount::Auto,
+      min_pow_difficulty: 24,
+      pow_algorithm: PowAlgorithm::Sha256,
+      pow_argon2_memory_cost: 4096,
+      pow_argon2_time_cost: 1,
+      pow_argon2_parallelism: 1,
+      pow_argon2_min_difficulty: 8,
+      challenge_rate_limit: 30,
+      publish_rate_limit: 10,
+      search_max_query_length: 200,
+      search_stream_max_rows: 100000,
+      publish_body_limit: 256 * 1024,
+      publish_min_lines: 2,
+      publish_min_chars: 20,
+      publish_metadata_echo_threshold: 0.9,
+      provider_timeout: 10,
+      provider_timeouts: HashMap::new(),
+      request_timeout: 15,
+      route_timeouts: HashMap::new(),
+      provider_failure_threshold: 5,
+      provider_cooldown: 60 * 5,
+      provider_max_in_flight: 16,
+      enable_queue: true,
+      exists_queue_missing: false,
+      track_filter: false,
+      track_filter_rebuild_interval: 60 * 60,
+      track_filter_false_positive_rate: 0.01,
+      queue_grace_period: 30,
+      queue_capacity: 600000,
+      db_busy_timeout: 5000,
+      db_pool_size: None,
+      db_pool_min_idle: None,
+      db_pool_timeout: 5000,
+      db_pool_slow_wait: 100,
+      db_slow_query_threshold: 200,
+      db_pool_saturation_warning: 30,
+      db_cache_size: 65536,
+      publish_token_secret: None,
+      flag_eviction_threshold: 3,
+      flag_dedup_ttl: 60 * 60 * 24,
+      flag_track_rate_limit: 10,
+      live_max_connections: 1000,
+      api_keys_enabled: false,
+      cache_sizing: CacheSizing::Entries,
+      cache_memory_budget: 1024,
+      challenge_cache_ttl: 60 * 5,
+      challenge_cache_capacity: 100000,
+      get_cache_ttl: 60 * 60 * 24 * 7,
+      get_cache_capacity: 5000000,
+      search_cache_ttl: 60 * 60 * 24,
+      search_cache_tti: 60 * 60 * 4,
+      search_cache_capacity: 400000,
+      missing_track_cache_ttl: 60 * 10,
+      missing_track_cache_capacity: 100000,
+      idempotency_cache_ttl: 60 * 60 * 24,
+      idempotency_cache_capacity: 100000,
+      api_key_cache_ttl: 60,
+      artist_alias_cache_ttl: 60 * 60,
+      leaderboard_cache_ttl: 60 * 5,
+      cors_allowed_origins: None,
+      cors_allowed_methods: None,
+      cors_allowed_headers: None,
+      cors_allow_credentials: false,
+      user_agent_denylist: Vec::new(),
+      user_agent_allowlist: None,
+      user_agent_case_sensitive: false,
+      missing_user_agent: MissingUserAgent::Allow,
+      trusted_proxies: Vec::new(),
+      vacuum_mode: VacuumMode::Off,
+      vacuum_window_start: 3,
+      vacuum_window_end: 5,
+      vacuum_max_recent_lyrics: 20,
+      vacuum_batch_pages: 1000,
+      prune_interval: 60 * 60,
+      dead_letter_retention_days: 30,
+      flag_retention_days: 90,
+      prune_deleted_track_votes: false,
+      prune_batch_size: 500,
+    }
+  }
+}
+
+impl Config {
+  pub fn load(path: &Path) -> Result<Self> {
+    let content = std::fs::read_to_string(path)
+      .with_context(|| format!("cannot read config file {}", path.display()))?;
+    toml::from_str(&content)
+      .with_context(|| format!("cannot parse config file {}", path.display()))
+  }
+
+  /// The configured pool size, or the default one for the number of queue workers
+  pub fn pool_size(&self) -> u32 {
+    self.db_pool_size.unwrap_or_else(|| {
+      let cores = std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1);
+      let workers = if self.enable_queue { self.workers_count.resolve() } else { 0 };
+      (workers + cores * 4).max(8) as u32
+    })
+  }
+
+  /// Checks the settings that cannot be checked by their type alone, so that a bad config fails
+  /// at startup instead of when serving requests
+  pub fn validate(&self) -> Result<()> {
+    #[cfg(not(unix))]
+    if self.unix_socket.is_some() {
+      bail!("unix_socket: Unix domain sockets are not supported on this platform, use bind_address and port instead");
+    }
+
+    if self.workers_count == WorkersCount::Fixed(0) {
+      bail!("workers_count: at least one worker is needed, or auto for one per CPU core");
+    }
+
+    if self.provider_max_in_flight == 0 {
+      bail!("provider_max_in_flight: at least one fetch must be allowed at a time");
+    }
+
+    if self.pow_algorithm == PowAlgorithm::Argon2id {
+      if let Err(err) = argon2::Params::new(self.pow_argon2_memory_cost, self.pow_argon2_time_cost, self.pow_argon2_parallelism, None) {
+        bail!("pow_argon2_memory_cost: invalid argon2id parameters, {}", err);
+      }
+    }
+
+    if self.tls_cert_file.is_some() != self.tls_key_file.is_some() {
+      bail!("tls_cert_file: tls_cert_file and tls_key_file must be set together");
+    }
+
+    if self.unix_socket.is_some() && self.tls_cert_file.is_some() {
+      bail!("tls_cert_file: TLS is not supported on a Unix domain socket, leave it to the reverse proxy");
+    }
+
+    if let Some(path) = self.route_timeouts.keys().find(|path| !path.starts_with("/api/")) {
+      bail!("route_timeouts: invalid route {:?}, expected a path like \"/api/publish\"", path);
+    }
+
+    if self.db_pool_size == Some(0) {
+      bail!("db_pool_size: the pool needs at least one connection");
+    }
+
+    if self.db_pool_min_idle.is_some_and(|min_idle| min_idle > self.pool_size()) {
+      bail!("db_pool_min_idle: cannot be larger than the pool size of {} connections", self.pool_size());
+    }
+
+    for origin in self.cors_allowed_origins.iter().flatten() {
+      let is_valid = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .is_some_and(|host| !host.is_empty() && !host.contains('/'))
+        && HeaderValue::from_str(origin).is_ok();
+      if !is_valid {
+        bail!("cors_allowed_origins: invalid origin {:?}, expected a scheme and a host like \"https://example.com\"", origin);
+      }
+    }
+
+    for method in self.cors_allowed_methods.iter().flatten() {
+      if Method::from_bytes(method.as_bytes()).is_err() {
+        bail!("cors_allowed_methods: invalid method {:?}", method);
+      }
+    }
+
+    for header in self.cors_allowed_headers.iter().flatten() {
+      if HeaderName::from_bytes(header.as_bytes()).is_err() {
+        bail!("cors_allowed_headers: invalid header name {:?}", header);
+      }
+    }
+
+    if self.cors_allow_credentials && self.cors_allowed_origins.is_none() {
+      bail!("cors_allow_credentials: credentials can only be allowed for the origins listed in cors_allowed_origins");
+    }
+
+    if let Err(network) = TrustedProxies::parse(&self.trusted_proxies) {
+      bail!("trusted_proxies: invalid network {:?}, expected an address or a CIDR range like \"10.0.0.0/8\"", network);
+    }
+
+    if self.cache_sizing == CacheSizing::Auto && self.cache_memory_budget == 0 {
+      bail!("cache_memory_budget: auto cache sizing needs a memory budget of at least 1 MiB");
+    }
+
+    if self.vacuum_window_start > 23 || self.vacuum_window_end > 23 {
+      bail!("vacuum_window_start: the vacuum window is given in UTC hours, from 0 to 23");
+    }
+
+    if self.vacuum_window_start == self.vacuum_window_end {
+      bail!("vacuum_window_end: the vacuum window must last at least an hour");
+    }
+
+    if self.vacuum_batch_pages == 0 {
+      bail!("vacuum_batch_pages: each incremental vacuum batch must reclaim at least one page");
+    }
+
+    if !(0.0..=1.0).contains(&self.publish_metadata_echo_threshold) {
+      bail!("publish_metadata_echo_threshold: must be between 0 and 1");
+    }
+
+    if !(self.track_filter_false_positive_rate > 0.0 && self.track_filter_false_positive_rate < 1.0) {
+      bail!("track_filter_false_positive_rate: must be between 0 and 1, exclusive");
+    }
+
+    if self.track_filter && self.track_filter_rebuild_interval == 0 {
+      bail!("track_filter_rebuild_interval: the track filter must be rebuilt at least every so often");
+    }
+
+    if self.prune_batch_size == 0 {
+      bail!("prune_batch_size: each pruning batch must delete at least one row");
+    }
+
+    if self.user_agent_denylist.iter().any(|pattern| pattern.is_empty()) {
+      bail!("user_agent_denylist: an empty pattern would deny every client");
+    }
+
+    if self.user_agent_allowlist.iter().flatten().any(|pattern| pattern.is_empty()) {
+      bail!("user_agent_allowlist: an empty pattern would allow every client");
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use crate::test_utils::TestApp;
+  use super::{Config, WorkersCount};
+
+  #[tokio::test]
+  async fn the_caches_live_as_long_as_the_config_file_says() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lrclib.toml");
+    std::fs::write(&path, indoc::indoc! {r#"
+      port = 3399
+      workers_count = 1
+      get_cache_ttl = 120
+      search_cache_ttl = 60
+      search_cache_tti = 30
+      challenge_cache_ttl = 45
+      missing_track_cache_ttl = 15
+    "#}).unwrap();
+    let loaded = Config::load(&path).unwrap();
+    assert_eq!(loaded.port, 3399);
+    // Missing settings keep their default
+    assert_eq!(loaded.idempotency_cache_ttl, Config::default().idempotency_cache_ttl);
+
+    let app = TestApp::with_config(|config| *config = Config { database: config.database.take(), ..loaded });
+    assert_eq!(app.state.get_cache.policy().time_to_live(), Some(Duration::from_secs(120)));
+    assert_eq!(app.state.search_cache.policy().time_to_live(), Some(Duration::from_secs(60)));
+    assert_eq!(app.state.search_cache.policy().time_to_idle(), Some(Duration::from_secs(30)));
+    assert_eq!(app.state.challenge_cache.policy().time_to_live(), Some(Duration::from_secs(45)));
+    assert_eq!(app.state.missing_track_cache.policy().time_to_live(), Some(Duration::from_secs(15)));
+  }
+
+  #[test]
+  fn invalid_values_are_reported_with_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lrclib.toml");
+    std::fs::write(&path, "get_cache_ttl = \"a week\"").unwrap();
+
+    let err = Config::load(&path).unwrap_err();
+    assert!(err.to_string().starts_with("cannot parse config file"), "{}", err);
+  }
+
+  #[test]
+  fn auto_workers_count_is_one_per_cpu_core() {
+    let cores = std::thread::available_parallelism().unwrap().get();
+    assert_eq!("auto".parse::<WorkersCount>().unwrap().resolve(), cores);
+    assert_eq!("3".parse::<WorkersCount>().unwrap().resolve(), 3);
+    assert!("three".parse::<WorkersCount>().is_err());
+
+    let config: Config = toml::from_str("workers_count = \"auto\"").unwrap();
+    assert_eq!(config.workers_count, WorkersCount::Auto);
+    let config: Config = toml::from_str("workers_count = 3").unwrap();
+    assert_eq!(config.workers_count, WorkersCount::Fixed(3));
+  }
+
+  #[test]
+  fn rejects_zero_workers() {
+    let config = Config { workers_count: WorkersCount::Fixed(0), ..Config::default() };
+
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().starts_with("workers_count:"), "{}", err);
+  }
+}
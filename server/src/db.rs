@@ -1,42 +1,309 @@
-use std::path::PathBuf;
+use std::{
+  path::PathBuf,
+  sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc},
+  time::{Duration, Instant},
+};
 use include_dir::{include_dir, Dir};
 use lazy_static::lazy_static;
-use rusqlite::Connection;
-use rusqlite_migration::Migrations;
-use anyhow::Result;
-use r2d2::Pool;
+use rusqlite::{functions::FunctionFlags, Connection, OpenFlags};
+use rusqlite_migration::{Migrations, SchemaVersion};
+use anyhow::{bail, Result};
+use r2d2::{event::{CheckoutEvent, HandleEvent, TimeoutEvent}, Pool};
 use r2d2_sqlite::SqliteConnectionManager;
+use crate::utils::{keyed_client_id, language::detect_language, lyrics_content_hash};
 
 static MIGRATIONS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/migrations");
 
+/// Statements of the pooled connections running longer than this are logged, 0 to log none. Global,
+/// as the profiling callback of SQLite is a plain function.
+static SLOW_QUERY_THRESHOLD_MICROS: AtomicU64 = AtomicU64::new(0);
+/// Statements logged as slow, exported by `/metrics`
+pub static SLOW_QUERIES: AtomicUsize = AtomicUsize::new(0);
+/// Longest SQL logged with a slow query, in characters
+const MAX_LOGGED_SQL: usize = 500;
+
 lazy_static! {
   static ref MIGRATIONS: Migrations<'static> =
     Migrations::from_directory(&MIGRATIONS_DIR).unwrap();
 }
 
-pub fn init_db(path: &PathBuf) -> Result<Pool<SqliteConnectionManager>> {
-  let manager = SqliteConnectionManager::file(path);
+pub struct PoolOptions {
+  pub size: u32,
+  /// Connections kept open while idle, `None` for all of them
+  pub min_idle: Option<u32>,
+  /// How long getting a connection waits for one to be returned to the pool
+  pub timeout: Duration,
+  pub busy_timeout: Duration,
+  pub cache_size_kib: u32,
+  pub metrics: Arc<PoolMetrics>,
+  /// Statements running longer than this are logged, `Duration::ZERO` to log none
+  pub slow_query: Duration,
+}
+
+/// Waits for pooled connections, exported by `/metrics`
+#[derive(Debug)]
+pub struct PoolMetrics {
+  /// Waits longer than this count as slow
+  slow_wait: Duration,
+  pub slow_waits: AtomicUsize,
+  /// Waits that gave up after the pool timeout
+  pub timeouts: AtomicUsize,
+}
+
+impl PoolMetrics {
+  pub fn new(slow_wait: Duration) -> Self {
+    Self { slow_wait, slow_waits: AtomicUsize::new(0), timeouts: AtomicUsize::new(0) }
+  }
+}
+
+#[derive(Debug)]
+struct PoolEventHandler(Arc<PoolMetrics>);
+
+impl HandleEvent for PoolEventHandler {
+  fn handle_checkout(&self, event: CheckoutEvent) {
+    if event.duration() > self.0.slow_wait {
+      self.0.slow_waits.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  fn handle_timeout(&self, _event: TimeoutEvent) {
+    self.0.timeouts.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+pub fn init_db(path: &PathBuf, options: PoolOptions) -> Result<Pool<SqliteConnectionManager>> {
+  let PoolOptions { size, min_idle, timeout, busy_timeout, cache_size_kib, metrics, slow_query } = options;
+  SLOW_QUERY_THRESHOLD_MICROS.store(slow_query.as_micros() as u64, Ordering::Relaxed);
+  // The pragmas are applied to every pooled connection, since most of them are per connection
+  let manager = SqliteConnectionManager::file(path)
+    .with_init(move |conn| {
+      if !slow_query.is_zero() {
+        conn.profile(Some(log_slow_query));
+      }
+      set_pragma(conn, busy_timeout, cache_size_kib)
+    });
   let pool = r2d2::Pool::builder()
-    .max_size(30)
+    .max_size(size)
+    .min_idle(min_idle)
+    .connection_timeout(timeout)
+    .event_handler(Box::new(PoolEventHandler(metrics)))
     .build(manager)?;
 
   let mut conn = pool.get()?;
 
-  set_pragma(&mut conn)?;
   migrate(&mut conn)?;
 
   Ok(pool)
 }
 
-pub fn set_pragma(conn: &mut Connection) -> Result<()> {
+/// Called by SQLite after each statement of the pooled connections. The time of a statement runs
+/// until it's reset, so a query whose rows are streamed counts the time spent handling them too.
+fn log_slow_query(sql: &str, duration: Duration) {
+  if duration.as_micros() as u64 <= SLOW_QUERY_THRESHOLD_MICROS.load(Ordering::Relaxed) {
+    return;
+  }
+
+  SLOW_QUERIES.fetch_add(1, Ordering::Relaxed);
+  let sql = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+  tracing::warn!(
+    message = "slow database query",
+    query = query_name(&sql),
+    duration = duration.as_millis() as u64,
+    sql = sql.chars().take(MAX_LOGGED_SQL).collect::<String>(),
+  );
+}
+
+/// Names a statement by its kind and main table, like `SELECT tracks` or `INSERT lyrics`. The main
+/// table is the least nested one, so that a subquery in the selected columns doesn't name the query.
+fn query_name(sql: &str) -> String {
+  let words: Vec<&str> = sql.split_whitespace().collect();
+  let kind = words.first().map(|word| word.to_uppercase()).unwrap_or_default();
+  let table_after = match kind.as_str() {
+    "INSERT" | "REPLACE" => "INTO",
+    "UPDATE" => "UPDATE",
+    _ => "FROM",
+  };
+
+  let mut depth = 0;
+  let mut table: Option<(i32, &str)> = None;
+  for pair in words.windows(2) {
+    if pair[0].eq_ignore_ascii_case(table_after) && !pair[1].starts_with('(') && table.map_or(true, |(min_depth, _)| depth < min_depth) {
+      table = Some((depth, pair[1].trim_matches(|c: char| !c.is_alphanumeric() && c != '_')));
+    }
+    depth += pair[0].matches('(').count() as i32 - pair[0].matches(')').count() as i32;
+  }
+
+  match table {
+    Some((_, table)) => format!("{} {}", kind, table),
+    None => kind,
+  }
+}
+
+/// Logs a warning when every connection of the pool stays in use for `sustained`, once per
+/// saturation, and when the pool recovers
+pub async fn watch_pool_saturation(pool: Pool<SqliteConnectionManager>, sustained: Duration) {
+  let mut interval = tokio::time::interval(Duration::from_secs(1));
+  let mut saturated_since: Option<Instant> = None;
+  let mut warned = false;
+
+  loop {
+    interval.tick().await;
+    let state = pool.state();
+    let saturated = state.connections == pool.max_size() && state.idle_connections == 0;
+
+    if !saturated {
+      if warned {
+        tracing::info!(message = "database connection pool no longer saturated");
+      }
+      saturated_since = None;
+      warned = false;
+      continue;
+    }
+
+    let since = *saturated_since.get_or_insert_with(Instant::now);
+    if !warned && since.elapsed() >= sustained {
+      tracing::warn!(
+        message = "database connection pool saturated, every connection has been in use",
+        seconds = since.elapsed().as_secs(),
+        pool_size = pool.max_size(),
+      );
+      warned = true;
+    }
+  }
+}
+
+/// Opens a read-only connection outside of the pool, for long reads that would otherwise keep a
+/// pooled connection from the other requests
+pub fn open_read_only(path: &PathBuf, busy_timeout: Duration) -> Result<Connection> {
+  let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+  conn.busy_timeout(busy_timeout)?;
+  Ok(conn)
+}
+
+pub fn set_pragma(conn: &mut Connection, busy_timeout: Duration, cache_size_kib: u32) -> rusqlite::Result<()> {
   conn.pragma_update(None, "journal_mode", "WAL")?;
   conn.pragma_update(None, "synchronous", "NORMAL")?;
   conn.pragma_update(None, "temp_store", "MEMORY")?;
   conn.pragma_update(None, "mmap_size", "30000000000")?;
+  // A negative cache_size is interpreted by SQLite as KiB rather than a number of pages
+  conn.pragma_update(None, "cache_size", -(cache_size_kib as i64))?;
+  conn.busy_timeout(busy_timeout)?;
   Ok(())
 }
 
+/// The schema version of this build, which is the number of migrations
+pub fn latest_schema_version() -> usize {
+  MIGRATIONS_DIR.dirs().count()
+}
+
+/// Applies the migrations the database is missing, in order. The schema version is the number of
+/// applied migrations, recorded by rusqlite_migration in SQLite's `user_version`. A database
+/// migrated by a newer build is refused rather than used with a schema this build doesn't know.
 pub fn migrate(conn: &mut Connection) -> Result<()> {
+  register_functions(conn)?;
+
+  let latest_version = latest_schema_version();
+  let from_version = match MIGRATIONS.current_version(conn)? {
+    SchemaVersion::Outside(version) => bail!(
+      "the database schema is at version {}, but this build only supports up to version {}. Upgrade LRCLIB, or restore a backup made before the upgrade.",
+      version,
+      latest_version,
+    ),
+    version => usize::from(&version),
+  };
+
   MIGRATIONS.to_latest(conn)?;
+
+  if from_version == latest_version {
+    tracing::info!(message = "database schema is up to date", schema_version = latest_version);
+  } else {
+    tracing::info!(message = "migrated database schema", from_version, to_version = latest_version);
+  }
+
   Ok(())
 }
+
+/// Functions implemented in Rust that are used by the migrations
+fn register_functions(conn: &mut Connection) -> Result<()> {
+  conn.create_scalar_function(
+    "lyrics_content_hash",
+    3,
+    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+    |ctx| {
+      let plain_lyrics = ctx.get::<Option<String>>(0)?;
+      let synced_lyrics = ctx.get::<Option<String>>(1)?;
+      let instrumental = ctx.get::<Option<bool>>(2)?.unwrap_or_default();
+      Ok(lyrics_content_hash(plain_lyrics.as_deref(), synced_lyrics.as_deref(), instrumental))
+    },
+  )?;
+  conn.create_scalar_function(
+    "detect_language",
+    1,
+    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+    |ctx| {
+      let text = ctx.get::<Option<String>>(0)?;
+      Ok(text.as_deref().and_then(detect_language))
+    },
+  )?;
+  conn.create_scalar_function(
+    "keyed_client_id",
+    2,
+    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+    |ctx| {
+      let salt = ctx.get::<Vec<u8>>(0)?;
+      let hashed_identity = ctx.get::<Option<String>>(1)?;
+      Ok(hashed_identity.map(|hashed_identity| keyed_client_id(&salt, &hashed_identity)))
+    },
+  )?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use rusqlite::{types::Value, Connection};
+  use sha2::{Digest, Sha256};
+  use crate::{repositories::secret_repository, test_utils::TestApp, utils::keyed_client_id};
+  use super::{migrate, register_functions, MIGRATIONS};
+
+  #[tokio::test]
+  async fn every_pooled_connection_gets_the_pragmas() {
+    let app = TestApp::with_config(|config| {
+      config.db_pool_size = Some(2);
+      config.db_busy_timeout = 1234;
+      config.db_cache_size = 2048;
+    });
+
+    // Holding both connections at once, so that the second one isn't the first one again
+    let conns = [app.state.pool.get().unwrap(), app.state.pool.get().unwrap()];
+    for conn in &conns {
+      let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, Value>(0)).unwrap();
+      assert_eq!(pragma("journal_mode"), Value::Text("wal".to_owned()));
+      assert_eq!(pragma("synchronous"), Value::Integer(1));
+      assert_eq!(pragma("busy_timeout"), Value::Integer(1234));
+      assert_eq!(pragma("cache_size"), Value::Integer(-2048));
+    }
+  }
+
+  #[test]
+  fn migrating_keys_the_stored_client_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    register_functions(&mut conn).unwrap();
+    MIGRATIONS.to_version(&mut conn, 20).unwrap();
+
+    // Stored before the ids were keyed, as a plain hash of the IP
+    let plain_id = hex::encode(Sha256::digest(b"203.0.113.7"));
+    conn.execute_batch("INSERT INTO tracks (id, name) VALUES (1, 'Hello'); INSERT INTO lyrics (id, track_id) VALUES (1, 1);").unwrap();
+    conn.execute("INSERT INTO votes (lyrics_id, voter, value) VALUES (1, ?1, 1)", [&plain_id]).unwrap();
+    migrate(&mut conn).unwrap();
+
+    let salt = secret_repository::get_by_name("client_id_salt", &mut conn).unwrap().unwrap();
+    assert_eq!(salt.len(), 32);
+    let voter: String = conn.query_row("SELECT voter FROM votes", [], |row| row.get(0)).unwrap();
+    assert_eq!(voter, keyed_client_id(&salt, &plain_id));
+
+    // Every install has a salt of its own
+    let mut other_conn = Connection::open_in_memory().unwrap();
+    migrate(&mut other_conn).unwrap();
+    assert_ne!(secret_repository::get_by_name("client_id_salt", &mut other_conn).unwrap(), Some(salt));
+  }
+}
@@ -1,3 +1,13 @@
 pub mod track;
 pub mod lyrics;
 pub mod missing_track;
+pub mod translation;
+pub mod stats;
+pub mod flag;
+pub mod live_event;
+pub mod indexed_lyrics;
+pub mod api_key;
+pub mod dead_letter_track;
+pub mod vote;
+pub mod lyrics_candidate;
+pub mod contributor;
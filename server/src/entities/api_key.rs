@@ -0,0 +1,7 @@
+#[derive(Clone)]
+pub struct ApiKey {
+  pub id: i64,
+  pub name: String,
+  /// Requests allowed per UTC day
+  pub daily_quota: u64,
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// Publishes counted for a contributor over the window of a leaderboard
+#[derive(Serialize, Deserialize)]
+pub struct ContributorPublishes {
+  pub handle: String,
+  pub publishes: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Leaderboard {
+  /// The contributors credited under a handle, most publishes first
+  pub contributors: Vec<ContributorPublishes>,
+  /// The contributors without a handle, only counted together
+  pub anonymous_contributors: i64,
+  pub anonymous_publishes: i64,
+}
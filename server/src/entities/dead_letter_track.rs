@@ -0,0 +1,10 @@
+use chrono::prelude::*;
+use super::missing_track::MissingTrack;
+
+/// A missing track that exhausted its retries
+pub struct DeadLetterTrack {
+  pub id: i64,
+  pub missing_track: MissingTrack,
+  pub last_error: Option<String>,
+  pub created_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,47 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagReason {
+  IncorrectLyrics,
+  WrongTiming,
+  Spam,
+  Duplicate,
+  Offensive,
+  Other,
+}
+
+impl FlagReason {
+  pub const ALL: [FlagReason; 6] = [
+    FlagReason::IncorrectLyrics,
+    FlagReason::WrongTiming,
+    FlagReason::Spam,
+    FlagReason::Duplicate,
+    FlagReason::Offensive,
+    FlagReason::Other,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      FlagReason::IncorrectLyrics => "incorrect_lyrics",
+      FlagReason::WrongTiming => "wrong_timing",
+      FlagReason::Spam => "spam",
+      FlagReason::Duplicate => "duplicate",
+      FlagReason::Offensive => "offensive",
+      FlagReason::Other => "other",
+    }
+  }
+
+  pub fn parse(reason: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|candidate| candidate.as_str() == reason)
+  }
+}
+
+pub struct Flag {
+  pub id: i64,
+  pub lyrics_id: Option<i64>,
+  pub reason: FlagReason,
+  pub content: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,15 @@
+/// Lyrics along with the values derived from them, as read by the reindex
+pub struct IndexedLyrics {
+  pub id: i64,
+  pub track_id: i64,
+  pub plain_lyrics: Option<String>,
+  pub synced_lyrics: Option<String>,
+  pub instrumental: bool,
+  pub content_hash: Option<String>,
+  pub language: Option<String>,
+  /// Whether these are the current lyrics of their track
+  pub is_current: bool,
+  /// Whether the `search_fts` row of the track differs from the track and these lyrics, only
+  /// meaningful for the current lyrics
+  pub is_search_stale: bool,
+}
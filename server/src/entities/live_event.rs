@@ -0,0 +1,10 @@
+use serde::Serialize;
+
+/// Pushed to the live feed subscribers each time new lyrics are published
+#[derive(Serialize, Debug, Clone)]
+pub struct LiveEvent {
+  /// Id of the track the lyrics were published to
+  pub id: i64,
+  pub track_name: String,
+  pub artist_name: String,
+}
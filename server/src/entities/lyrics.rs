@@ -13,8 +13,15 @@ pub struct Lyrics {
   pub updated_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Default)]
 pub struct SimpleLyrics {
+  pub id: Option<i64>,
   pub plain_lyrics: Option<String>,
   pub synced_lyrics: Option<String>,
   pub instrumental: bool,
+  pub updated_at: Option<DateTime<Utc>>,
+  /// BCP-47 tag of the language, when it was given or detected
+  pub language: Option<String>,
+  /// Where the lyrics were taken from, `None` when unknown
+  pub attribution: Option<String>,
 }
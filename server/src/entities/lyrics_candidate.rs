@@ -0,0 +1,13 @@
+use super::track::SimpleTrack;
+
+/// One of the lyrics of a track matching a lookup, current or not
+pub struct LyricsCandidate {
+  /// The track, with the candidate lyrics as `last_lyrics`
+  pub track: SimpleTrack,
+  /// Where the lyrics come from, `lrclib` for published lyrics
+  pub source: Option<String>,
+  /// Net vote score of the lyrics
+  pub score: i64,
+  /// Whether these are the lyrics the track currently serves
+  pub is_current: bool,
+}
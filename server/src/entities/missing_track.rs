@@ -1,3 +1,4 @@
+use chrono::prelude::*;
 use serde::{Deserialize,Serialize};
 use std::fmt;
 use std::hash::{Hash, Hasher};
@@ -8,6 +9,12 @@ pub struct MissingTrack {
   pub artist_name: String,
   pub album_name: String,
   pub duration: f64,
+  /// Number of failed attempts to fetch lyrics for this track
+  #[serde(default)]
+  pub retry_count: u32,
+  /// The track is not retried before this time
+  #[serde(default)]
+  pub next_attempt_at: Option<DateTime<Utc>>,
 }
 
 impl fmt::Display for MissingTrack {
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct LyricsStats {
+  pub total_lyrics: i64,
+  pub synced_lyrics: i64,
+  pub plain_lyrics: i64,
+  pub last_24h_lyrics: i64,
+  /// Lyrics published to LRCLIB itself, which sets the proof-of-work difficulty
+  pub last_10_mins_published_lyrics: i64,
+  pub queued_tracks: i64,
+}
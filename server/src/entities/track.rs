@@ -1,5 +1,6 @@
 use super::lyrics::SimpleLyrics;
 use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub struct Track {
   pub id: i64,
@@ -21,3 +22,31 @@ pub struct SimpleTrack {
   pub duration: Option<f64>,
   pub last_lyrics: Option<SimpleLyrics>,
 }
+
+/// What lyrics a track has, without the lyrics themselves. The names let the cached flags be
+/// invalidated like the cached tracks.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackFlags {
+  pub id: i64,
+  #[serde(rename = "trackName")]
+  pub name: Option<String>,
+  pub artist_name: Option<String>,
+  pub album_name: Option<String>,
+  pub synced: bool,
+  pub instrumental: bool,
+}
+
+impl From<&SimpleTrack> for TrackFlags {
+  fn from(track: &SimpleTrack) -> Self {
+    let lyrics = track.last_lyrics.as_ref();
+    TrackFlags {
+      id: track.id,
+      name: track.name.to_owned(),
+      artist_name: track.artist_name.to_owned(),
+      album_name: track.album_name.to_owned(),
+      synced: lyrics.is_some_and(|lyrics| lyrics.synced_lyrics.is_some()),
+      instrumental: lyrics.is_some_and(|lyrics| lyrics.instrumental),
+    }
+  }
+}
@@ -0,0 +1,5 @@
+pub struct Translation {
+  pub language: String,
+  pub plain_lyrics: Option<String>,
+  pub synced_lyrics: Option<String>,
+}
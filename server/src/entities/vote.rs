@@ -0,0 +1,34 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Vote {
+  Up,
+  Down,
+}
+
+impl Vote {
+  pub fn parse(vote: &str) -> Option<Self> {
+    match vote {
+      "up" => Some(Vote::Up),
+      "down" => Some(Vote::Down),
+      _ => None,
+    }
+  }
+
+  /// The value stored in the votes table, which sums up to the score
+  pub fn value(&self) -> i64 {
+    match self {
+      Vote::Up => 1,
+      Vote::Down => -1,
+    }
+  }
+}
+
+pub struct VoteCounts {
+  pub upvotes: i64,
+  pub downvotes: i64,
+}
+
+impl VoteCounts {
+  pub fn score(&self) -> i64 {
+    self.upvotes - self.downvotes
+  }
+}
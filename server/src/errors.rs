@@ -1,9 +1,10 @@
 use axum::{
   response::{IntoResponse, Response},
-  http::StatusCode,
+  http::{header, StatusCode},
   Json,
 };
 use serde::Serialize;
+use crate::repositories::BusyError;
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,11 +16,33 @@ pub struct ApiErrorResponse {
 
 pub enum ApiError {
   TrackNotFoundError,
+  TranslationNotFoundError,
+  /// The track has no lyrics of the kind required with `prefer` and `fallback=false`
+  PreferredLyricsNotFoundError(&'static str),
   IncorrectPublishTokenError,
+  UnauthorizedError,
+  /// API keys are enabled, and the request has no valid one
+  InvalidApiKeyError,
+  /// The client is refused by its user agent
+  ForbiddenError,
   ValidationError(String),
+  RateLimitedError(u64),
+  ServiceUnavailableError,
+  /// The lyrics were changed since the version an edit is based on
+  VersionConflictError,
+  /// The `Idempotency-Key` of a publish was already sent with another request
+  IdempotencyKeyReusedError,
+  /// All database connections stayed checked out for the whole pool timeout, or the database stayed
+  /// locked by other writers
+  DatabaseBusyError,
+  /// The handler took longer than the timeout of the route
+  TimeoutError,
   UnknownError(anyhow::Error),
 }
 
+/// Seconds a client should wait before retrying when the database connections are exhausted
+const DATABASE_BUSY_RETRY_AFTER: u64 = 5;
+
 impl IntoResponse for ApiError {
   fn into_response(self) -> Response {
     match self {
@@ -33,6 +56,24 @@ impl IntoResponse for ApiError {
             }
           )
         ).into_response(),
+      ApiError::TranslationNotFoundError => (
+        StatusCode::NOT_FOUND,
+        Json(
+          ApiErrorResponse {
+            message: "Failed to find a translation in the specified language".to_owned(),
+            name: "TranslationNotFound".to_owned(),
+            status_code: StatusCode::NOT_FOUND.as_u16(),
+          }
+        )
+      ).into_response(),
+      ApiError::PreferredLyricsNotFoundError(kind) => (
+        StatusCode::NOT_FOUND,
+        Json(ApiErrorResponse {
+          message: format!("The track has no {} lyrics", kind),
+          name: "PreferredLyricsNotFound".to_owned(),
+          status_code: StatusCode::NOT_FOUND.as_u16(),
+        }),
+      ).into_response(),
       ApiError::IncorrectPublishTokenError => (
         StatusCode::BAD_REQUEST,
         Json(
@@ -43,6 +84,30 @@ impl IntoResponse for ApiError {
           }
         )
       ).into_response(),
+      ApiError::UnauthorizedError => (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiErrorResponse {
+          message: "A valid operator token is required".to_owned(),
+          name: "UnauthorizedError".to_owned(),
+          status_code: StatusCode::UNAUTHORIZED.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::InvalidApiKeyError => (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiErrorResponse {
+          message: "A valid API key is required in the X-API-Key header".to_owned(),
+          name: "InvalidApiKeyError".to_owned(),
+          status_code: StatusCode::UNAUTHORIZED.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::ForbiddenError => (
+        StatusCode::FORBIDDEN,
+        Json(ApiErrorResponse {
+          message: "This client is not allowed to use the API".to_owned(),
+          name: "ForbiddenError".to_owned(),
+          status_code: StatusCode::FORBIDDEN.as_u16(),
+        }),
+      ).into_response(),
       ApiError::ValidationError(err_msg) => (
         StatusCode::BAD_REQUEST,
         Json(ApiErrorResponse {
@@ -51,6 +116,56 @@ impl IntoResponse for ApiError {
           status_code: StatusCode::BAD_REQUEST.as_u16(),
         }),
       ).into_response(),
+      ApiError::RateLimitedError(retry_after) => (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(ApiErrorResponse {
+          message: "Too many requests, please try again later".to_owned(),
+          name: "RateLimitedError".to_owned(),
+          status_code: StatusCode::TOO_MANY_REQUESTS.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::ServiceUnavailableError => (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiErrorResponse {
+          message: "The service is temporarily unavailable".to_owned(),
+          name: "ServiceUnavailableError".to_owned(),
+          status_code: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::VersionConflictError => (
+        StatusCode::CONFLICT,
+        Json(ApiErrorResponse {
+          message: "The lyrics were changed since the version your edit is based on, fetch them again and retry".to_owned(),
+          name: "VersionConflictError".to_owned(),
+          status_code: StatusCode::CONFLICT.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::IdempotencyKeyReusedError => (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(ApiErrorResponse {
+          message: "The Idempotency-Key was already used for another request, use a new key for each publish".to_owned(),
+          name: "IdempotencyKeyReusedError".to_owned(),
+          status_code: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::DatabaseBusyError => (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, DATABASE_BUSY_RETRY_AFTER.to_string())],
+        Json(ApiErrorResponse {
+          message: "The server is too busy right now, please try again later".to_owned(),
+          name: "DatabaseBusyError".to_owned(),
+          status_code: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+        }),
+      ).into_response(),
+      ApiError::TimeoutError => (
+        StatusCode::GATEWAY_TIMEOUT,
+        Json(ApiErrorResponse {
+          message: "The request took too long to process, please try again later".to_owned(),
+          name: "TimeoutError".to_owned(),
+          status_code: StatusCode::GATEWAY_TIMEOUT.as_u16(),
+        }),
+      ).into_response(),
       ApiError::UnknownError(err) => {
         tracing::error!(message = "unknown error happened", error = err.to_string());
         (
@@ -73,6 +188,44 @@ where
   E: Into<anyhow::Error>,
 {
   fn from(err: E) -> Self {
-    ApiError::UnknownError(err.into())
+    let err = err.into();
+    // The pool only fails to hand out a connection when it timed out waiting for one, which can
+    // happen deep inside any repository call
+    if err.chain().any(|cause| cause.is::<r2d2::Error>()) {
+      tracing::warn!(message = "database connection pool exhausted", error = err.to_string());
+      return ApiError::DatabaseBusyError;
+    }
+    // SQLite kept the database locked for longer than the busy timeout and the write retries
+    if err.is_busy() {
+      return ApiError::DatabaseBusyError;
+    }
+    ApiError::UnknownError(err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::Ordering;
+  use axum::http::{header, StatusCode};
+  use crate::test_utils::{body_json, TestApp};
+
+  #[tokio::test]
+  async fn answers_503_while_every_connection_is_checked_out() {
+    let app = TestApp::with_config(|config| {
+      config.db_pool_size = Some(1);
+      config.db_pool_timeout = 50;
+    });
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+
+    let conn = app.state.pool.get().unwrap();
+    let response = app.get(&format!("/api/get/{}", track_id)).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+    assert_eq!(body_json(response).await["name"], "DatabaseBusyError");
+    assert_eq!(app.state.pool_metrics.timeouts.load(Ordering::Relaxed), 1);
+
+    // The server goes on once the connection is back
+    drop(conn);
+    assert_eq!(app.get(&format!("/api/get/{}", track_id)).await.status(), StatusCode::OK);
   }
 }
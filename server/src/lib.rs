@@ -1,38 +1,86 @@
 use axum::{
   http::{
     header,
+    HeaderName,
     Request,
+    StatusCode,
   },
   body::Body,
+  extract::DefaultBodyLimit,
+  middleware,
   response::Response,
-  routing::{get, post},
+  routing::{get, patch, post},
   Router,
 };
-use entities::missing_track::MissingTrack;
-use repositories::lyrics_repository::get_last_10_mins_lyrics_count;
+use entities::{live_event::LiveEvent, missing_track::MissingTrack};
 use tracing_subscriber::EnvFilter;
-use std::{path::PathBuf, time::Duration};
+use std::{convert::Infallible, net::SocketAddr, path::PathBuf, sync::Mutex, time::Duration};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use routes::{
   get_lyrics_by_metadata,
+  get_lyrics_batch,
   get_lyrics_by_track_id,
+  get_lyrics_by_track_ids,
+  get_random_lyrics,
   search_lyrics,
+  search_stream,
   request_challenge,
   publish_lyrics,
   flag_lyrics,
+  vote_lyrics,
+  get_metrics,
+  get_translations,
+  get_health,
+  get_ready,
+  get_stats,
+  get_leaderboard,
+  export_lyrics,
+  changes,
+  live,
+  invalidate_cache,
+  delete_lyrics,
+  get_providers_status,
+  dead_letter,
+  artist_aliases,
+  get_capabilities,
+  get_preview,
+  get_exists,
 };
 use std::sync::Arc;
-use db::init_db;
+use db::{init_db, watch_pool_saturation, PoolMetrics, PoolOptions};
+use config::{Config, LogFormat, VacuumMode};
 use tower_http::{
-  cors::{Any, CorsLayer}, trace::{self, TraceLayer}
+  compression::{
+    predicate::{DefaultPredicate, Predicate, SizeAbove},
+    CompressionLayer,
+  },
+  cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer},
+  limit::RequestBodyLimitLayer,
+  request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+  trace::{self, TraceLayer},
 };
 use tracing::Span;
 use moka::future::Cache;
-use tokio::signal;
-use queue::start_queue;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::{signal, sync::{broadcast, watch}};
+use queue::{drain_queue, flush_to_disk, start_queue, QueueState};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use crossbeam_queue::ArrayQueue;
+use metrics::{CacheMetrics, LatencyHistogram};
+use providers::{noop::NoopProvider, LyricsProvider, ProviderRegistry, ProviderSettings};
+use routes::publish_lyrics::{PublishResponse, IDEMPOTENCY_KEY_HEADER};
+use api_keys::{require_api_key, API_KEY_HEADER};
+use user_agents::{filter_user_agent, user_agent, UserAgentFilter};
+use timeouts::{limit_duration, RequestTimeouts};
+use entities::api_key::ApiKey;
+use rate_limit::{limit_challenge, limit_publish, RateLimit, RateLimitCache, TokenBucket};
+use utils::{pow::{self, PowScheme}, TrustedProxies};
+use cache_sizing::{weigh_key, weigh_string, with_capacity, SizedCache};
+use vacuum::{run_scheduled_vacuum, VacuumMetrics, VacuumSettings};
+use prune::{run_scheduled_prune, PruneSettings};
+use track_filter::{run_scheduled_rebuild, TrackFilter};
+use repositories::secret_repository;
+use anyhow::Context;
 
 pub mod errors;
 pub mod routes;
@@ -42,104 +90,400 @@ pub mod utils;
 pub mod db;
 pub mod queue;
 pub mod providers;
+pub mod metrics;
+pub mod rate_limit;
+pub mod auth;
+pub mod api_keys;
+pub mod config;
+pub mod reindex;
+pub mod listener;
+pub mod user_agents;
+pub mod timeouts;
+pub mod vacuum;
+pub mod prune;
+pub mod track_filter;
+pub mod cache_sizing;
+#[cfg(test)]
+mod test_utils;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Number of events buffered for each live feed subscriber before the oldest ones are dropped
+const LIVE_FEED_CAPACITY: usize = 256;
+/// Health probes are polled constantly by load balancers, so they are left out of the request metrics
+pub(crate) const PROBE_PATHS: [&str; 2] = ["/api/health", "/api/ready"];
 
 pub struct AppState {
   pool: Pool<SqliteConnectionManager>,
+  pool_metrics: Arc<PoolMetrics>,
   challenge_cache: Cache<String, String>,
   get_cache: Cache<String, String>,
   search_cache: Cache<String, String>,
+  stats_cache: Cache<String, String>,
+  /// Normalized metadata of the tracks recently sent to the queue
+  missing_track_cache: Cache<String, ()>,
   queue: ArrayQueue<MissingTrack>,
+  queue_enabled: bool,
+  exists_queue_missing: bool,
+  /// Exported as `lrclib_queue_full_total`
+  queue_full_count: AtomicUsize,
+  /// Unix timestamp of the last warning about the queue being full
+  queue_full_warned_at: AtomicI64,
+  /// Exported as `lrclib_requests_total`
   request_counter: AtomicUsize,
+  /// Exported as `lrclib_recent_lyrics_count`
   recent_lyrics_count: AtomicUsize,
+  /// Exported as `lrclib_cache_hits_total{cache="get"}` and `lrclib_cache_misses_total{cache="get"}`
+  get_cache_metrics: CacheMetrics,
+  /// Exported as `lrclib_cache_hits_total{cache="search"}` and `lrclib_cache_misses_total{cache="search"}`
+  search_cache_metrics: CacheMetrics,
+  /// Exported as `lrclib_cache_hits_total{cache="challenge"}` and `lrclib_cache_misses_total{cache="challenge"}`
+  challenge_cache_metrics: CacheMetrics,
+  /// Exported as `lrclib_request_duration_seconds`
+  request_latency: LatencyHistogram,
+  min_pow_difficulty: u8,
+  pow_scheme: PowScheme,
+  rate_limit_cache: RateLimitCache,
+  challenge_rate_limit: RateLimit,
+  publish_rate_limit: RateLimit,
+  publish_min_lines: usize,
+  publish_min_chars: usize,
+  publish_metadata_echo_threshold: f64,
+  providers: ProviderRegistry,
+  /// Secret used to verify the signed publish tokens of trusted clients, if any
+  publish_token_secret: Option<String>,
+  /// Keys the hashes identifying the clients, see `utils::client_id`
+  client_id_salt: Vec<u8>,
+  /// Number of unreviewed flags after which lyrics are evicted from `get_cache`, 0 disables the eviction
+  flag_eviction_threshold: u32,
+  /// Tracks recently flagged, keyed on the client IP and the track id
+  flag_dedup_cache: Cache<String, ()>,
+  flag_track_rate_limit: RateLimit,
+  /// Longest search parameter accepted, in characters
+  search_max_query_length: usize,
+  search_stream_max_rows: usize,
+  /// The database file, for the connections opened outside of the pool
+  database: PathBuf,
+  db_busy_timeout: Duration,
+  /// Published lyrics, broadcast to the live feed connections
+  live_feed: broadcast::Sender<LiveEvent>,
+  live_connections: AtomicUsize,
+  live_max_connections: usize,
+  /// Responses of the publishes made with an `Idempotency-Key`, by client and key, along with the
+  /// hash of the request they answered
+  idempotency_cache: Cache<String, (String, StatusCode, PublishResponse)>,
+  /// Whether requests to the API need a valid API key
+  api_keys_enabled: bool,
+  /// API keys by hash, `None` for unknown or revoked keys
+  api_key_cache: Cache<String, Option<ApiKey>>,
+  /// Requests made with each API key, keyed on the key id and the UTC day
+  api_key_usage_cache: Cache<String, Arc<AtomicU64>>,
+  /// Canonical artist names by alias, `None` for names that aren't an alias
+  artist_alias_cache: Cache<String, Option<String>>,
+  /// Contributors leaderboards by window and limit
+  leaderboard_cache: Cache<String, String>,
+  user_agent_filter: UserAgentFilter,
+  request_timeouts: RequestTimeouts,
+  trusted_proxies: TrustedProxies,
+  capabilities: get_capabilities::Capabilities,
+  /// Exported as `lrclib_vacuum_last_run_timestamp_seconds` and `lrclib_vacuum_last_duration_seconds`
+  vacuum_metrics: VacuumMetrics,
+  /// Consulted before the metadata lookups, only built with the `track_filter` setting
+  track_filter: TrackFilter,
+}
+
+#[derive(Clone, Default)]
+struct MakeRequestUuidV7;
+
+impl MakeRequestId for MakeRequestUuidV7 {
+  fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+    uuid::Uuid::now_v7().to_string().parse().ok().map(RequestId::new)
+  }
 }
 
-pub async fn serve(port: u16, database: &PathBuf, workers_count: u8) {
-  tracing_subscriber::fmt()
-    .compact()
-    .with_env_filter(EnvFilter::from_env("LRCLIB_LOG"))
-    .init();
+/// Builds the CORS layer from the settings checked by `Config::validate`
+fn cors_layer(config: &Config) -> CorsLayer {
+  let allow_origin = match &config.cors_allowed_origins {
+    Some(origins) => AllowOrigin::list(origins.iter().map(|origin| origin.parse().expect("origins are validated"))),
+    None => AllowOrigin::from(Any),
+  };
+
+  // Credentials cannot be combined with a wildcard, so the requested method is allowed instead
+  let allow_methods = match &config.cors_allowed_methods {
+    Some(methods) => AllowMethods::list(methods.iter().map(|method| method.parse().expect("methods are validated"))),
+    None if config.cors_allow_credentials => AllowMethods::mirror_request(),
+    None => AllowMethods::from(Any),
+  };
 
-  let pool = init_db(database).expect("Cannot initialize connection to SQLite database!");
+  let allow_headers = match &config.cors_allowed_headers {
+    Some(headers) => AllowHeaders::list(headers.iter().map(|header| header.parse().expect("headers are validated"))),
+    None => AllowHeaders::list([
+      header::CONTENT_TYPE,
+      header::AUTHORIZATION,
+      header::IF_MATCH,
+      "X-User-Agent".parse().unwrap(),
+      "Lrclib-Client".parse().unwrap(),
+      HeaderName::from_static(REQUEST_ID_HEADER),
+      HeaderName::from_static(API_KEY_HEADER),
+      HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+    ]),
+  };
 
-  let state = Arc::new(
+  CorsLayer::new()
+    .allow_origin(allow_origin)
+    .allow_methods(allow_methods)
+    .allow_headers(allow_headers)
+    .allow_credentials(config.cors_allow_credentials)
+    .expose_headers([
+      header::ETAG,
+      header::RETRY_AFTER,
+      HeaderName::from_static(REQUEST_ID_HEADER),
+      "X-Instrumental".parse().unwrap(),
+      "X-Next-Cursor".parse().unwrap(),
+      "X-Cache".parse().unwrap(),
+      "X-Queue-Status".parse().unwrap(),
+      "X-Lyrics-Kind".parse().unwrap(),
+    ])
+}
+
+/// Opens the database and builds the state shared by the routes and the background tasks
+pub(crate) fn build_state(config: &Config, providers: Vec<Box<dyn LyricsProvider>>) -> anyhow::Result<Arc<AppState>> {
+  let database = config.database.as_ref().expect("Database file is not configured!");
+  let pool_metrics = Arc::new(PoolMetrics::new(Duration::from_millis(config.db_pool_slow_wait)));
+  let pool_size = config.pool_size();
+  tracing::info!(message = "database connection pool", pool_size, min_idle = config.db_pool_min_idle);
+  let pool = init_db(
+    database,
+    PoolOptions {
+      size: pool_size,
+      min_idle: config.db_pool_min_idle,
+      timeout: Duration::from_millis(config.db_pool_timeout),
+      busy_timeout: Duration::from_millis(config.db_busy_timeout),
+      cache_size_kib: config.db_cache_size,
+      metrics: pool_metrics.clone(),
+      slow_query: Duration::from_millis(config.db_slow_query_threshold),
+    },
+  )?;
+  let client_id_salt = secret_repository::get_by_name("client_id_salt", &mut *pool.get()?)?
+    .context("the client id salt is missing from the database")?;
+
+  Ok(Arc::new(
     AppState {
       pool,
-      challenge_cache: Cache::<String, String>::builder()
-        .time_to_live(Duration::from_secs(60 * 5))
-        .max_capacity(100000)
-        .build(),
-      get_cache: Cache::<String, String>::builder()
-        .time_to_live(Duration::from_secs(60 * 60 * 24 * 7))
-        .max_capacity(5000000)
+      pool_metrics,
+      challenge_cache: with_capacity(
+        Cache::<String, String>::builder().time_to_live(Duration::from_secs(config.challenge_cache_ttl)),
+        SizedCache::Challenge,
+        config,
+        |key, value| weigh_string(key, value),
+      ).build(),
+      get_cache: with_capacity(
+        Cache::<String, String>::builder().time_to_live(Duration::from_secs(config.get_cache_ttl)),
+        SizedCache::Get,
+        config,
+        |key, value| weigh_string(key, value),
+      )
+        .support_invalidation_closures()
         .build(),
-      search_cache: Cache::<String, String>::builder()
-        .time_to_live(Duration::from_secs(60 * 60 * 24))
-        .time_to_idle(Duration::from_secs(60 * 60 * 4))
-        .max_capacity(400000)
+      search_cache: with_capacity(
+        Cache::<String, String>::builder()
+          .time_to_live(Duration::from_secs(config.search_cache_ttl))
+          .time_to_idle(Duration::from_secs(config.search_cache_tti)),
+        SizedCache::Search,
+        config,
+        |key, value| weigh_string(key, value),
+      ).build(),
+      // Outlives the one minute between two refreshes by the stats task, so that the stats route
+      // only computes them itself before the first refresh or when a refresh failed
+      stats_cache: Cache::<String, String>::builder()
+        .time_to_live(Duration::from_secs(90))
+        .max_capacity(1)
         .build(),
-      queue: ArrayQueue::new(600000),
+      missing_track_cache: with_capacity(
+        Cache::<String, ()>::builder().time_to_live(Duration::from_secs(config.missing_track_cache_ttl)),
+        SizedCache::MissingTrack,
+        config,
+        |key, value| weigh_key(key, value),
+      ).build(),
+      // Nothing is pushed to a disabled queue, so don't allocate room for it
+      queue: ArrayQueue::new(if config.enable_queue { config.queue_capacity } else { 1 }),
+      queue_enabled: config.enable_queue,
+      exists_queue_missing: config.exists_queue_missing,
+      queue_full_count: AtomicUsize::new(0),
+      queue_full_warned_at: AtomicI64::new(0),
       request_counter: AtomicUsize::new(0),
       recent_lyrics_count: AtomicUsize::new(0),
+      get_cache_metrics: CacheMetrics::default(),
+      search_cache_metrics: CacheMetrics::default(),
+      challenge_cache_metrics: CacheMetrics::default(),
+      request_latency: LatencyHistogram::default(),
+      min_pow_difficulty: pow::min_difficulty(config),
+      pow_scheme: PowScheme::from_config(config),
+      rate_limit_cache: Cache::<String, Arc<Mutex<TokenBucket>>>::builder()
+        .time_to_idle(Duration::from_secs(60 * 10))
+        .max_capacity(100000)
+        .build(),
+      challenge_rate_limit: RateLimit { per_minute: config.challenge_rate_limit },
+      publish_rate_limit: RateLimit { per_minute: config.publish_rate_limit },
+      publish_min_lines: config.publish_min_lines,
+      publish_min_chars: config.publish_min_chars,
+      publish_metadata_echo_threshold: config.publish_metadata_echo_threshold,
+      providers: ProviderRegistry::new(
+        providers,
+        &ProviderSettings {
+          timeout: Duration::from_secs(config.provider_timeout),
+          timeouts: config.provider_timeouts
+            .iter()
+            .map(|(name, timeout)| (name.to_owned(), Duration::from_secs(*timeout)))
+            .collect(),
+          failure_threshold: config.provider_failure_threshold,
+          cooldown: Duration::from_secs(config.provider_cooldown),
+          max_in_flight: config.provider_max_in_flight,
+        },
+      ),
+      publish_token_secret: config.publish_token_secret.clone(),
+      client_id_salt,
+      flag_eviction_threshold: config.flag_eviction_threshold,
+      flag_dedup_cache: Cache::<String, ()>::builder()
+        .time_to_live(Duration::from_secs(config.flag_dedup_ttl))
+        .max_capacity(100000)
+        .build(),
+      flag_track_rate_limit: RateLimit { per_minute: config.flag_track_rate_limit },
+      search_max_query_length: config.search_max_query_length,
+      search_stream_max_rows: config.search_stream_max_rows,
+      database: database.clone(),
+      db_busy_timeout: Duration::from_millis(config.db_busy_timeout),
+      live_feed: broadcast::channel(LIVE_FEED_CAPACITY).0,
+      live_connections: AtomicUsize::new(0),
+      live_max_connections: config.live_max_connections,
+      idempotency_cache: with_capacity(
+        Cache::<String, (String, StatusCode, PublishResponse)>::builder().time_to_live(Duration::from_secs(config.idempotency_cache_ttl)),
+        SizedCache::Idempotency,
+        config,
+        |key, value| weigh_key(key, value),
+      ).build(),
+      api_keys_enabled: config.api_keys_enabled,
+      api_key_cache: Cache::<String, Option<ApiKey>>::builder()
+        .time_to_live(Duration::from_secs(config.api_key_cache_ttl))
+        .max_capacity(10000)
+        .build(),
+      // A day's counter is created during that day, so it expires once the day is over
+      api_key_usage_cache: Cache::<String, Arc<AtomicU64>>::builder()
+        .time_to_live(Duration::from_secs(60 * 60 * 25))
+        .max_capacity(100000)
+        .build(),
+      artist_alias_cache: Cache::<String, Option<String>>::builder()
+        .time_to_live(Duration::from_secs(config.artist_alias_cache_ttl))
+        .max_capacity(100000)
+        .build(),
+      leaderboard_cache: Cache::<String, String>::builder()
+        .time_to_live(Duration::from_secs(config.leaderboard_cache_ttl))
+        .max_capacity(1000)
+        .build(),
+      user_agent_filter: UserAgentFilter::new(config),
+      request_timeouts: RequestTimeouts::new(config.request_timeout, &config.route_timeouts),
+      // Validated by `Config::validate`
+      trusted_proxies: TrustedProxies::parse(&config.trusted_proxies).unwrap_or_default(),
+      capabilities: get_capabilities::Capabilities::new(config),
+      vacuum_metrics: VacuumMetrics::default(),
+      track_filter: TrackFilter::new(config.track_filter_false_positive_rate),
     }
-  );
+  ))
+}
 
+/// The routes of the server with their middleware, without the background tasks
+pub(crate) fn router(state: Arc<AppState>, config: &Config) -> Router {
   let state_for_logging = state.clone();
-  let state_for_metrics = state.clone();
-  let state_for_recent_lyrics_count = state.clone();
-  let state_for_queue = state.clone();
+  let state_for_latency = state.clone();
 
+  // The `get` routes also answer HEAD requests, running the same handler and dropping the body, so
+  // the status and headers are the ones of the GET response
   let api_routes = Router::new()
     .route("/get", get(get_lyrics_by_metadata::route))
+    .route("/get-cached", get(get_lyrics_by_metadata::cached_route))
+    .route("/exists", get(get_exists::route))
+    .route("/get/batch", post(get_lyrics_batch::route))
+    .route("/get/random", get(get_random_lyrics::route))
+    .route("/get/ids", get(get_lyrics_by_track_ids::route).post(get_lyrics_by_track_ids::post_route))
     .route("/get/:track_id", get(get_lyrics_by_track_id::route))
+    .route("/get/:track_id/translations", get(get_translations::route))
+    .route("/get/:track_id/votes", get(vote_lyrics::track_route))
+    .route("/preview/:track_id", get(get_preview::route))
     .route("/search", get(search_lyrics::route))
-    .route("/request-challenge", post(request_challenge::route))
-    .route("/publish", post(publish_lyrics::route))
-    .route("/flag", post(flag_lyrics::route));
-
-  // Metrics
-  tokio::spawn(async move {
-    tokio::time::sleep(Duration::from_secs(60)).await;
-    let mut interval = tokio::time::interval(Duration::from_secs(60));
-    loop {
-      interval.tick().await;
-      let count = state_for_metrics.request_counter.swap(0, Ordering::Relaxed);
-      tracing::info!(message = "requests in the last minute", requests_count = count);
-    }
-  });
-
-  // Recent lyrics count
-  tokio::spawn(async move {
-    tokio::time::sleep(Duration::from_secs(60)).await;
-    let mut interval = tokio::time::interval(Duration::from_secs(60));
-    loop {
-      interval.tick().await;
-      let mut conn = state_for_recent_lyrics_count.pool.get().unwrap();
-      let count = get_last_10_mins_lyrics_count(&mut conn).unwrap();
-      state_for_recent_lyrics_count.recent_lyrics_count.store(count as usize, Ordering::Relaxed);
-    }
-  });
+    .route("/search/stream", get(search_stream::route))
+    .route(
+      "/request-challenge",
+      post(request_challenge::route).layer(middleware::from_fn_with_state(state.clone(), limit_challenge)),
+    )
+    .route(
+      "/publish",
+      post(publish_lyrics::route)
+        .layer::<_, Infallible>(middleware::from_fn_with_state(state.clone(), limit_publish))
+        // Replaces the default limit of the extractors, so that the configured limit is the only one
+        .layer::<_, Infallible>(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.publish_body_limit)),
+    )
+    .route(
+      "/publish/:track_id",
+      patch(publish_lyrics::patch_route)
+        .layer::<_, Infallible>(middleware::from_fn_with_state(state.clone(), limit_publish))
+        .layer::<_, Infallible>(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.publish_body_limit)),
+    )
+    .route("/flag", post(flag_lyrics::route))
+    .route("/vote", post(vote_lyrics::route))
+    .route("/votes/:lyrics_id", get(vote_lyrics::get_route))
+    .route("/stats", get(get_stats::route))
+    .route("/contributors/leaderboard", get(get_leaderboard::route))
+    .route("/export", get(export_lyrics::route))
+    .route("/changes", get(changes::route))
+    .route("/live", get(live::route))
+    .route("/admin/cache/invalidate", post(invalidate_cache::route))
+    .route("/admin/delete/:track_id", post(delete_lyrics::route))
+    .route("/admin/dead-letter", get(dead_letter::route))
+    .route("/admin/dead-letter/requeue", post(dead_letter::requeue_route))
+    .route("/admin/artist-aliases", post(artist_aliases::route).delete(artist_aliases::delete_route))
+    .route("/providers/status", get(get_providers_status::route))
+    .route("/capabilities", get(get_capabilities::route))
+    // Inside the API key check, which isn't counted in the time of the request
+    .route_layer(middleware::from_fn_with_state(state.clone(), limit_duration))
+    // Only applies to the routes above, the health probes must stay reachable without a key
+    .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+    .route("/health", get(get_health::route))
+    .route("/ready", get(get_ready::route));
 
-  let app = Router::new()
+  Router::new()
     .nest("/api", api_routes)
+    .route("/metrics", get(get_metrics::route))
+    .layer(middleware::from_fn_with_state(state.clone(), filter_user_agent))
     .with_state(state)
+    .layer(
+      // Compress according to the client's Accept-Encoding. Responses that already carry a
+      // Content-Encoding are passed through, and tiny responses are not worth compressing.
+      CompressionLayer::new()
+        .gzip(true)
+        .br(true)
+        .compress_when(DefaultPredicate::new().and(SizeAbove::new(256)))
+    )
     .layer(
       TraceLayer::new_for_http()
         .make_span_with(|request: &Request<Body>| {
           let headers = request.headers();
-          let user_agent = headers
-            .get("Lrclib-Client")
+          let user_agent = user_agent(headers).unwrap_or("");
+          let request_id = headers
+            .get(REQUEST_ID_HEADER)
             .and_then(|value| value.to_str().ok())
-            .or_else(|| headers.get("X-User-Agent").and_then(|value| value.to_str().ok()))
-            .or_else(|| headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()))
             .unwrap_or("");
           let method = request.method().to_string();
           let uri = request.uri().to_string();
 
-          tracing::debug_span!("request", method, uri, user_agent)
+          tracing::debug_span!("request", request_id, method, uri, user_agent)
         })
-        .on_response(|response: &Response, latency: Duration, _span: &Span| {
+        .on_response(move |response: &Response, latency: Duration, _span: &Span| {
+          state_for_latency.request_latency.observe(latency);
+
           let status_code = response.status().as_u16();
-          let latency = latency.as_millis();
+          // As u64, since wider integers are logged as strings
+          let latency = latency.as_millis() as u64;
 
           if latency > 500 {
             tracing::info!(
@@ -157,31 +501,176 @@ pub async fn serve(port: u16, database: &PathBuf, workers_count: u8) {
           }
         })
         .on_failure(trace::DefaultOnFailure::new().level(tracing::Level::ERROR))
-        .on_request(move |_request: &Request<Body>, _span: &Span| {
-          state_for_logging.request_counter.fetch_add(1, Ordering::Relaxed);
+        .on_request(move |request: &Request<Body>, _span: &Span| {
+          if !PROBE_PATHS.contains(&request.uri().path()) {
+            state_for_logging.request_counter.fetch_add(1, Ordering::Relaxed);
+          }
         })
     )
-    .layer(
-      CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers([
-          header::CONTENT_TYPE,
-          "X-User-Agent".parse().unwrap(),
-          "Lrclib-Client".parse().unwrap()
-        ])
-    );
+    // A request id sent by the client is kept as is, otherwise a new one is generated
+    .layer(PropagateRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER)))
+    .layer(SetRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER), MakeRequestUuidV7))
+    .layer(cors_layer(config))
+
+}
+
+pub async fn serve(config: Config) {
+  match config.log_format {
+    LogFormat::Compact => tracing_subscriber::fmt()
+      .compact()
+      .with_env_filter(EnvFilter::from_env("LRCLIB_LOG"))
+      .init(),
+    // The fields of the event and of the request span become top-level keys of each line
+    LogFormat::Json => tracing_subscriber::fmt()
+      .json()
+      .flatten_event(true)
+      .with_current_span(true)
+      .with_span_list(false)
+      .with_env_filter(EnvFilter::from_env("LRCLIB_LOG"))
+      .init(),
+  }
 
+  let providers: Vec<Box<dyn LyricsProvider>> = vec![
+    Box::new(NoopProvider::new()),
+  ];
+  let state = build_state(&config, providers).unwrap_or_else(|err| {
+    eprintln!("Cannot initialize the SQLite database: {:#}", err);
+    std::process::exit(1);
+  });
+
+  let state_for_metrics = state.clone();
+  let state_for_stats = state.clone();
+  let state_for_queue = state.clone();
+  let state_for_vacuum = state.clone();
+  let state_for_shutdown = state.clone();
+
+  // Metrics
   tokio::spawn(async move {
-    start_queue(workers_count, state_for_queue).await;
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let mut last_total = 0;
+    let mut last_get_cache = (0, 0);
+    let mut last_search_cache = (0, 0);
+    let mut last_challenge_cache = (0, 0);
+    loop {
+      interval.tick().await;
+      // The counters are cumulative (they are exported by /metrics), so log the delta since the last tick
+      let total = state_for_metrics.request_counter.load(Ordering::Relaxed);
+      let count = total.wrapping_sub(last_total);
+      last_total = total;
+
+      let get_cache = state_for_metrics.get_cache_metrics.snapshot();
+      let search_cache = state_for_metrics.search_cache_metrics.snapshot();
+      let challenge_cache = state_for_metrics.challenge_cache_metrics.snapshot();
+
+      tracing::info!(
+        message = "requests in the last minute",
+        requests_count = count,
+        get_cache_hits = get_cache.0.wrapping_sub(last_get_cache.0),
+        get_cache_misses = get_cache.1.wrapping_sub(last_get_cache.1),
+        search_cache_hits = search_cache.0.wrapping_sub(last_search_cache.0),
+        search_cache_misses = search_cache.1.wrapping_sub(last_search_cache.1),
+        challenge_cache_hits = challenge_cache.0.wrapping_sub(last_challenge_cache.0),
+        challenge_cache_misses = challenge_cache.1.wrapping_sub(last_challenge_cache.1),
+      );
+
+      last_get_cache = get_cache;
+      last_search_cache = search_cache;
+      last_challenge_cache = challenge_cache;
+    }
   });
 
-  let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
-  println!("LRCLIB server is listening on {}!", listener.local_addr().unwrap());
-  axum::serve(listener, app)
-    .with_graceful_shutdown(shutdown_signal())
-    .await
-    .unwrap();
+  // Lyrics stats, including the recent lyrics count
+  tokio::spawn(async move {
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+      interval.tick().await;
+      if let Err(err) = get_stats::refresh_stats(&state_for_stats).await {
+        tracing::error!(message = "failed to compute the lyrics stats", error = err.to_string());
+      }
+    }
+  });
+
+  // Warning about the saturated connection pool
+  if config.db_pool_saturation_warning > 0 {
+    tokio::spawn(watch_pool_saturation(state.pool.clone(), Duration::from_secs(config.db_pool_saturation_warning)));
+  }
+
+  // Scheduled vacuum, during the low-traffic window
+  if config.vacuum_mode != VacuumMode::Off {
+    tokio::spawn(run_scheduled_vacuum(state_for_vacuum, VacuumSettings::new(&config)));
+  }
+
+  // Track filter, built right away and then rebuilt on every interval
+  if config.track_filter {
+    tokio::spawn(run_scheduled_rebuild(state.clone(), Duration::from_secs(config.track_filter_rebuild_interval)));
+  }
+
+  // Pruning of the expired rows of the audit tables
+  let prune_settings = PruneSettings::new(&config);
+  if prune_settings.is_enabled() {
+    tokio::spawn(run_scheduled_prune(state.clone(), prune_settings));
+  }
+
+  let app = router(state.clone(), &config);
+
+  let tls_acceptor = match (&config.tls_cert_file, &config.tls_key_file) {
+    (Some(cert_file), Some(key_file)) => Some(listener::tls_acceptor(cert_file, key_file).unwrap_or_else(|err| {
+      eprintln!("Failed to load the TLS certificate: {:#}", err);
+      std::process::exit(1);
+    })),
+    _ => None,
+  };
+
+  let (queue_control, queue_control_receiver) = watch::channel(QueueState::Running);
+  let queue_workers = if config.enable_queue {
+    let workers_count = config.workers_count.resolve();
+    tracing::info!(message = "queue enabled, starting queue workers", workers_count);
+    start_queue(workers_count, state_for_queue, queue_control_receiver).await
+  } else {
+    tracing::info!(message = "queue disabled, missing tracks will not be fetched");
+    Vec::new()
+  };
+
+  let bind_address = SocketAddr::new(config.bind_address, config.port);
+  match (&config.unix_socket, tls_acceptor) {
+    // Rejected by `Config::validate` on other platforms
+    #[cfg(unix)]
+    (Some(path), _) => {
+      if let Err(err) = listener::serve_unix(path, app, shutdown_signal()).await {
+        eprintln!("Failed to listen on {}: {}", path.display(), err);
+        std::process::exit(1);
+      }
+    },
+    (_, Some(tls_acceptor)) => {
+      if let Err(err) = listener::serve_tls(bind_address, tls_acceptor, app, shutdown_signal()).await {
+        eprintln!("Failed to listen on {}: {}", bind_address, err);
+        std::process::exit(1);
+      }
+    },
+    _ => {
+      let listener = tokio::net::TcpListener::bind(bind_address).await.unwrap_or_else(|err| {
+        eprintln!("Failed to listen on {}: {}", bind_address, err);
+        std::process::exit(1);
+      });
+      println!("LRCLIB server is listening on {}!", listener.local_addr().unwrap());
+      axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+    },
+  }
+
+  if config.enable_queue {
+    drain_queue(queue_workers, &queue_control, Duration::from_secs(config.queue_grace_period)).await;
+
+    // Persist unprocessed missing tracks so they are restored on the next boot
+    match flush_to_disk(&state_for_shutdown) {
+      Ok(count) => println!("Saved {} queued tracks to the database", count),
+      Err(err) => eprintln!("Failed to save queued tracks: {}", err),
+    }
+  }
 }
 
 async fn shutdown_signal() {
@@ -212,3 +701,34 @@ async fn shutdown_signal() {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+  use axum::{body::Body, http::{Request, StatusCode}};
+  use crate::{test_utils::TestApp, REQUEST_ID_HEADER};
+
+  #[tokio::test]
+  async fn request_id_sent_by_the_client_is_echoed_back() {
+    let app = TestApp::new();
+
+    let request = Request::get("/api/health").header(REQUEST_ID_HEADER, "client-request-1").body(Body::empty()).unwrap();
+    let response = app.send(request).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "client-request-1");
+  }
+
+  #[tokio::test]
+  async fn request_id_is_generated_when_missing() {
+    let app = TestApp::new();
+
+    let first = app.get("/api/health").await;
+    let second = app.get("/api/health").await;
+
+    let first_id = first.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_owned();
+    let second_id = second.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_owned();
+    let uuid = uuid::Uuid::parse_str(&first_id).unwrap();
+    assert_eq!(uuid.get_version_num(), 7);
+    assert_ne!(first_id, second_id);
+  }
+}
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+  rt::{TokioExecutor, TokioIo},
+  server::conn::auto::Builder,
+  service::TowerToHyperService,
+};
+use std::{
+  fs::File,
+  future::Future,
+  io::{self, BufReader},
+  net::SocketAddr,
+  path::Path,
+  sync::Arc,
+  time::Duration,
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite},
+  net::TcpListener,
+  sync::watch,
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tower::ServiceExt;
+
+/// Connections that don't complete the TLS handshake in time are dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Loads the PEM encoded certificate chain and private key, so that bad files are reported before
+/// the server starts
+pub fn tls_acceptor(cert_file: &Path, key_file: &Path) -> Result<TlsAcceptor> {
+  let certs = rustls_pemfile::certs(&mut BufReader::new(open(cert_file)?))
+    .collect::<Result<Vec<_>, _>>()
+    .with_context(|| format!("failed to read the certificates of {}", cert_file.display()))?;
+  if certs.is_empty() {
+    bail!("no certificate found in {}", cert_file.display());
+  }
+
+  let key = rustls_pemfile::private_key(&mut BufReader::new(open(key_file)?))
+    .with_context(|| format!("failed to read the private key of {}", key_file.display()))?
+    .ok_or_else(|| anyhow!("no private key found in {}", key_file.display()))?;
+
+  let mut config = ServerConfig::builder()
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .context("the certificate doesn't match the private key")?;
+  config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+  Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn open(path: &Path) -> Result<File> {
+  File::open(path).with_context(|| format!("failed to open {}", path.display()))
+}
+
+/// Serves the app over HTTPS until `shutdown` resolves, then waits for the open connections to
+/// finish their requests
+pub async fn serve_tls(address: SocketAddr, acceptor: TlsAcceptor, app: Router, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+  let listener = TcpListener::bind(address).await?;
+  println!("LRCLIB server is listening on https://{}!", listener.local_addr()?);
+
+  let accept = || async { listener.accept().await.map(|(stream, peer_addr)| (stream, Some(peer_addr))) };
+  let handshake = move |stream| {
+    let acceptor = acceptor.clone();
+    async move {
+      tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")))
+    }
+  };
+  serve(accept, handshake, app, shutdown).await;
+
+  Ok(())
+}
+
+/// Serves the app on a Unix domain socket until `shutdown` resolves, then waits for the open
+/// connections to finish their requests. A socket file left over by a server that didn't shut down
+/// cleanly is replaced, and the socket file is removed on shutdown.
+#[cfg(unix)]
+pub async fn serve_unix(path: &Path, app: Router, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+  remove_stale_socket(path)?;
+  let listener = tokio::net::UnixListener::bind(path)?;
+  println!("LRCLIB server is listening on {}!", path.display());
+
+  // Peers of a Unix domain socket have no IP address
+  let accept = || async { listener.accept().await.map(|(stream, _)| (stream, None)) };
+  serve(accept, |stream| async { Ok(stream) }, app, shutdown).await;
+
+  drop(listener);
+  std::fs::remove_file(path)
+}
+
+/// Removes the socket file at `path` unless a server is still listening on it
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+  use std::os::unix::fs::FileTypeExt;
+
+  let metadata = match std::fs::symlink_metadata(path) {
+    Ok(metadata) => metadata,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  if !metadata.file_type().is_socket() {
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+  }
+
+  if std::os::unix::net::UnixStream::connect(path).is_ok() {
+    return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("another server is listening on {}", path.display())));
+  }
+
+  std::fs::remove_file(path)
+}
+
+/// Accepts connections until `shutdown` resolves, like `axum::serve` does for plain TCP listeners.
+/// Each accepted stream goes through `establish`, such as a TLS handshake, in its own task.
+async fn serve<A, AF, S, E, EF, I>(accept: A, establish: E, app: Router, shutdown: impl Future<Output = ()>)
+where
+  A: Fn() -> AF,
+  AF: Future<Output = io::Result<(S, Option<SocketAddr>)>>,
+  E: Fn(S) -> EF,
+  EF: Future<Output = io::Result<I>> + Send + 'static,
+  I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+  // Every connection holds a receiver, so that the sender is closed once they are all done
+  let (shutdown_tx, shutdown_rx) = watch::channel(());
+  tokio::pin!(shutdown);
+
+  loop {
+    let accepted = tokio::select! {
+      result = accept() => result,
+      _ = &mut shutdown => break,
+    };
+
+    match accepted {
+      Ok((stream, peer_addr)) => {
+        let connection = establish(stream);
+        let app = app.clone();
+        let shutdown_rx = shutdown_rx.clone();
+
+        tokio::spawn(async move {
+          match connection.await {
+            Ok(stream) => serve_connection(stream, peer_addr, app, shutdown_rx).await,
+            Err(err) => tracing::debug!(message = "failed to establish a connection", error = err.to_string()),
+          }
+        });
+      },
+      Err(err) => {
+        // Most likely out of file descriptors, which won't get better right away
+        tracing::error!(message = "failed to accept a connection", error = err.to_string());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+      },
+    }
+  }
+
+  drop(shutdown_rx);
+  let _ = shutdown_tx.send(());
+  shutdown_tx.closed().await;
+}
+
+async fn serve_connection<I>(stream: I, peer_addr: Option<SocketAddr>, app: Router, mut shutdown: watch::Receiver<()>)
+where
+  I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+  // What `into_make_service_with_connect_info` provides to the handlers over plain TCP
+  let service = app.map_request(move |mut request: Request<Incoming>| {
+    if let Some(peer_addr) = peer_addr {
+      request.extensions_mut().insert(ConnectInfo(peer_addr));
+    }
+    request
+  });
+
+  let builder = Builder::new(TokioExecutor::new());
+  let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service));
+  tokio::pin!(connection);
+
+  tokio::select! {
+    result = connection.as_mut() => {
+      if let Err(err) = result {
+        tracing::debug!(message = "failed to serve a connection", error = err.to_string());
+      }
+      return;
+    },
+    _ = shutdown.changed() => connection.as_mut().graceful_shutdown(),
+  }
+
+  if let Err(err) = connection.await {
+    tracing::debug!(message = "failed to serve a connection", error = err.to_string());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::{Ipv4Addr, SocketAddr}, path::PathBuf, time::Duration};
+  use axum::http::{StatusCode, Version};
+  use tokio::sync::oneshot;
+  use crate::test_utils::TestApp;
+  use super::{serve_tls, tls_acceptor};
+
+  /// A certificate for `localhost` and its key, self-signed and valid until 2126
+  fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name)
+  }
+
+  #[tokio::test]
+  async fn serves_https_with_a_self_signed_certificate() {
+    let app = TestApp::new();
+    let acceptor = tls_acceptor(&fixture("localhost.crt"), &fixture("localhost.key")).unwrap();
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(serve_tls(
+      SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+      acceptor,
+      app.router.clone(),
+      async { let _ = stopped.await; },
+    ));
+
+    let certificate = reqwest::Certificate::from_pem(&std::fs::read(fixture("localhost.crt")).unwrap()).unwrap();
+    let client = reqwest::Client::builder().add_root_certificate(certificate).build().unwrap();
+    let url = format!("https://localhost:{}/api/health", port);
+    let mut response = client.get(&url).send().await;
+    // The server may not be listening yet
+    for _ in 0..50 {
+      if response.is_ok() {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      response = client.get(&url).send().await;
+    }
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Negotiated with ALPN
+    assert_eq!(response.version(), Version::HTTP_2);
+
+    // Plain HTTP doesn't get through the handshake
+    assert!(client.get(format!("http://localhost:{}/api/health", port)).send().await.is_err());
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+  }
+
+  #[test]
+  fn reports_files_without_a_certificate() {
+    let Err(err) = tls_acceptor(&fixture("localhost.key"), &fixture("localhost.key")) else {
+      panic!("a key was read as a certificate");
+    };
+    assert_eq!(err.to_string(), format!("no certificate found in {}", fixture("localhost.key").display()));
+  }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+pub struct CacheMetrics {
+  pub hits: AtomicUsize,
+  pub misses: AtomicUsize,
+}
+
+impl CacheMetrics {
+  pub fn record(&self, hit: bool) {
+    if hit {
+      self.hits.fetch_add(1, Ordering::Relaxed);
+    } else {
+      self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  pub fn snapshot(&self) -> (usize, usize) {
+    (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+  }
+}
+
+#[derive(Default)]
+pub struct LatencyHistogram {
+  buckets: [AtomicUsize; LATENCY_BUCKETS.len()],
+  count: AtomicUsize,
+  sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+  pub fn observe(&self, latency: Duration) {
+    let seconds = latency.as_secs_f64();
+    if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+      self.buckets[index].fetch_add(1, Ordering::Relaxed);
+    }
+    self.count.fetch_add(1, Ordering::Relaxed);
+    self.sum_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+  }
+}
+
+pub struct MetricsWriter {
+  output: String,
+}
+
+impl MetricsWriter {
+  pub fn new() -> Self {
+    Self { output: String::new() }
+  }
+
+  pub fn counter(&mut self, name: &str, help: &str, value: usize) {
+    self.header(name, help, "counter");
+    let _ = writeln!(self.output, "{} {}", name, value);
+  }
+
+  pub fn gauge(&mut self, name: &str, help: &str, value: usize) {
+    self.header(name, help, "gauge");
+    let _ = writeln!(self.output, "{} {}", name, value);
+  }
+
+  pub fn float_gauge(&mut self, name: &str, help: &str, value: f64) {
+    self.header(name, help, "gauge");
+    let _ = writeln!(self.output, "{} {}", name, value);
+  }
+
+  /// Writes a gauge with one sample per label value
+  pub fn labeled_gauges(&mut self, name: &str, help: &str, label: &str, samples: &[(&str, usize)]) {
+    self.header(name, help, "gauge");
+    for (label_value, value) in samples {
+      let _ = writeln!(self.output, "{}{{{}=\"{}\"}} {}", name, label, label_value, value);
+    }
+  }
+
+  pub fn cache_counters(&mut self, caches: &[(&str, &CacheMetrics)]) {
+    self.header("lrclib_cache_hits_total", "Number of cache lookups that found an entry.", "counter");
+    for (cache, metrics) in caches {
+      let _ = writeln!(self.output, "lrclib_cache_hits_total{{cache=\"{}\"}} {}", cache, metrics.hits.load(Ordering::Relaxed));
+    }
+    self.header("lrclib_cache_misses_total", "Number of cache lookups that did not find an entry.", "counter");
+    for (cache, metrics) in caches {
+      let _ = writeln!(self.output, "lrclib_cache_misses_total{{cache=\"{}\"}} {}", cache, metrics.misses.load(Ordering::Relaxed));
+    }
+  }
+
+  pub fn histogram(&mut self, name: &str, help: &str, histogram: &LatencyHistogram) {
+    self.header(name, help, "histogram");
+    let mut cumulative = 0;
+    for (bound, bucket) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+      cumulative += bucket.load(Ordering::Relaxed);
+      let _ = writeln!(self.output, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+    }
+    let count = histogram.count.load(Ordering::Relaxed);
+    let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(self.output, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+    let _ = writeln!(self.output, "{}_sum {}", name, sum);
+    let _ = writeln!(self.output, "{}_count {}", name, count);
+  }
+
+  pub fn finish(self) -> String {
+    self.output
+  }
+
+  fn header(&mut self, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(self.output, "# HELP {} {}", name, help);
+    let _ = writeln!(self.output, "# TYPE {} {}", name, kind);
+  }
+}
+
+impl Default for MetricsWriter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
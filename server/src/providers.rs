@@ -1 +1,401 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use crate::{entities::missing_track::MissingTrack, metrics::LatencyHistogram};
+
 pub mod noop;
+
+/// Number of recent fetches the success rate of a provider is computed over
+const RECENT_FETCHES: usize = 100;
+
+#[derive(Debug)]
+pub struct FetchedLyrics {
+  pub plain_lyrics: Option<String>,
+  pub synced_lyrics: Option<String>,
+  pub instrumental: bool,
+  /// Where the provider found the lyrics, like the URL of a page. The registry falls back to the
+  /// name of the provider.
+  pub attribution: Option<String>,
+}
+
+#[async_trait]
+pub trait LyricsProvider: Send + Sync {
+  /// A short, stable identifier used in logs
+  fn name(&self) -> &str;
+
+  async fn fetch(&self, track: &MissingTrack) -> Result<Option<FetchedLyrics>>;
+}
+
+pub struct ProviderSettings {
+  /// How long a fetch can take before it counts as a failure
+  pub timeout: Duration,
+  /// Timeouts overriding the default one, by provider name
+  pub timeouts: HashMap<String, Duration>,
+  /// Consecutive failures after which a provider is not called for `cooldown`, 0 disables the breaker
+  pub failure_threshold: u32,
+  pub cooldown: Duration,
+  /// Most fetches running at once, over all the providers
+  pub max_in_flight: usize,
+}
+
+/// Stops calling a provider that keeps failing, so that a provider that is down doesn't stall the
+/// queue workers. Once the cooldown is over a single call is let through, and the breaker closes
+/// again if it succeeds.
+struct CircuitBreaker {
+  failure_threshold: u32,
+  cooldown: Duration,
+  state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+  consecutive_failures: u32,
+  open_until: Option<Instant>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+  Closed,
+  Open,
+  /// The cooldown is over, and the next call decides whether the breaker closes or opens again
+  HalfOpen,
+}
+
+impl CircuitBreaker {
+  fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+    Self { failure_threshold, cooldown, state: Mutex::new(BreakerState::default()) }
+  }
+
+  fn is_open(&self) -> bool {
+    self.circuit_state() == CircuitState::Open
+  }
+
+  fn circuit_state(&self) -> CircuitState {
+    let state = self.state.lock().unwrap();
+    match state.open_until {
+      Some(open_until) if Instant::now() < open_until => CircuitState::Open,
+      Some(_) => CircuitState::HalfOpen,
+      None => CircuitState::Closed,
+    }
+  }
+
+  fn consecutive_failures(&self) -> u32 {
+    self.state.lock().unwrap().consecutive_failures
+  }
+
+  fn record_success(&self) {
+    *self.state.lock().unwrap() = BreakerState::default();
+  }
+
+  /// Returns true when this failure opened the breaker
+  fn record_failure(&self) -> bool {
+    if self.failure_threshold == 0 {
+      return false;
+    }
+
+    let mut state = self.state.lock().unwrap();
+    state.consecutive_failures += 1;
+    if state.consecutive_failures >= self.failure_threshold {
+      state.open_until = Some(Instant::now() + self.cooldown);
+      return true;
+    }
+    false
+  }
+}
+
+/// Outcomes of the recent fetches of a provider, for the status endpoint
+#[derive(Default)]
+struct ProviderHealth {
+  last_success_at: Option<DateTime<Utc>>,
+  last_failure_at: Option<DateTime<Utc>>,
+  /// Whether each of the last `RECENT_FETCHES` fetches succeeded, the oldest first
+  recent_fetches: VecDeque<bool>,
+}
+
+impl ProviderHealth {
+  fn record(&mut self, success: bool) {
+    let now = Some(Utc::now());
+    if success {
+      self.last_success_at = now;
+    } else {
+      self.last_failure_at = now;
+    }
+
+    if self.recent_fetches.len() == RECENT_FETCHES {
+      self.recent_fetches.pop_front();
+    }
+    self.recent_fetches.push_back(success);
+  }
+}
+
+struct RegisteredProvider {
+  provider: Box<dyn LyricsProvider>,
+  timeout: Duration,
+  breaker: CircuitBreaker,
+  health: Mutex<ProviderHealth>,
+}
+
+impl RegisteredProvider {
+  fn record_success(&self) {
+    self.breaker.record_success();
+    self.health.lock().unwrap().record(true);
+  }
+
+  /// Returns true when this failure opened the breaker
+  fn record_failure(&self) -> bool {
+    self.health.lock().unwrap().record(false);
+    self.breaker.record_failure()
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderStatus {
+  pub name: String,
+  /// Whether the provider is called for the next missing track, which is not the case while its breaker is open
+  pub enabled: bool,
+  pub circuit: CircuitState,
+  pub consecutive_failures: u32,
+  pub last_success_at: Option<DateTime<Utc>>,
+  pub last_failure_at: Option<DateTime<Utc>>,
+  /// Share of the recent fetches that succeeded, `None` before the first fetch
+  pub success_rate: Option<f64>,
+  pub recent_fetches: usize,
+}
+
+/// Tries each provider in priority order until one of them returns lyrics
+pub struct ProviderRegistry {
+  providers: Vec<RegisteredProvider>,
+  /// Shared by the queue workers, so that the outbound connections are bounded by `max_in_flight`
+  /// rather than by the number of workers
+  fetch_permits: Semaphore,
+  max_in_flight: usize,
+  /// Time spent waiting for a fetch permit
+  pub fetch_wait: LatencyHistogram,
+}
+
+impl ProviderRegistry {
+  pub fn new(providers: Vec<Box<dyn LyricsProvider>>, settings: &ProviderSettings) -> Self {
+    let providers = providers
+      .into_iter()
+      .map(|provider| RegisteredProvider {
+        timeout: settings.timeouts.get(provider.name()).copied().unwrap_or(settings.timeout),
+        breaker: CircuitBreaker::new(settings.failure_threshold, settings.cooldown),
+        health: Mutex::new(ProviderHealth::default()),
+        provider,
+      })
+      .collect();
+
+    Self {
+      providers,
+      fetch_permits: Semaphore::new(settings.max_in_flight),
+      max_in_flight: settings.max_in_flight,
+      fetch_wait: LatencyHistogram::default(),
+    }
+  }
+
+  /// Returns the first lyrics found. When no provider has lyrics but some of them failed or were
+  /// skipped because of their open breaker, the last error is returned so that the track can be
+  /// retried later.
+  pub async fn fetch(&self, track: &MissingTrack) -> Result<Option<FetchedLyrics>> {
+    let mut last_error = None;
+
+    for registered in &self.providers {
+      let provider = &registered.provider;
+
+      if registered.breaker.is_open() {
+        last_error = Some(anyhow!("provider {} is temporarily disabled after repeated failures", provider.name()));
+        continue;
+      }
+
+      let result = {
+        // The wait for a permit doesn't count towards the timeout of the provider
+        let wait_started_at = Instant::now();
+        let _permit = self.fetch_permits.acquire().await?;
+        self.fetch_wait.observe(wait_started_at.elapsed());
+
+        match tokio::time::timeout(registered.timeout, provider.fetch(track)).await {
+          Ok(result) => result,
+          Err(_) => Err(anyhow!("provider {} timed out after {:?}", provider.name(), registered.timeout)),
+        }
+      };
+
+      match result {
+        Ok(Some(mut lyrics)) => {
+          registered.record_success();
+          lyrics.attribution.get_or_insert_with(|| provider.name().to_owned());
+          return Ok(Some(lyrics));
+        },
+        Ok(None) => registered.record_success(),
+        Err(err) => {
+          tracing::warn!(
+            message = "provider failed to fetch lyrics",
+            provider = provider.name(),
+            error = err.to_string(),
+            queue = true,
+          );
+          if registered.record_failure() {
+            tracing::warn!(
+              message = "provider disabled after repeated failures",
+              provider = provider.name(),
+              cooldown_secs = registered.breaker.cooldown.as_secs(),
+              queue = true,
+            );
+          }
+          last_error = Some(err);
+        },
+      }
+    }
+
+    match last_error {
+      Some(err) => Err(err),
+      None => Ok(None),
+    }
+  }
+
+  /// Number of provider fetches currently running
+  pub fn in_flight(&self) -> usize {
+    self.max_in_flight - self.fetch_permits.available_permits()
+  }
+
+  /// Whether the breaker of each provider is currently open, by provider name
+  pub fn breaker_states(&self) -> Vec<(&str, bool)> {
+    self.providers
+      .iter()
+      .map(|registered| (registered.provider.name(), registered.breaker.is_open()))
+      .collect()
+  }
+
+  /// The live health of each provider, in priority order
+  pub fn statuses(&self) -> Vec<ProviderStatus> {
+    self.providers
+      .iter()
+      .map(|registered| {
+        let circuit = registered.breaker.circuit_state();
+        let health = registered.health.lock().unwrap();
+        let successes = health.recent_fetches.iter().filter(|success| **success).count();
+
+        ProviderStatus {
+          name: registered.provider.name().to_owned(),
+          enabled: circuit != CircuitState::Open,
+          circuit,
+          consecutive_failures: registered.breaker.consecutive_failures(),
+          last_success_at: health.last_success_at,
+          last_failure_at: health.last_failure_at,
+          success_rate: (!health.recent_fetches.is_empty())
+            .then(|| successes as f64 / health.recent_fetches.len() as f64),
+          recent_fetches: health.recent_fetches.len(),
+        }
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, time::Duration};
+  use anyhow::{anyhow, Result};
+  use async_trait::async_trait;
+  use crate::entities::missing_track::MissingTrack;
+  use super::{CircuitState, FetchedLyrics, LyricsProvider, ProviderRegistry, ProviderSettings};
+
+  /// Answers every fetch with the same lyrics, no lyrics or an error
+  struct CannedProvider {
+    name: &'static str,
+    result: Result<Option<&'static str>, &'static str>,
+  }
+
+  impl CannedProvider {
+    fn new(name: &'static str, result: Result<Option<&'static str>, &'static str>) -> Self {
+      Self { name, result }
+    }
+  }
+
+  #[async_trait]
+  impl LyricsProvider for CannedProvider {
+    fn name(&self) -> &str {
+      self.name
+    }
+
+    async fn fetch(&self, _track: &MissingTrack) -> Result<Option<FetchedLyrics>> {
+      match self.result {
+        Ok(plain_lyrics) => Ok(plain_lyrics.map(|plain_lyrics| FetchedLyrics {
+          plain_lyrics: Some(plain_lyrics.to_owned()),
+          synced_lyrics: None,
+          instrumental: false,
+          attribution: None,
+        })),
+        Err(message) => Err(anyhow!(message)),
+      }
+    }
+  }
+
+  fn registry(providers: Vec<CannedProvider>) -> ProviderRegistry {
+    let providers = providers.into_iter().map(|provider| Box::new(provider) as Box<dyn LyricsProvider>).collect();
+    ProviderRegistry::new(providers, &ProviderSettings {
+      timeout: Duration::from_secs(1),
+      timeouts: HashMap::new(),
+      failure_threshold: 2,
+      cooldown: Duration::from_secs(60),
+      max_in_flight: 1,
+    })
+  }
+
+  fn missing_track() -> MissingTrack {
+    MissingTrack {
+      name: "Hello".to_owned(),
+      artist_name: "Adele".to_owned(),
+      album_name: "25".to_owned(),
+      duration: 295.0,
+      retry_count: 0,
+      next_attempt_at: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn returns_the_lyrics_of_the_first_provider_that_has_them() {
+    let registry = registry(vec![
+      CannedProvider::new("empty", Ok(None)),
+      CannedProvider::new("failing", Err("unavailable")),
+      CannedProvider::new("canned", Ok(Some("Hello, it's me"))),
+      CannedProvider::new("unused", Ok(Some("Never called"))),
+    ]);
+
+    let lyrics = registry.fetch(&missing_track()).await.unwrap().unwrap();
+    assert_eq!(lyrics.plain_lyrics.as_deref(), Some("Hello, it's me"));
+    // Falls back to the name of the provider
+    assert_eq!(lyrics.attribution.as_deref(), Some("canned"));
+
+    let fetches: Vec<usize> = registry.statuses().iter().map(|status| status.recent_fetches).collect();
+    assert_eq!(fetches, vec![1, 1, 1, 0]);
+  }
+
+  #[tokio::test]
+  async fn returns_the_last_error_when_no_provider_has_lyrics() {
+    let registry = registry(vec![CannedProvider::new("failing", Err("unavailable")), CannedProvider::new("empty", Ok(None))]);
+
+    let err = registry.fetch(&missing_track()).await.unwrap_err();
+    assert_eq!(err.to_string(), "unavailable");
+  }
+
+  #[tokio::test]
+  async fn skips_a_provider_once_its_breaker_opens() {
+    let registry = registry(vec![CannedProvider::new("failing", Err("unavailable")), CannedProvider::new("canned", Ok(Some("Hello, it's me")))]);
+
+    for _ in 0..3 {
+      assert!(registry.fetch(&missing_track()).await.unwrap().is_some());
+    }
+
+    let failing = &registry.statuses()[0];
+    assert_eq!(failing.circuit, CircuitState::Open);
+    assert_eq!(failing.recent_fetches, 2);
+  }
+}
@@ -1,14 +1,24 @@
 use anyhow::Result;
-use crate::queue::ScrapedData;
+use async_trait::async_trait;
+use crate::entities::missing_track::MissingTrack;
+use super::{FetchedLyrics, LyricsProvider};
 
+#[derive(Default)]
 pub struct NoopProvider {}
 
 impl NoopProvider {
   pub fn new() -> Self {
     Self {}
   }
+}
+
+#[async_trait]
+impl LyricsProvider for NoopProvider {
+  fn name(&self) -> &str {
+    "noop"
+  }
 
-  pub async fn retrieve_lyrics(&mut self, _track_name: &str, _artist_name: &str, _album_name: &str, _duration: f64) -> Result<Option<ScrapedData>> {
+  async fn fetch(&self, _track: &MissingTrack) -> Result<Option<FetchedLyrics>> {
     Ok(None)
   }
 }
@@ -0,0 +1,124 @@
+use std::{sync::Arc, time::Duration};
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::Connection;
+use crate::{
+  config::Config,
+  repositories::{dead_letter_repository, flag_repository, retry_on_busy_blocking, vote_repository},
+  AppState,
+};
+
+/// Pause between the batches, so that the publishes waiting for the write lock get it first
+const BATCH_PAUSE: Duration = Duration::from_millis(50);
+
+pub struct PruneSettings {
+  pub interval: Duration,
+  /// `None` keeps the rows of the table
+  pub dead_letter_retention: Option<chrono::Duration>,
+  pub flag_retention: Option<chrono::Duration>,
+  pub deleted_track_votes: bool,
+  pub batch_size: usize,
+}
+
+impl PruneSettings {
+  pub fn new(config: &Config) -> Self {
+    let retention = |days: u64| match days {
+      0 => None,
+      days => Some(chrono::Duration::days(days as i64)),
+    };
+
+    PruneSettings {
+      interval: Duration::from_secs(config.prune_interval),
+      dead_letter_retention: retention(config.dead_letter_retention_days),
+      flag_retention: retention(config.flag_retention_days),
+      deleted_track_votes: config.prune_deleted_track_votes,
+      batch_size: config.prune_batch_size,
+    }
+  }
+
+  /// Whether any table is pruned
+  pub fn is_enabled(&self) -> bool {
+    !self.interval.is_zero()
+      && (self.dead_letter_retention.is_some() || self.flag_retention.is_some() || self.deleted_track_votes)
+  }
+}
+
+#[derive(Default)]
+struct PruneOutcome {
+  dead_letters: usize,
+  flags: usize,
+  votes: usize,
+}
+
+/// Deletes the expired rows of the audit tables on every interval. Like the vacuum, the pruning
+/// runs on a connection of its own, so it never holds the pooled connections.
+pub async fn run_scheduled_prune(state: Arc<AppState>, settings: PruneSettings) {
+  let settings = Arc::new(settings);
+  let start = tokio::time::Instant::now() + settings.interval;
+  let mut interval = tokio::time::interval_at(start, settings.interval);
+
+  loop {
+    interval.tick().await;
+
+    let state_for_prune = state.clone();
+    let settings_for_prune = settings.clone();
+    let result = tokio::task::spawn_blocking(move || prune(&state_for_prune, &settings_for_prune)).await;
+
+    match result {
+      Ok(Ok(outcome)) => tracing::info!(
+        message = "pruned the audit tables",
+        dead_letters = outcome.dead_letters,
+        flags = outcome.flags,
+        votes = outcome.votes,
+      ),
+      Ok(Err(err)) => tracing::error!(message = "failed to prune the audit tables", error = format!("{:#}", err)),
+      Err(err) => tracing::error!(message = "prune task panicked", error = err.to_string()),
+    }
+  }
+}
+
+fn prune(state: &AppState, settings: &PruneSettings) -> Result<PruneOutcome> {
+  let mut conn = Connection::open(&state.database)?;
+  conn.busy_timeout(state.db_busy_timeout)?;
+  let now = Utc::now();
+  let mut outcome = PruneOutcome::default();
+
+  if let Some(retention) = settings.dead_letter_retention {
+    let before = now - retention;
+    outcome.dead_letters = in_batches(settings.batch_size, &mut conn, |limit, conn| {
+      dead_letter_repository::delete_created_before(before, limit, conn)
+    })?;
+  }
+
+  if let Some(retention) = settings.flag_retention {
+    let before = now - retention;
+    outcome.flags = in_batches(settings.batch_size, &mut conn, |limit, conn| {
+      flag_repository::delete_reviewed_before(before, limit, conn)
+    })?;
+  }
+
+  if settings.deleted_track_votes {
+    outcome.votes = in_batches(settings.batch_size, &mut conn, vote_repository::delete_on_deleted_tracks)?;
+  }
+
+  Ok(outcome)
+}
+
+/// Runs `delete` until a batch deletes fewer rows than the batch size, each batch in its own
+/// implicit transaction. Returns the total of deleted rows.
+fn in_batches(
+  batch_size: usize,
+  conn: &mut Connection,
+  mut delete: impl FnMut(usize, &mut Connection) -> Result<usize>,
+) -> Result<usize> {
+  let mut total = 0;
+
+  loop {
+    let deleted = retry_on_busy_blocking(|| delete(batch_size, conn))?;
+    total += deleted;
+    if deleted < batch_size {
+      return Ok(total);
+    }
+    std::thread::sleep(BATCH_PAUSE);
+  }
+}
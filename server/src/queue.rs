@@ -1,57 +1,198 @@
-use std::sync::Arc;
+use std::{sync::{atomic::Ordering, Arc}, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
 use anyhow::Result;
+use chrono::Utc;
 use rusqlite::Connection;
-use crate::providers::noop::NoopProvider;
-use crate::repositories::{lyrics_repository, track_repository};
+use crate::providers::FetchedLyrics;
+use crate::repositories::{dead_letter_repository, lyrics_repository, queued_track_repository, retry_on_busy, track_repository};
 use crate::entities::missing_track::MissingTrack;
+use crate::utils::{language::detect_language, lyrics_content_hash, normalize::normalize, prepare_input};
 use crate::AppState;
 
-#[derive(Debug)]
-pub struct ScrapedData {
-  pub plain_lyrics: Option<String>,
-  pub synced_lyrics: Option<String>,
-  pub instrumental: bool,
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueueState {
+  Running,
+  /// Workers finish their current job, but do not take new ones
+  Draining,
+  /// Workers abandon their current job, pushing it back to the queue
+  Stopped,
 }
 
-pub async fn start_queue(workers_count: u8, state: Arc<AppState>) {
-  // Do not start queue if the workers_count is equal or smaller than zero
-  if workers_count <= 0 {
-    return
-  }
-
-  for _ in 0..workers_count {
+pub async fn start_queue(workers_count: usize, state: Arc<AppState>, control: watch::Receiver<QueueState>) -> Vec<JoinHandle<()>> {
+  (0..workers_count).map(|_| {
     let state_clone = Arc::clone(&state);
+    let control_clone = control.clone();
 
     tokio::spawn(async move {
-      worker(state_clone).await;
-    });
+      worker(state_clone, control_clone).await;
+    })
+  }).collect()
+}
+
+/// Asks the workers to finish their in-flight jobs, waiting up to `grace_period` before forcing them
+/// to stop. Jobs interrupted by the forced stop are pushed back to the queue.
+pub async fn drain_queue(mut workers: Vec<JoinHandle<()>>, control: &watch::Sender<QueueState>, grace_period: Duration) {
+  let _ = control.send(QueueState::Draining);
+
+  let drained = tokio::time::timeout(grace_period, async {
+    // Awaiting a finished handle again panics, so each one is dropped as soon as its worker is done
+    while let Some(worker) = workers.last_mut() {
+      let _ = worker.await;
+      workers.pop();
+    }
+  }).await;
+
+  if drained.is_err() {
+    tracing::warn!(message = "queue workers did not finish in time, stopping them", queue = true);
+    let _ = control.send(QueueState::Stopped);
+    for worker in workers {
+      let _ = worker.await;
+    }
   }
 }
 
-async fn worker(state: Arc<AppState>) {
-  let mut provider = NoopProvider::new();
+async fn worker(state: Arc<AppState>, mut control: watch::Receiver<QueueState>) {
   loop {
+    if *control.borrow() != QueueState::Running {
+      break;
+    }
+
     let maybe_missing_track = get_next_track(&state).await;
 
     if let Some(missing_track) = maybe_missing_track {
-      process_track(&state, &mut provider, missing_track).await;
+      let mut stop_control = control.clone();
+      tokio::select! {
+        _ = process_track(&state, missing_track.clone()) => {},
+        _ = stop_control.wait_for(|queue_state| *queue_state == QueueState::Stopped) => {
+          if let Err(err) = push_track(&state, missing_track) {
+            tracing::error!(message = "failed to push interrupted track back to the queue", error = err.to_string(), queue = true);
+          }
+          break;
+        },
+      }
     } else {
-      tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+      tokio::select! {
+        _ = tokio::time::sleep(std::time::Duration::from_millis(50)) => {},
+        _ = control.changed() => {},
+      }
     }
   }
 }
 
+/// How many persisted tracks are moved back into the in-memory queue at once
+const REFILL_BATCH_SIZE: usize = 1000;
+/// Number of failed attempts after which a track is moved to the dead-letter table
+const MAX_ATTEMPTS: u32 = 6;
+/// Delay before the first retry, doubled after each failed attempt (5m, 10m, 20m, ... ~5h in total)
+const BASE_RETRY_DELAY_SECS: i64 = 5 * 60;
+/// Minimum seconds between two warnings about the in-memory queue being full
+const QUEUE_FULL_WARNING_INTERVAL_SECS: i64 = 60;
+
+/// Schedules a failed track for another attempt with exponential backoff. Deferred tracks wait in the
+/// queued_tracks table, which only hands them back once they are due, so a permanently failing track
+/// cannot be retried in a tight loop. After `MAX_ATTEMPTS`, the track is moved to the dead-letter table.
+fn retry_later(state: &Arc<AppState>, mut missing_track: MissingTrack, error: &str) -> Result<()> {
+  missing_track.retry_count += 1;
+  let mut conn = state.pool.get()?;
+
+  if missing_track.retry_count >= MAX_ATTEMPTS {
+    tracing::warn!(
+      message = "giving up on track after too many failed attempts",
+      track_name = missing_track.name,
+      artist_name = missing_track.artist_name,
+      album_name = missing_track.album_name,
+      duration = missing_track.duration,
+      retry_count = missing_track.retry_count,
+      queue = true,
+    );
+    dead_letter_repository::add_one(&missing_track, error, &mut conn)?;
+    return Ok(());
+  }
+
+  let delay_secs = BASE_RETRY_DELAY_SECS * 2_i64.pow(missing_track.retry_count - 1);
+  missing_track.next_attempt_at = Some(Utc::now() + chrono::Duration::seconds(delay_secs));
+  queued_track_repository::add_many(&[missing_track], &mut conn)?;
+
+  Ok(())
+}
+
+/// Pushes a missing track to the in-memory queue. When the in-memory queue is full, the track
+/// is persisted to the queued_tracks table instead of being dropped, and will be picked up once
+/// the workers have drained the in-memory queue.
+pub fn push_track(state: &Arc<AppState>, missing_track: MissingTrack) -> Result<()> {
+  if let Err(missing_track) = state.queue.push(missing_track) {
+    record_queue_full(state);
+    let mut conn = state.pool.get()?;
+    queued_track_repository::add_many(&[missing_track], &mut conn)?;
+  }
+
+  Ok(())
+}
+
+/// Counts a push to the full in-memory queue. The warning is logged at most once per
+/// `QUEUE_FULL_WARNING_INTERVAL_SECS`, as a full queue usually stays full for a while.
+fn record_queue_full(state: &Arc<AppState>) {
+  let queue_full_total = state.queue_full_count.fetch_add(1, Ordering::Relaxed) + 1;
+
+  let now = Utc::now().timestamp();
+  let warned_at = state.queue_full_warned_at.load(Ordering::Relaxed);
+  let should_warn = now - warned_at >= QUEUE_FULL_WARNING_INTERVAL_SECS
+    && state.queue_full_warned_at.compare_exchange(warned_at, now, Ordering::Relaxed, Ordering::Relaxed).is_ok();
+
+  if should_warn {
+    tracing::warn!(
+      message = "in-memory queue is full, persisting missing tracks to the database instead",
+      capacity = state.queue.capacity(),
+      queue_full_total,
+      queue = true,
+    );
+  }
+}
+
+/// Moves every track still waiting in the in-memory queue to the queued_tracks table, so they
+/// can be restored on the next boot.
+pub fn flush_to_disk(state: &Arc<AppState>) -> Result<usize> {
+  let mut missing_tracks = Vec::with_capacity(state.queue.len());
+  while let Some(missing_track) = state.queue.pop() {
+    missing_tracks.push(missing_track);
+  }
+
+  let mut conn = state.pool.get()?;
+  queued_track_repository::add_many(&missing_tracks, &mut conn)?;
+
+  Ok(missing_tracks.len())
+}
+
 async fn get_next_track(state: &Arc<AppState>) -> Option<MissingTrack> {
+  if let Some(missing_track) = state.queue.pop() {
+    return Some(missing_track);
+  }
+
+  if let Err(err) = refill_from_disk(state) {
+    tracing::error!(message = "failed to restore queued tracks", error = err.to_string(), queue = true);
+  }
+
   state.queue.pop()
 }
 
-async fn process_track(state: &Arc<AppState>, provider: &mut NoopProvider, missing_track: MissingTrack) {
-  let maybe_data = provider.retrieve_lyrics(
-    &missing_track.name,
-    &missing_track.artist_name,
-    &missing_track.album_name,
-    missing_track.duration,
-  ).await;
+fn refill_from_disk(state: &Arc<AppState>) -> Result<()> {
+  let mut conn = state.pool.get()?;
+  let missing_tracks = queued_track_repository::take_batch(REFILL_BATCH_SIZE, &mut conn)?;
+
+  let overflow: Vec<MissingTrack> = missing_tracks
+    .into_iter()
+    .filter_map(|missing_track| state.queue.push(missing_track).err())
+    .collect();
+
+  if !overflow.is_empty() {
+    queued_track_repository::add_many(&overflow, &mut conn)?;
+  }
+
+  Ok(())
+}
+
+async fn process_track(state: &Arc<AppState>, missing_track: MissingTrack) {
+  let maybe_data = state.providers.fetch(&missing_track).await;
 
   match maybe_data {
     Ok(data) => {
@@ -68,19 +209,44 @@ async fn process_track(state: &Arc<AppState>, provider: &mut NoopProvider, missi
         queue = true,
       );
 
-      // Push the track back to the queue
-      let _ = state.queue.push(missing_track);
+      if let Err(err) = retry_later(state, missing_track, &err.to_string()) {
+        tracing::error!(message = "failed to schedule track for retry", error = err.to_string(), queue = true);
+      }
     },
   }
 }
 
-async fn process_lyrics_result(missing_track: &MissingTrack, data: Option<ScrapedData>, state: &Arc<AppState>) {
-  let mut conn = state.pool.get().unwrap();
-  let remaining_jobs = get_remaining_jobs(&state).await;
+async fn process_lyrics_result(missing_track: &MissingTrack, data: Option<FetchedLyrics>, state: &Arc<AppState>) {
+  let mut conn = match state.pool.get() {
+    Ok(conn) => conn,
+    Err(err) => {
+      tracing::error!(message = "failed to get a database connection", error = err.to_string(), queue = true);
+      if let Err(err) = retry_later(state, missing_track.clone(), &err.to_string()) {
+        tracing::error!(message = "failed to schedule track for retry", error = err.to_string(), queue = true);
+      }
+      return;
+    },
+  };
+  let remaining_jobs = get_remaining_jobs(state).await;
 
   if let Some(data) = data {
-    match add_found(missing_track, &data, &mut conn).await {
-      Ok(_) => tracing::info!(
+    let added = retry_on_busy(|| add_found(missing_track, &data, &mut conn)).await;
+    if added.is_ok() {
+      state.track_filter.add_track(&prepare_input(&missing_track.name), &prepare_input(&missing_track.artist_name));
+    }
+    match added {
+      Ok(AddedLyrics::Linked { lyrics_id, duplicate_track_id }) => tracing::info!(
+        message = format!("linked near-duplicate lyrics"),
+        track_name = missing_track.name,
+        artist_name = missing_track.artist_name,
+        album_name = missing_track.album_name,
+        duration = missing_track.duration,
+        lyrics_id,
+        duplicate_track_id,
+        remaining_jobs = remaining_jobs,
+        queue = true,
+      ),
+      Ok(AddedLyrics::New) => tracing::info!(
         message = format!("added new lyrics"),
         track_name = missing_track.name,
         artist_name = missing_track.artist_name,
@@ -113,31 +279,190 @@ async fn process_lyrics_result(missing_track: &MissingTrack, data: Option<Scrape
   }
 }
 
-async fn add_found(missing_track: &MissingTrack, data: &ScrapedData, conn: &mut Connection) -> Result<()> {
+enum AddedLyrics {
+  New,
+  /// The track points to the lyrics of a near-duplicate track instead of a copy of them
+  Linked { lyrics_id: i64, duplicate_track_id: i64 },
+}
+
+/// Stores the track with the fetched lyrics. When another track with the same normalized names and
+/// about the same duration already has the same lyrics (as hashed by `lyrics_content_hash`, like the
+/// publish route does), the new track points to those lyrics rather than storing a duplicate.
+fn add_found(missing_track: &MissingTrack, data: &FetchedLyrics, conn: &mut Connection) -> Result<AddedLyrics> {
   let mut tx = conn.transaction()?;
 
+  let content_hash = lyrics_content_hash(data.plain_lyrics.as_deref(), data.synced_lyrics.as_deref(), data.instrumental);
+  let track_name_normalized = normalize(&missing_track.name);
+  let artist_name_normalized = normalize(&missing_track.artist_name);
+  let near_duplicate = match track_name_normalized.is_empty() || artist_name_normalized.is_empty() {
+    true => None,
+    false => lyrics_repository::get_near_duplicate_tx(
+      &track_name_normalized,
+      &artist_name_normalized,
+      missing_track.duration,
+      &content_hash,
+      &mut tx,
+    )?,
+  };
+
   let track_id = track_repository::add_one_tx(
-    &missing_track.name.trim(),
-    &missing_track.artist_name.trim(),
-    &missing_track.album_name.trim(),
+    missing_track.name.trim(),
+    missing_track.artist_name.trim(),
+    missing_track.album_name.trim(),
     missing_track.duration,
     &mut tx,
   )?;
 
+  if let Some((lyrics_id, duplicate_track_id)) = near_duplicate {
+    track_repository::set_last_lyrics_id_tx(track_id, lyrics_id, &mut tx)?;
+    // Like a duplicate publish, so that the new track shows up in the changes feed and the exports
+    lyrics_repository::touch_tx(lyrics_id, &mut tx)?;
+    tx.commit()?;
+    return Ok(AddedLyrics::Linked { lyrics_id, duplicate_track_id });
+  }
+
+  let language = data.plain_lyrics.as_deref().and_then(detect_language);
+
   lyrics_repository::add_one_tx(
     &data.plain_lyrics,
     &data.synced_lyrics,
     track_id,
     data.instrumental,
     &None,
+    language.as_deref(),
+    data.attribution.as_deref(),
     &mut tx,
   )?;
 
   tx.commit()?;
 
-  Ok(())
+  Ok(AddedLyrics::New)
 }
 
 async fn get_remaining_jobs(state: &Arc<AppState>) -> usize {
   state.queue.len()
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, time::Duration};
+  use anyhow::Result;
+  use async_trait::async_trait;
+  use tokio::sync::{watch, Notify};
+  use crate::{
+    entities::missing_track::MissingTrack,
+    providers::{FetchedLyrics, LyricsProvider},
+    test_utils::TestApp,
+  };
+  use super::{add_found, drain_queue, flush_to_disk, start_queue, AddedLyrics, QueueState};
+
+  /// Never answers for the track named `stuck`, and finds nothing for the others after `delay`
+  struct SlowProvider {
+    started: Arc<Notify>,
+    delay: Duration,
+  }
+
+  #[async_trait]
+  impl LyricsProvider for SlowProvider {
+    fn name(&self) -> &str {
+      "slow"
+    }
+
+    async fn fetch(&self, track: &MissingTrack) -> Result<Option<FetchedLyrics>> {
+      self.started.notify_one();
+      if track.name == "stuck" {
+        std::future::pending::<()>().await;
+      }
+      tokio::time::sleep(self.delay).await;
+      Ok(None)
+    }
+  }
+
+  fn missing_track(name: &str) -> MissingTrack {
+    MissingTrack {
+      name: name.to_owned(),
+      artist_name: "Artist".to_owned(),
+      album_name: "Album".to_owned(),
+      duration: 200.0,
+      retry_count: 0,
+      next_attempt_at: None,
+    }
+  }
+
+  fn test_app(delay: Duration) -> (TestApp, Arc<Notify>) {
+    let started = Arc::new(Notify::new());
+    let provider = SlowProvider { started: started.clone(), delay };
+    (TestApp::with_providers(vec![Box::new(provider)], |_| {}), started)
+  }
+
+  fn persisted_track_names(app: &TestApp) -> Vec<String> {
+    let conn = app.state.pool.get().unwrap();
+    let mut statement = conn.prepare("SELECT name FROM queued_tracks ORDER BY name").unwrap();
+    let names = statement.query_map([], |row| row.get(0)).unwrap();
+    names.collect::<Result<_, _>>().unwrap()
+  }
+
+  #[tokio::test]
+  async fn linking_near_duplicate_lyrics_touches_them() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let mut conn = app.state.pool.get().unwrap();
+    conn.execute("UPDATE lyrics SET updated_at = '2020-01-01 00:00:00+00:00'", []).unwrap();
+
+    let data = FetchedLyrics {
+      plain_lyrics: Some("Hello, it's me".to_owned()),
+      synced_lyrics: None,
+      instrumental: false,
+      attribution: None,
+    };
+    let missing_track = MissingTrack { name: "Hello (feat. Someone)".to_owned(), ..missing_track("Hello") };
+    let added = add_found(&MissingTrack { artist_name: "Adele".to_owned(), ..missing_track }, &data, &mut conn).unwrap();
+
+    let AddedLyrics::Linked { lyrics_id, duplicate_track_id } = added else {
+      panic!("the lyrics were not linked");
+    };
+    assert_eq!(duplicate_track_id, track_id);
+    let updated_at: String = conn.query_row("SELECT updated_at FROM lyrics WHERE id = ?", [lyrics_id], |row| row.get(0)).unwrap();
+    assert!(updated_at.as_str() > "2020-01-01 00:00:00+00:00");
+  }
+
+  #[tokio::test]
+  async fn stopped_jobs_are_re_enqueued_and_waiting_jobs_checkpointed() {
+    let (app, started) = test_app(Duration::ZERO);
+    let (control, control_receiver) = watch::channel(QueueState::Running);
+    app.state.queue.push(missing_track("stuck")).unwrap();
+    let stuck_workers = start_queue(1, app.state.clone(), control_receiver.clone()).await;
+    started.notified().await;
+    // Idle, so it stops as soon as the drain starts, before the stuck one. Its handle comes first, so
+    // that it has finished by the time the drain gives up on the stuck worker.
+    let mut workers = start_queue(1, app.state.clone(), control_receiver).await;
+    workers.extend(stuck_workers);
+
+    control.send(QueueState::Draining).unwrap();
+    while !workers[0].is_finished() {
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    app.state.queue.push(missing_track("waiting 1")).unwrap();
+    app.state.queue.push(missing_track("waiting 2")).unwrap();
+    drain_queue(workers, &control, Duration::from_millis(100)).await;
+
+    assert_eq!(flush_to_disk(&app.state).unwrap(), 3);
+    assert_eq!(persisted_track_names(&app), ["stuck", "waiting 1", "waiting 2"]);
+  }
+
+  #[tokio::test]
+  async fn in_flight_jobs_finish_within_the_grace_period() {
+    let (app, started) = test_app(Duration::from_millis(50));
+    let (control, control_receiver) = watch::channel(QueueState::Running);
+    app.state.queue.push(missing_track("in flight")).unwrap();
+    let workers = start_queue(1, app.state.clone(), control_receiver).await;
+    started.notified().await;
+
+    app.state.queue.push(missing_track("waiting")).unwrap();
+    drain_queue(workers, &control, Duration::from_secs(5)).await;
+
+    // The finished job isn't pushed back, and the job the worker never took is checkpointed
+    assert_eq!(flush_to_disk(&app.state).unwrap(), 1);
+    assert_eq!(persisted_track_names(&app), ["waiting"]);
+  }
+}
@@ -0,0 +1,104 @@
+use axum::{
+  extract::{ConnectInfo, Request, State},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use std::{
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use moka::future::Cache;
+use crate::{
+  auth::bearer_claims,
+  errors::ApiError,
+  utils::client_ip,
+  AppState,
+};
+
+/// Key used when the client IP cannot be determined, so such requests share a single bucket
+const GLOBAL_BUCKET: &str = "global";
+
+pub struct TokenBucket {
+  tokens: f64,
+  last_refill: Instant,
+}
+
+pub type RateLimitCache = Cache<String, Arc<Mutex<TokenBucket>>>;
+
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+  /// Number of requests allowed per minute, also used as the burst size. Zero disables the limit.
+  pub per_minute: u32,
+}
+
+impl RateLimit {
+  fn refill_per_second(&self) -> f64 {
+    self.per_minute as f64 / 60.0
+  }
+}
+
+/// Takes a token from the bucket identified by `key`. When the bucket is empty, returns how long
+/// the client should wait before the next token becomes available.
+pub async fn check(cache: &RateLimitCache, key: String, limit: RateLimit) -> Result<(), Duration> {
+  if limit.per_minute == 0 {
+    return Ok(());
+  }
+
+  let bucket = cache
+    .get_with(key, async {
+      Arc::new(Mutex::new(TokenBucket {
+        tokens: limit.per_minute as f64,
+        last_refill: Instant::now(),
+      }))
+    })
+    .await;
+
+  let mut bucket = bucket.lock().unwrap();
+  let now = Instant::now();
+  let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+  bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_second()).min(limit.per_minute as f64);
+  bucket.last_refill = now;
+
+  if bucket.tokens >= 1.0 {
+    bucket.tokens -= 1.0;
+    Ok(())
+  } else {
+    let missing = 1.0 - bucket.tokens;
+    Err(Duration::from_secs_f64(missing / limit.refill_per_second()))
+  }
+}
+
+async fn limit_request(
+  scope: &str,
+  limit: RateLimit,
+  state: &Arc<AppState>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let peer_addr = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+  let key = match client_ip(request.headers(), peer_addr, &state.trusted_proxies) {
+    Some(ip) => format!("{}:{}", scope, ip),
+    None => format!("{}:{}", scope, GLOBAL_BUCKET),
+  };
+
+  match check(&state.rate_limit_cache, key, limit).await {
+    Ok(_) => next.run(request).await,
+    Err(retry_after) => ApiError::RateLimitedError(retry_after.as_secs().max(1)).into_response(),
+  }
+}
+
+pub async fn limit_challenge(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+  let limit = state.challenge_rate_limit;
+  limit_request("challenge", limit, &state, request, next).await
+}
+
+pub async fn limit_publish(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+  let claims = bearer_claims(request.headers(), state.publish_token_secret.as_deref());
+  if claims.is_some_and(|claims| !claims.class.is_rate_limited()) {
+    return next.run(request).await;
+  }
+
+  let limit = state.publish_rate_limit;
+  limit_request("publish", limit, &state, request, next).await
+}
@@ -0,0 +1,101 @@
+use std::{path::PathBuf, time::Duration};
+use anyhow::Result;
+use rusqlite::{Connection, TransactionBehavior};
+use crate::{
+  db::migrate,
+  repositories::reindex_repository,
+  utils::{language::detect_language, lyrics_content_hash},
+};
+
+pub struct ReindexOptions {
+  /// Lyrics rows processed, and committed, at once
+  pub batch_size: usize,
+  /// Only count the rows that would change, without writing anything
+  pub dry_run: bool,
+  /// Start over from the first row instead of resuming an interrupted reindex
+  pub restart: bool,
+  pub busy_timeout: Duration,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct ReindexProgress {
+  pub last_lyrics_id: i64,
+  pub processed: usize,
+  pub changed: usize,
+}
+
+/// Recomputes the content hash, the language and the search index entry of every lyrics row.
+///
+/// The rows are processed in batches of their own transaction, so that a server using the same
+/// database only waits for one batch at a time. The position is stored along with each batch, and an
+/// interrupted reindex resumes from there. Languages given by publishers are kept, only missing ones
+/// are detected.
+pub fn reindex(database: &PathBuf, options: &ReindexOptions, mut on_batch: impl FnMut(&ReindexProgress)) -> Result<ReindexProgress> {
+  let mut conn = Connection::open(database)?;
+  conn.busy_timeout(options.busy_timeout)?;
+  migrate(&mut conn)?;
+
+  if options.restart && !options.dry_run {
+    reindex_repository::clear_progress(&mut conn)?;
+  }
+
+  let mut progress = ReindexProgress {
+    last_lyrics_id: match options.restart {
+      true => 0,
+      false => reindex_repository::get_progress(&mut conn)?.unwrap_or(0),
+    },
+    ..Default::default()
+  };
+
+  loop {
+    let batch = reindex_repository::get_batch(progress.last_lyrics_id, options.batch_size, &mut conn)?;
+    let Some(last) = batch.last() else {
+      break;
+    };
+    progress.last_lyrics_id = last.id;
+
+    // A dry run only reads, so it doesn't take the write lock
+    let behavior = if options.dry_run { TransactionBehavior::Deferred } else { TransactionBehavior::Immediate };
+    let mut tx = conn.transaction_with_behavior(behavior)?;
+
+    for lyrics in &batch {
+      let content_hash = lyrics_content_hash(lyrics.plain_lyrics.as_deref(), lyrics.synced_lyrics.as_deref(), lyrics.instrumental);
+      let language = match lyrics.instrumental {
+        true => lyrics.language.clone(),
+        false => lyrics.language.clone().or_else(|| lyrics.plain_lyrics.as_deref().and_then(detect_language)),
+      };
+
+      let is_lyrics_stale = lyrics.content_hash.as_deref() != Some(content_hash.as_str()) || language != lyrics.language;
+      let is_search_stale = lyrics.is_current && lyrics.is_search_stale;
+
+      if is_lyrics_stale || is_search_stale {
+        progress.changed += 1;
+      }
+
+      if options.dry_run {
+        continue;
+      }
+      if is_lyrics_stale {
+        reindex_repository::update_derived_tx(lyrics.id, &content_hash, language.as_deref(), &mut tx)?;
+      }
+      if is_search_stale {
+        reindex_repository::rebuild_search_entry_tx(lyrics.track_id, &mut tx)?;
+      }
+    }
+
+    progress.processed += batch.len();
+
+    if !options.dry_run {
+      reindex_repository::set_progress_tx(progress.last_lyrics_id, &mut tx)?;
+      tx.commit()?;
+    }
+
+    on_batch(&progress);
+  }
+
+  if !options.dry_run {
+    reindex_repository::clear_progress(&mut conn)?;
+  }
+
+  Ok(progress)
+}
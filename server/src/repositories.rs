@@ -1,3 +1,127 @@
 pub mod track_repository;
 pub mod lyrics_repository;
 pub mod missing_track_repository;
+pub mod queued_track_repository;
+pub mod dead_letter_repository;
+pub mod flag_repository;
+pub mod reindex_repository;
+pub mod api_key_repository;
+pub mod vote_repository;
+pub mod artist_alias_repository;
+pub mod secret_repository;
+
+use rand::Rng;
+use rusqlite::ErrorCode;
+use std::time::Duration;
+use crate::errors::ApiError;
+
+/// Attempts made by `retry_on_busy`, including the first one
+const BUSY_RETRY_ATTEMPTS: u32 = 4;
+const BUSY_RETRY_BASE_DELAY_MS: u64 = 50;
+
+/// Errors that may come from SQLite refusing a write because another connection holds the lock
+pub trait BusyError {
+  fn is_busy(&self) -> bool;
+}
+
+impl BusyError for anyhow::Error {
+  fn is_busy(&self) -> bool {
+    self.chain().any(|cause| match cause.downcast_ref::<rusqlite::Error>() {
+      Some(err) => matches!(err.sqlite_error_code(), Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)),
+      None => false,
+    })
+  }
+}
+
+impl BusyError for ApiError {
+  fn is_busy(&self) -> bool {
+    matches!(self, ApiError::DatabaseBusyError)
+  }
+}
+
+/// Runs a write transaction again, after a jittered exponential backoff, when SQLite reports the
+/// database as busy or locked. The busy timeout doesn't cover every case: a deferred transaction
+/// that has to upgrade its read lock to a write lock fails right away instead of waiting.
+///
+/// Waits without holding up the runtime thread, so that other requests go on meanwhile.
+pub async fn retry_on_busy<T, E: BusyError>(mut write: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+  let mut attempt = 1;
+  loop {
+    match write() {
+      Err(err) if err.is_busy() && attempt < BUSY_RETRY_ATTEMPTS => {
+        tokio::time::sleep(busy_retry_delay(attempt)).await;
+        attempt += 1;
+      },
+      result => return result,
+    }
+  }
+}
+
+/// Same as `retry_on_busy`, for writes already running on a blocking thread, which sleeps while
+/// waiting
+pub fn retry_on_busy_blocking<T, E: BusyError>(mut write: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+  let mut attempt = 1;
+  loop {
+    match write() {
+      Err(err) if err.is_busy() && attempt < BUSY_RETRY_ATTEMPTS => {
+        std::thread::sleep(busy_retry_delay(attempt));
+        attempt += 1;
+      },
+      result => return result,
+    }
+  }
+}
+
+fn busy_retry_delay(attempt: u32) -> Duration {
+  let delay = BUSY_RETRY_BASE_DELAY_MS << (attempt - 1);
+  let delay = rand::thread_rng().gen_range(delay / 2..=delay);
+  tracing::warn!(message = "database busy, retrying write", attempt, delay_ms = delay);
+  Duration::from_millis(delay)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::Duration};
+  use anyhow::Result;
+  use rusqlite::{Connection, TransactionBehavior};
+  use super::retry_on_busy;
+
+  #[tokio::test]
+  async fn retries_a_write_until_the_lock_is_released() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("busy.sqlite3");
+    let conn = Connection::open(&path).unwrap();
+    conn.execute("CREATE TABLE writes (id INTEGER PRIMARY KEY)", ()).unwrap();
+    // Fails right away instead of waiting, to leave the waiting to the retries
+    conn.busy_timeout(Duration::ZERO).unwrap();
+
+    let (locked_sender, locked_receiver) = std::sync::mpsc::channel();
+    let holder = thread::spawn(move || {
+      let mut conn = Connection::open(&path).unwrap();
+      let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+      locked_sender.send(()).unwrap();
+      thread::sleep(Duration::from_millis(60));
+      tx.commit().unwrap();
+    });
+    locked_receiver.recv().unwrap();
+
+    let ticked = Arc::new(AtomicBool::new(false));
+    let ticker = tokio::spawn({
+      let ticked = ticked.clone();
+      async move { ticked.store(true, Ordering::SeqCst) }
+    });
+
+    let mut attempts = 0;
+    let written = retry_on_busy(|| -> Result<usize> {
+      attempts += 1;
+      Ok(conn.execute("INSERT INTO writes DEFAULT VALUES", ())?)
+    }).await;
+
+    assert_eq!(written.unwrap(), 1);
+    assert!(attempts > 1);
+    // The test runtime has a single thread, which the other task only got while the write waited
+    assert!(ticked.load(Ordering::SeqCst));
+    ticker.await.unwrap();
+    holder.join().unwrap();
+  }
+}
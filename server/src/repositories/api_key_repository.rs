@@ -0,0 +1,46 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use indoc::indoc;
+use crate::entities::api_key::ApiKey;
+
+pub fn add_one(key_hash: &str, name: &str, daily_quota: u64, conn: &mut Connection) -> Result<i64> {
+  let query = indoc! {"
+    INSERT INTO api_keys (key_hash, name, daily_quota)
+    VALUES (?, ?, ?)
+  "};
+  let mut statement = conn.prepare(query)?;
+  let id = statement.insert((key_hash, name, daily_quota))?;
+  Ok(id)
+}
+
+/// Returns the key with the given hash, unless it was revoked
+pub fn get_by_hash(key_hash: &str, conn: &mut Connection) -> Result<Option<ApiKey>> {
+  let query = indoc! {"
+    SELECT id, name, daily_quota
+    FROM api_keys
+    WHERE key_hash = ? AND revoked_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let api_key = statement
+    .query_row([key_hash], |row| {
+      Ok(ApiKey {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        daily_quota: row.get("daily_quota")?,
+      })
+    })
+    .optional()?;
+  Ok(api_key)
+}
+
+/// Revokes the key with the given name, returning whether there was one
+pub fn revoke_by_name(name: &str, conn: &mut Connection) -> Result<bool> {
+  let query = indoc! {"
+    UPDATE api_keys
+    SET revoked_at = CURRENT_TIMESTAMP
+    WHERE name = ? AND revoked_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let updated = statement.execute([name])?;
+  Ok(updated > 0)
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use indoc::indoc;
+
+/// Adds an alias of the artist, or points an existing alias to it
+pub fn add_or_replace(alias_lower: &str, artist_name_lower: &str, conn: &mut Connection) -> Result<()> {
+  let query = indoc! {"
+    INSERT INTO artist_aliases (alias_lower, artist_name_lower)
+    VALUES (?, ?)
+    ON CONFLICT (alias_lower) DO UPDATE SET artist_name_lower = excluded.artist_name_lower
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((alias_lower, artist_name_lower))?;
+  Ok(())
+}
+
+/// Removes the alias, returning whether there was one
+pub fn delete_one(alias_lower: &str, conn: &mut Connection) -> Result<bool> {
+  let query = indoc! {"
+    DELETE FROM artist_aliases WHERE alias_lower = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let deleted = statement.execute([alias_lower])?;
+  Ok(deleted > 0)
+}
+
+/// Returns the canonical name of the artist known under the given alias
+pub fn resolve_artist_alias(alias_lower: &str, conn: &mut Connection) -> Result<Option<String>> {
+  let query = indoc! {"
+    SELECT artist_name_lower FROM artist_aliases WHERE alias_lower = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let artist_name_lower = statement.query_row([alias_lower], |row| row.get(0)).optional()?;
+  Ok(artist_name_lower)
+}
+
+/// Lists every alias, lowercased
+pub fn get_all_aliases(conn: &mut Connection) -> Result<Vec<String>> {
+  let query = indoc! {"
+    SELECT alias_lower FROM artist_aliases
+  "};
+  let mut statement = conn.prepare(query)?;
+  let aliases = statement.query_map([], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+  Ok(aliases)
+}
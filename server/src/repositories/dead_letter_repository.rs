@@ -0,0 +1,119 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, Row};
+use indoc::indoc;
+use chrono::prelude::*;
+use crate::entities::{dead_letter_track::DeadLetterTrack, missing_track::MissingTrack};
+
+pub fn add_one(missing_track: &MissingTrack, last_error: &str, conn: &mut Connection) -> Result<i64> {
+  let now = Utc::now();
+  let query = indoc! {"
+    INSERT INTO dead_letter_tracks (
+      name,
+      artist_name,
+      album_name,
+      duration,
+      retry_count,
+      last_error,
+      created_at
+    )
+    VALUES (?, ?, ?, ?, ?, ?, ?)
+  "};
+  let mut statement = conn.prepare(query)?;
+  let row_id = statement.insert(
+    (
+      &missing_track.name,
+      &missing_track.artist_name,
+      &missing_track.album_name,
+      missing_track.duration,
+      missing_track.retry_count,
+      last_error,
+      now,
+    )
+  )?;
+  Ok(row_id)
+}
+
+/// Lists the dead-lettered tracks with an id greater than `after_id`, oldest first
+pub fn get_page(after_id: i64, limit: usize, conn: &mut Connection) -> Result<Vec<DeadLetterTrack>> {
+  let query = indoc! {"
+    SELECT id, name, artist_name, album_name, duration, retry_count, last_error, created_at
+    FROM dead_letter_tracks
+    WHERE id > ?
+    ORDER BY id
+    LIMIT ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((after_id, limit))?;
+
+  let mut tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    tracks.push(map_row(row)?);
+  }
+
+  Ok(tracks)
+}
+
+pub fn get_by_id(id: i64, conn: &mut Connection) -> Result<Option<DeadLetterTrack>> {
+  let query = indoc! {"
+    SELECT id, name, artist_name, album_name, duration, retry_count, last_error, created_at
+    FROM dead_letter_tracks
+    WHERE id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let track = statement.query_row([id], map_row).optional()?;
+  Ok(track)
+}
+
+pub fn count(conn: &mut Connection) -> Result<i64> {
+  let query = indoc! {"
+    SELECT COUNT(*) FROM dead_letter_tracks
+  "};
+  let mut statement = conn.prepare(query)?;
+  let count = statement.query_row([], |row| row.get(0))?;
+  Ok(count)
+}
+
+pub fn delete_many(ids: &[i64], conn: &mut Connection) -> Result<()> {
+  let tx = conn.transaction()?;
+  {
+    let query = indoc! {"
+      DELETE FROM dead_letter_tracks WHERE id = ?
+    "};
+    let mut statement = tx.prepare(query)?;
+    for id in ids {
+      statement.execute([id])?;
+    }
+  }
+  tx.commit()?;
+  Ok(())
+}
+
+fn map_row(row: &Row) -> rusqlite::Result<DeadLetterTrack> {
+  Ok(DeadLetterTrack {
+    id: row.get("id")?,
+    missing_track: MissingTrack {
+      name: row.get::<_, Option<String>>("name")?.unwrap_or_default(),
+      artist_name: row.get::<_, Option<String>>("artist_name")?.unwrap_or_default(),
+      album_name: row.get::<_, Option<String>>("album_name")?.unwrap_or_default(),
+      duration: row.get::<_, Option<f64>>("duration")?.unwrap_or_default(),
+      retry_count: row.get::<_, Option<u32>>("retry_count")?.unwrap_or_default(),
+      next_attempt_at: None,
+    },
+    last_error: row.get("last_error")?,
+    created_at: row.get("created_at")?,
+  })
+}
+
+/// Deletes up to `limit` of the tracks dead-lettered before `before`, returning how many were deleted
+pub fn delete_created_before(before: DateTime<Utc>, limit: usize, conn: &mut Connection) -> Result<usize> {
+  let query = indoc! {"
+    DELETE FROM dead_letter_tracks
+    WHERE id IN (
+      SELECT id FROM dead_letter_tracks WHERE created_at < ? LIMIT ?
+    )
+  "};
+  let mut statement = conn.prepare(query)?;
+  let deleted = statement.execute((before, limit))?;
+  Ok(deleted)
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use indoc::indoc;
+use chrono::prelude::*;
+use crate::entities::flag::{Flag, FlagReason};
+
+/// Lists the flags that have not been reviewed yet, the oldest first
+pub fn get_unreviewed(limit: usize, conn: &mut Connection) -> Result<Vec<Flag>> {
+  let query = indoc! {"
+    SELECT id, lyrics_id, reason, content, created_at
+    FROM flags
+    WHERE reviewed_at IS NULL
+    ORDER BY id
+    LIMIT ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query([limit])?;
+
+  let mut flags = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    let reason = row.get::<_, Option<String>>("reason")?;
+    flags.push(Flag {
+      id: row.get("id")?,
+      lyrics_id: row.get("lyrics_id")?,
+      reason: reason.as_deref().and_then(FlagReason::parse).unwrap_or(FlagReason::Other),
+      content: row.get("content")?,
+      created_at: row.get("created_at")?,
+    });
+  }
+
+  Ok(flags)
+}
+
+pub fn count_unreviewed(conn: &mut Connection) -> Result<i64> {
+  let query = indoc! {"
+    SELECT COUNT(*) FROM flags WHERE reviewed_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let count = statement.query_row([], |row| row.get(0))?;
+  Ok(count)
+}
+
+pub fn count_unreviewed_by_lyrics_id(lyrics_id: i64, conn: &mut Connection) -> Result<i64> {
+  let query = indoc! {"
+    SELECT COUNT(*) FROM flags WHERE lyrics_id = ? AND reviewed_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let count = statement.query_row([lyrics_id], |row| row.get(0))?;
+  Ok(count)
+}
+
+/// Deletes up to `limit` of the flags reviewed before `before`, returning how many were deleted
+pub fn delete_reviewed_before(before: DateTime<Utc>, limit: usize, conn: &mut Connection) -> Result<usize> {
+  let query = indoc! {"
+    DELETE FROM flags
+    WHERE id IN (
+      SELECT id FROM flags WHERE reviewed_at < ? LIMIT ?
+    )
+  "};
+  let mut statement = conn.prepare(query)?;
+  let deleted = statement.execute((before, limit))?;
+  Ok(deleted)
+}
@@ -1,18 +1,27 @@
 use anyhow::Result;
-use rusqlite::{Connection, Transaction};
+use rusqlite::{params_from_iter, Connection, OptionalExtension, Row, Transaction};
 use indoc::indoc;
 use chrono::prelude::*;
+use crate::{
+  entities::{contributor::{ContributorPublishes, Leaderboard}, lyrics::SimpleLyrics, stats::LyricsStats, track::SimpleTrack, translation::Translation},
+  repositories::track_repository::SearchFilters,
+  utils::{lyrics_content_hash, normalize::featuring_patterns, prepare_input},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn add_one(
   plain_lyrics: &Option<String>,
   synced_lyrics: &Option<String>,
   track_id: i64,
   instrumental: bool,
   source: &Option<String>,
+  language: Option<&str>,
+  attribution: Option<&str>,
   conn: &mut Connection
 ) -> Result<i64> {
   let plain_lyrics = plain_lyrics.as_ref().filter(|s| !s.is_empty());
   let synced_lyrics = synced_lyrics.as_ref().filter(|s| !s.is_empty());
+  let content_hash = lyrics_content_hash(plain_lyrics.map(|s| s.as_str()), synced_lyrics.map(|s| s.as_str()), instrumental);
 
   let now = Utc::now();
   let query = indoc! {"
@@ -24,10 +33,13 @@ pub fn add_one(
       instrumental,
       track_id,
       source,
+      content_hash,
+      language,
+      attribution,
       created_at,
       updated_at
     )
-    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
   "};
   let mut statement = conn.prepare(query)?;
   let row_id = statement.insert(
@@ -39,6 +51,9 @@ pub fn add_one(
       instrumental,
       track_id,
       source,
+      content_hash,
+      language,
+      attribution,
       now,
       now,
     )
@@ -46,16 +61,20 @@ pub fn add_one(
   Ok(row_id)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn add_one_tx(
   plain_lyrics: &Option<String>,
   synced_lyrics: &Option<String>,
   track_id: i64,
   instrumental: bool,
   source: &Option<String>,
+  language: Option<&str>,
+  attribution: Option<&str>,
   conn: &mut Transaction,
 ) -> Result<i64> {
   let plain_lyrics = plain_lyrics.as_ref().filter(|s| !s.is_empty());
   let synced_lyrics = synced_lyrics.as_ref().filter(|s| !s.is_empty());
+  let content_hash = lyrics_content_hash(plain_lyrics.map(|s| s.as_str()), synced_lyrics.map(|s| s.as_str()), instrumental);
 
   let now = Utc::now();
   let query = indoc! {"
@@ -67,10 +86,13 @@ pub fn add_one_tx(
       instrumental,
       track_id,
       source,
+      content_hash,
+      language,
+      attribution,
       created_at,
       updated_at
     )
-    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
   "};
   let mut statement = conn.prepare(query)?;
   let row_id = statement.insert(
@@ -82,6 +104,9 @@ pub fn add_one_tx(
       instrumental,
       track_id,
       source,
+      content_hash,
+      language,
+      attribution,
       now,
       now,
     )
@@ -89,13 +114,405 @@ pub fn add_one_tx(
   Ok(row_id)
 }
 
-pub fn get_last_10_mins_lyrics_count(conn: &mut Connection) -> Result<i64> {
+/// Finds lyrics of the track with the same content, see `lyrics_content_hash`
+pub fn get_id_by_content_hash_tx(track_id: i64, content_hash: &str, conn: &mut Transaction) -> Result<Option<i64>> {
   let query = indoc! {"
-    SELECT COUNT(*) FROM lyrics
-    WHERE created_at > DATETIME('now', '-10 minute')
-    AND source = 'lrclib'
+    SELECT id FROM lyrics
+    WHERE track_id = ? AND content_hash = ?
+    ORDER BY id DESC
+    LIMIT 1
   "};
   let mut statement = conn.prepare(query)?;
-  let count = statement.query_row([], |row| row.get(0))?;
+  let lyrics_id = statement.query_row((track_id, content_hash), |row| row.get(0)).optional()?;
+  Ok(lyrics_id)
+}
+
+pub fn exists(lyrics_id: i64, conn: &mut Connection) -> Result<bool> {
+  let query = indoc! {"
+    SELECT EXISTS (SELECT 1 FROM lyrics WHERE id = ?)
+  "};
+  let mut statement = conn.prepare(query)?;
+  let exists = statement.query_row([lyrics_id], |row| row.get(0))?;
+  Ok(exists)
+}
+
+/// Finds lyrics with the same content on another track with the same normalized names (see
+/// `normalize`) and a duration within ±2 seconds, returning the ids of the lyrics and of their track.
+/// The album is ignored, as providers often disagree on it.
+pub fn get_near_duplicate_tx(
+  track_name_normalized: &str,
+  artist_name_normalized: &str,
+  duration: f64,
+  content_hash: &str,
+  conn: &mut Transaction,
+) -> Result<Option<(i64, i64)>> {
+  let mut where_clauses = vec![
+    "lyrics.content_hash = ?".to_string(),
+    "tracks.deleted_at IS NULL".to_string(),
+    "tracks.duration >= ?".to_string(),
+    "tracks.duration <= ?".to_string(),
+  ];
+  let mut params: Vec<rusqlite::types::Value> = vec![
+    content_hash.to_string().into(),
+    (duration - 2.0).into(),
+    (duration + 2.0).into(),
+  ];
+
+  // Match the normalized names exactly, or followed by a featured-artist suffix
+  for (column, normalized) in [("tracks.name_lower", track_name_normalized), ("tracks.artist_name_lower", artist_name_normalized)] {
+    let mut alternatives = vec![format!("{} = ?", column)];
+    params.push(normalized.to_string().into());
+    for pattern in featuring_patterns(normalized) {
+      alternatives.push(format!("{} LIKE ?", column));
+      params.push(pattern.into());
+    }
+    where_clauses.push(format!("({})", alternatives.join(" OR ")));
+  }
+
+  let query = format!(
+    "SELECT lyrics.id, lyrics.track_id FROM tracks JOIN lyrics ON lyrics.track_id = tracks.id WHERE {} ORDER BY lyrics.id LIMIT 1",
+    where_clauses.join(" AND "),
+  );
+  let mut statement = conn.prepare(&query)?;
+  let ids = statement.query_row(params_from_iter(params.iter()), |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+  Ok(ids)
+}
+
+/// Bumps the updated_at of lyrics, so that they show up in the changes feed again
+pub fn touch_tx(lyrics_id: i64, conn: &mut Transaction) -> Result<()> {
+  let now = Utc::now();
+  let query = indoc! {"
+    UPDATE lyrics SET updated_at = ? WHERE id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((now, lyrics_id))?;
+  Ok(())
+}
+
+/// Credits the lyrics to their publisher, see `get_leaderboard`
+pub fn set_contributor_tx(lyrics_id: i64, contributor: &str, contributor_handle: Option<&str>, conn: &mut Transaction) -> Result<()> {
+  let query = indoc! {"
+    UPDATE lyrics SET contributor = ?, contributor_handle = ? WHERE id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((contributor, contributor_handle, lyrics_id))?;
+  Ok(())
+}
+
+/// Returns the contributor who first published under the handle, whatever its case, who owns it
+pub fn get_handle_owner_tx(handle: &str, conn: &mut Transaction) -> Result<Option<String>> {
+  let query = indoc! {"
+    SELECT contributor
+    FROM lyrics
+    WHERE contributor_handle = ? COLLATE NOCASE
+    ORDER BY id
+    LIMIT 1
+  "};
+  let mut statement = conn.prepare(query)?;
+  let owner: Option<Option<String>> = statement.query_row([handle], |row| row.get(0)).optional()?;
+  Ok(owner.flatten())
+}
+
+/// Counts the lyrics published by each contributor since `since` (of all time without it), leaving
+/// out the deleted tracks. A contributor is credited under the handle of their latest publish, so
+/// publishing without a handle opts out again. A handle is only ever credited to the contributor
+/// who owns it, see `get_handle_owner_tx`. The contributors without a handle are only counted
+/// together.
+pub fn get_leaderboard(since: Option<DateTime<Utc>>, limit: usize, conn: &mut Connection) -> Result<Leaderboard> {
+  let contributions = indoc! {"
+    WITH contributions AS (
+      SELECT
+        (
+          SELECT latest.contributor_handle
+          FROM lyrics AS latest
+          WHERE latest.contributor = lyrics.contributor
+          ORDER BY latest.id DESC
+          LIMIT 1
+        ) AS handle,
+        COUNT(*) AS publishes
+      FROM
+        lyrics
+        JOIN tracks ON lyrics.track_id = tracks.id
+      WHERE
+        lyrics.contributor IS NOT NULL
+        AND (?1 IS NULL OR lyrics.created_at >= ?1)
+        AND tracks.deleted_at IS NULL
+      GROUP BY
+        lyrics.contributor
+    )
+  "};
+
+  let query = format!("{}{}", contributions, indoc! {"
+    SELECT handle, publishes
+    FROM contributions
+    WHERE handle IS NOT NULL
+    ORDER BY publishes DESC, handle
+    LIMIT ?2
+  "});
+  let mut statement = conn.prepare(&query)?;
+  let contributors = statement
+    .query_map((since, limit), |row| {
+      Ok(ContributorPublishes {
+        handle: row.get("handle")?,
+        publishes: row.get("publishes")?,
+      })
+    })?
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let query = format!("{}{}", contributions, indoc! {"
+    SELECT COUNT(*) AS contributors, COALESCE(SUM(publishes), 0) AS publishes
+    FROM contributions
+    WHERE handle IS NULL
+  "});
+  let mut statement = conn.prepare(&query)?;
+  let (anonymous_contributors, anonymous_publishes) = statement
+    .query_row([since], |row| Ok((row.get("contributors")?, row.get("publishes")?)))?;
+
+  Ok(Leaderboard {
+    contributors,
+    anonymous_contributors,
+    anonymous_publishes,
+  })
+}
+
+pub fn get_lyrics_stats(conn: &mut Connection) -> Result<LyricsStats> {
+  // Plain lyrics only counts lyrics without a synced version, so that the two figures add up. All the
+  // aggregates are computed in a single scan of the lyrics table.
+  let query = indoc! {"
+    SELECT
+      COUNT(*) AS total_lyrics,
+      COALESCE(SUM(has_synced_lyrics), 0) AS synced_lyrics,
+      COALESCE(SUM(has_plain_lyrics AND NOT has_synced_lyrics), 0) AS plain_lyrics,
+      COALESCE(SUM(created_at > DATETIME('now', '-1 day')), 0) AS last_24h_lyrics,
+      COALESCE(SUM(created_at > DATETIME('now', '-10 minute') AND source = 'lrclib'), 0) AS last_10_mins_published_lyrics,
+      (SELECT COUNT(*) FROM queued_tracks) AS queued_tracks
+    FROM lyrics
+  "};
+  let mut statement = conn.prepare(query)?;
+  let stats = statement.query_row([], |row| {
+    Ok(LyricsStats {
+      total_lyrics: row.get("total_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      plain_lyrics: row.get("plain_lyrics")?,
+      last_24h_lyrics: row.get("last_24h_lyrics")?,
+      last_10_mins_published_lyrics: row.get("last_10_mins_published_lyrics")?,
+      queued_tracks: row.get("queued_tracks")?,
+    })
+  })?;
+  Ok(stats)
+}
+
+pub fn get_translations_by_track_id(track_id: i64, conn: &mut Connection) -> Result<Vec<Translation>> {
+  let query = indoc! {"
+    SELECT
+      translations.language,
+      translations.plain_lyrics,
+      translations.synced_lyrics
+    FROM
+      translations
+      JOIN tracks ON translations.track_id = tracks.id
+    WHERE
+      translations.track_id = ?
+      AND tracks.deleted_at IS NULL
+    ORDER BY
+      translations.language
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query([track_id])?;
+
+  let mut translations = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    translations.push(Translation {
+      language: row.get("language")?,
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+    });
+  }
+
+  Ok(translations)
+}
+
+/// Builds an FTS5 query matching all words of the input. Every word is quoted, so characters and
+/// keywords with a special meaning in the FTS5 syntax are matched literally.
+fn escape_fts_query(q: &str) -> String {
+  prepare_input(q)
+    .split_whitespace()
+    .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+/// Searches tracks by keyword in their metadata and lyrics text, the most relevant first. With a
+/// language, only lyrics in that language (or one of its regional variants, like `pt-BR` for `pt`)
+/// are returned. Every track comes with its rank, and `after` continues the search after the rank
+/// and id of the last track of the previous page.
+pub fn search_fts(
+  q: &str,
+  filters: &SearchFilters,
+  limit: usize,
+  after: Option<(f64, i64)>,
+  conn: &mut Connection,
+) -> Result<Vec<(SimpleTrack, f64)>> {
+  let fts_query = escape_fts_query(q);
+  if fts_query.is_empty() {
+    return Ok(vec![]);
+  }
+
+  tracing::debug!("FTS query: {}", fts_query);
+
+  // Matches in the track name weigh the most, and matches in the lyrics text the least. The
+  // ranks are computed before the page is filtered, as bm25 can only be used in the FTS query.
+  // Publishing lyrics changes the ranks of all tracks a little, so the page starts after the
+  // current rank of the last track, and only falls back to its former rank once it stops matching.
+  let query = indoc! {"
+    WITH ranked AS MATERIALIZED (
+      SELECT search_fts.rowid, bm25(search_fts, 10.0, 5.0, 3.0, 1.0) AS score
+      FROM
+        search_fts
+        JOIN tracks ON search_fts.rowid = tracks.id
+        LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+      WHERE
+        search_fts MATCH ?1
+        AND tracks.deleted_at IS NULL
+        AND (?2 IS NULL OR lyrics.language = ?2 COLLATE NOCASE OR lyrics.language LIKE ?2 || '-%')
+        AND (?3 IS NULL OR tracks.duration >= ?3)
+        AND (?4 IS NULL OR tracks.duration <= ?4)
+    ),
+    anchor AS (
+      SELECT COALESCE((SELECT score FROM ranked WHERE rowid = ?6), ?5) AS score
+    )
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.artist_name,
+      tracks.album_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution,
+      search_results.score
+    FROM
+      (
+        SELECT rowid, score
+        FROM ranked
+        WHERE
+          ?6 IS NULL
+          OR score > (SELECT score FROM anchor)
+          OR (score = (SELECT score FROM anchor) AND rowid > ?6)
+        ORDER BY score, rowid
+        LIMIT ?7
+      ) AS search_results
+      JOIN tracks ON search_results.rowid = tracks.id
+      LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    ORDER BY search_results.score, search_results.rowid
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((
+    fts_query,
+    filters.language,
+    filters.min_duration,
+    filters.max_duration,
+    after.map(|(score, _)| score),
+    after.map(|(_, id)| id),
+    limit as i64,
+  ))?;
+
+  let mut tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    tracks.push((map_search_row(row)?, row.get("score")?));
+  }
+
+  Ok(tracks)
+}
+
+/// Passes every track matching the query to `on_track`, in id order, until `on_track` returns false
+/// or `max_rows` tracks were passed. Unlike `search_fts`, the matches are neither ranked nor
+/// collected, so that any number of them can be read while holding only one row at a time. Returns
+/// the number of tracks passed.
+pub fn search_fts_each(
+  q: &str,
+  filters: &SearchFilters,
+  max_rows: usize,
+  conn: &mut Connection,
+  mut on_track: impl FnMut(SimpleTrack) -> bool,
+) -> Result<usize> {
+  let fts_query = escape_fts_query(q);
+  if fts_query.is_empty() {
+    return Ok(0);
+  }
+
+  let query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.artist_name,
+      tracks.album_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      search_fts
+      JOIN tracks ON search_fts.rowid = tracks.id
+      LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      search_fts MATCH ?1
+      AND tracks.deleted_at IS NULL
+      AND (?2 IS NULL OR lyrics.language = ?2 COLLATE NOCASE OR lyrics.language LIKE ?2 || '-%')
+      AND (?3 IS NULL OR tracks.duration >= ?3)
+      AND (?4 IS NULL OR tracks.duration <= ?4)
+    ORDER BY search_fts.rowid
+    LIMIT ?5
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((
+    fts_query,
+    filters.language,
+    filters.min_duration,
+    filters.max_duration,
+    max_rows as i64,
+  ))?;
+
+  let mut count = 0;
+  while let Some(row) = rows.next()? {
+    count += 1;
+    if !on_track(map_search_row(row)?) {
+      break;
+    }
+  }
+
   Ok(count)
 }
+
+fn map_search_row(row: &Row) -> rusqlite::Result<SimpleTrack> {
+  let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
+  let last_lyrics = SimpleLyrics {
+    plain_lyrics: row.get("plain_lyrics")?,
+    synced_lyrics: row.get("synced_lyrics")?,
+    id: row.get("lyrics_id")?,
+    updated_at: row.get("lyrics_updated_at")?,
+    language: row.get("language")?,
+    attribution: row.get("attribution")?,
+    instrumental,
+  };
+
+  Ok(SimpleTrack {
+    id: row.get("id")?,
+    name: row.get("name")?,
+    artist_name: row.get("artist_name")?,
+    album_name: row.get("album_name")?,
+    duration: row.get("duration")?,
+    last_lyrics: Some(last_lyrics),
+  })
+}
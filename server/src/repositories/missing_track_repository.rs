@@ -27,13 +27,12 @@ pub fn get_track_id_by_metadata(
   let mut statement = conn.prepare(query)?;
   let row = statement.query_row(
     (track_name_lower, artist_name_lower, album_name_lower, duration - 2.0, duration + 2.0),
-    |row| {
-      Ok(row.get("id")?)
-    }
+    |row| row.get("id")
   ).optional()?;
   Ok(row)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn add_one(
   track_name: &str,
   artist_name: &str,
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use indoc::indoc;
+use chrono::prelude::*;
+use crate::entities::missing_track::MissingTrack;
+
+pub fn add_many(missing_tracks: &[MissingTrack], conn: &mut Connection) -> Result<()> {
+  let now = Utc::now();
+  let tx = conn.transaction()?;
+  {
+    let query = indoc! {"
+      INSERT INTO queued_tracks (
+        name,
+        artist_name,
+        album_name,
+        duration,
+        retry_count,
+        next_attempt_at,
+        created_at
+      )
+      VALUES (?, ?, ?, ?, ?, ?, ?)
+    "};
+    let mut statement = tx.prepare(query)?;
+    for missing_track in missing_tracks {
+      statement.execute(
+        (
+          &missing_track.name,
+          &missing_track.artist_name,
+          &missing_track.album_name,
+          missing_track.duration,
+          missing_track.retry_count,
+          missing_track.next_attempt_at,
+          now,
+        )
+      )?;
+    }
+  }
+  tx.commit()?;
+  Ok(())
+}
+
+/// Takes the oldest tracks that are due for an attempt out of the table
+pub fn take_batch(limit: usize, conn: &mut Connection) -> Result<Vec<MissingTrack>> {
+  let now = Utc::now();
+  let query = indoc! {"
+    DELETE FROM queued_tracks
+    WHERE id IN (
+      SELECT id FROM queued_tracks
+      WHERE next_attempt_at IS NULL OR next_attempt_at <= ?
+      ORDER BY id
+      LIMIT ?
+    )
+    RETURNING name, artist_name, album_name, duration, retry_count, next_attempt_at
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((now, limit))?;
+
+  let mut missing_tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    missing_tracks.push(MissingTrack {
+      name: row.get("name")?,
+      artist_name: row.get("artist_name")?,
+      album_name: row.get("album_name")?,
+      duration: row.get("duration")?,
+      retry_count: row.get("retry_count")?,
+      next_attempt_at: row.get("next_attempt_at")?,
+    });
+  }
+
+  Ok(missing_tracks)
+}
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, Transaction};
+use indoc::indoc;
+use chrono::prelude::*;
+use crate::entities::indexed_lyrics::IndexedLyrics;
+
+/// Returns a batch of lyrics in id order, with the state of their search index entry
+pub fn get_batch(after_id: i64, limit: usize, conn: &mut Connection) -> Result<Vec<IndexedLyrics>> {
+  let query = indoc! {"
+    SELECT
+      lyrics.id,
+      lyrics.track_id,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.instrumental,
+      lyrics.content_hash,
+      lyrics.language,
+      tracks.last_lyrics_id IS lyrics.id AS is_current,
+      search_fts.rowid IS NULL
+        OR search_fts.name_lower IS NOT tracks.name_lower
+        OR search_fts.artist_name_lower IS NOT tracks.artist_name_lower
+        OR search_fts.album_name_lower IS NOT tracks.album_name_lower
+        OR search_fts.lyrics IS NOT COALESCE(lyrics.plain_lyrics, lyrics.synced_lyrics, '') AS is_search_stale
+    FROM
+      lyrics
+      LEFT JOIN tracks ON lyrics.track_id = tracks.id
+      LEFT JOIN search_fts ON search_fts.rowid = tracks.id
+    WHERE
+      lyrics.id > ?
+    ORDER BY
+      lyrics.id
+    LIMIT ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((after_id, limit as i64))?;
+
+  let mut batch = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    batch.push(IndexedLyrics {
+      id: row.get("id")?,
+      track_id: row.get("track_id")?,
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      instrumental: row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default(),
+      content_hash: row.get("content_hash")?,
+      language: row.get("language")?,
+      is_current: row.get("is_current")?,
+      is_search_stale: row.get("is_search_stale")?,
+    });
+  }
+
+  Ok(batch)
+}
+
+/// Stores the recomputed values, without touching `updated_at` since the lyrics themselves are unchanged
+pub fn update_derived_tx(lyrics_id: i64, content_hash: &str, language: Option<&str>, conn: &mut Transaction) -> Result<()> {
+  let query = indoc! {"
+    UPDATE lyrics SET content_hash = ?, language = ? WHERE id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((content_hash, language, lyrics_id))?;
+  Ok(())
+}
+
+/// Rebuilds the `search_fts` row of the track from the track and its current lyrics
+pub fn rebuild_search_entry_tx(track_id: i64, conn: &mut Transaction) -> Result<()> {
+  let delete_query = indoc! {"
+    DELETE FROM search_fts WHERE rowid = ?
+  "};
+  let mut statement = conn.prepare(delete_query)?;
+  statement.execute([track_id])?;
+
+  let insert_query = indoc! {"
+    INSERT INTO search_fts (rowid, name_lower, artist_name_lower, album_name_lower, lyrics)
+    SELECT tracks.id, tracks.name_lower, tracks.artist_name_lower, tracks.album_name_lower, COALESCE(lyrics.plain_lyrics, lyrics.synced_lyrics, '')
+    FROM tracks LEFT JOIN lyrics ON lyrics.id = tracks.last_lyrics_id
+    WHERE tracks.id = ?
+  "};
+  let mut statement = conn.prepare(insert_query)?;
+  statement.execute([track_id])?;
+  Ok(())
+}
+
+pub fn get_progress(conn: &mut Connection) -> Result<Option<i64>> {
+  let query = indoc! {"
+    SELECT last_lyrics_id FROM reindex_progress WHERE id = 1
+  "};
+  let mut statement = conn.prepare(query)?;
+  let last_lyrics_id = statement.query_row([], |row| row.get(0)).optional()?;
+  Ok(last_lyrics_id)
+}
+
+pub fn set_progress_tx(last_lyrics_id: i64, conn: &mut Transaction) -> Result<()> {
+  let now = Utc::now();
+
+  let query = indoc! {"
+    INSERT INTO reindex_progress (id, last_lyrics_id, updated_at) VALUES (1, ?, ?)
+    ON CONFLICT (id) DO UPDATE SET last_lyrics_id = excluded.last_lyrics_id, updated_at = excluded.updated_at
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((last_lyrics_id, now))?;
+  Ok(())
+}
+
+pub fn clear_progress(conn: &mut Connection) -> Result<()> {
+  let query = indoc! {"
+    DELETE FROM reindex_progress
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute([])?;
+  Ok(())
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use indoc::indoc;
+
+/// Returns a secret of this install, generated by the migrations
+pub fn get_by_name(name: &str, conn: &mut Connection) -> Result<Option<Vec<u8>>> {
+  let query = indoc! {"
+    SELECT value
+    FROM secrets
+    WHERE name = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let value = statement.query_row([name], |row| row.get("value")).optional()?;
+  Ok(value)
+}
@@ -2,12 +2,16 @@ use anyhow::Result;
 use rusqlite::{Connection, OptionalExtension, Transaction};
 use indoc::indoc;
 use crate::{
-  entities::{lyrics::SimpleLyrics, track::SimpleTrack},
-  utils::prepare_input,
+  entities::{flag::FlagReason, lyrics::SimpleLyrics, lyrics_candidate::LyricsCandidate, track::{SimpleTrack, TrackFlags}},
+  repositories::vote_repository::LAST_LYRICS_SCORE,
+  utils::{normalize::featuring_patterns, prepare_input},
 };
 use chrono::prelude::*;
 use rusqlite::params_from_iter;
 
+/// Tolerance (in seconds) used when matching tracks by duration
+pub const DEFAULT_DURATION_TOLERANCE: f64 = 2.0;
+
 pub fn get_track_by_id(track_id: i64, conn: &mut Connection) -> Result<Option<SimpleTrack>> {
   let query = indoc! {"
     SELECT
@@ -19,25 +23,198 @@ pub fn get_track_by_id(track_id: i64, conn: &mut Connection) -> Result<Option<Si
       tracks.last_lyrics_id,
       lyrics.instrumental,
       lyrics.plain_lyrics,
-      lyrics.synced_lyrics
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
     FROM
       tracks
       LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
     WHERE
       tracks.id = ?
+      AND tracks.deleted_at IS NULL
   "};
   let mut statement = conn.prepare(query)?;
   let row = statement.query_row(
     [track_id],
     |row| {
-      let instrumental = match row.get("instrumental")? {
-        Some(value) => value,
-        None => false
+      let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
+      let last_lyrics = SimpleLyrics {
+        plain_lyrics: row.get("plain_lyrics")?,
+        synced_lyrics: row.get("synced_lyrics")?,
+        id: row.get("lyrics_id")?,
+        updated_at: row.get("lyrics_updated_at")?,
+        language: row.get("language")?,
+        attribution: row.get("attribution")?,
+        instrumental,
       };
 
+      Ok(SimpleTrack {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        artist_name: row.get("artist_name")?,
+        album_name: row.get("album_name")?,
+        duration: row.get("duration")?,
+        last_lyrics: Some(last_lyrics),
+      })
+    }
+  ).optional()?;
+  Ok(row)
+}
+
+/// Looks up several tracks at once, ids that don't exist are left out of the result
+pub fn get_tracks_by_ids(track_ids: &[i64], conn: &mut Connection) -> Result<Vec<SimpleTrack>> {
+  if track_ids.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let placeholders = vec!["?"; track_ids.len()].join(", ");
+  let query = format!(
+    indoc! {"
+      SELECT
+        tracks.id,
+        tracks.name,
+        tracks.album_name,
+        tracks.artist_name,
+        tracks.duration,
+        lyrics.instrumental,
+        lyrics.plain_lyrics,
+        lyrics.synced_lyrics,
+        lyrics.id AS lyrics_id,
+        lyrics.updated_at AS lyrics_updated_at,
+        lyrics.language,
+        lyrics.attribution
+      FROM
+        tracks
+        LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+      WHERE
+        tracks.id IN ({placeholders})
+        AND tracks.deleted_at IS NULL
+    "},
+    placeholders = placeholders,
+  );
+  let mut statement = conn.prepare(&query)?;
+  let mut rows = statement.query(params_from_iter(track_ids.iter()))?;
+
+  let mut tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
+    let last_lyrics = SimpleLyrics {
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      id: row.get("lyrics_id")?,
+      updated_at: row.get("lyrics_updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
+      instrumental,
+    };
+
+    tracks.push(SimpleTrack {
+      id: row.get("id")?,
+      name: row.get("name")?,
+      artist_name: row.get("artist_name")?,
+      album_name: row.get("album_name")?,
+      duration: row.get("duration")?,
+      last_lyrics: Some(last_lyrics),
+    });
+  }
+
+  Ok(tracks)
+}
+
+/// Returns the lowest and highest track ids, or None when there are no tracks
+pub fn get_track_id_range(conn: &mut Connection) -> Result<Option<(i64, i64)>> {
+  let query = indoc! {"
+    SELECT MIN(id), MAX(id) FROM tracks
+  "};
+  let mut statement = conn.prepare(query)?;
+  let range = statement.query_row([], |row| {
+    Ok(row.get::<_, Option<i64>>(0)?.zip(row.get::<_, Option<i64>>(1)?))
+  })?;
+  Ok(range)
+}
+
+/// Counts the tracks that are not deleted
+pub fn count_live_tracks(conn: &mut Connection) -> Result<usize> {
+  let query = indoc! {"
+    SELECT COUNT(*) FROM tracks WHERE deleted_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let count = statement.query_row([], |row| row.get(0))?;
+  Ok(count)
+}
+
+/// Calls `visit` with the lowercased track and artist names of every track that is not deleted,
+/// streaming the rows rather than loading them all
+pub fn for_each_track_name(conn: &mut Connection, mut visit: impl FnMut(&str, &str)) -> Result<()> {
+  let query = indoc! {"
+    SELECT name_lower, artist_name_lower
+    FROM tracks
+    WHERE deleted_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query([])?;
+  while let Some(row) = rows.next()? {
+    let track_name_lower: Option<String> = row.get(0)?;
+    let artist_name_lower: Option<String> = row.get(1)?;
+    if let (Some(track_name_lower), Some(artist_name_lower)) = (track_name_lower, artist_name_lower) {
+      visit(&track_name_lower, &artist_name_lower);
+    }
+  }
+  Ok(())
+}
+
+/// Returns the first track from the given id on that has (non instrumental) lyrics matching the filters
+pub fn get_next_track_with_lyrics(
+  from_id: i64,
+  min_duration: Option<f64>,
+  synced_only: bool,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
+  let query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.album_name,
+      tracks.artist_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      tracks
+      JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      tracks.id >= ?
+      AND tracks.deleted_at IS NULL
+      AND lyrics.instrumental = 0
+      AND (? IS NULL OR tracks.duration >= ?)
+      AND (? = 0 OR lyrics.has_synced_lyrics = 1)
+    ORDER BY
+      tracks.id
+    LIMIT 1
+  "};
+  let mut statement = conn.prepare(query)?;
+  let row = statement.query_row(
+    (from_id, min_duration, min_duration, synced_only),
+    |row| {
+      let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
       let last_lyrics = SimpleLyrics {
         plain_lyrics: row.get("plain_lyrics")?,
         synced_lyrics: row.get("synced_lyrics")?,
+        id: row.get("lyrics_id")?,
+        updated_at: row.get("lyrics_updated_at")?,
+        language: row.get("language")?,
+        attribution: row.get("attribution")?,
         instrumental,
       };
 
@@ -54,6 +231,139 @@ pub fn get_track_by_id(track_id: i64, conn: &mut Connection) -> Result<Option<Si
   Ok(row)
 }
 
+/// Returns a batch of tracks with lyrics in id order, for exporting the whole database page by page.
+/// With `since`, only tracks whose lyrics were updated after that time are returned.
+pub fn get_tracks_for_export(
+  after_id: i64,
+  since: Option<DateTime<Utc>>,
+  limit: usize,
+  conn: &mut Connection,
+) -> Result<Vec<SimpleTrack>> {
+  let query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.album_name,
+      tracks.artist_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      tracks
+      JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      tracks.id > ?
+      AND tracks.deleted_at IS NULL
+      AND (? IS NULL OR lyrics.updated_at > ?)
+    ORDER BY
+      tracks.id
+    LIMIT ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((after_id, since, since, limit as i64))?;
+
+  let mut tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
+    let last_lyrics = SimpleLyrics {
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      id: row.get("lyrics_id")?,
+      updated_at: row.get("lyrics_updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
+      instrumental,
+    };
+
+    tracks.push(SimpleTrack {
+      id: row.get("id")?,
+      name: row.get("name")?,
+      artist_name: row.get("artist_name")?,
+      album_name: row.get("album_name")?,
+      duration: row.get("duration")?,
+      last_lyrics: Some(last_lyrics),
+    });
+  }
+
+  Ok(tracks)
+}
+
+/// Returns the tracks whose current lyrics were updated after the `(since, after_id)` watermark,
+/// ordered by lyrics update time. Tracks whose lyrics were updated at the same instant are ordered
+/// by their id, which unlike the lyrics id is unique as tracks can share lyrics, so a page boundary
+/// falling in the middle of them never skips a row.
+pub fn get_changed_tracks(
+  since: Option<DateTime<Utc>>,
+  after_id: i64,
+  limit: usize,
+  conn: &mut Connection,
+) -> Result<Vec<SimpleTrack>> {
+  let query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.album_name,
+      tracks.artist_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      lyrics
+      JOIN tracks ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      tracks.deleted_at IS NULL
+      AND (
+        ?1 IS NULL
+        OR lyrics.updated_at > ?1
+        OR (lyrics.updated_at = ?1 AND tracks.id > ?2)
+      )
+    ORDER BY
+      lyrics.updated_at, tracks.id
+    LIMIT ?3
+  "};
+  let mut statement = conn.prepare(query)?;
+  let mut rows = statement.query((since, after_id, limit as i64))?;
+
+  let mut tracks = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
+
+    let last_lyrics = SimpleLyrics {
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      id: row.get("lyrics_id")?,
+      updated_at: row.get("lyrics_updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
+      instrumental,
+    };
+
+    tracks.push(SimpleTrack {
+      id: row.get("id")?,
+      name: row.get("name")?,
+      artist_name: row.get("artist_name")?,
+      album_name: row.get("album_name")?,
+      duration: row.get("duration")?,
+      last_lyrics: Some(last_lyrics),
+    });
+  }
+
+  Ok(tracks)
+}
+
 pub fn get_track_id_by_metadata(track_name: &str, artist_name: &str, album_name: &str, duration: f64, conn: &mut Connection) -> Result<Option<i64>> {
   let track_name_lower = prepare_input(track_name);
   let artist_name_lower = prepare_input(artist_name);
@@ -76,9 +386,7 @@ pub fn get_track_id_by_metadata(track_name: &str, artist_name: &str, album_name:
   let mut statement = conn.prepare(query)?;
   let row = statement.query_row(
     (track_name_lower, artist_name_lower, album_name_lower, duration - 2.0, duration + 2.0),
-    |row| {
-      Ok(row.get("id")?)
-    }
+    |row| row.get("id")
   ).optional()?;
   Ok(row)
 }
@@ -105,18 +413,19 @@ pub fn get_track_id_by_metadata_tx(track_name: &str, artist_name: &str, album_na
   let mut statement = conn.prepare(query)?;
   let row = statement.query_row(
     (track_name_lower, artist_name_lower, album_name_lower, duration - 2.0, duration + 2.0),
-    |row| {
-      Ok(row.get("id")?)
-    }
+    |row| row.get("id")
   ).optional()?;
   Ok(row)
 }
 
+/// Without an explicit `duration_tolerance`, the ±2 seconds default is used and the oldest matching
+/// track wins. With one, the track closest to the requested duration wins.
 pub fn get_track_by_metadata(
   track_name_lower: &str,
   artist_name_lower: &str,
   album_name_lower: Option<&str>,
   duration: Option<f64>,
+  duration_tolerance: Option<f64>,
   conn: &mut Connection,
 ) -> Result<Option<SimpleTrack>> {
   // Start building the SQL query
@@ -130,7 +439,11 @@ pub fn get_track_by_metadata(
       tracks.last_lyrics_id,
       lyrics.instrumental,
       lyrics.plain_lyrics,
-      lyrics.synced_lyrics
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
     FROM
       tracks
       LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
@@ -140,6 +453,7 @@ pub fn get_track_by_metadata(
   let mut where_clauses = vec![
     "tracks.name_lower = ?".to_string(),
     "tracks.artist_name_lower = ?".to_string(),
+    "tracks.deleted_at IS NULL".to_string(),
   ];
   let mut params: Vec<rusqlite::types::Value> = vec![
     track_name_lower.to_string().into(),
@@ -148,8 +462,9 @@ pub fn get_track_by_metadata(
 
   // Conditionally add duration constraints
   if let Some(dur) = duration {
-    let duration_min = dur - 2.0;
-    let duration_max = dur + 2.0;
+    let tolerance = duration_tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE);
+    let duration_min = dur - tolerance;
+    let duration_max = dur + tolerance;
     where_clauses.push("tracks.duration >= ?".to_string());
     where_clauses.push("tracks.duration <= ?".to_string());
     params.push(duration_min.into());
@@ -162,11 +477,21 @@ pub fn get_track_by_metadata(
     params.push(album_name_lower.to_string().into());
   }
 
+  // Among several matching tracks, the lyrics voted best win
+  let order_clause = match (duration, duration_tolerance) {
+    (Some(dur), Some(_)) => {
+      params.push(dur.into());
+      format!("{} DESC, ABS(tracks.duration - ?), tracks.id", LAST_LYRICS_SCORE)
+    },
+    _ => format!("{} DESC, tracks.id", LAST_LYRICS_SCORE),
+  };
+
   // Combine all parts of the query
   let query = format!(
-    "{select} WHERE {where_clause} ORDER BY tracks.id",
+    "{select} WHERE {where_clause} ORDER BY {order_clause}",
     select = select_query,
-    where_clause = where_clauses.join(" AND ")
+    where_clause = where_clauses.join(" AND "),
+    order_clause = order_clause,
   );
 
   // Prepare and execute the statement
@@ -179,6 +504,351 @@ pub fn get_track_by_metadata(
       let last_lyrics = SimpleLyrics {
         plain_lyrics: row.get("plain_lyrics")?,
         synced_lyrics: row.get("synced_lyrics")?,
+        id: row.get("lyrics_id")?,
+        updated_at: row.get("lyrics_updated_at")?,
+        language: row.get("language")?,
+        attribution: row.get("attribution")?,
+        instrumental,
+      };
+
+      Ok(SimpleTrack {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        artist_name: row.get("artist_name")?,
+        album_name: row.get("album_name")?,
+        duration: row.get("duration")?,
+        last_lyrics: Some(last_lyrics),
+      })
+    }
+  ).optional()?;
+
+  Ok(row)
+}
+
+/// Finds the track like `get_track_by_metadata`, reading only the flags of its lyrics
+pub fn get_track_flags_by_metadata(
+  track_name_lower: &str,
+  artist_name_lower: &str,
+  album_name_lower: Option<&str>,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<Option<TrackFlags>> {
+  let select_query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.artist_name,
+      tracks.album_name,
+      lyrics.has_synced_lyrics,
+      lyrics.instrumental
+    FROM
+      tracks
+      LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+  "};
+
+  let mut where_clauses = vec![
+    "tracks.name_lower = ?".to_string(),
+    "tracks.artist_name_lower = ?".to_string(),
+    "tracks.deleted_at IS NULL".to_string(),
+  ];
+  let mut params: Vec<rusqlite::types::Value> = vec![
+    track_name_lower.to_string().into(),
+    artist_name_lower.to_string().into(),
+  ];
+
+  if let Some(dur) = duration {
+    let tolerance = duration_tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE);
+    where_clauses.push("tracks.duration >= ?".to_string());
+    where_clauses.push("tracks.duration <= ?".to_string());
+    params.push((dur - tolerance).into());
+    params.push((dur + tolerance).into());
+  }
+
+  if let Some(album_name_lower) = album_name_lower {
+    where_clauses.push("tracks.album_name_lower = ?".to_string());
+    params.push(album_name_lower.to_string().into());
+  }
+
+  // The same track as `get_track_by_metadata` would return
+  let order_clause = match (duration, duration_tolerance) {
+    (Some(dur), Some(_)) => {
+      params.push(dur.into());
+      format!("{} DESC, ABS(tracks.duration - ?), tracks.id", LAST_LYRICS_SCORE)
+    },
+    _ => format!("{} DESC, tracks.id", LAST_LYRICS_SCORE),
+  };
+
+  let query = format!(
+    "{select} WHERE {where_clause} ORDER BY {order_clause} LIMIT 1",
+    select = select_query,
+    where_clause = where_clauses.join(" AND "),
+    order_clause = order_clause,
+  );
+
+  let mut statement = conn.prepare(&query)?;
+  let row = statement.query_row(
+    params_from_iter(params.iter().map(|v| v as &dyn rusqlite::ToSql)),
+    |row| {
+      let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or(false);
+
+      Ok(TrackFlags {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        artist_name: row.get("artist_name")?,
+        album_name: row.get("album_name")?,
+        synced: !instrumental && row.get::<_, Option<bool>>("has_synced_lyrics")?.unwrap_or(false),
+        instrumental,
+      })
+    }
+  ).optional()?;
+
+  Ok(row)
+}
+
+/// Counts the tracks matching the names like `get_track_by_metadata`, whatever their duration, and
+/// how many of them are also within the duration tolerance. Used to explain lookups.
+pub fn count_tracks_by_metadata(
+  track_name_lower: &str,
+  artist_name_lower: &str,
+  album_name_lower: Option<&str>,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<(usize, usize)> {
+  let mut params: Vec<rusqlite::types::Value> = Vec::new();
+
+  // Without a duration, every track with the names is within the tolerance
+  let duration_count = match duration {
+    Some(dur) => {
+      let tolerance = duration_tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE);
+      params.push((dur - tolerance).into());
+      params.push((dur + tolerance).into());
+      "COALESCE(SUM(duration >= ? AND duration <= ?), 0)"
+    },
+    None => "COUNT(*)",
+  };
+
+  let mut where_clauses = vec![
+    "name_lower = ?",
+    "artist_name_lower = ?",
+    "deleted_at IS NULL",
+  ];
+  params.push(track_name_lower.to_string().into());
+  params.push(artist_name_lower.to_string().into());
+
+  if let Some(album_name_lower) = album_name_lower {
+    where_clauses.push("album_name_lower = ?");
+    params.push(album_name_lower.to_string().into());
+  }
+
+  let query = format!(
+    "SELECT COUNT(*), {duration_count} FROM tracks WHERE {where_clause}",
+    duration_count = duration_count,
+    where_clause = where_clauses.join(" AND "),
+  );
+  let mut statement = conn.prepare(&query)?;
+  let (name_count, duration_count): (i64, i64) = statement.query_row(
+    params_from_iter(params.iter().map(|v| v as &dyn rusqlite::ToSql)),
+    |row| Ok((row.get(0)?, row.get(1)?)),
+  )?;
+
+  Ok((name_count as usize, duration_count as usize))
+}
+
+/// Returns all the lyrics of the tracks matching the metadata, like `get_track_by_metadata`, the
+/// best voted first. Lyrics with the same score are ordered by how close their track is to the
+/// requested duration, then the current lyrics of a track come before its older ones, and synced
+/// lyrics before plain ones.
+pub fn get_lyrics_candidates(
+  track_name_lower: &str,
+  artist_name_lower: &str,
+  album_name_lower: Option<&str>,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  limit: usize,
+  conn: &mut Connection,
+) -> Result<Vec<LyricsCandidate>> {
+  let select_query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.artist_name,
+      tracks.album_name,
+      tracks.duration,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution,
+      lyrics.source,
+      lyrics.id = tracks.last_lyrics_id AS is_current,
+      (SELECT COALESCE(SUM(votes.value), 0) FROM votes WHERE votes.lyrics_id = lyrics.id) AS score
+    FROM
+      tracks
+      -- The current lyrics may belong to a near-duplicate track, when they were linked by the queue
+      JOIN lyrics ON lyrics.track_id = tracks.id OR lyrics.id = tracks.last_lyrics_id
+  "};
+
+  let mut where_clauses = vec![
+    "tracks.name_lower = ?",
+    "tracks.artist_name_lower = ?",
+    "tracks.deleted_at IS NULL",
+  ];
+  let mut params: Vec<rusqlite::types::Value> = vec![
+    track_name_lower.to_string().into(),
+    artist_name_lower.to_string().into(),
+  ];
+
+  if let Some(dur) = duration {
+    let tolerance = duration_tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE);
+    where_clauses.push("tracks.duration >= ?");
+    where_clauses.push("tracks.duration <= ?");
+    params.push((dur - tolerance).into());
+    params.push((dur + tolerance).into());
+  }
+
+  if let Some(album_name_lower) = album_name_lower {
+    where_clauses.push("tracks.album_name_lower = ?");
+    params.push(album_name_lower.to_string().into());
+  }
+
+  let duration_order = match duration {
+    Some(dur) => {
+      params.push(dur.into());
+      "ABS(tracks.duration - ?), "
+    },
+    None => "",
+  };
+  params.push((limit as i64).into());
+
+  let query = format!(
+    "{select} WHERE {where_clause} ORDER BY score DESC, {duration_order}is_current DESC, lyrics.has_synced_lyrics DESC, lyrics.id DESC LIMIT ?",
+    select = select_query,
+    where_clause = where_clauses.join(" AND "),
+    duration_order = duration_order,
+  );
+
+  let mut statement = conn.prepare(&query)?;
+  let mut rows = statement.query(params_from_iter(params.iter().map(|v| v as &dyn rusqlite::ToSql)))?;
+  let mut candidates = Vec::new();
+
+  while let Some(row) = rows.next()? {
+    let last_lyrics = SimpleLyrics {
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      id: row.get("lyrics_id")?,
+      updated_at: row.get("lyrics_updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
+      instrumental: row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default(),
+    };
+
+    candidates.push(LyricsCandidate {
+      track: SimpleTrack {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        artist_name: row.get("artist_name")?,
+        album_name: row.get("album_name")?,
+        duration: row.get("duration")?,
+        last_lyrics: Some(last_lyrics),
+      },
+      source: row.get("source")?,
+      score: row.get("score")?,
+      is_current: row.get::<_, Option<bool>>("is_current")?.unwrap_or_default(),
+    });
+  }
+
+  Ok(candidates)
+}
+
+pub fn get_track_by_normalized_metadata(
+  track_name_normalized: &str,
+  artist_name_normalized: &str,
+  album_name_lower: Option<&str>,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
+  let select_query = indoc! {"
+    SELECT
+      tracks.id,
+      tracks.name,
+      tracks.artist_name,
+      tracks.album_name,
+      tracks.duration,
+      tracks.last_lyrics_id,
+      lyrics.instrumental,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      tracks
+      LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+  "};
+
+  let mut where_clauses = vec!["tracks.deleted_at IS NULL".to_string()];
+  let mut params: Vec<rusqlite::types::Value> = vec![];
+
+  // Match the normalized names exactly, or followed by a featured-artist suffix
+  for (column, normalized) in [("tracks.name_lower", track_name_normalized), ("tracks.artist_name_lower", artist_name_normalized)] {
+    let patterns = featuring_patterns(normalized);
+    let mut alternatives = vec![format!("{} = ?", column)];
+    params.push(normalized.to_string().into());
+    for pattern in patterns {
+      alternatives.push(format!("{} LIKE ?", column));
+      params.push(pattern.into());
+    }
+    where_clauses.push(format!("({})", alternatives.join(" OR ")));
+  }
+
+  if let Some(dur) = duration {
+    let tolerance = duration_tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE);
+    where_clauses.push("tracks.duration >= ?".to_string());
+    where_clauses.push("tracks.duration <= ?".to_string());
+    params.push((dur - tolerance).into());
+    params.push((dur + tolerance).into());
+  }
+
+  if let Some(album_name_lower) = album_name_lower {
+    where_clauses.push("tracks.album_name_lower = ?".to_string());
+    params.push(album_name_lower.to_string().into());
+  }
+
+  // Prefer the best voted candidate, then the one closest to the requested duration
+  let order_clause = match duration {
+    Some(dur) => {
+      params.push(dur.into());
+      format!("{} DESC, ABS(tracks.duration - ?), tracks.id", LAST_LYRICS_SCORE)
+    },
+    None => format!("{} DESC, tracks.id", LAST_LYRICS_SCORE),
+  };
+
+  let query = format!(
+    "{select} WHERE {where_clause} ORDER BY {order_clause} LIMIT 1",
+    select = select_query,
+    where_clause = where_clauses.join(" AND "),
+    order_clause = order_clause,
+  );
+
+  let mut statement = conn.prepare(&query)?;
+  let row = statement.query_row(
+    params_from_iter(params.iter()),
+    |row| {
+      let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or(false);
+
+      let last_lyrics = SimpleLyrics {
+        plain_lyrics: row.get("plain_lyrics")?,
+        synced_lyrics: row.get("synced_lyrics")?,
+        id: row.get("lyrics_id")?,
+        updated_at: row.get("lyrics_updated_at")?,
+        language: row.get("language")?,
+        attribution: row.get("attribution")?,
         instrumental,
       };
 
@@ -196,11 +866,27 @@ pub fn get_track_by_metadata(
   Ok(row)
 }
 
+pub struct SearchPage {
+  pub after_id: Option<i64>,
+  pub limit: usize,
+}
+
+/// Restricts the results of a search, on top of the text query
+pub struct SearchFilters<'a> {
+  /// BCP-47 tag of the lyrics language, which also matches its subtags
+  pub language: Option<&'a str>,
+  /// Bounds of the track duration in seconds, both inclusive
+  pub min_duration: Option<f64>,
+  pub max_duration: Option<f64>,
+}
+
 pub fn get_tracks_by_keyword(
   q: Option<&str>,
   track_name: Option<&str>,
   artist_name: Option<&str>,
   album_name: Option<&str>,
+  filters: &SearchFilters,
+  page: Option<&SearchPage>,
   conn: &mut Connection,
 ) -> Result<Vec<SimpleTrack>> {
   // To search track by keyword, at least q or track_name must be present
@@ -217,12 +903,31 @@ pub fn get_tracks_by_keyword(
     true
   };
 
-  // Build the subquery with or without ORDER BY rank
-  let subquery = if is_ordered {
-    indoc! {"SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ? ORDER BY rank LIMIT 20"}.to_string()
+  // Build the subquery with or without ORDER BY rank. Paginated searches are ordered by rowid
+  // instead, so that a cursor pointing at the last returned id stays stable between page fetches.
+  // Deleted tracks and filtered out ones are left out before the limit, so they don't take up
+  // result slots
+  let subquery_select = indoc! {"
+    SELECT tracks_fts.rowid
+    FROM
+      tracks_fts
+      JOIN tracks ON tracks_fts.rowid = tracks.id
+      LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      tracks_fts MATCH ?1
+      AND tracks.deleted_at IS NULL
+      AND (?2 IS NULL OR lyrics.language = ?2 COLLATE NOCASE OR lyrics.language LIKE ?2 || '-%')
+      AND (?3 IS NULL OR tracks.duration >= ?3)
+      AND (?4 IS NULL OR tracks.duration <= ?4)
+  "};
+  let subquery = if page.is_some() {
+    format!("{} AND tracks_fts.rowid > ?5 ORDER BY tracks_fts.rowid LIMIT ?6", subquery_select)
+  } else if is_ordered {
+    format!("{} ORDER BY tracks_fts.rank LIMIT 20", subquery_select)
   } else {
-    indoc! {"SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ? LIMIT 20"}.to_string()
+    format!("{} LIMIT 20", subquery_select)
   };
+  let order_clause = if page.is_some() { "ORDER BY search_results.rowid" } else { "" };
 
   // Build the complete query using the subquery
   let query = format!(
@@ -234,18 +939,24 @@ pub fn get_tracks_by_keyword(
       tracks.duration,
       lyrics.instrumental,
       lyrics.plain_lyrics,
-      lyrics.synced_lyrics
+      lyrics.synced_lyrics,
+      lyrics.id AS lyrics_id,
+      lyrics.updated_at AS lyrics_updated_at,
+      lyrics.language,
+      lyrics.attribution
     FROM
       ({subquery}) AS search_results
       LEFT JOIN tracks ON search_results.rowid = tracks.id
       LEFT JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    {order_clause}
     ",
-    subquery = subquery
+    subquery = subquery,
+    order_clause = order_clause,
   );
 
   let mut statement = conn.prepare(&query)?;
   let fts_query = match q {
-    Some(q) => prepare_input(&q),
+    Some(q) => prepare_input(q),
     None => {
       match track_name {
         Some(track_name) => {
@@ -265,19 +976,31 @@ pub fn get_tracks_by_keyword(
 
   tracing::debug!("FTS query: {}", fts_query);
 
-  let mut rows = statement.query([fts_query])?;
+  let mut params: Vec<rusqlite::types::Value> = vec![
+    fts_query.into(),
+    filters.language.map(str::to_owned).into(),
+    filters.min_duration.into(),
+    filters.max_duration.into(),
+  ];
+  if let Some(page) = page {
+    params.push(page.after_id.unwrap_or(0).into());
+    params.push((page.limit as i64).into());
+  }
+
+  let mut rows = statement.query(params_from_iter(params.iter()))?;
 
   let mut tracks = Vec::new();
 
   while let Some(row) = rows.next()? {
-    let instrumental = match row.get("instrumental")? {
-      Some(value) => value,
-      None => false
-    };
+    let instrumental = row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default();
 
     let last_lyrics = SimpleLyrics {
       plain_lyrics: row.get("plain_lyrics")?,
       synced_lyrics: row.get("synced_lyrics")?,
+      id: row.get("lyrics_id")?,
+      updated_at: row.get("lyrics_updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
       instrumental,
     };
 
@@ -382,14 +1105,71 @@ pub fn add_one_tx(
   Ok(row_id)
 }
 
-pub fn flag_track_last_lyrics(track_id: i64, content: &str, conn: &mut Connection) -> Result<()> {
+pub fn get_last_lyrics_tx(track_id: i64, conn: &mut Transaction) -> Result<Option<SimpleLyrics>> {
+  let query = indoc! {"
+    SELECT
+      lyrics.id,
+      lyrics.plain_lyrics,
+      lyrics.synced_lyrics,
+      lyrics.instrumental,
+      lyrics.updated_at,
+      lyrics.language,
+      lyrics.attribution
+    FROM
+      tracks
+      JOIN lyrics ON tracks.last_lyrics_id = lyrics.id
+    WHERE
+      tracks.id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let row = statement.query_row([track_id], |row| {
+    Ok(SimpleLyrics {
+      id: row.get("id")?,
+      plain_lyrics: row.get("plain_lyrics")?,
+      synced_lyrics: row.get("synced_lyrics")?,
+      instrumental: row.get::<_, Option<bool>>("instrumental")?.unwrap_or_default(),
+      updated_at: row.get("updated_at")?,
+      language: row.get("language")?,
+      attribution: row.get("attribution")?,
+    })
+  }).optional()?;
+  Ok(row)
+}
+
+/// Points the track to other lyrics, returning whether the track changed
+pub fn set_last_lyrics_id_tx(track_id: i64, lyrics_id: i64, conn: &mut Transaction) -> Result<bool> {
+  let query = indoc! {"
+    UPDATE tracks SET last_lyrics_id = ?
+    WHERE id = ? AND last_lyrics_id IS NOT ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let updated_rows = statement.execute((lyrics_id, track_id, lyrics_id))?;
+  Ok(updated_rows > 0)
+}
+
+/// Flags the current lyrics of the track, returning the id of the flagged lyrics
+pub fn flag_track_last_lyrics(track_id: i64, reason: FlagReason, content: &str, conn: &mut Connection) -> Result<Option<i64>> {
   let now = Utc::now();
 
   let query = indoc! {"
-    INSERT INTO flags (lyrics_id, content, created_at)
-    SELECT last_lyrics_id, ?, ? FROM tracks WHERE id = ?
+    INSERT INTO flags (lyrics_id, reason, content, created_at)
+    SELECT last_lyrics_id, ?, ?, ? FROM tracks WHERE id = ?
+    RETURNING lyrics_id
   "};
   let mut statement = conn.prepare(query)?;
-  statement.execute((content, now, track_id))?;
-  Ok(())
+  let lyrics_id = statement.query_row((reason.as_str(), content, now, track_id), |row| row.get(0)).optional()?;
+  Ok(lyrics_id.flatten())
+}
+
+/// Hides the track and its lyrics from every read query, while keeping the rows for audit.
+/// Returns false when there is no such track, or it was already deleted.
+pub fn soft_delete_lyrics(track_id: i64, conn: &mut Connection) -> Result<bool> {
+  let now = Utc::now();
+
+  let query = indoc! {"
+    UPDATE tracks SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL
+  "};
+  let mut statement = conn.prepare(query)?;
+  let updated_rows = statement.execute((now, track_id))?;
+  Ok(updated_rows > 0)
 }
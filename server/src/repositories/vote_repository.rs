@@ -0,0 +1,90 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use indoc::indoc;
+use chrono::prelude::*;
+use crate::entities::vote::{Vote, VoteCounts};
+
+/// The net score of the last lyrics of `tracks`, for ordering the tracks of a query. Relies on the
+/// index on `votes.lyrics_id`.
+pub const LAST_LYRICS_SCORE: &str = "(SELECT COALESCE(SUM(votes.value), 0) FROM votes WHERE votes.lyrics_id = tracks.last_lyrics_id)";
+
+/// Records a vote, replacing the vote the same voter already cast on the same lyrics
+pub fn add_or_replace(lyrics_id: i64, voter: &str, vote: Vote, conn: &mut Connection) -> Result<()> {
+  let now = Utc::now();
+  let query = indoc! {"
+    INSERT INTO votes (lyrics_id, voter, value, created_at, updated_at)
+    VALUES (?1, ?2, ?3, ?4, ?4)
+    ON CONFLICT (lyrics_id, voter) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+  "};
+  let mut statement = conn.prepare(query)?;
+  statement.execute((lyrics_id, voter, vote.value(), now))?;
+  Ok(())
+}
+
+pub fn get_counts(lyrics_id: i64, conn: &mut Connection) -> Result<VoteCounts> {
+  let query = indoc! {"
+    SELECT
+      COALESCE(SUM(value = 1), 0) AS upvotes,
+      COALESCE(SUM(value = -1), 0) AS downvotes
+    FROM votes
+    WHERE lyrics_id = ?
+  "};
+  let mut statement = conn.prepare(query)?;
+  let counts = statement.query_row([lyrics_id], |row| {
+    Ok(VoteCounts {
+      upvotes: row.get("upvotes")?,
+      downvotes: row.get("downvotes")?,
+    })
+  })?;
+  Ok(counts)
+}
+
+/// Deletes up to `limit` of the votes on the lyrics of deleted tracks, returning how many were deleted
+pub fn delete_on_deleted_tracks(limit: usize, conn: &mut Connection) -> Result<usize> {
+  let query = indoc! {"
+    DELETE FROM votes
+    WHERE id IN (
+      SELECT votes.id
+      FROM votes
+      JOIN lyrics ON lyrics.id = votes.lyrics_id
+      JOIN tracks ON tracks.id = lyrics.track_id
+      WHERE tracks.deleted_at IS NOT NULL
+      LIMIT ?
+    )
+  "};
+  let mut statement = conn.prepare(query)?;
+  let deleted = statement.execute([limit])?;
+  Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, Utc};
+  use crate::{entities::vote::Vote, repositories::track_repository, test_utils::TestApp};
+  use super::{add_or_replace, get_counts};
+
+  #[tokio::test]
+  async fn a_voter_keeps_a_single_vote_on_the_same_lyrics() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let mut conn = app.state.pool.get().unwrap();
+    let lyrics_id = track_repository::get_track_by_id(track_id, &mut conn).unwrap()
+      .and_then(|track| track.last_lyrics.and_then(|lyrics| lyrics.id))
+      .unwrap();
+
+    // A vote cast days ago is replaced as well, rather than counted again
+    conn.execute(
+      "INSERT INTO votes (lyrics_id, voter, value, created_at, updated_at) VALUES (?1, 'voter', 1, ?2, ?2)",
+      (lyrics_id, Utc::now() - Duration::days(3)),
+    ).unwrap();
+    add_or_replace(lyrics_id, "voter", Vote::Up, &mut conn).unwrap();
+    add_or_replace(lyrics_id, "voter", Vote::Up, &mut conn).unwrap();
+    let counts = get_counts(lyrics_id, &mut conn).unwrap();
+    assert_eq!((counts.upvotes, counts.downvotes), (1, 0));
+
+    add_or_replace(lyrics_id, "voter", Vote::Down, &mut conn).unwrap();
+    add_or_replace(lyrics_id, "other voter", Vote::Up, &mut conn).unwrap();
+    let counts = get_counts(lyrics_id, &mut conn).unwrap();
+    assert_eq!((counts.upvotes, counts.downvotes), (1, 1));
+  }
+}
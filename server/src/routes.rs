@@ -1,6 +1,28 @@
 pub mod get_lyrics_by_metadata;
+pub mod get_lyrics_batch;
 pub mod get_lyrics_by_track_id;
+pub mod get_lyrics_by_track_ids;
+pub mod get_random_lyrics;
 pub mod search_lyrics;
+pub mod search_stream;
 pub mod request_challenge;
 pub mod publish_lyrics;
 pub mod flag_lyrics;
+pub mod get_metrics;
+pub mod get_translations;
+pub mod get_health;
+pub mod get_ready;
+pub mod get_stats;
+pub mod export_lyrics;
+pub mod changes;
+pub mod live;
+pub mod invalidate_cache;
+pub mod delete_lyrics;
+pub mod get_providers_status;
+pub mod dead_letter;
+pub mod artist_aliases;
+pub mod vote_lyrics;
+pub mod get_capabilities;
+pub mod get_preview;
+pub mod get_exists;
+pub mod get_leaderboard;
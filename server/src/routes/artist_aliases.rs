@@ -0,0 +1,91 @@
+use axum::{extract::State, http::{HeaderMap, StatusCode}, Json};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::sync::Arc;
+use crate::{
+  auth::is_operator,
+  errors::ApiError,
+  repositories::artist_alias_repository,
+  utils::process_param,
+  AppState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasRequest {
+  alias: Option<String>,
+  artist_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAliasRequest {
+  alias: Option<String>,
+}
+
+/// Makes lookups for the alias match the tracks of the artist, when nothing matches the alias
+/// itself. Lookups already cached for the alias are served until they expire.
+pub async fn route(
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<AliasRequest>,
+) -> Result<StatusCode, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let alias_lower = process_param(payload.alias.as_deref())
+    .ok_or_else(|| ApiError::ValidationError("alias: cannot be empty".to_owned()))?;
+  let artist_name_lower = process_param(payload.artist_name.as_deref())
+    .ok_or_else(|| ApiError::ValidationError("artistName: cannot be empty".to_owned()))?;
+  if alias_lower == artist_name_lower {
+    return Err(ApiError::ValidationError("alias: cannot be the name of the artist itself".to_owned()));
+  }
+
+  {
+    let mut conn = state.pool.get()?;
+    artist_alias_repository::add_or_replace(&alias_lower, &artist_name_lower, &mut conn)?;
+  }
+  state.artist_alias_cache.invalidate(&alias_lower).await;
+  state.track_filter.add_alias(&alias_lower);
+  tracing::info!(message = "added artist alias", alias = alias_lower, artist_name = artist_name_lower);
+
+  Ok(StatusCode::CREATED)
+}
+
+pub async fn delete_route(
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<DeleteAliasRequest>,
+) -> Result<StatusCode, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let alias_lower = process_param(payload.alias.as_deref())
+    .ok_or_else(|| ApiError::ValidationError("alias: cannot be empty".to_owned()))?;
+
+  let deleted = {
+    let mut conn = state.pool.get()?;
+    artist_alias_repository::delete_one(&alias_lower, &mut conn)?
+  };
+  state.artist_alias_cache.invalidate(&alias_lower).await;
+  if deleted {
+    tracing::info!(message = "deleted artist alias", alias = alias_lower);
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns the canonical name of the artist, as in `tracks.artist_name_lower`, when the given name
+/// is one of its aliases. The aliases rarely change, so they are cached, along with the names that
+/// aren't aliases.
+pub async fn resolve_artist_alias(alias_lower: &str, state: &Arc<AppState>, conn: &mut Connection) -> anyhow::Result<Option<String>> {
+  if let Some(artist_name_lower) = state.artist_alias_cache.get(alias_lower).await {
+    return Ok(artist_name_lower);
+  }
+
+  let artist_name_lower = artist_alias_repository::resolve_artist_alias(alias_lower, conn)?;
+  state.artist_alias_cache.insert(alias_lower.to_owned(), artist_name_lower.clone()).await;
+
+  Ok(artist_name_lower)
+}
@@ -0,0 +1,129 @@
+use axum::{
+  extract::{Query, State},
+  Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{
+  entities::track::SimpleTrack,
+  errors::ApiError,
+  repositories::track_repository::get_changed_tracks,
+  AppState,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// Only return lyrics updated after this time
+  since: Option<DateTime<Utc>>,
+  /// Together with `since`, only return lyrics updated exactly at `since` if their track id is greater
+  after_id: Option<i64>,
+  limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangesResponse {
+  changes: Vec<ChangedTrack>,
+  /// The watermark to pass back as `since` and `after_id` to get the next changes. It stays the
+  /// same when there are no new changes.
+  max_updated_at: Option<DateTime<Utc>>,
+  last_id: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangedTrack {
+  id: i64,
+  lyrics_id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  duration: Option<f64>,
+  instrumental: bool,
+  plain_lyrics: Option<String>,
+  synced_lyrics: Option<String>,
+  updated_at: Option<DateTime<Utc>>,
+}
+
+/// Lists the tracks whose lyrics changed since a watermark, oldest change first, so that mirrors
+/// can sync incrementally by polling with the watermark of the previous response.
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<ChangesResponse>, ApiError> {
+  let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+  if limit == 0 || limit > MAX_LIMIT {
+    return Err(ApiError::ValidationError(format!("limit must be between 1 and {}", MAX_LIMIT)));
+  }
+
+  let after_id = params.after_id.unwrap_or(0);
+
+  let tracks = {
+    let mut conn = state.pool.get()?;
+    get_changed_tracks(params.since, after_id, limit, &mut conn)?
+  };
+
+  let changes: Vec<ChangedTrack> = tracks.into_iter().map(create_change).collect();
+
+  let (max_updated_at, last_id) = match changes.last() {
+    Some(change) => (change.updated_at, change.id),
+    None => (params.since, after_id),
+  };
+
+  Ok(Json(ChangesResponse {
+    changes,
+    max_updated_at,
+    last_id,
+  }))
+}
+
+fn create_change(track: SimpleTrack) -> ChangedTrack {
+  let lyrics = track.last_lyrics.unwrap_or_default();
+
+  ChangedTrack {
+    id: track.id,
+    // Always set, since changed tracks are joined with their lyrics
+    lyrics_id: lyrics.id.unwrap_or_default(),
+    track_name: track.name,
+    artist_name: track.artist_name,
+    album_name: track.album_name,
+    duration: track.duration,
+    instrumental: lyrics.instrumental,
+    plain_lyrics: lyrics.plain_lyrics,
+    synced_lyrics: lyrics.synced_lyrics,
+    updated_at: lyrics.updated_at,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::test_utils::{body_json, TestApp};
+
+  #[tokio::test]
+  async fn pages_through_tracks_sharing_their_lyrics() {
+    let app = TestApp::new();
+    let first_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let second_id = app.add_track("Hello (Radio Edit)", "Adele", None, None);
+    {
+      let conn = app.state.pool.get().unwrap();
+      conn.execute("UPDATE tracks SET last_lyrics_id = (SELECT last_lyrics_id FROM tracks WHERE id = ?) WHERE id = ?", (first_id, second_id)).unwrap();
+    }
+
+    let first_page = body_json(app.get("/api/changes?limit=1").await).await;
+    assert_eq!(first_page["changes"][0]["id"], first_id);
+    assert_eq!(first_page["lastId"], first_id);
+
+    let since = first_page["maxUpdatedAt"].as_str().unwrap().replace('+', "%2B");
+    let second_page = body_json(app.get(&format!("/api/changes?limit=1&since={}&after_id={}", since, first_id)).await).await;
+    assert_eq!(second_page["changes"][0]["id"], second_id);
+    assert_eq!(second_page["changes"][0]["lyricsId"], first_page["changes"][0]["lyricsId"]);
+
+    let since = second_page["maxUpdatedAt"].as_str().unwrap().replace('+', "%2B");
+    let last_page = body_json(app.get(&format!("/api/changes?limit=1&since={}&after_id={}", since, second_id)).await).await;
+    assert_eq!(last_page["changes"].as_array().unwrap().len(), 0);
+  }
+}
@@ -0,0 +1,184 @@
+use axum::{
+  extract::{Query, State},
+  http::HeaderMap,
+  Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{
+  auth::is_operator,
+  entities::dead_letter_track::DeadLetterTrack,
+  errors::ApiError,
+  repositories::dead_letter_repository,
+  AppState,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+/// Dead-lettered tracks read at once when requeuing all of them
+const REQUEUE_BATCH_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// Only return the tracks with a greater id
+  after_id: Option<i64>,
+  limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterResponse {
+  tracks: Vec<DeadLetterTrackResponse>,
+  /// The number of dead-lettered tracks, on every page
+  total: i64,
+  /// The id to pass back as `after_id` to get the next page
+  last_id: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeadLetterTrackResponse {
+  id: i64,
+  track_name: String,
+  artist_name: String,
+  album_name: String,
+  duration: f64,
+  retry_count: u32,
+  last_error: Option<String>,
+  created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct RequeueRequest {
+  /// Requeues every dead-lettered track when not given
+  id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct RequeueResponse {
+  requeued: usize,
+  /// Tracks that didn't fit in the full queue, they stay dead-lettered
+  dropped: usize,
+}
+
+/// Lists the tracks whose lyrics could not be fetched after all retries, oldest first
+pub async fn route(
+  headers: HeaderMap,
+  Query(params): Query<QueryParams>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<DeadLetterResponse>, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+  if limit == 0 || limit > MAX_LIMIT {
+    return Err(ApiError::ValidationError(format!("limit must be between 1 and {}", MAX_LIMIT)));
+  }
+
+  let after_id = params.after_id.unwrap_or(0);
+
+  let (tracks, total) = {
+    let mut conn = state.pool.get()?;
+    (
+      dead_letter_repository::get_page(after_id, limit, &mut conn)?,
+      dead_letter_repository::count(&mut conn)?,
+    )
+  };
+
+  let last_id = tracks.last().map_or(after_id, |track| track.id);
+
+  Ok(Json(DeadLetterResponse {
+    tracks: tracks.into_iter().map(create_response).collect(),
+    total,
+    last_id,
+  }))
+}
+
+/// Moves dead-lettered tracks back to the in-memory queue with a fresh retry count. Tracks only
+/// leave the dead-letter store once they are in the queue, so those that don't fit are kept for a
+/// later requeue. Nothing can be requeued while the queue is disabled.
+pub async fn requeue_route(
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<RequeueRequest>,
+) -> Result<Json<RequeueResponse>, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  if !state.queue_enabled {
+    return Err(ApiError::ServiceUnavailableError);
+  }
+
+  let mut conn = state.pool.get()?;
+
+  let response = match payload.id {
+    Some(id) => {
+      let track = dead_letter_repository::get_by_id(id, &mut conn)?.ok_or(ApiError::TrackNotFoundError)?;
+      let requeued = requeue(&state, vec![track], &mut conn)?;
+      RequeueResponse { requeued, dropped: 1 - requeued }
+    },
+    None => {
+      let total = dead_letter_repository::count(&mut conn)? as usize;
+      let mut requeued = 0;
+      let mut after_id = 0;
+
+      loop {
+        let tracks = dead_letter_repository::get_page(after_id, REQUEUE_BATCH_SIZE, &mut conn)?;
+        let Some(last) = tracks.last() else {
+          break;
+        };
+        after_id = last.id;
+
+        let batch_size = tracks.len();
+        let batch_requeued = requeue(&state, tracks, &mut conn)?;
+        requeued += batch_requeued;
+        if batch_requeued < batch_size {
+          break;
+        }
+      }
+
+      RequeueResponse { requeued, dropped: total.saturating_sub(requeued) }
+    },
+  };
+
+  tracing::info!(message = "requeued dead-lettered tracks", requeued = response.requeued, dropped = response.dropped);
+
+  Ok(Json(response))
+}
+
+/// Pushes the tracks to the queue until it is full, removing the pushed ones from the dead-letter
+/// store, and returns how many were pushed
+fn requeue(state: &Arc<AppState>, tracks: Vec<DeadLetterTrack>, conn: &mut rusqlite::Connection) -> anyhow::Result<usize> {
+  let mut requeued_ids = Vec::with_capacity(tracks.len());
+
+  for track in tracks {
+    let mut missing_track = track.missing_track;
+    missing_track.retry_count = 0;
+    missing_track.next_attempt_at = None;
+
+    if state.queue.push(missing_track).is_err() {
+      break;
+    }
+    requeued_ids.push(track.id);
+  }
+
+  dead_letter_repository::delete_many(&requeued_ids, conn)?;
+
+  Ok(requeued_ids.len())
+}
+
+fn create_response(track: DeadLetterTrack) -> DeadLetterTrackResponse {
+  DeadLetterTrackResponse {
+    id: track.id,
+    track_name: track.missing_track.name,
+    artist_name: track.missing_track.artist_name,
+    album_name: track.missing_track.album_name,
+    duration: track.missing_track.duration,
+    retry_count: track.missing_track.retry_count,
+    last_error: track.last_error,
+    created_at: track.created_at,
+  }
+}
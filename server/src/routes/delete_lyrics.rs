@@ -0,0 +1,35 @@
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use std::sync::Arc;
+use crate::{
+  auth::is_operator,
+  errors::ApiError,
+  repositories::track_repository::soft_delete_lyrics,
+  routes::invalidate_cache::evict_track,
+  AppState,
+};
+
+/// Removes a track and its lyrics, e.g. for DMCA requests. The rows are only marked as deleted, so
+/// they stay available for audit, but every read endpoint treats the track as not found from now on.
+pub async fn route(
+  Path(track_id): Path<i64>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let deleted = {
+    let mut conn = state.pool.get()?;
+    soft_delete_lyrics(track_id, &mut conn)?
+  };
+
+  if !deleted {
+    return Err(ApiError::TrackNotFoundError);
+  }
+
+  let evicted = evict_track(&state, track_id).await;
+  tracing::info!(message = "deleted lyrics", track_id, evicted);
+
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,114 @@
+use axum::{
+  body::{Body, Bytes},
+  extract::{Query, State},
+  http::{header, HeaderMap},
+  response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{
+  auth::is_operator,
+  entities::track::SimpleTrack,
+  errors::ApiError,
+  repositories::track_repository::get_tracks_for_export,
+  AppState,
+};
+
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// Only export lyrics updated after this time, for incremental syncs
+  since: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedTrack {
+  id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  duration: Option<f64>,
+  instrumental: bool,
+  plain_lyrics: Option<String>,
+  synced_lyrics: Option<String>,
+  updated_at: Option<DateTime<Utc>>,
+}
+
+/// Streams all tracks with lyrics as newline-delimited JSON.
+///
+/// The tracks are read in batches, each with its own pool connection that is returned before the
+/// batch is sent, so a slow client never holds a connection that other requests need. The tradeoff
+/// is that the export is not a consistent snapshot: lyrics published while the export is running are
+/// only included if their track comes after the current position. Mirrors should follow up with
+/// an export `since` the time the previous one started.
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let since = params.since;
+
+  // The state carries the id of the last exported track, or None once the last batch was sent
+  let batches = stream::try_unfold(Some(0), move |after_id| {
+    let state = state.clone();
+
+    async move {
+      let Some(after_id) = after_id else {
+        return Ok::<_, anyhow::Error>(None);
+      };
+
+      let tracks = {
+        let mut conn = state.pool.get()?;
+        get_tracks_for_export(after_id, since, EXPORT_BATCH_SIZE, &mut conn)?
+      };
+
+      if tracks.is_empty() {
+        return Ok(None);
+      }
+
+      // A short batch is the last one
+      let next_after_id = if tracks.len() == EXPORT_BATCH_SIZE {
+        tracks.last().map(|track| track.id)
+      } else {
+        None
+      };
+
+      let mut chunk = Vec::new();
+      for track in tracks {
+        serde_json::to_writer(&mut chunk, &create_line(track))?;
+        chunk.push(b'\n');
+      }
+
+      Ok(Some((Bytes::from(chunk), next_after_id)))
+    }
+  });
+
+  Ok((
+    [(header::CONTENT_TYPE, "application/x-ndjson")],
+    Body::from_stream(batches),
+  ).into_response())
+}
+
+fn create_line(track: SimpleTrack) -> ExportedTrack {
+  let lyrics = track.last_lyrics.unwrap_or_default();
+
+  ExportedTrack {
+    id: track.id,
+    track_name: track.name,
+    artist_name: track.artist_name,
+    album_name: track.album_name,
+    duration: track.duration,
+    instrumental: lyrics.instrumental,
+    plain_lyrics: lyrics.plain_lyrics,
+    synced_lyrics: lyrics.synced_lyrics,
+    updated_at: lyrics.updated_at,
+  }
+}
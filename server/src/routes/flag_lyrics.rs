@@ -1,42 +1,97 @@
 use anyhow::Result;
 use axum::{
-  extract::State,
+  extract::{ConnectInfo, State},
   http::{
     StatusCode,
     HeaderMap,
   },
   Json,
 };
-use serde::Deserialize;
-use std::sync::Arc;
-use crate::{errors::ApiError, repositories::track_repository, AppState};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use crate::{
+  entities::flag::FlagReason,
+  errors::ApiError,
+  rate_limit,
+  repositories::{flag_repository, track_repository},
+  routes::get_lyrics_by_metadata::evict_cached_track,
+  AppState,
+};
 use axum_macros::debug_handler;
-use crate::utils::is_valid_publish_token;
+use crate::utils::{client_ip, is_valid_publish_token};
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct FlagLyricsRequest {
     track_id: i64,
+    reason: Option<String>,
     content: Option<String>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagLyricsResponse {
+  /// The client already flagged the track recently, so this flag wasn't counted again
+  already_flagged: bool,
+}
+
 #[debug_handler]
 pub async fn route(
   headers: HeaderMap,
+  connect_info: Option<ConnectInfo<SocketAddr>>,
   State(state): State<Arc<AppState>>,
   Json(payload): Json<FlagLyricsRequest>,
-) -> Result<StatusCode, ApiError> {
+) -> Result<(StatusCode, Json<FlagLyricsResponse>), ApiError> {
+  let reason = validate_reason(&payload)?;
+
   match headers.get("X-Publish-Token") {
     Some(publish_token) => {
-      let is_valid = is_valid_publish_token(publish_token.to_str()?, &state.challenge_cache).await;
+      let is_valid = is_valid_publish_token(publish_token.to_str()?, &state.challenge_cache, &state.challenge_cache_metrics).await;
 
       if is_valid {
         let content = payload.content.unwrap_or("".to_string());
         let track_id = payload.track_id;
+
+        // Clients whose IP is unknown can't be told apart, so their flags are never deduplicated
+        let dedup_key = client_ip(&headers, connect_info.map(|ConnectInfo(addr)| addr), &state.trusted_proxies)
+          .map(|ip| format!("{}:{}", ip, track_id));
+        if dedup_key.as_ref().is_some_and(|key| state.flag_dedup_cache.contains_key(key)) {
+          return Ok(already_flagged());
+        }
+
+        let rate_limit_key = format!("flag:track:{}", track_id);
+        if let Err(retry_after) = rate_limit::check(&state.rate_limit_cache, rate_limit_key, state.flag_track_rate_limit).await {
+          return Err(ApiError::RateLimitedError(retry_after.as_secs().max(1)));
+        }
+
         let mut conn = state.pool.get()?;
-        track_repository::flag_track_last_lyrics(track_id, &content, &mut conn)?;
+        // Concurrent flags of the same client: only the one inserting the key is counted
+        if let Some(key) = &dedup_key {
+          if !state.flag_dedup_cache.entry(key.to_owned()).or_insert(()).await.is_fresh() {
+            return Ok(already_flagged());
+          }
+        }
+
+        let lyrics_id = match track_repository::flag_track_last_lyrics(track_id, reason, &content, &mut conn) {
+          Ok(lyrics_id) => lyrics_id,
+          Err(err) => {
+            // The flag wasn't recorded, so the client can try again
+            if let Some(key) = &dedup_key {
+              state.flag_dedup_cache.invalidate(key).await;
+            }
+            return Err(err.into());
+          },
+        };
 
-        Ok(StatusCode::CREATED)
+        // Stop serving lyrics from the cache once enough people reported them
+        if let Some(lyrics_id) = lyrics_id {
+          let flags_count = flag_repository::count_unreviewed_by_lyrics_id(lyrics_id, &mut conn)?;
+          if state.flag_eviction_threshold > 0 && flags_count >= state.flag_eviction_threshold as i64 {
+            evict_cached_track(&state, track_id)?;
+          }
+        }
+
+        Ok((StatusCode::CREATED, Json(FlagLyricsResponse { already_flagged: false })))
       } else {
         Err(ApiError::IncorrectPublishTokenError)
       }
@@ -44,3 +99,21 @@ pub async fn route(
     None => Err(ApiError::IncorrectPublishTokenError)
   }
 }
+
+fn already_flagged() -> (StatusCode, Json<FlagLyricsResponse>) {
+  (StatusCode::OK, Json(FlagLyricsResponse { already_flagged: true }))
+}
+
+fn validate_reason(payload: &FlagLyricsRequest) -> Result<FlagReason, ApiError> {
+  let reason = payload.reason.as_deref().and_then(FlagReason::parse).ok_or_else(|| {
+    let reasons = FlagReason::ALL.map(|reason| reason.as_str()).join(", ");
+    ApiError::ValidationError(format!("reason: must be one of {}", reasons))
+  })?;
+
+  let has_content = payload.content.as_deref().is_some_and(|content| !content.trim().is_empty());
+  if reason == FlagReason::Other && !has_content {
+    return Err(ApiError::ValidationError("content: is required when reason is other".to_owned()));
+  }
+
+  Ok(reason)
+}
@@ -0,0 +1,123 @@
+use axum::{
+  extract::State,
+  http::{header, HeaderMap, HeaderValue},
+  response::Response,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use crate::{
+  config::Config,
+  db::latest_schema_version,
+  routes::{get_lyrics_batch, get_lyrics_by_metadata, get_lyrics_by_track_ids, search_lyrics},
+  utils::{conditional_response, pow::{self, Argon2Params, PowScheme}},
+  AppState,
+};
+
+/// The capabilities only change with the build or the config, so clients can keep them for a day
+const CAPABILITIES_MAX_AGE: u64 = 60 * 60 * 24;
+
+/// Features every build of the server supports
+const FEATURES: [&str; 17] = [
+  "enhancedLrc",
+  "lrcFormat",
+  "srtFormat",
+  "msgpackFormat",
+  "translations",
+  "romanization",
+  "votes",
+  "flags",
+  "candidates",
+  "sparseFieldsets",
+  "artistAliases",
+  "batchLookup",
+  "changes",
+  "liveFeed",
+  "preview",
+  "exists",
+  "jsonLd",
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CapabilitiesResponse {
+  /// Bumped by every database migration of the server
+  schema_version: usize,
+  features: Vec<&'static str>,
+  proof_of_work: ProofOfWork,
+  limits: Limits,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProofOfWork {
+  algorithm: &'static str,
+  /// Only for `argon2id`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  params: Option<Argon2Params>,
+  /// The least difficulty of the challenges, busy servers raise it
+  min_difficulty: u8,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Limits {
+  max_batch_size: usize,
+  max_track_ids: usize,
+  max_search_page_size: usize,
+  max_candidates: usize,
+  /// In bytes
+  max_publish_body_size: usize,
+  /// In seconds, 0 when requests are never timed out
+  request_timeout: u64,
+}
+
+/// The capabilities document, serialized once at startup
+pub struct Capabilities {
+  body: String,
+  etag: String,
+}
+
+impl Capabilities {
+  pub fn new(config: &Config) -> Self {
+    let mut features = FEATURES.to_vec();
+    if config.enable_queue {
+      features.push("missingTrackQueue");
+    }
+    if config.api_keys_enabled {
+      features.push("apiKeys");
+    }
+
+    let pow_scheme = PowScheme::from_config(config);
+    let response = CapabilitiesResponse {
+      schema_version: latest_schema_version(),
+      features,
+      proof_of_work: ProofOfWork {
+        algorithm: pow_scheme.name(),
+        params: pow_scheme.argon2_params(),
+        min_difficulty: pow::min_difficulty(config),
+      },
+      limits: Limits {
+        max_batch_size: get_lyrics_batch::MAX_BATCH_SIZE,
+        max_track_ids: get_lyrics_by_track_ids::MAX_IDS,
+        max_search_page_size: search_lyrics::MAX_PAGE_SIZE,
+        max_candidates: get_lyrics_by_metadata::MAX_CANDIDATES,
+        max_publish_body_size: config.publish_body_limit,
+        request_timeout: config.request_timeout,
+      },
+    };
+
+    let body = serde_json::to_string(&response).expect("capabilities are serializable");
+    let etag = format!("W/\"{}\"", hex::encode(&Sha256::digest(body.as_bytes())[..16]));
+
+    Capabilities { body, etag }
+  }
+}
+
+/// Describes what this server supports, for clients talking to several LRCLIB-compatible servers
+pub async fn route(headers: HeaderMap, State(state): State<Arc<AppState>>) -> Response {
+  let capabilities = &state.capabilities;
+  let body = ([(header::CONTENT_TYPE, HeaderValue::from_static("application/json"))], capabilities.body.clone());
+
+  conditional_response(&headers, &capabilities.etag, None, CAPABILITIES_MAX_AGE, body)
+}
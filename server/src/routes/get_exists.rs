@@ -0,0 +1,34 @@
+use axum::{extract::{Query, State}, http::HeaderMap, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use crate::{
+  errors::ApiError,
+  routes::get_lyrics_by_metadata::{lookup_flags, QueryParams},
+  utils::bypasses_cache,
+  AppState,
+};
+use axum_macros::debug_handler;
+use validator::Validate;
+
+#[derive(Serialize)]
+pub struct ExistsResponse {
+  found: bool,
+  synced: bool,
+  instrumental: bool,
+}
+
+/// Whether `/api/get` would find the track and what lyrics it has, without the lyrics, for
+/// library scans deciding what to download. A miss is not an error, and only queues the track
+/// when `exists_queue_missing` is enabled.
+#[debug_handler]
+pub async fn route(Query(params): Query<QueryParams>, headers: HeaderMap, State(state): State<Arc<AppState>>) -> Result<Json<ExistsResponse>, ApiError> {
+  params.validate().map_err(|e| ApiError::ValidationError(e.to_string()))?;
+
+  let queue_missing = state.queue_enabled && state.exists_queue_missing;
+  let response = match lookup_flags(&params, queue_missing, !bypasses_cache(&headers), &state).await? {
+    Some(flags) => ExistsResponse { found: true, synced: flags.synced, instrumental: flags.instrumental },
+    None => ExistsResponse { found: false, synced: false, instrumental: false },
+  };
+
+  Ok(Json(response))
+}
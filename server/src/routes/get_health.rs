@@ -0,0 +1,6 @@
+use axum::http::StatusCode;
+
+/// Liveness probe, answers as long as the server is able to handle requests
+pub async fn route() -> StatusCode {
+  StatusCode::OK
+}
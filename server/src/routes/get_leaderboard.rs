@@ -0,0 +1,144 @@
+use axum::{
+  extract::{Query, State},
+  Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{entities::contributor::Leaderboard, errors::ApiError, repositories::lyrics_repository::get_leaderboard, AppState};
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// `day`, `week`, `month` (default), `year` or `all`
+  window: Option<String>,
+  limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardResponse {
+  window: &'static str,
+  contributors: Vec<RankedContributor>,
+  anonymous: AnonymousContributors,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RankedContributor {
+  /// Contributors with as many publishes share a rank
+  rank: usize,
+  handle: String,
+  publishes: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnonymousContributors {
+  contributors: i64,
+  publishes: i64,
+}
+
+#[derive(Clone, Copy)]
+enum Window {
+  Day,
+  Week,
+  Month,
+  Year,
+  All,
+}
+
+impl Window {
+  fn parse(window: &str) -> Option<Self> {
+    match window {
+      "day" => Some(Window::Day),
+      "week" => Some(Window::Week),
+      "month" => Some(Window::Month),
+      "year" => Some(Window::Year),
+      "all" => Some(Window::All),
+      _ => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      Window::Day => "day",
+      Window::Week => "week",
+      Window::Month => "month",
+      Window::Year => "year",
+      Window::All => "all",
+    }
+  }
+
+  /// The start of the window, `None` for all time
+  fn since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match self {
+      Window::Day => Some(now - Duration::days(1)),
+      Window::Week => Some(now - Duration::days(7)),
+      Window::Month => Some(now - Duration::days(30)),
+      Window::Year => Some(now - Duration::days(365)),
+      Window::All => None,
+    }
+  }
+}
+
+/// The contributors with the most lyrics published over a window, for those who chose to be credited
+/// under a handle. The others are only counted together.
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<LeaderboardResponse>, ApiError> {
+  let window = match params.window.as_deref() {
+    Some(window) => Window::parse(window)
+      .ok_or_else(|| ApiError::ValidationError("window: must be one of day, week, month, year, all".to_owned()))?,
+    None => Window::Month,
+  };
+  let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+  if limit == 0 || limit > MAX_LIMIT {
+    return Err(ApiError::ValidationError(format!("limit must be between 1 and {}", MAX_LIMIT)));
+  }
+
+  let cache_key = format!("leaderboard:{}:{}", window.name(), limit);
+  let cached_leaderboard = state.leaderboard_cache.get(&cache_key).await
+    .and_then(|cached_leaderboard| serde_json::from_str::<Leaderboard>(&cached_leaderboard).ok());
+
+  let leaderboard = match cached_leaderboard {
+    Some(leaderboard) => leaderboard,
+    None => {
+      let leaderboard = {
+        let mut conn = state.pool.get()?;
+        get_leaderboard(window.since(Utc::now()), limit, &mut conn)?
+      };
+      state.leaderboard_cache.insert(cache_key, serde_json::to_string(&leaderboard)?).await;
+      leaderboard
+    },
+  };
+
+  Ok(Json(create_response(window, leaderboard)))
+}
+
+fn create_response(window: Window, leaderboard: Leaderboard) -> LeaderboardResponse {
+  let mut contributors: Vec<RankedContributor> = Vec::with_capacity(leaderboard.contributors.len());
+  for (index, contributor) in leaderboard.contributors.into_iter().enumerate() {
+    let rank = match contributors.last() {
+      Some(previous) if previous.publishes == contributor.publishes => previous.rank,
+      _ => index + 1,
+    };
+    contributors.push(RankedContributor {
+      rank,
+      handle: contributor.handle,
+      publishes: contributor.publishes,
+    });
+  }
+
+  LeaderboardResponse {
+    window: window.name(),
+    contributors,
+    anonymous: AnonymousContributors {
+      contributors: leaderboard.anonymous_contributors,
+      publishes: leaderboard.anonymous_publishes,
+    },
+  }
+}
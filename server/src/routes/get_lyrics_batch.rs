@@ -0,0 +1,77 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use crate::{
+  errors::ApiError,
+  routes::get_lyrics_by_metadata::{lookup, QueryParams, TrackResponse},
+  AppState,
+};
+use axum_macros::debug_handler;
+use validator::Validate;
+
+pub const MAX_BATCH_SIZE: usize = 200;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum BatchItemResponse {
+  Found(Box<TrackResponse>),
+  Error { error: BatchItemError },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemError {
+  message: String,
+  name: String,
+}
+
+#[debug_handler]
+pub async fn route(
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<Vec<serde_json::Value>>,
+) -> Result<Json<Vec<Option<BatchItemResponse>>>, ApiError> {
+  if payload.len() > MAX_BATCH_SIZE {
+    return Err(ApiError::ValidationError(format!("batch cannot contain more than {} tracks", MAX_BATCH_SIZE)));
+  }
+
+  let mut results = Vec::with_capacity(payload.len());
+
+  for item in payload {
+    results.push(resolve_item(item, &state).await);
+  }
+
+  Ok(Json(results))
+}
+
+async fn resolve_item(item: serde_json::Value, state: &Arc<AppState>) -> Option<BatchItemResponse> {
+  let params = match serde_json::from_value::<QueryParams>(item) {
+    Ok(params) => params,
+    Err(err) => return Some(validation_error(err.to_string())),
+  };
+
+  if let Err(err) = params.validate() {
+    return Some(validation_error(err.to_string()));
+  }
+
+  match lookup(&params, true, true, state).await {
+    Ok(maybe_track) => maybe_track.map(|track| BatchItemResponse::Found(Box::new(track.response))),
+    Err(err) => {
+      tracing::error!(message = "failed to resolve batch item", error = err.to_string());
+      Some(BatchItemResponse::Error {
+        error: BatchItemError {
+          message: "Something bad happened when processing this track".to_owned(),
+          name: "UnknownError".to_owned(),
+        },
+      })
+    }
+  }
+}
+
+fn validation_error(message: String) -> BatchItemResponse {
+  BatchItemResponse::Error {
+    error: BatchItemError {
+      message,
+      name: "ValidationError".to_owned(),
+    },
+  }
+}
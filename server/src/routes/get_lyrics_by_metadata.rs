@@ -1,18 +1,35 @@
-use axum::{extract::{Query, State}, Json};
+use axum::{extract::{Query, State}, http::{header, HeaderMap, HeaderValue, Method}, response::{IntoResponse, Response}, Json};
 use rusqlite::Connection;
 use serde::{Deserialize,Serialize};
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
+use chrono::{DateTime, Utc};
 use crate::{
-    entities::{missing_track::MissingTrack, track::SimpleTrack},
+    entities::{lyrics_candidate::LyricsCandidate, missing_track::MissingTrack, track::{SimpleTrack, TrackFlags}},
     errors::ApiError,
-    repositories::track_repository::get_track_by_metadata,
-    utils::process_param,
+    queue::push_track,
+    repositories::track_repository::{count_tracks_by_metadata, get_lyrics_candidates, get_track_by_metadata, get_track_flags_by_metadata, get_track_by_normalized_metadata, DEFAULT_DURATION_TOLERANCE},
+    routes::{artist_aliases::resolve_artist_alias, get_lyrics_by_track_id::jsonld_cache_key, get_lyrics_by_track_ids::track_cache_key, get_preview::preview_cache_key},
+    utils::{
+      bypasses_cache,
+      cache_status,
+      conditional_response,
+      fields::Fields,
+      format::{lyrics_text_response, subtitles_response, LyricsPreference, ResponseFormat, X_LYRICS_KIND},
+      lrc::strip_word_timings,
+      lyrics_etag,
+      normalize::normalize,
+      process_param,
+      quality::LyricsQuality,
+      variant_etag,
+      LYRICS_MAX_AGE,
+      X_CACHE,
+      X_QUEUE_STATUS,
+    },
     AppState,
 };
 use axum_macros::debug_handler;
 use validator::Validate;
 use anyhow::Result;
-use crossbeam_queue::ArrayQueue;
 
 #[derive(Clone, Validate, Deserialize)]
 pub struct QueryParams {
@@ -23,9 +40,26 @@ pub struct QueryParams {
   album_name: Option<String>,
   #[validate(range(min = 1.0, max = 3600.0, message = "must be between 1 and 3600"))]
   duration: Option<f64>,
+  /// Widens the duration window (in seconds), clamped to `MAX_DURATION_TOLERANCE`
+  duration_tolerance: Option<f64>,
+  format: Option<String>,
+  /// Retry with normalized metadata (featured artists stripped) when the exact lookup misses
+  fuzzy: Option<bool>,
+  /// Remove the enhanced LRC word timings, for players that only support line-level LRC
+  stripped: Option<bool>,
+  /// Comma-separated fields to return in JSON responses, instead of the whole track
+  fields: Option<String>,
+  /// Return all the matching lyrics instead of the best one, for clients picking by themselves
+  candidates: Option<bool>,
+  /// Return how the lookup found its track, or why it found none, along with the track
+  explain: Option<bool>,
+  /// The kind of lyrics to return, `synced`, `plain` or `any` (the default)
+  prefer: Option<String>,
+  /// Return the other kind of lyrics when the preferred one is missing, instead of a 404. True by default.
+  fallback: Option<bool>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TrackResponse {
   id: i64,
@@ -37,90 +71,748 @@ pub struct TrackResponse {
   instrumental: bool,
   plain_lyrics: Option<String>,
   synced_lyrics: Option<String>,
+  language: Option<String>,
+  /// Where the lyrics were taken from, as given by the publisher or the provider
+  source: Option<String>,
+  #[serde(flatten)]
+  quality: LyricsQuality,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidateResponse {
+  /// The id of the lyrics, as used by `/api/vote`
+  id: i64,
+  track_id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  duration: Option<f64>,
+  /// Where the lyrics come from, `lrclib` for published lyrics
+  source: Option<String>,
+  instrumental: bool,
+  has_plain_lyrics: bool,
+  has_synced_lyrics: bool,
+  plain_lyrics: Option<String>,
+  synced_lyrics: Option<String>,
+  language: Option<String>,
+  #[serde(flatten)]
+  quality: LyricsQuality,
+  score: i64,
+  /// Whether these are the lyrics the track serves by default
+  current: bool,
+}
+
+/// The result of a lookup with `explain`, kept apart from the diagnostics so clients can ignore them
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExplainResponse {
+  /// The track the lookup returns, `None` on a miss
+  result: Option<TrackResponse>,
+  explain: Explanation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Explanation {
+  /// Applied to the names before matching them
+  normalization: Vec<&'static str>,
+  /// The names as matched against the database, `None` when nothing is left of them
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  /// Seconds around the requested duration that a track can be off by
+  duration_tolerance: f64,
+  /// Whether the regular response of this lookup is in the cache
+  cached: bool,
+  /// The attempts of the lookup in order, until one found a track
+  steps: Vec<ExplainStep>,
+  /// Why the track was chosen among the candidates of the last step, `None` on a miss
+  reason: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExplainStep {
+  /// `exact`, `withoutAlbum`, `artistAlias`, `artistAliasWithoutAlbum` or `fuzzy`
+  step: &'static str,
+  artist_name: String,
+  album_name: Option<String>,
+  /// Tracks with the names, whatever their duration. Not counted for the fuzzy step.
+  name_matches: Option<usize>,
+  /// Of those, the tracks within the duration tolerance
+  duration_matches: Option<usize>,
+  matched_track_id: Option<i64>,
+}
+
+/// The normalization of `process_param`, plus `normalize` for fuzzy lookups
+const NORMALIZATION: [&str; 4] = ["lowercase", "stripDiacritics", "stripPunctuation", "collapseWhitespace"];
+
+const MAX_DURATION_TOLERANCE: f64 = 10.0;
+/// Most lyrics returned with `candidates`
+pub const MAX_CANDIDATES: usize = 20;
+
+#[derive(Serialize, Deserialize)]
+pub struct TrackResult {
+  pub response: TrackResponse,
+  pub etag: String,
+  /// When the lyrics were last updated, `None` for tracks without lyrics
+  #[serde(default)]
+  pub last_modified: Option<DateTime<Utc>>,
+  /// Whether the result was read from `get_cache`
+  #[serde(skip)]
+  pub cache_hit: bool,
+}
+
+/// Also answers HEAD, with the status and headers of the GET response and no body. A HEAD only
+/// checks whether the lyrics exist, so it doesn't queue a missing track.
 #[debug_handler]
-pub async fn route(Query(params): Query<QueryParams>, State(state): State<Arc<AppState>>) -> Result<Json<TrackResponse>, ApiError> {
+pub async fn route(method: Method, Query(params): Query<QueryParams>, headers: HeaderMap, State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
   params.validate().map_err(|e| ApiError::ValidationError(e.to_string()))?;
+  let format = ResponseFormat::negotiate(params.format.as_deref(), &headers)?;
+  let preference = LyricsPreference::parse(params.prefer.as_deref())?;
+  format.check_preference(preference)?;
 
-  // Process input parameters once
+  if params.candidates.unwrap_or(false) {
+    if format != ResponseFormat::Json {
+      return Err(ApiError::ValidationError("candidates: candidates are only available as JSON".to_owned()));
+    }
+    return candidates_response(&params, &state).await;
+  }
+
+  if params.explain.unwrap_or(false) {
+    if format != ResponseFormat::Json {
+      return Err(ApiError::ValidationError("explain: explanations are only available as JSON".to_owned()));
+    }
+    return explain_response(&params, &state).await;
+  }
+
+  let queue_missing = state.queue_enabled && method != Method::HEAD;
+
+  match lookup(&params, queue_missing, !bypasses_cache(&headers), &state).await? {
+    Some(track) => track_response(track, &params, &headers, format, preference),
+    None => {
+      let mut response = ApiError::TrackNotFoundError.into_response();
+      if queue_missing && state.queue.is_full() {
+        response.headers_mut().insert(X_QUEUE_STATUS, HeaderValue::from_static("full"));
+      }
+      Ok(response)
+    },
+  }
+}
+
+/// Returns all the lyrics matching the lookup, retrying without the album like `lookup`. The
+/// candidates are always read from the database, and a miss doesn't queue the track.
+async fn candidates_response(params: &QueryParams, state: &Arc<AppState>) -> Result<Response, ApiError> {
+  let (Some(track_name_lower), Some(artist_name_lower)) = (
+    process_param(Some(params.track_name.as_str())),
+    process_param(Some(params.artist_name.as_str())),
+  ) else {
+    return Err(ApiError::TrackNotFoundError);
+  };
+  let album_name_lower = process_param(params.album_name.as_deref());
+  let duration_tolerance = duration_tolerance(params);
+
+  let candidates = {
+    let mut conn = state.pool.get()?;
+    let mut candidates = get_lyrics_candidates(&track_name_lower, &artist_name_lower, album_name_lower.as_deref(), params.duration, duration_tolerance, MAX_CANDIDATES, &mut conn)?;
+    if candidates.is_empty() && album_name_lower.is_some() {
+      candidates = get_lyrics_candidates(&track_name_lower, &artist_name_lower, None, params.duration, duration_tolerance, MAX_CANDIDATES, &mut conn)?;
+    }
+    candidates
+  };
+
+  if candidates.is_empty() {
+    return Err(ApiError::TrackNotFoundError);
+  }
+
+  let candidates: Vec<CandidateResponse> = candidates.into_iter().map(create_candidate_response).collect();
+  let body = match Fields::parse(params.fields.as_deref()) {
+    Some(fields) => fields.select(&candidates)?,
+    None => serde_json::to_value(candidates)?,
+  };
+
+  Ok(Json(body).into_response())
+}
+
+/// Replays the steps of `load`, recording what each of them matched. The cache is neither read nor
+/// filled, and a miss doesn't queue the track, so explaining a lookup never changes its regular
+/// response.
+async fn explain_response(params: &QueryParams, state: &Arc<AppState>) -> Result<Response, ApiError> {
   let track_name_lower = process_param(Some(params.track_name.as_str()));
   let artist_name_lower = process_param(Some(params.artist_name.as_str()));
   let album_name_lower = process_param(params.album_name.as_deref());
+  let fuzzy = params.fuzzy.unwrap_or(false);
+  let tolerance = duration_tolerance(params);
 
-  let mut conn = state.pool.get()?;
+  let mut normalization = NORMALIZATION.to_vec();
+  if fuzzy {
+    normalization.push("dropFeaturedArtists");
+  }
+
+  let mut explanation = Explanation {
+    normalization,
+    track_name: track_name_lower.clone(),
+    artist_name: artist_name_lower.clone(),
+    album_name: album_name_lower.clone(),
+    duration_tolerance: tolerance.unwrap_or(DEFAULT_DURATION_TOLERANCE),
+    cached: cache_key(params).is_some_and(|cache_key| state.get_cache.contains_key(&cache_key)),
+    steps: Vec::new(),
+    reason: None,
+  };
+
+  let mut maybe_track = None;
+  if let (Some(track_name_lower), Some(artist_name_lower)) = (&track_name_lower, &artist_name_lower) {
+    let mut conn = state.pool.get()?;
+
+    let mut attempts = vec![("exact", artist_name_lower.to_owned(), album_name_lower.clone())];
+    if album_name_lower.is_some() {
+      attempts.push(("withoutAlbum", artist_name_lower.to_owned(), None));
+    }
+    maybe_track = explain_attempts(&attempts, track_name_lower, params, &mut explanation, &mut conn).await?;
+
+    if maybe_track.is_none() {
+      if let Some(artist_name_canonical) = resolve_artist_alias(artist_name_lower, state, &mut conn).await? {
+        let mut attempts = vec![("artistAlias", artist_name_canonical.clone(), album_name_lower.clone())];
+        if album_name_lower.is_some() {
+          attempts.push(("artistAliasWithoutAlbum", artist_name_canonical, None));
+        }
+        maybe_track = explain_attempts(&attempts, track_name_lower, params, &mut explanation, &mut conn).await?;
+      }
+    }
+
+    if maybe_track.is_none() && fuzzy {
+      maybe_track = fetch_track_fuzzy(params, album_name_lower.as_deref(), tolerance, &mut conn).await?;
+      explanation.steps.push(ExplainStep {
+        step: "fuzzy",
+        artist_name: normalize(&params.artist_name),
+        album_name: album_name_lower.clone(),
+        name_matches: None,
+        duration_matches: None,
+        matched_track_id: maybe_track.as_ref().map(|track| track.id),
+      });
+      if maybe_track.is_some() {
+        explanation.reason = Some("matched the names without their featured artists, the best voted lyrics first".to_owned());
+      }
+    }
+  }
+
+  let response = ExplainResponse {
+    result: maybe_track.map(create_response),
+    explain: explanation,
+  };
+
+  // Diagnostics are for debugging, there is no point in keeping them
+  Ok(([(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))], Json(response)).into_response())
+}
+
+/// Runs the lookup attempts in order, until one finds a track
+async fn explain_attempts(
+  attempts: &[(&'static str, String, Option<String>)],
+  track_name_lower: &str,
+  params: &QueryParams,
+  explanation: &mut Explanation,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
+  let tolerance = duration_tolerance(params);
+
+  for (step, artist_name_lower, album_name_lower) in attempts {
+    let (name_matches, duration_matches) = count_tracks_by_metadata(track_name_lower, artist_name_lower, album_name_lower.as_deref(), params.duration, tolerance, conn)?;
+    let maybe_track = fetch_track(track_name_lower, artist_name_lower, album_name_lower.as_deref(), params.duration, tolerance, conn).await?;
+
+    explanation.steps.push(ExplainStep {
+      step,
+      artist_name: artist_name_lower.to_owned(),
+      album_name: album_name_lower.to_owned(),
+      name_matches: Some(name_matches),
+      duration_matches: Some(duration_matches),
+      matched_track_id: maybe_track.as_ref().map(|track| track.id),
+    });
+
+    if maybe_track.is_some() {
+      explanation.reason = Some(match duration_matches {
+        1 => "the only track matching".to_owned(),
+        count => format!(
+          "the best voted lyrics among {} matching tracks, then {}",
+          count,
+          // Mirrors the order of `get_track_by_metadata`
+          match (params.duration, params.duration_tolerance) {
+            (Some(_), Some(_)) => "the closest duration, then the oldest track",
+            _ => "the oldest track",
+          },
+        ),
+      });
+      return Ok(maybe_track);
+    }
+  }
+
+  Ok(None)
+}
+
+/// A variant of `route` that only answers from the warm `get_cache`, for clients that poll often
+/// and would rather get a quick miss than wait on the database. A miss is a 404, and intentionally
+/// neither reads the database, populates the cache, nor queues the track as missing.
+#[debug_handler]
+pub async fn cached_route(Query(params): Query<QueryParams>, headers: HeaderMap, State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+  params.validate().map_err(|e| ApiError::ValidationError(e.to_string()))?;
+  let format = ResponseFormat::negotiate(params.format.as_deref(), &headers)?;
+  let preference = LyricsPreference::parse(params.prefer.as_deref())?;
+  format.check_preference(preference)?;
+
+  let cached_track = match cache_key(&params) {
+    Some(cache_key) => get_cached(&cache_key, &state).await,
+    None => None,
+  };
+
+  match cached_track {
+    Some(track) => track_response(track, &params, &headers, format, preference),
+    None => Err(ApiError::TrackNotFoundError),
+  }
+}
+
+fn track_response(
+  mut track: TrackResult,
+  params: &QueryParams,
+  headers: &HeaderMap,
+  format: ResponseFormat,
+  preference: LyricsPreference,
+) -> Result<Response, ApiError> {
+  if params.stripped.unwrap_or(false) {
+    track.etag = variant_etag(&track.etag, "stripped");
+    track.response.synced_lyrics = track.response.synced_lyrics.as_deref().map(strip_word_timings);
+  }
+
+  let lyrics_kind = preference.apply(
+    params.fallback.unwrap_or(true),
+    &mut track.response.plain_lyrics,
+    &mut track.response.synced_lyrics,
+    track.response.instrumental,
+  )?;
+  track.etag = preference.etag(&track.etag);
+
+  let etag = format.etag(&track.etag);
+
+  let mut response = match format {
+    ResponseFormat::Json | ResponseFormat::MsgPack => match Fields::parse(params.fields.as_deref()) {
+      Some(fields) => {
+        let etag = variant_etag(&etag, &fields.etag_variant());
+        conditional_response(headers, &etag, track.last_modified, LYRICS_MAX_AGE, format.structured_body(fields.select(&track.response)?))
+      },
+      None => conditional_response(headers, &etag, track.last_modified, LYRICS_MAX_AGE, format.structured_body(track.response)),
+    },
+    ResponseFormat::Lrc => {
+      let body = lyrics_text_response(
+        track.response.synced_lyrics.as_deref(),
+        track.response.plain_lyrics.as_deref(),
+        track.response.instrumental,
+      );
+      conditional_response(headers, &etag, track.last_modified, LYRICS_MAX_AGE, body)
+    },
+    ResponseFormat::Srt => {
+      let body = subtitles_response(track.response.synced_lyrics.as_deref(), track.response.instrumental)?;
+      conditional_response(headers, &etag, track.last_modified, LYRICS_MAX_AGE, body)
+    },
+  };
+  response.headers_mut().insert(X_CACHE, cache_status(track.cache_hit));
+  response.headers_mut().insert(X_LYRICS_KIND, lyrics_kind.header_value());
+  response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+
+  Ok(response)
+}
+
+/// Why a coalesced load of a lookup left nothing in `get_cache`
+#[derive(Debug)]
+enum LoadError {
+  NotFound,
+  Failed(anyhow::Error),
+}
+
+/// A failed load, as seen by every request of the group. The original error is its source, so that
+/// it's still reported as a busy database when it was one.
+#[derive(Debug)]
+struct SharedLoadError(Arc<LoadError>);
+
+impl fmt::Display for SharedLoadError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "failed to look the track up")
+  }
+}
+
+impl std::error::Error for SharedLoadError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self.0.as_ref() {
+      LoadError::Failed(err) => Some(err.as_ref()),
+      LoadError::NotFound => None,
+    }
+  }
+}
+
+/// Looks the track up in `get_cache` (unless `read_cache` is false), then in the database. With
+/// `queue_missing`, a track that is not in the database is queued to be fetched from the providers.
+///
+/// Concurrent cache misses of the same lookup are coalesced: a single request loads the track,
+/// so that the database is queried and the missing track queued once, and the others wait for it.
+pub async fn lookup(params: &QueryParams, queue_missing: bool, read_cache: bool, state: &Arc<AppState>) -> Result<Option<TrackResult>> {
+  let Some(cache_key) = cache_key(params) else {
+    return Ok(None);
+  };
+
+  if !read_cache {
+    let maybe_result = load(params, queue_missing, state).await?;
+    if let Some(result) = &maybe_result {
+      state.get_cache.insert(cache_key, serde_json::to_string(result)?).await;
+    }
+    return Ok(maybe_result);
+  }
+
+  if let Some(response) = get_cached(&cache_key, state).await {
+    return Ok(Some(response));
+  }
+
+  let loaded = state.get_cache.try_get_with(cache_key, async {
+    match load(params, queue_missing, state).await {
+      Ok(Some(result)) => serde_json::to_string(&result).map_err(|err| LoadError::Failed(err.into())),
+      Ok(None) => Err(LoadError::NotFound),
+      Err(err) => Err(LoadError::Failed(err)),
+    }
+  }).await;
+
+  match loaded {
+    Ok(cached_response) => Ok(Some(serde_json::from_str(&cached_response)?)),
+    Err(err) => match err.as_ref() {
+      LoadError::NotFound => Ok(None),
+      LoadError::Failed(_) => Err(SharedLoadError(err).into()),
+    },
+  }
+}
+
+/// Looks the track up in the database, without any cache
+async fn load(params: &QueryParams, queue_missing: bool, state: &Arc<AppState>) -> Result<Option<TrackResult>> {
+  // Process input parameters once
+  let track_name_lower = process_param(Some(params.track_name.as_str()));
+  let artist_name_lower = process_param(Some(params.artist_name.as_str()));
+  let album_name_lower = process_param(params.album_name.as_deref());
 
   if let (Some(track_name_lower), Some(artist_name_lower)) = (track_name_lower, artist_name_lower) {
+    if !state.track_filter.may_contain(&track_name_lower, &artist_name_lower) {
+      skip_missing_track(params, queue_missing, album_name_lower.as_deref(), state).await;
+      return Ok(None);
+    }
+
+    let fuzzy = params.fuzzy.unwrap_or(false);
+    let duration_tolerance = duration_tolerance(params);
+
+    let mut conn = state.pool.get()?;
+
     // Attempt to fetch the track with all provided metadata
-    if let Some(track) = fetch_track(&track_name_lower, &artist_name_lower, album_name_lower.as_deref(), params.duration, &mut conn).await? {
-      return Ok(Json(create_response(track)));
+    let mut maybe_track = fetch_track(&track_name_lower, &artist_name_lower, album_name_lower.as_deref(), params.duration, duration_tolerance, &mut conn).await?;
+
+    if maybe_track.is_none() {
+      // If not found, handle missing track logic
+      if queue_missing && state.queue_enabled {
+        if let Err(e) = handle_missing_track(params, album_name_lower.as_deref(), state, Some(&mut conn)).await {
+          tracing::error!(message = "failed to handle missing track", error = e.to_string());
+        }
+      }
+
+      // Retry fetching the track without the album name
+      if album_name_lower.is_some() {
+        maybe_track = fetch_track_without_album(&track_name_lower, &artist_name_lower, params.duration, duration_tolerance, &mut conn).await?;
+      }
     }
 
-    // If not found, handle missing track logic
-    if let Err(e) = handle_missing_track(&params, &track_name_lower, &artist_name_lower, album_name_lower.as_deref(), &state).await {
-      tracing::error!(message = "failed to handle missing track", error = e.to_string());
+    // Retry under the canonical name of the artist, when the requested one is a known alias
+    if maybe_track.is_none() {
+      if let Some(artist_name_canonical) = resolve_artist_alias(&artist_name_lower, state, &mut conn).await? {
+        maybe_track = fetch_track(&track_name_lower, &artist_name_canonical, album_name_lower.as_deref(), params.duration, duration_tolerance, &mut conn).await?;
+
+        if maybe_track.is_none() && album_name_lower.is_some() {
+          maybe_track = fetch_track_without_album(&track_name_lower, &artist_name_canonical, params.duration, duration_tolerance, &mut conn).await?;
+        }
+      }
+    }
+
+    if maybe_track.is_none() && fuzzy {
+      maybe_track = fetch_track_fuzzy(params, album_name_lower.as_deref(), duration_tolerance, &mut conn).await?;
+    }
+
+    if let Some(track) = maybe_track {
+      return Ok(Some(TrackResult {
+        etag: lyrics_etag(track.id, track.last_lyrics.as_ref()),
+        last_modified: track.last_lyrics.as_ref().and_then(|lyrics| lyrics.updated_at),
+        response: create_response(track),
+        cache_hit: false,
+      }));
+    }
+  }
+
+  Ok(None)
+}
+
+/// Looks up what lyrics the track has like `lookup`, without reading the lyrics from the database.
+/// A full lookup already in `get_cache` answers as well, otherwise the flags are cached under their
+/// own key.
+pub async fn lookup_flags(params: &QueryParams, queue_missing: bool, read_cache: bool, state: &Arc<AppState>) -> Result<Option<TrackFlags>> {
+  let Some(cache_key) = cache_key(params) else {
+    return Ok(None);
+  };
+  let flags_key = flags_cache_key(&cache_key);
+
+  if read_cache {
+    let cached_flags = match state.get_cache.get(&flags_key).await {
+      Some(cached_flags) => serde_json::from_str::<TrackFlags>(&cached_flags).ok(),
+      None => state.get_cache.get(&cache_key).await
+        .and_then(|cached_response| serde_json::from_str::<TrackResult>(&cached_response).ok())
+        .map(|result| result.response.flags()),
+    };
+    state.get_cache_metrics.record(cached_flags.is_some());
+
+    if cached_flags.is_some() {
+      return Ok(cached_flags);
+    }
+  }
+
+  let maybe_flags = load_flags(params, queue_missing, state).await?;
+  if let Some(flags) = &maybe_flags {
+    state.get_cache.insert(flags_key, serde_json::to_string(flags)?).await;
+  }
+
+  Ok(maybe_flags)
+}
+
+/// Looks the flags up in the database through the same steps as `load`
+async fn load_flags(params: &QueryParams, queue_missing: bool, state: &Arc<AppState>) -> Result<Option<TrackFlags>> {
+  let track_name_lower = process_param(Some(params.track_name.as_str()));
+  let artist_name_lower = process_param(Some(params.artist_name.as_str()));
+  let album_name_lower = process_param(params.album_name.as_deref());
+
+  let (Some(track_name_lower), Some(artist_name_lower)) = (track_name_lower, artist_name_lower) else {
+    return Ok(None);
+  };
+  if !state.track_filter.may_contain(&track_name_lower, &artist_name_lower) {
+    skip_missing_track(params, queue_missing, album_name_lower.as_deref(), state).await;
+    return Ok(None);
+  }
+  let duration_tolerance = duration_tolerance(params);
+
+  let mut conn = state.pool.get()?;
+
+  let mut maybe_flags = get_track_flags_by_metadata(&track_name_lower, &artist_name_lower, album_name_lower.as_deref(), params.duration, duration_tolerance, &mut conn)?;
+
+  if maybe_flags.is_none() {
+    if queue_missing && state.queue_enabled {
+      if let Err(e) = handle_missing_track(params, album_name_lower.as_deref(), state, Some(&mut conn)).await {
+        tracing::error!(message = "failed to handle missing track", error = e.to_string());
+      }
     }
 
-    // Retry fetching the track without the album name
     if album_name_lower.is_some() {
-      if let Some(track) = fetch_track_without_album(&track_name_lower, &artist_name_lower, params.duration, &mut conn).await? {
-        return Ok(Json(create_response(track)));
+      maybe_flags = get_track_flags_by_metadata(&track_name_lower, &artist_name_lower, None, params.duration, duration_tolerance, &mut conn)?;
+    }
+  }
+
+  if maybe_flags.is_none() {
+    if let Some(artist_name_canonical) = resolve_artist_alias(&artist_name_lower, state, &mut conn).await? {
+      maybe_flags = get_track_flags_by_metadata(&track_name_lower, &artist_name_canonical, album_name_lower.as_deref(), params.duration, duration_tolerance, &mut conn)?;
+
+      if maybe_flags.is_none() && album_name_lower.is_some() {
+        maybe_flags = get_track_flags_by_metadata(&track_name_lower, &artist_name_canonical, None, params.duration, duration_tolerance, &mut conn)?;
       }
     }
   }
 
-  Err(ApiError::TrackNotFoundError)
+  // Fuzzy lookups are rare enough to read the whole track
+  if maybe_flags.is_none() && params.fuzzy.unwrap_or(false) {
+    maybe_flags = fetch_track_fuzzy(params, album_name_lower.as_deref(), duration_tolerance, &mut conn).await?
+      .as_ref()
+      .map(TrackFlags::from);
+  }
+
+  Ok(maybe_flags)
 }
 
-async fn fetch_track(track_name_lower: &str, artist_name_lower: &str, album_name_lower: Option<&str>, duration: Option<f64>, conn: &mut Connection) -> Result<Option<SimpleTrack>> {
+fn duration_tolerance(params: &QueryParams) -> Option<f64> {
+  params.duration_tolerance.map(|tolerance| tolerance.clamp(0.0, MAX_DURATION_TOLERANCE))
+}
+
+/// The `get_cache` key of a lookup, or `None` when the track or artist name is blank
+fn cache_key(params: &QueryParams) -> Option<String> {
+  let track_name_lower = process_param(Some(params.track_name.as_str()))?;
+  let artist_name_lower = process_param(Some(params.artist_name.as_str()))?;
+  let album_name_lower = process_param(params.album_name.as_deref());
+
+  Some(format!(
+    "get:{}:{}:{}:{}:{}{}",
+    track_name_lower,
+    artist_name_lower,
+    album_name_lower.as_deref().unwrap_or_default(),
+    params.duration.map(|duration| duration.to_string()).unwrap_or_default(),
+    duration_tolerance(params).map(|tolerance| tolerance.to_string()).unwrap_or_default(),
+    if params.fuzzy.unwrap_or(false) { ":fuzzy" } else { "" },
+  ))
+}
+
+/// The `get_cache` key of the flags of a lookup, next to its full response
+fn flags_cache_key(cache_key: &str) -> String {
+  format!("exists:{}", cache_key.strip_prefix("get:").unwrap_or(cache_key))
+}
+
+async fn get_cached(cache_key: &str, state: &Arc<AppState>) -> Option<TrackResult> {
+  let cached_response = state.get_cache.get(cache_key).await
+    .and_then(|cached_response| serde_json::from_str::<TrackResult>(&cached_response).ok());
+  state.get_cache_metrics.record(cached_response.is_some());
+
+  cached_response.map(|mut response| {
+    response.cache_hit = true;
+    response
+  })
+}
+
+async fn fetch_track(
+  track_name_lower: &str,
+  artist_name_lower: &str,
+  album_name_lower: Option<&str>,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
   get_track_by_metadata(
     track_name_lower,
     artist_name_lower,
     album_name_lower,
     duration,
+    duration_tolerance,
     conn,
   )
 }
 
-async fn fetch_track_without_album(track_name_lower: &str, artist_name_lower: &str, duration: Option<f64>, conn: &mut Connection) -> Result<Option<SimpleTrack>> {
+async fn fetch_track_without_album(
+  track_name_lower: &str,
+  artist_name_lower: &str,
+  duration: Option<f64>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
   get_track_by_metadata(
     track_name_lower,
     artist_name_lower,
     None,
     duration,
+    duration_tolerance,
     conn,
   )
 }
 
+async fn fetch_track_fuzzy(
+  params: &QueryParams,
+  album_name_lower: Option<&str>,
+  duration_tolerance: Option<f64>,
+  conn: &mut Connection,
+) -> Result<Option<SimpleTrack>> {
+  let track_name_normalized = normalize(&params.track_name);
+  let artist_name_normalized = normalize(&params.artist_name);
+
+  if track_name_normalized.is_empty() || artist_name_normalized.is_empty() {
+    return Ok(None);
+  }
+
+  let maybe_track = get_track_by_normalized_metadata(
+    &track_name_normalized,
+    &artist_name_normalized,
+    album_name_lower,
+    params.duration,
+    duration_tolerance,
+    conn,
+  )?;
+
+  if maybe_track.is_none() && album_name_lower.is_some() {
+    return get_track_by_normalized_metadata(
+      &track_name_normalized,
+      &artist_name_normalized,
+      None,
+      params.duration,
+      duration_tolerance,
+      conn,
+    );
+  }
+
+  Ok(maybe_track)
+}
+
+/// Handles a lookup the track filter ruled out, queueing the track without querying the database
+async fn skip_missing_track(params: &QueryParams, queue_missing: bool, album_name_lower: Option<&str>, state: &Arc<AppState>) {
+  if queue_missing && state.queue_enabled {
+    if let Err(e) = handle_missing_track(params, album_name_lower, state, None).await {
+      tracing::error!(message = "failed to handle missing track", error = e.to_string());
+    }
+  }
+}
+
+/// Queues the track for the providers, unless the database already knows it under the same
+/// normalized metadata (e.g. with a different duration), or it was queued recently. Without `conn`,
+/// the track filter already ruled the track out and the database isn't checked.
 async fn handle_missing_track(
   params: &QueryParams,
-  track_name_lower: &str,
-  artist_name_lower: &str,
   album_name_lower: Option<&str>,
   state: &Arc<AppState>,
+  conn: Option<&mut Connection>,
 ) -> Result<()> {
   if let (Some(album_name), Some(album_name_lower), Some(duration)) = (
     params.album_name.as_deref(),
     album_name_lower,
     params.duration,
   ) {
+    let track_name_normalized = normalize(&params.track_name);
+    let artist_name_normalized = normalize(&params.artist_name);
+
+    let cache_key = format!("{}:{}:{}:{}", track_name_normalized, artist_name_normalized, album_name_lower, duration.round());
+    if state.missing_track_cache.contains_key(&cache_key) {
+      return Ok(());
+    }
+
+    if let Some(conn) = conn {
+      let known_track = get_track_by_normalized_metadata(
+        &track_name_normalized,
+        &artist_name_normalized,
+        Some(album_name_lower),
+        None,
+        None,
+        conn,
+      )?;
+      if known_track.is_some() {
+        return Ok(());
+      }
+    }
+
     let missing_track = MissingTrack {
       name: params.track_name.trim().to_owned(),
       artist_name: params.artist_name.trim().to_owned(),
       album_name: album_name.trim().to_owned(),
       duration,
+      retry_count: 0,
+      next_attempt_at: None,
     };
 
-    let cache_key = format!("missing_track:{}:{}:{}:{}", track_name_lower, artist_name_lower, album_name_lower, duration);
-    if !state.get_cache.contains_key(&cache_key) {
-      state.get_cache.insert(cache_key, "1".to_owned()).await;
-      send_to_queue(missing_track, &state.queue);
-    }
+    state.missing_track_cache.insert(cache_key, ()).await;
+    send_to_queue(missing_track, state);
   }
 
   Ok(())
 }
 
+impl TrackResponse {
+  fn flags(&self) -> TrackFlags {
+    TrackFlags {
+      id: self.id,
+      name: self.track_name.to_owned(),
+      artist_name: self.artist_name.to_owned(),
+      album_name: self.album_name.to_owned(),
+      synced: self.synced_lyrics.is_some(),
+      instrumental: self.instrumental,
+    }
+  }
+}
+
 fn create_response(track: SimpleTrack) -> TrackResponse {
   let plain_lyrics = match track.last_lyrics {
     Some(ref lyrics) => lyrics.plain_lyrics.to_owned(),
@@ -137,6 +829,16 @@ fn create_response(track: SimpleTrack) -> TrackResponse {
     None => false
   };
 
+  let language = match track.last_lyrics {
+    Some(ref lyrics) => lyrics.language.to_owned(),
+    None => None
+  };
+
+  let source = match track.last_lyrics {
+    Some(ref lyrics) => lyrics.attribution.to_owned(),
+    None => None
+  };
+
   TrackResponse {
     id: track.id,
     name: track.name.to_owned(),
@@ -144,14 +846,41 @@ fn create_response(track: SimpleTrack) -> TrackResponse {
     artist_name: track.artist_name.to_owned(),
     album_name: track.album_name.to_owned(),
     duration: track.duration,
+    quality: LyricsQuality::new(plain_lyrics.as_deref(), synced_lyrics.as_deref(), instrumental),
     instrumental,
     plain_lyrics,
     synced_lyrics,
+    language,
+    source,
   }
 }
 
-fn send_to_queue(missing_track: MissingTrack, queue: &ArrayQueue<MissingTrack>) {
-  match queue.push(missing_track.clone()) {
+fn create_candidate_response(candidate: LyricsCandidate) -> CandidateResponse {
+  let track = candidate.track;
+  let lyrics = track.last_lyrics.unwrap_or_default();
+
+  CandidateResponse {
+    id: lyrics.id.unwrap_or_default(),
+    track_id: track.id,
+    track_name: track.name,
+    artist_name: track.artist_name,
+    album_name: track.album_name,
+    duration: track.duration,
+    source: candidate.source,
+    instrumental: lyrics.instrumental,
+    has_plain_lyrics: lyrics.plain_lyrics.is_some(),
+    has_synced_lyrics: lyrics.synced_lyrics.is_some(),
+    quality: LyricsQuality::new(lyrics.plain_lyrics.as_deref(), lyrics.synced_lyrics.as_deref(), lyrics.instrumental),
+    plain_lyrics: lyrics.plain_lyrics,
+    synced_lyrics: lyrics.synced_lyrics,
+    language: lyrics.language,
+    score: candidate.score,
+    current: candidate.is_current,
+  }
+}
+
+fn send_to_queue(missing_track: MissingTrack, state: &Arc<AppState>) {
+  match push_track(state, missing_track.clone()) {
     Ok(_) => tracing::debug!(
       message = "sent missing track to queue",
       track_name = missing_track.name,
@@ -159,12 +888,195 @@ fn send_to_queue(missing_track: MissingTrack, queue: &ArrayQueue<MissingTrack>)
       album_name = missing_track.album_name,
       duration = missing_track.duration,
     ),
-    Err(missing_track) => tracing::debug!(
+    Err(err) => tracing::error!(
       message = "failed to push to queue",
       track_name = missing_track.name,
       artist_name = missing_track.artist_name,
       album_name = missing_track.album_name,
       duration = missing_track.duration,
+      error = err.to_string(),
     ),
   }
 }
+
+/// Removes the cached lookups that resolved to the given track, as well as the track, its preview
+/// and its JSON-LD cached by id, so that the next lookup reads the database again
+pub fn evict_cached_track(state: &Arc<AppState>, track_id: i64) -> Result<()> {
+  let track_key = track_cache_key(track_id);
+  let preview_key = preview_cache_key(track_id);
+  let jsonld_key = jsonld_cache_key(track_id);
+  state.get_cache.invalidate_entries_if(move |key, value| {
+    if *key == track_key || *key == preview_key || *key == jsonld_key {
+      return true;
+    }
+    if key.starts_with("exists:") {
+      return serde_json::from_str::<TrackFlags>(value).is_ok_and(|flags| flags.id == track_id);
+    }
+    key.starts_with("get:")
+      && serde_json::from_str::<TrackResult>(value).is_ok_and(|result| result.response.id == track_id)
+  })?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+  use axum::{body::{to_bytes, Body}, http::{header, Request, Response, StatusCode}};
+  use tower::ServiceExt;
+  use crate::{
+    test_utils::{body_json, body_msgpack, headers_of, TestApp},
+    utils::{format::MSGPACK_CONTENT_TYPE, X_CACHE, X_QUEUE_STATUS},
+  };
+
+  async fn head(app: &TestApp, uri: &str) -> Response<Body> {
+    app.send(Request::head(uri).body(Body::empty()).unwrap()).await
+  }
+
+  #[tokio::test]
+  async fn head_answers_like_get_without_a_body() {
+    let app = TestApp::new();
+    app.add_track("Hello", "Adele", Some("Hello, it's me"), Some("[00:01.00]Hello, it's me"));
+
+    for uri in ["/api/get?track_name=Hello&artist_name=Adele", "/api/get?track_name=Hello&artist_name=Adele&format=lrc"] {
+      // Cached by the first GET, so that both of the compared responses are cache hits
+      app.get(uri).await;
+      let get = app.get(uri).await;
+      let head = head(&app, uri).await;
+
+      assert_eq!(head.status(), StatusCode::OK);
+      assert_eq!(head.status(), get.status());
+      assert_eq!(headers_of(&head), headers_of(&get), "{}", uri);
+      assert!(get.headers().contains_key(header::ETAG));
+      assert!(to_bytes(head.into_body(), usize::MAX).await.unwrap().is_empty());
+    }
+  }
+
+  #[tokio::test]
+  async fn head_of_a_missing_track_doesnt_queue_it() {
+    let app = TestApp::new();
+    let uri = "/api/get?track_name=Hello&artist_name=Adele&album_name=25&duration=295";
+
+    let head = head(&app, uri).await;
+    assert_eq!(head.status(), StatusCode::NOT_FOUND);
+    assert_eq!(app.state.queue.len(), 0);
+
+    let get = app.get(uri).await;
+    assert_eq!(get.status(), head.status());
+    assert_eq!(headers_of(&get), headers_of(&head));
+    assert_eq!(app.state.queue.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn missing_track_is_a_404_without_the_queue() {
+    let app = TestApp::with_config(|config| config.enable_queue = false);
+    let uri = "/api/get?track_name=Hello&artist_name=Adele&album_name=25&duration=295";
+
+    for _ in 0..2 {
+      let response = app.get(uri).await;
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+      assert!(!response.headers().contains_key(X_QUEUE_STATUS));
+      assert_eq!(app.state.queue.len(), 0);
+    }
+  }
+
+  /// Caches the lookup of a track, then changes its lyrics behind the back of the cache. Returns
+  /// the URI of the lookup.
+  async fn cache_then_change_lyrics(app: &TestApp) -> &'static str {
+    let uri = "/api/get?track_name=Hello&artist_name=Adele";
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    assert_eq!(body_json(app.get(uri).await).await["plainLyrics"], "Hello, it's me");
+
+    let conn = app.state.pool.get().unwrap();
+    conn.execute("UPDATE lyrics SET plain_lyrics = 'Hello from the other side' WHERE track_id = ?", [track_id]).unwrap();
+    uri
+  }
+
+  /// The header only exists with the `debug-cache` feature
+  fn bypassing_cache(uri: &str) -> Request<Body> {
+    Request::get(uri).header("X-Cache-Bypass", "true").body(Body::empty()).unwrap()
+  }
+
+  #[cfg(feature = "debug-cache")]
+  #[tokio::test]
+  async fn cache_bypass_reads_the_database() {
+    let app = TestApp::new();
+    let uri = cache_then_change_lyrics(&app).await;
+
+    let response = app.send(bypassing_cache(uri)).await;
+    assert_eq!(response.headers()[X_CACHE], "MISS");
+    assert_eq!(body_json(response).await["plainLyrics"], "Hello from the other side");
+
+    // What was read from the database replaced the cached lookup
+    let response = app.get(uri).await;
+    assert_eq!(response.headers()[X_CACHE], "HIT");
+    assert_eq!(body_json(response).await["plainLyrics"], "Hello from the other side");
+  }
+
+  #[cfg(not(feature = "debug-cache"))]
+  #[tokio::test]
+  async fn cache_bypass_is_ignored_without_the_debug_cache_feature() {
+    let app = TestApp::new();
+    let uri = cache_then_change_lyrics(&app).await;
+
+    let response = app.send(bypassing_cache(uri)).await;
+    assert_eq!(response.headers()[X_CACHE], "HIT");
+    assert_eq!(body_json(response).await["plainLyrics"], "Hello, it's me");
+  }
+
+  /// Statements run by the connections `count_statements` was called on
+  static STATEMENTS: AtomicUsize = AtomicUsize::new(0);
+
+  fn count_statement(_sql: &str, _duration: Duration) {
+    STATEMENTS.fetch_add(1, Ordering::SeqCst);
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn concurrent_misses_of_the_same_lookup_query_the_database_once() {
+    let app = TestApp::with_config(|config| config.db_pool_size = Some(2));
+    app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    app.add_track("Skyfall", "Adele", Some("This is the end"), None);
+
+    // Holds every connection of the pool, so that the lookups all miss the cache before any of them
+    // gets to the database
+    let mut conns = [app.state.pool.get().unwrap(), app.state.pool.get().unwrap()];
+    for conn in &mut conns {
+      conn.profile(Some(count_statement));
+    }
+
+    let requests: Vec<_> = (0..10)
+      .map(|_| {
+        let router = app.router.clone();
+        tokio::spawn(router.oneshot(Request::get("/api/get?track_name=Hello&artist_name=Adele").body(Body::empty()).unwrap()))
+      })
+      .collect();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(conns);
+
+    for request in requests {
+      assert_eq!(request.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+    let coalesced_statements = STATEMENTS.swap(0, Ordering::SeqCst);
+
+    assert_eq!(app.get("/api/get?track_name=Skyfall&artist_name=Adele").await.status(), StatusCode::OK);
+    let single_lookup_statements = STATEMENTS.load(Ordering::SeqCst);
+    assert!(single_lookup_statements > 0);
+    assert_eq!(coalesced_statements, single_lookup_statements);
+  }
+
+  #[tokio::test]
+  async fn answers_in_msgpack_with_the_structure_of_the_json() {
+    let app = TestApp::new();
+    app.add_track("Hello", "Adele", Some("Hello, it's me"), Some("[00:01.00]Hello, it's me"));
+    let uri = "/api/get?track_name=Hello&artist_name=Adele";
+
+    // The JSON is cached first, so that the MessagePack ones are encoded from the cached track
+    let json = body_json(app.get(uri).await).await;
+
+    let accept = Request::get(uri).header(header::ACCEPT, MSGPACK_CONTENT_TYPE).body(Body::empty()).unwrap();
+    for response in [app.get(&format!("{}&format=msgpack", uri)).await, app.send(accept).await] {
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers()[header::CONTENT_TYPE], MSGPACK_CONTENT_TYPE);
+      assert_eq!(body_msgpack(response).await, json);
+    }
+  }
+}
@@ -1,9 +1,61 @@
-use axum::{extract::{Path, State}, Json};
-use serde::Serialize;
-use crate::{entities::track::SimpleTrack, errors::ApiError, repositories::track_repository::get_track_by_id, AppState};
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, HeaderValue}, response::Response};
+use serde::{Deserialize, Serialize};
+use crate::{
+  entities::track::SimpleTrack,
+  errors::ApiError,
+  repositories::track_repository::get_track_by_id,
+  routes::get_translations::{fetch_translations, TranslationResponse},
+  utils::{
+    bypasses_cache,
+    cache_status,
+    conditional_response,
+    fields::Fields,
+    format::{lyrics_text_response, subtitles_response, LyricsPreference, ResponseFormat, X_LYRICS_KIND},
+    jsonld::{MusicComposition, JSON_LD_CONTENT_TYPE},
+    language::{accepted_languages, best_language, is_language_tag},
+    lrc::{shift, strip_word_timings},
+    lyrics_content_hash,
+    lyrics_etag,
+    quality::LyricsQuality,
+    romanize::romanize,
+    variant_etag,
+    LYRICS_MAX_AGE,
+    X_CACHE,
+  },
+  AppState,
+};
 use std::sync::Arc;
+use chrono::{DateTime, Utc};
 
-#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// `json`, `lrc`, `srt` or `jsonld`, falling back to the Accept header
+  format: Option<String>,
+  romanize: Option<bool>,
+  /// Remove the enhanced LRC word timings, for players that only support line-level LRC
+  stripped: Option<bool>,
+  /// Comma-separated fields to return in JSON responses, instead of the whole track
+  fields: Option<String>,
+  /// Language of the translation to include, instead of the best one for `Accept-Language`
+  lang: Option<String>,
+  /// The kind of lyrics to return, `synced`, `plain` or `any` (the default)
+  prefer: Option<String>,
+  /// Return the other kind of lyrics when the preferred one is missing, instead of a 404. True by default.
+  fallback: Option<bool>,
+  /// Milliseconds to shift the synced lyrics by, later for a positive offset
+  offset: Option<i64>,
+}
+
+/// Largest timing offset, either way
+const MAX_OFFSET_MS: i64 = 60_000;
+
+#[derive(Serialize, Deserialize)]
+struct RomanizedLyrics {
+  plain_lyrics: Option<String>,
+  synced_lyrics: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TrackResponse {
   id: i64,
@@ -15,9 +67,60 @@ pub struct TrackResponse {
   instrumental: bool,
   plain_lyrics: Option<String>,
   synced_lyrics: Option<String>,
+  language: Option<String>,
+  /// Where the lyrics were taken from, as given by the publisher or the provider
+  source: Option<String>,
+  #[serde(flatten)]
+  quality: LyricsQuality,
+  /// The translation best matching the requested languages, if any
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  translation: Option<TranslationResponse>,
+}
+
+/// The JSON-LD of a track cached in `get_cache`, along with the names of the track so that it's
+/// invalidated like the tracks cached by id
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedJsonLd {
+  id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  etag: String,
+  #[serde(default)]
+  last_modified: Option<DateTime<Utc>>,
+  document: MusicComposition,
+}
+
+pub fn jsonld_cache_key(track_id: i64) -> String {
+  format!("track:{}:jsonld", track_id)
 }
 
-pub async fn route(Path(track_id): Path<i64>, State(state): State<Arc<AppState>>) -> Result<Json<TrackResponse>, ApiError> {
+pub async fn route(
+  Path(track_id): Path<i64>,
+  Query(params): Query<QueryParams>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  if wants_json_ld(params.format.as_deref(), &headers) {
+    return json_ld_response(track_id, &headers, &state).await;
+  }
+
+  let format = ResponseFormat::negotiate(params.format.as_deref(), &headers)?;
+  let preference = LyricsPreference::parse(params.prefer.as_deref())?;
+  format.check_preference(preference)?;
+
+  if let Some(lang) = params.lang.as_deref() {
+    if !is_language_tag(lang) {
+      return Err(ApiError::ValidationError("lang: must be a valid BCP-47 language tag".to_owned()));
+    }
+  }
+
+  let offset = params.offset.unwrap_or(0);
+  if !(-MAX_OFFSET_MS..=MAX_OFFSET_MS).contains(&offset) {
+    return Err(ApiError::ValidationError(format!("offset: must be between -{} and {} milliseconds", MAX_OFFSET_MS, MAX_OFFSET_MS)));
+  }
+
   let maybe_track = {
     let mut conn = state.pool.get()?;
     get_track_by_id(track_id, &mut conn)?
@@ -25,7 +128,97 @@ pub async fn route(Path(track_id): Path<i64>, State(state): State<Arc<AppState>>
 
   match maybe_track {
     Some(track) => {
-      Ok(Json(create_response(track)))
+      let romanize = params.romanize.unwrap_or(false);
+      let mut etag = lyrics_etag(track.id, track.last_lyrics.as_ref());
+      let last_modified = track.last_lyrics.as_ref().and_then(|lyrics| lyrics.updated_at);
+      let lyrics_id = track.last_lyrics.as_ref().and_then(|lyrics| lyrics.id);
+      let mut response = create_response(track);
+      // The track itself is always read from the database, only the romanized text is cached
+      let mut cache_hit = None;
+
+      if romanize {
+        etag = variant_etag(&etag, "romanized");
+        if let Some(lyrics_id) = lyrics_id {
+          let (romanized, hit) = romanize_lyrics(lyrics_id, &response, !bypasses_cache(&headers), &state).await?;
+          response.plain_lyrics = romanized.plain_lyrics;
+          response.synced_lyrics = romanized.synced_lyrics;
+          cache_hit = Some(hit);
+        }
+      }
+
+      if params.stripped.unwrap_or(false) {
+        etag = variant_etag(&etag, "stripped");
+        response.synced_lyrics = response.synced_lyrics.as_deref().map(strip_word_timings);
+      }
+
+      // Shifted on every request, so the server-side caches never hold a copy per offset
+      if offset != 0 {
+        etag = variant_etag(&etag, &format!("offset{}", offset));
+        response.synced_lyrics = response.synced_lyrics.as_deref().map(|synced_lyrics| shift(synced_lyrics, offset));
+      }
+
+      let lyrics_kind = preference.apply(
+        params.fallback.unwrap_or(true),
+        &mut response.plain_lyrics,
+        &mut response.synced_lyrics,
+        response.instrumental,
+      )?;
+      etag = preference.etag(&etag);
+
+      // Translations only fit in JSON responses
+      let accepted_languages = match (format.is_structured(), params.lang.as_deref()) {
+        (true, Some(lang)) => vec![lang.to_owned()],
+        (true, None) => headers
+          .get(header::ACCEPT_LANGUAGE)
+          .and_then(|value| value.to_str().ok())
+          .map(accepted_languages)
+          .unwrap_or_default(),
+        _ => Vec::new(),
+      };
+      if !accepted_languages.is_empty() {
+        response.translation = preferred_translation(track_id, response.language.as_deref(), &accepted_languages, &state).await?;
+        if let Some(translation) = response.translation.as_mut().filter(|_| offset != 0) {
+          translation.synced_lyrics = translation.synced_lyrics.as_deref().map(|synced_lyrics| shift(synced_lyrics, offset));
+        }
+        if let Some(translation) = &response.translation {
+          let content_hash = lyrics_content_hash(translation.plain_lyrics.as_deref(), translation.synced_lyrics.as_deref(), false);
+          etag = variant_etag(&etag, &format!("translation-{}", &content_hash[..16]));
+        }
+      }
+
+      let etag = format.etag(&etag);
+
+      let mut http_response = match format {
+        ResponseFormat::Json | ResponseFormat::MsgPack => match Fields::parse(params.fields.as_deref()) {
+          Some(fields) => {
+            let etag = variant_etag(&etag, &fields.etag_variant());
+            conditional_response(&headers, &etag, last_modified, LYRICS_MAX_AGE, format.structured_body(fields.select(&response)?))
+          },
+          None => conditional_response(&headers, &etag, last_modified, LYRICS_MAX_AGE, format.structured_body(response)),
+        },
+        ResponseFormat::Lrc => {
+          let body = lyrics_text_response(
+            response.synced_lyrics.as_deref(),
+            response.plain_lyrics.as_deref(),
+            response.instrumental,
+          );
+          conditional_response(&headers, &etag, last_modified, LYRICS_MAX_AGE, body)
+        },
+        ResponseFormat::Srt => {
+          let body = subtitles_response(response.synced_lyrics.as_deref(), response.instrumental)?;
+          conditional_response(&headers, &etag, last_modified, LYRICS_MAX_AGE, body)
+        },
+      };
+      if let Some(hit) = cache_hit {
+        http_response.headers_mut().insert(X_CACHE, cache_status(hit));
+      }
+      http_response.headers_mut().insert(X_LYRICS_KIND, lyrics_kind.header_value());
+      if format.is_structured() && params.lang.is_none() {
+        http_response.headers_mut().append(header::VARY, HeaderValue::from_static("accept-language"));
+      }
+      http_response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+
+      Ok(http_response)
     }
     None => {
       Err(ApiError::TrackNotFoundError)
@@ -33,7 +226,115 @@ pub async fn route(Path(track_id): Path<i64>, State(state): State<Arc<AppState>>
   }
 }
 
-fn create_response(track: SimpleTrack) -> TrackResponse {
+fn wants_json_ld(format: Option<&str>, request_headers: &HeaderMap) -> bool {
+  match format {
+    Some(format) => format == "jsonld",
+    None => request_headers
+      .get(header::ACCEPT)
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or_default()
+      .split(',')
+      .any(|media_type| media_type.split(';').next().unwrap_or_default().trim() == JSON_LD_CONTENT_TYPE),
+  }
+}
+
+/// The track as a schema.org document, for the structured data of web pages. It's a plain
+/// serialization of the stored lyrics, so the other parameters don't apply to it.
+async fn json_ld_response(track_id: i64, headers: &HeaderMap, state: &Arc<AppState>) -> Result<Response, ApiError> {
+  let cache_key = jsonld_cache_key(track_id);
+
+  let cached = match bypasses_cache(headers) {
+    true => None,
+    false => {
+      let cached = state.get_cache.get(&cache_key).await
+        .and_then(|cached| serde_json::from_str::<CachedJsonLd>(&cached).ok());
+      state.get_cache_metrics.record(cached.is_some());
+      cached
+    },
+  };
+  let cache_hit = cached.is_some();
+
+  let cached = match cached {
+    Some(cached) => cached,
+    None => {
+      let track = {
+        let mut conn = state.pool.get()?;
+        get_track_by_id(track_id, &mut conn)?.ok_or(ApiError::TrackNotFoundError)?
+      };
+      let cached = CachedJsonLd {
+        id: track.id,
+        track_name: track.name.to_owned(),
+        artist_name: track.artist_name.to_owned(),
+        album_name: track.album_name.to_owned(),
+        etag: variant_etag(&lyrics_etag(track.id, track.last_lyrics.as_ref()), "jsonld"),
+        last_modified: track.last_lyrics.as_ref().and_then(|lyrics| lyrics.updated_at),
+        document: MusicComposition::new(&track),
+      };
+      state.get_cache.insert(cache_key, serde_json::to_string(&cached)?).await;
+      cached
+    },
+  };
+
+  let body = (
+    [(header::CONTENT_TYPE, HeaderValue::from_static(JSON_LD_CONTENT_TYPE))],
+    serde_json::to_string(&cached.document)?,
+  );
+  let mut response = conditional_response(headers, &cached.etag, cached.last_modified, LYRICS_MAX_AGE, body);
+  response.headers_mut().insert(X_CACHE, cache_status(cache_hit));
+  response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+
+  Ok(response)
+}
+
+/// Returns the translation in the most preferred of the accepted languages, unless the lyrics are
+/// already in a more preferred one
+async fn preferred_translation(
+  track_id: i64,
+  lyrics_language: Option<&str>,
+  accepted_languages: &[String],
+  state: &Arc<AppState>,
+) -> Result<Option<TranslationResponse>, ApiError> {
+  let (translations, _) = fetch_translations(track_id, state).await?;
+
+  let available_languages: Vec<&str> = lyrics_language
+    .into_iter()
+    .chain(translations.iter().map(|translation| translation.language.as_str()))
+    .collect();
+  let Some(language) = best_language(accepted_languages, &available_languages) else {
+    return Ok(None);
+  };
+  if lyrics_language == Some(language) {
+    return Ok(None);
+  }
+
+  Ok(translations.iter().find(|translation| translation.language == language).cloned())
+}
+
+/// Returns the romanized lyrics, and whether they were read from the cache
+async fn romanize_lyrics(lyrics_id: i64, response: &TrackResponse, read_cache: bool, state: &Arc<AppState>) -> Result<(RomanizedLyrics, bool), ApiError> {
+  // Lyrics rows are never updated in place, so the romanized text can be cached per lyrics id
+  let cache_key = format!("lyrics:{}:romanized", lyrics_id);
+
+  if read_cache {
+    let cached_lyrics = state.get_cache.get(&cache_key).await
+      .and_then(|cached_lyrics| serde_json::from_str::<RomanizedLyrics>(&cached_lyrics).ok());
+    state.get_cache_metrics.record(cached_lyrics.is_some());
+
+    if let Some(romanized) = cached_lyrics {
+      return Ok((romanized, true));
+    }
+  }
+
+  let romanized = RomanizedLyrics {
+    plain_lyrics: response.plain_lyrics.as_deref().map(romanize),
+    synced_lyrics: response.synced_lyrics.as_deref().map(romanize),
+  };
+  state.get_cache.insert(cache_key, serde_json::to_string(&romanized)?).await;
+
+  Ok((romanized, false))
+}
+
+pub fn create_response(track: SimpleTrack) -> TrackResponse {
   let plain_lyrics = match track.last_lyrics {
     Some(ref lyrics) => lyrics.plain_lyrics.to_owned(),
     None => None
@@ -49,6 +350,16 @@ fn create_response(track: SimpleTrack) -> TrackResponse {
     None => false
   };
 
+  let language = match track.last_lyrics {
+    Some(ref lyrics) => lyrics.language.to_owned(),
+    None => None
+  };
+
+  let source = match track.last_lyrics {
+    Some(ref lyrics) => lyrics.attribution.to_owned(),
+    None => None
+  };
+
   TrackResponse {
     id: track.id,
     name: track.name.to_owned(),
@@ -56,8 +367,127 @@ fn create_response(track: SimpleTrack) -> TrackResponse {
     artist_name: track.artist_name.to_owned(),
     album_name: track.album_name.to_owned(),
     duration: track.duration,
+    quality: LyricsQuality::new(plain_lyrics.as_deref(), synced_lyrics.as_deref(), instrumental),
     instrumental,
     plain_lyrics,
     synced_lyrics,
+    language,
+    source,
+    translation: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{body::{to_bytes, Body}, http::{header, Request, StatusCode}};
+  use chrono::{DateTime, Duration};
+  use crate::{
+    test_utils::{body_json, body_msgpack, headers_of, TestApp},
+    utils::{format::MSGPACK_CONTENT_TYPE, http_date, jsonld::JSON_LD_CONTENT_TYPE, X_CACHE},
+  };
+  use super::jsonld_cache_key;
+
+  #[tokio::test]
+  async fn tells_which_kind_of_lyrics_was_returned() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+
+    let response = app.get(&format!("/api/get/{}?prefer=synced", track_id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Lyrics-Kind"], "plain");
+    assert_eq!(body_json(response).await["plainLyrics"], "Hello, it's me");
+
+    let response = app.get(&format!("/api/get/{}?prefer=synced&fallback=false", track_id)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(body_json(response).await["message"], "The track has no synced lyrics");
+  }
+
+  #[tokio::test]
+  async fn answers_304_until_the_lyrics_change() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let uri = format!("/api/get/{}", track_id);
+    let modified_since = |date: String| Request::get(&uri).header(header::IF_MODIFIED_SINCE, date).body(Body::empty()).unwrap();
+
+    let response = app.get(&uri).await;
+    let last_modified = response.headers()[header::LAST_MODIFIED].to_str().unwrap().to_owned();
+    let timestamp = DateTime::parse_from_rfc2822(&last_modified).unwrap().to_utc();
+
+    let response = app.send(modified_since(last_modified.clone())).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::LAST_MODIFIED], last_modified.as_str());
+
+    let response = app.send(modified_since(http_date(timestamp - Duration::seconds(1)))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["plainLyrics"], "Hello, it's me");
+  }
+
+  #[tokio::test]
+  async fn answers_in_msgpack_with_the_structure_of_the_json() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), Some("[00:01.00]Hello, it's me"));
+    let uri = format!("/api/get/{}", track_id);
+
+    let json = app.get(&uri).await;
+    let json_etag = json.headers()[header::ETAG].clone();
+    let json = body_json(json).await;
+
+    let accept = Request::get(&uri).header(header::ACCEPT, MSGPACK_CONTENT_TYPE).body(Body::empty()).unwrap();
+    for response in [app.get(&format!("{}?format=msgpack", uri)).await, app.send(accept).await] {
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers()[header::CONTENT_TYPE], MSGPACK_CONTENT_TYPE);
+      assert_ne!(response.headers()[header::ETAG], json_etag);
+      assert_eq!(body_msgpack(response).await, json);
+    }
+  }
+
+  #[tokio::test]
+  async fn head_answers_like_get_without_a_body() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), Some("[00:01.00]Hello, it's me"));
+
+    for (uri, status) in [(format!("/api/get/{}", track_id), StatusCode::OK), (format!("/api/get/{}", track_id + 1), StatusCode::NOT_FOUND)] {
+      // Cached by the first GET, so that both of the compared responses are cache hits
+      app.get(&uri).await;
+      let get = app.get(&uri).await;
+      let head = app.send(Request::head(&uri).body(Body::empty()).unwrap()).await;
+
+      assert_eq!(get.status(), status);
+      assert_eq!(head.status(), status);
+      assert_eq!(headers_of(&head), headers_of(&get), "{}", uri);
+      assert!(to_bytes(head.into_body(), usize::MAX).await.unwrap().is_empty());
+      if status == StatusCode::OK {
+        assert!(get.headers().contains_key(header::ETAG));
+        assert!(get.headers().contains_key(header::CACHE_CONTROL));
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn answers_json_ld_cached_apart_from_the_json() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let uri = format!("/api/get/{}", track_id);
+
+    let json = body_json(app.get(&uri).await).await;
+    assert_eq!(json["plainLyrics"], "Hello, it's me");
+    assert!(!app.state.get_cache.contains_key(&jsonld_cache_key(track_id)));
+
+    let accept = Request::get(&uri).header(header::ACCEPT, JSON_LD_CONTENT_TYPE).body(Body::empty()).unwrap();
+    for (response, cache_status) in [(app.get(&format!("{}?format=jsonld", uri)).await, "MISS"), (app.send(accept).await, "HIT")] {
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers()[header::CONTENT_TYPE], JSON_LD_CONTENT_TYPE);
+      assert_eq!(response.headers()[X_CACHE], cache_status);
+
+      let document = body_json(response).await;
+      assert_eq!(document["@context"], "https://schema.org");
+      assert_eq!(document["@type"], "MusicComposition");
+      assert_eq!(document["recordedAs"]["byArtist"]["name"], "Adele");
+      assert_eq!(document["lyrics"]["text"], "Hello, it's me");
+    }
+    assert!(app.state.get_cache.contains_key(&jsonld_cache_key(track_id)));
+
+    // The JSON is still the JSON once the JSON-LD is cached
+    assert_eq!(body_json(app.get(&uri).await).await, json);
   }
 }
@@ -0,0 +1,110 @@
+use axum::{extract::{Query, State}, http::HeaderMap, Json};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Arc};
+use crate::{
+  errors::ApiError,
+  repositories::track_repository::get_tracks_by_ids,
+  routes::get_lyrics_by_track_id::{create_response, TrackResponse},
+  utils::{bypasses_cache, fields::Fields},
+  AppState,
+};
+
+pub const MAX_IDS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// Comma-separated track ids
+  ids: String,
+  /// Comma-separated fields to return for each track, instead of the whole tracks
+  fields: Option<String>,
+}
+
+/// Looks up the tracks given as `?ids=1,2,3`
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<BTreeMap<i64, Value>>, ApiError> {
+  let track_ids = params.ids
+    .split(',')
+    .map(str::trim)
+    .filter(|id| !id.is_empty())
+    .map(|id| id.parse::<i64>().map_err(|_| ApiError::ValidationError(format!("ids: invalid track id {}", id))))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let fields = Fields::parse(params.fields.as_deref());
+  let tracks = lookup(track_ids, !bypasses_cache(&headers), &state).await?
+    .into_iter()
+    .map(|(track_id, track)| {
+      let track = match &fields {
+        Some(fields) => fields.select(&track)?,
+        None => serde_json::to_value(track)?,
+      };
+      Ok((track_id, track))
+    })
+    .collect::<Result<_, ApiError>>()?;
+
+  Ok(Json(tracks))
+}
+
+/// Looks up the tracks given as a JSON array of ids
+pub async fn post_route(
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  Json(track_ids): Json<Vec<i64>>,
+) -> Result<Json<BTreeMap<i64, TrackResponse>>, ApiError> {
+  lookup(track_ids, !bypasses_cache(&headers), &state).await.map(Json)
+}
+
+pub fn track_cache_key(track_id: i64) -> String {
+  format!("track:{}", track_id)
+}
+
+/// Returns the found tracks keyed by id. Tracks in `get_cache` are served from memory (unless
+/// `read_cache` is false), and all the others are read with a single query.
+async fn lookup(mut track_ids: Vec<i64>, read_cache: bool, state: &Arc<AppState>) -> Result<BTreeMap<i64, TrackResponse>, ApiError> {
+  track_ids.sort_unstable();
+  track_ids.dedup();
+
+  if track_ids.len() > MAX_IDS {
+    return Err(ApiError::ValidationError(format!("ids cannot contain more than {} track ids", MAX_IDS)));
+  }
+
+  let mut tracks = BTreeMap::new();
+  let mut missed_ids = Vec::new();
+
+  for track_id in track_ids {
+    if !read_cache {
+      missed_ids.push(track_id);
+      continue;
+    }
+
+    let cached_track = state.get_cache.get(&track_cache_key(track_id)).await
+      .and_then(|cached_track| serde_json::from_str::<TrackResponse>(&cached_track).ok());
+    state.get_cache_metrics.record(cached_track.is_some());
+
+    match cached_track {
+      Some(track) => { tracks.insert(track_id, track); },
+      None => missed_ids.push(track_id),
+    }
+  }
+
+  if missed_ids.is_empty() {
+    return Ok(tracks);
+  }
+
+  let found_tracks = {
+    let mut conn = state.pool.get()?;
+    get_tracks_by_ids(&missed_ids, &mut conn)?
+  };
+
+  for track in found_tracks {
+    let track_id = track.id;
+    let response = create_response(track);
+    state.get_cache.insert(track_cache_key(track_id), serde_json::to_string(&response)?).await;
+    tracks.insert(track_id, response);
+  }
+
+  Ok(tracks)
+}
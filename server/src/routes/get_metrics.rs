@@ -0,0 +1,141 @@
+use axum::{
+  extract::State,
+  http::header,
+  response::IntoResponse,
+};
+use std::sync::{atomic::Ordering, Arc};
+use crate::{db::SLOW_QUERIES, metrics::MetricsWriter, AppState};
+
+pub async fn route(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+  let mut writer = MetricsWriter::new();
+
+  writer.counter(
+    "lrclib_requests_total",
+    "Total number of HTTP requests received.",
+    state.request_counter.load(Ordering::Relaxed),
+  );
+  writer.gauge(
+    "lrclib_recent_lyrics_count",
+    "Number of lyrics published through LRCLIB in the last 10 minutes.",
+    state.recent_lyrics_count.load(Ordering::Relaxed),
+  );
+  writer.gauge(
+    "lrclib_queue_depth",
+    "Number of missing tracks waiting in the queue.",
+    state.queue.len(),
+  );
+  writer.counter(
+    "lrclib_queue_full_total",
+    "Total number of missing tracks that found the in-memory queue full, and were persisted to the database instead.",
+    state.queue_full_count.load(Ordering::Relaxed),
+  );
+  let breaker_states: Vec<(&str, usize)> = state.providers
+    .breaker_states()
+    .into_iter()
+    .map(|(provider, is_open)| (provider, is_open as usize))
+    .collect();
+  writer.labeled_gauges(
+    "lrclib_provider_circuit_open",
+    "Whether a lyrics provider is temporarily disabled after repeated failures (1) or called normally (0).",
+    "provider",
+    &breaker_states,
+  );
+  writer.gauge(
+    "lrclib_provider_fetches_in_flight",
+    "Number of lyrics provider fetches currently running.",
+    state.providers.in_flight(),
+  );
+  writer.histogram(
+    "lrclib_provider_fetch_wait_seconds",
+    "Time queue workers waited for a free provider fetch slot, in seconds.",
+    &state.providers.fetch_wait,
+  );
+  let pool_state = state.pool.state();
+  writer.gauge(
+    "lrclib_db_pool_size",
+    "Largest number of pooled database connections.",
+    state.pool.max_size() as usize,
+  );
+  writer.gauge(
+    "lrclib_db_pool_connections_in_use",
+    "Number of pooled database connections checked out by requests and workers.",
+    (pool_state.connections - pool_state.idle_connections) as usize,
+  );
+  writer.gauge(
+    "lrclib_db_pool_connections_idle",
+    "Number of open pooled database connections waiting to be checked out.",
+    pool_state.idle_connections as usize,
+  );
+  writer.counter(
+    "lrclib_db_pool_slow_waits_total",
+    "Total number of waits for a database connection longer than db_pool_slow_wait.",
+    state.pool_metrics.slow_waits.load(Ordering::Relaxed),
+  );
+  writer.counter(
+    "lrclib_db_pool_timeouts_total",
+    "Total number of waits for a database connection that timed out.",
+    state.pool_metrics.timeouts.load(Ordering::Relaxed),
+  );
+  writer.counter(
+    "lrclib_db_slow_queries_total",
+    "Total number of database queries slower than db_slow_query_threshold.",
+    SLOW_QUERIES.load(Ordering::Relaxed),
+  );
+  writer.cache_counters(&[
+    ("get", &state.get_cache_metrics),
+    ("search", &state.search_cache_metrics),
+    ("challenge", &state.challenge_cache_metrics),
+  ]);
+  let vacuum = &state.vacuum_metrics;
+  writer.gauge(
+    "lrclib_vacuum_last_run_timestamp_seconds",
+    "Unix time at which the last scheduled vacuum of the database finished, 0 before the first one.",
+    vacuum.last_run_at.load(Ordering::Relaxed) as usize,
+  );
+  writer.float_gauge(
+    "lrclib_vacuum_last_duration_seconds",
+    "Duration of the last scheduled vacuum of the database.",
+    vacuum.last_duration_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+  );
+  writer.gauge(
+    "lrclib_vacuum_last_reclaimed_pages",
+    "Free database pages reclaimed by the last scheduled vacuum.",
+    vacuum.last_reclaimed_pages.load(Ordering::Relaxed) as usize,
+  );
+  let track_filter = state.track_filter.stats();
+  writer.gauge(
+    "lrclib_track_filter_keys",
+    "Track names in the track filter, 0 before its first build.",
+    track_filter.keys,
+  );
+  writer.gauge(
+    "lrclib_track_filter_size_bytes",
+    "Memory taken by the bits of the track filter.",
+    track_filter.size_bytes,
+  );
+  writer.float_gauge(
+    "lrclib_track_filter_false_positive_rate",
+    "Estimated rate of the lookups for missing tracks that the track filter lets through to the database.",
+    track_filter.false_positive_rate,
+  );
+  writer.counter(
+    "lrclib_track_filter_negatives_total",
+    "Total number of lookups answered as missing by the track filter, without querying the database.",
+    state.track_filter.negatives.load(Ordering::Relaxed),
+  );
+  writer.gauge(
+    "lrclib_track_filter_last_rebuild_timestamp_seconds",
+    "Unix time at which the track filter was last rebuilt, 0 before the first build.",
+    state.track_filter.last_rebuild_at.load(Ordering::Relaxed) as usize,
+  );
+  writer.histogram(
+    "lrclib_request_duration_seconds",
+    "HTTP request latency in seconds.",
+    &state.request_latency,
+  );
+
+  (
+    [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+    writer.finish(),
+  )
+}
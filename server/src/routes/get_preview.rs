@@ -0,0 +1,239 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::{header, HeaderMap, HeaderValue},
+  response::Response,
+  Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use crate::{
+  entities::track::SimpleTrack,
+  errors::ApiError,
+  repositories::track_repository::get_track_by_id,
+  utils::{
+    bypasses_cache,
+    cache_status,
+    conditional_response,
+    lrc,
+    lyrics_etag,
+    quality::LyricsQuality,
+    variant_etag,
+    LYRICS_MAX_AGE,
+    X_CACHE,
+  },
+  AppState,
+};
+
+/// Most lines of lyrics in a snippet
+const SNIPPET_MAX_LINES: usize = 4;
+/// Longest snippet, in characters. Lines that don't fit are left out, only a first line that is
+/// too long on its own is cut.
+const SNIPPET_MAX_CHARS: usize = 200;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// `json` or `html`, falling back to the Accept header
+  format: Option<String>,
+}
+
+#[derive(PartialEq)]
+enum PreviewFormat {
+  Json,
+  /// OpenGraph meta tags, for the link unfurlers that only read HTML
+  Html,
+}
+
+impl PreviewFormat {
+  fn negotiate(format: Option<&str>, request_headers: &HeaderMap) -> Result<Self, ApiError> {
+    if let Some(format) = format {
+      return match format {
+        "json" => Ok(PreviewFormat::Json),
+        "html" => Ok(PreviewFormat::Html),
+        _ => Err(ApiError::ValidationError(format!("format: unsupported format {}, expected json or html", format))),
+      };
+    }
+
+    let accepts_html = request_headers
+      .get(header::ACCEPT)
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or_default()
+      .split(',')
+      .any(|media_type| media_type.split(';').next().unwrap_or_default().trim() == "text/html");
+    Ok(if accepts_html { PreviewFormat::Html } else { PreviewFormat::Json })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewResponse {
+  id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  duration: Option<f64>,
+  instrumental: bool,
+  /// The first lines of the lyrics, `None` for instrumental tracks
+  snippet: Option<String>,
+  #[serde(flatten)]
+  quality: LyricsQuality,
+}
+
+/// A preview cached in `get_cache`, along with the entity tag of its lyrics
+#[derive(Serialize, Deserialize)]
+struct CachedPreview {
+  etag: String,
+  #[serde(default)]
+  last_modified: Option<DateTime<Utc>>,
+  preview: PreviewResponse,
+}
+
+pub fn preview_cache_key(track_id: i64) -> String {
+  format!("preview:{}", track_id)
+}
+
+/// A small summary of a track, for link unfurling in chat apps and embeds
+pub async fn route(
+  Path(track_id): Path<i64>,
+  Query(params): Query<QueryParams>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  let format = PreviewFormat::negotiate(params.format.as_deref(), &headers)?;
+  let (cached, cache_hit) = fetch_preview(track_id, !bypasses_cache(&headers), &state).await?;
+
+  let mut response = match format {
+    PreviewFormat::Json => conditional_response(&headers, &cached.etag, cached.last_modified, LYRICS_MAX_AGE, Json(cached.preview)),
+    PreviewFormat::Html => {
+      let etag = variant_etag(&cached.etag, "html");
+      let body = ([(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))], render_html(&cached.preview));
+      conditional_response(&headers, &etag, cached.last_modified, LYRICS_MAX_AGE, body)
+    },
+  };
+  response.headers_mut().insert(X_CACHE, cache_status(cache_hit));
+  response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+
+  Ok(response)
+}
+
+/// Returns the preview of the track, and whether it was read from the cache
+async fn fetch_preview(track_id: i64, read_cache: bool, state: &Arc<AppState>) -> Result<(CachedPreview, bool), ApiError> {
+  let cache_key = preview_cache_key(track_id);
+
+  if read_cache {
+    let cached_preview = state.get_cache.get(&cache_key).await
+      .and_then(|cached_preview| serde_json::from_str::<CachedPreview>(&cached_preview).ok());
+    state.get_cache_metrics.record(cached_preview.is_some());
+
+    if let Some(cached_preview) = cached_preview {
+      return Ok((cached_preview, true));
+    }
+  }
+
+  let track = {
+    let mut conn = state.pool.get()?;
+    get_track_by_id(track_id, &mut conn)?.ok_or(ApiError::TrackNotFoundError)?
+  };
+
+  let cached_preview = CachedPreview {
+    etag: variant_etag(&lyrics_etag(track.id, track.last_lyrics.as_ref()), "preview"),
+    last_modified: track.last_lyrics.as_ref().and_then(|lyrics| lyrics.updated_at),
+    preview: create_response(track),
+  };
+  state.get_cache.insert(cache_key, serde_json::to_string(&cached_preview)?).await;
+
+  Ok((cached_preview, false))
+}
+
+fn create_response(track: SimpleTrack) -> PreviewResponse {
+  let (plain_lyrics, synced_lyrics, instrumental) = match &track.last_lyrics {
+    Some(lyrics) => (lyrics.plain_lyrics.as_deref(), lyrics.synced_lyrics.as_deref(), lyrics.instrumental),
+    None => (None, None, false),
+  };
+
+  let snippet = match instrumental {
+    true => None,
+    false => snippet(plain_lyrics, synced_lyrics),
+  };
+
+  PreviewResponse {
+    id: track.id,
+    track_name: track.name.to_owned(),
+    artist_name: track.artist_name.to_owned(),
+    album_name: track.album_name.to_owned(),
+    duration: track.duration,
+    instrumental,
+    snippet,
+    quality: LyricsQuality::new(plain_lyrics, synced_lyrics, instrumental),
+  }
+}
+
+/// The first lines with text of the plain lyrics, or of the synced ones without their timestamps
+fn snippet(plain_lyrics: Option<&str>, synced_lyrics: Option<&str>) -> Option<String> {
+  let lines: Vec<String> = match (plain_lyrics, synced_lyrics) {
+    (Some(plain_lyrics), _) => plain_lyrics.lines().map(|line| line.trim().to_owned()).collect(),
+    (None, Some(synced_lyrics)) => lrc::parse(synced_lyrics).ok()?.into_iter().map(|line| line.text.trim().to_owned()).collect(),
+    (None, None) => return None,
+  };
+
+  let mut snippet: Vec<String> = Vec::new();
+  let mut length = 0;
+  for line in lines.into_iter().filter(|line| !line.is_empty()).take(SNIPPET_MAX_LINES) {
+    let line_length = line.chars().count();
+    if snippet.is_empty() && line_length > SNIPPET_MAX_CHARS {
+      let cut: String = line.chars().take(SNIPPET_MAX_CHARS - 1).collect();
+      snippet.push(format!("{}…", cut.trim_end()));
+      break;
+    }
+    // Counting the newline before the line
+    if length + line_length + snippet.len() > SNIPPET_MAX_CHARS {
+      break;
+    }
+    length += line_length;
+    snippet.push(line);
+  }
+
+  match snippet.is_empty() {
+    true => None,
+    false => Some(snippet.join("\n")),
+  }
+}
+
+fn render_html(preview: &PreviewResponse) -> String {
+  let track_name = preview.track_name.as_deref().unwrap_or_default();
+  let title = match preview.artist_name.as_deref() {
+    Some(artist_name) => format!("{} - {}", track_name, artist_name),
+    None => track_name.to_owned(),
+  };
+  let description = match (&preview.snippet, preview.instrumental) {
+    (Some(snippet), _) => snippet.to_owned(),
+    (None, true) => "Instrumental".to_owned(),
+    (None, false) => String::new(),
+  };
+
+  let mut html = String::new();
+  html.push_str("<meta property=\"og:type\" content=\"music.song\">\n");
+  html.push_str(&format!("<meta property=\"og:title\" content=\"{}\">\n", escape_html(&title)));
+  html.push_str(&format!("<meta property=\"og:description\" content=\"{}\">\n", escape_html(&description)));
+  if let Some(duration) = preview.duration {
+    html.push_str(&format!("<meta property=\"music:duration\" content=\"{}\">\n", duration.round() as u64));
+  }
+  html.push_str("<meta name=\"twitter:card\" content=\"summary\">\n");
+  html.push_str(&format!("<title>{}</title>\n", escape_html(&title)));
+  html
+}
+
+fn escape_html(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for character in text.chars() {
+    match character {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      _ => escaped.push(character),
+    }
+  }
+  escaped
+}
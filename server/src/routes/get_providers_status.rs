@@ -0,0 +1,8 @@
+use axum::{extract::State, Json};
+use std::sync::Arc;
+use crate::{providers::ProviderStatus, AppState};
+
+/// Reports the health of each lyrics provider from the registry state, without any database access
+pub async fn route(State(state): State<Arc<AppState>>) -> Json<Vec<ProviderStatus>> {
+  Json(state.providers.statuses())
+}
@@ -0,0 +1,64 @@
+use axum::{
+  extract::{Query, State},
+  http::{header, HeaderValue},
+  response::{IntoResponse, Response},
+  Json,
+};
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::Arc;
+use crate::{
+  errors::ApiError,
+  repositories::track_repository::{get_next_track_with_lyrics, get_track_id_range},
+  routes::get_lyrics_by_track_id::create_response,
+  utils::fields::Fields,
+  AppState,
+};
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  /// Minimum track duration, in seconds
+  min_duration: Option<f64>,
+  synced_only: Option<bool>,
+  /// Comma-separated fields to return, instead of the whole track
+  fields: Option<String>,
+}
+
+/// Returns the lyrics of a random track. A random id is picked between the lowest and highest
+/// track ids, and the first matching track from there is returned, wrapping around to the start
+/// when the id falls after the last matching track. Gaps left by tracks without lyrics, or not
+/// matching the filters, make the tracks right after them more likely to be picked, which is fine
+/// for discovery and avoids sorting the whole table randomly.
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  let synced_only = params.synced_only.unwrap_or(false);
+
+  let maybe_track = {
+    let mut conn = state.pool.get()?;
+
+    match get_track_id_range(&mut conn)? {
+      Some((min_id, max_id)) => {
+        let from_id = rand::thread_rng().gen_range(min_id..=max_id);
+        match get_next_track_with_lyrics(from_id, params.min_duration, synced_only, &mut conn)? {
+          Some(track) => Some(track),
+          None => get_next_track_with_lyrics(min_id, params.min_duration, synced_only, &mut conn)?,
+        }
+      },
+      None => None,
+    }
+  };
+
+  match maybe_track {
+    Some(track) => {
+      let response = create_response(track);
+      let body = match Fields::parse(params.fields.as_deref()) {
+        Some(fields) => fields.select(&response)?,
+        None => serde_json::to_value(response)?,
+      };
+      Ok(([(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))], Json(body)).into_response())
+    },
+    None => Err(ApiError::TrackNotFoundError),
+  }
+}
@@ -0,0 +1,43 @@
+use axum::{extract::State, http::StatusCode};
+use std::{sync::Arc, time::Duration};
+use crate::{errors::ApiError, AppState};
+
+/// How long the readiness probe waits for a free connection before reporting the pool as exhausted
+const POOL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Readiness probe, checks that a SQLite connection can be obtained from the pool and queried
+pub async fn route(State(state): State<Arc<AppState>>) -> Result<StatusCode, ApiError> {
+  let conn = state.pool.get_timeout(POOL_TIMEOUT).map_err(|err| {
+    tracing::warn!(message = "readiness check failed to get a connection", error = err.to_string());
+    ApiError::ServiceUnavailableError
+  })?;
+
+  conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)).map_err(|err| {
+    tracing::warn!(message = "readiness check failed to query the database", error = err.to_string());
+    ApiError::ServiceUnavailableError
+  })?;
+
+  Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::http::StatusCode;
+  use crate::test_utils::TestApp;
+
+  #[tokio::test]
+  async fn ready_while_a_connection_can_be_queried() {
+    let app = TestApp::new();
+    assert_eq!(app.get("/api/ready").await.status(), StatusCode::OK);
+  }
+
+  #[tokio::test]
+  async fn unavailable_while_the_pool_has_no_connection_to_give() {
+    let app = TestApp::with_config(|config| config.db_pool_size = Some(1));
+    let _conn = app.state.pool.get().unwrap();
+
+    assert_eq!(app.get("/api/ready").await.status(), StatusCode::SERVICE_UNAVAILABLE);
+    // The liveness probe doesn't touch the database
+    assert_eq!(app.get("/api/health").await.status(), StatusCode::OK);
+  }
+}
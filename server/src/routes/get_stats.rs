@@ -0,0 +1,51 @@
+use axum::{extract::State, Json};
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::{atomic::Ordering, Arc};
+use crate::{entities::stats::LyricsStats, errors::ApiError, repositories::lyrics_repository::get_lyrics_stats, AppState};
+
+const STATS_CACHE_KEY: &str = "stats";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsResponse {
+  total_lyrics: i64,
+  synced_lyrics: i64,
+  plain_lyrics: i64,
+  last_24h_lyrics: i64,
+  queue_size: i64,
+}
+
+pub async fn route(State(state): State<Arc<AppState>>) -> Result<Json<StatsResponse>, ApiError> {
+  let cached_stats = state.stats_cache.get(STATS_CACHE_KEY).await
+    .and_then(|cached_stats| serde_json::from_str::<LyricsStats>(&cached_stats).ok());
+
+  let stats = match cached_stats {
+    Some(stats) => stats,
+    None => refresh_stats(&state).await?,
+  };
+
+  Ok(Json(StatsResponse {
+    total_lyrics: stats.total_lyrics,
+    synced_lyrics: stats.synced_lyrics,
+    plain_lyrics: stats.plain_lyrics,
+    last_24h_lyrics: stats.last_24h_lyrics,
+    // The persisted part of the queue backlog is cached with the other aggregates, the in-memory part is cheap to read
+    queue_size: stats.queued_tracks + state.queue.len() as i64,
+  }))
+}
+
+/// Computes the lyrics aggregates, caching them for the stats route and updating the recent lyrics
+/// count used by the metrics and the proof-of-work difficulty. Called every minute in the
+/// background, so the route usually reads the cached result.
+pub async fn refresh_stats(state: &Arc<AppState>) -> Result<LyricsStats> {
+  let stats = {
+    let mut conn = state.pool.get()?;
+    get_lyrics_stats(&mut conn)?
+  };
+
+  state.recent_lyrics_count.store(stats.last_10_mins_published_lyrics as usize, Ordering::Relaxed);
+  state.stats_cache.insert(STATS_CACHE_KEY.to_owned(), serde_json::to_string(&stats)?).await;
+
+  Ok(stats)
+}
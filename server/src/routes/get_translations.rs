@@ -0,0 +1,88 @@
+use axum::{extract::{Path, Query, State}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{
+  entities::translation::Translation,
+  errors::ApiError,
+  repositories::lyrics_repository::get_translations_by_track_id,
+  utils::{cache_status, language::is_language_tag, X_CACHE},
+  AppState,
+};
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  lang: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationResponse {
+  pub language: String,
+  pub synced_lyrics: Option<String>,
+  pub plain_lyrics: Option<String>,
+}
+
+pub fn translations_cache_key(track_id: i64) -> String {
+  format!("translations:{}", track_id)
+}
+
+pub async fn route(
+  Path(track_id): Path<i64>,
+  Query(params): Query<QueryParams>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  if let Some(lang) = params.lang.as_deref() {
+    if !is_language_tag(lang) {
+      return Err(ApiError::ValidationError("lang: must be a valid BCP-47 language tag".to_owned()));
+    }
+  }
+
+  let (translations, cache_hit) = fetch_translations(track_id, &state).await?;
+
+  let mut response = match params.lang {
+    Some(lang) => {
+      translations
+        .into_iter()
+        .find(|translation| translation.language.eq_ignore_ascii_case(&lang))
+        .map(|translation| Json(translation).into_response())
+        .ok_or(ApiError::TranslationNotFoundError)?
+    },
+    None => Json(translations).into_response(),
+  };
+  response.headers_mut().insert(X_CACHE, cache_status(cache_hit));
+
+  Ok(response)
+}
+
+/// Returns the translations of the track, and whether they were read from the cache
+pub async fn fetch_translations(track_id: i64, state: &Arc<AppState>) -> Result<(Vec<TranslationResponse>, bool), ApiError> {
+  let cache_key = translations_cache_key(track_id);
+
+  let cached_translations = state.get_cache.get(&cache_key).await
+    .and_then(|cached_translations| serde_json::from_str::<Vec<TranslationResponse>>(&cached_translations).ok());
+  state.get_cache_metrics.record(cached_translations.is_some());
+
+  if let Some(translations) = cached_translations {
+    return Ok((translations, true));
+  }
+
+  let translations = {
+    let mut conn = state.pool.get()?;
+    get_translations_by_track_id(track_id, &mut conn)?
+  };
+
+  let response = create_response(translations);
+  state.get_cache.insert(cache_key, serde_json::to_string(&response)?).await;
+
+  Ok((response, false))
+}
+
+fn create_response(translations: Vec<Translation>) -> Vec<TranslationResponse> {
+  translations.into_iter().map(
+    |translation| TranslationResponse {
+      language: translation.language,
+      synced_lyrics: translation.synced_lyrics,
+      plain_lyrics: translation.plain_lyrics,
+    }
+  ).collect()
+}
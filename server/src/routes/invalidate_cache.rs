@@ -0,0 +1,235 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::{
+  auth::is_operator,
+  errors::ApiError,
+  routes::{
+    get_lyrics_by_track_id::jsonld_cache_key, get_lyrics_by_track_ids::track_cache_key, get_preview::preview_cache_key,
+    get_translations::translations_cache_key,
+  },
+  utils::process_param,
+  AppState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidateRequest {
+  track_id: Option<i64>,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct InvalidateResponse {
+  evicted: usize,
+}
+
+/// The fields shared by every cached track, whichever route cached it
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedTrack {
+  id: i64,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+}
+
+/// A lookup cached by `get_lyrics_by_metadata`
+#[derive(Deserialize)]
+struct CachedLookup {
+  response: CachedTrack,
+}
+
+/// A preview cached by `get_preview`
+#[derive(Deserialize)]
+struct CachedPreview {
+  preview: CachedTrack,
+}
+
+/// A page of results cached by `search_lyrics`
+#[derive(Deserialize)]
+struct CachedSearch {
+  tracks: Vec<CachedTrack>,
+}
+
+enum Target {
+  TrackId(i64),
+  /// Canonical names, as returned by `process_param`
+  Metadata {
+    track_name: String,
+    artist_name: String,
+    album_name: Option<String>,
+  },
+}
+
+impl Target {
+  fn matches(&self, track: &CachedTrack) -> bool {
+    match self {
+      Target::TrackId(track_id) => track.id == *track_id,
+      Target::Metadata { track_name, artist_name, album_name } => {
+        process_param(track.track_name.as_deref()).as_ref() == Some(track_name)
+          && process_param(track.artist_name.as_deref()).as_ref() == Some(artist_name)
+          && album_name.as_ref().map_or(true, |album_name| process_param(track.album_name.as_deref()).as_ref() == Some(album_name))
+      },
+    }
+  }
+}
+
+/// Evicts the cached lookups and searches of a track right away, instead of letting stale lyrics
+/// linger until the caches expire them. The track is given by id, or by its metadata, in which case
+/// every cached track with the same names is evicted.
+pub async fn route(
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<InvalidateRequest>,
+) -> Result<Json<InvalidateResponse>, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let target = match (payload.track_id, process_param(payload.track_name.as_deref()), process_param(payload.artist_name.as_deref())) {
+    (Some(track_id), _, _) => Target::TrackId(track_id),
+    (None, Some(track_name), Some(artist_name)) => Target::Metadata {
+      track_name,
+      artist_name,
+      album_name: process_param(payload.album_name.as_deref()),
+    },
+    _ => return Err(ApiError::ValidationError("trackId: either trackId, or trackName and artistName must be given".to_owned())),
+  };
+
+  let evicted = evict(&state, target).await;
+  tracing::info!(message = "invalidated cached lyrics", evicted);
+
+  Ok(Json(InvalidateResponse { evicted }))
+}
+
+/// Evicts the cached lookups and searches of the track, returning the number of evicted entries
+pub async fn evict_track(state: &Arc<AppState>, track_id: i64) -> usize {
+  evict(state, Target::TrackId(track_id)).await
+}
+
+async fn evict(state: &Arc<AppState>, target: Target) -> usize {
+  let mut evicted = 0;
+
+  // The entries cached by id are removed by key, without reading any other entry
+  if let Target::TrackId(track_id) = target {
+    for key in [track_cache_key(track_id), preview_cache_key(track_id), jsonld_cache_key(track_id), translations_cache_key(track_id)] {
+      if state.get_cache.remove(&key).await.is_some() {
+        evicted += 1;
+      }
+    }
+  }
+
+  // Finding the lookups and searches that returned the track takes parsing every one of them, which
+  // is left to a blocking thread so that it doesn't hold up other requests
+  let get_cache = state.get_cache.clone();
+  let search_cache = state.search_cache.clone();
+  let stale_keys = tokio::task::spawn_blocking(move || {
+    let get_keys: Vec<Arc<String>> = get_cache
+      .iter()
+      .filter(|(key, value)| is_stale_lookup(key, value, &target))
+      .map(|(key, _)| key)
+      .collect();
+
+    let search_keys: Vec<Arc<String>> = search_cache
+      .iter()
+      .filter(|(_, value)| {
+        serde_json::from_str::<CachedSearch>(value).is_ok_and(|search| search.tracks.iter().any(|track| target.matches(track)))
+      })
+      .map(|(key, _)| key)
+      .collect();
+
+    (get_keys, search_keys)
+  }).await;
+
+  let (get_keys, search_keys) = match stale_keys {
+    Ok(stale_keys) => stale_keys,
+    Err(err) => {
+      tracing::error!(message = "failed to find the stale cached lookups", error = err.to_string());
+      return evicted;
+    },
+  };
+
+  for key in &get_keys {
+    state.get_cache.invalidate(key.as_str()).await;
+  }
+  for key in &search_keys {
+    state.search_cache.invalidate(key.as_str()).await;
+  }
+
+  evicted + get_keys.len() + search_keys.len()
+}
+
+fn is_stale_lookup(key: &str, value: &str, target: &Target) -> bool {
+  if key.starts_with("get:") {
+    return serde_json::from_str::<CachedLookup>(value).is_ok_and(|lookup| target.matches(&lookup.response));
+  }
+
+  // The flags cached by `get_exists` have the fields of the tracks
+  if key.starts_with("exists:") {
+    return serde_json::from_str::<CachedTrack>(value).is_ok_and(|track| target.matches(&track));
+  }
+
+  match target {
+    // Already removed by key
+    Target::TrackId(_) => false,
+    // The tracks and their JSON-LD have the fields of the tracks as well
+    Target::Metadata { .. } if key.starts_with("track:") => {
+      serde_json::from_str::<CachedTrack>(value).is_ok_and(|track| target.matches(&track))
+    },
+    Target::Metadata { .. } if key.starts_with("preview:") => {
+      serde_json::from_str::<CachedPreview>(value).is_ok_and(|cached| target.matches(&cached.preview))
+    },
+    Target::Metadata { .. } => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::http::StatusCode;
+  use crate::{
+    routes::{get_lyrics_by_track_ids::track_cache_key, get_translations::translations_cache_key},
+    test_utils::TestApp,
+  };
+  use super::{evict, evict_track, Target};
+
+  #[tokio::test]
+  async fn evicts_the_entries_of_the_track_only() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let other_track_id = app.add_track("Someone Like You", "Adele", Some("Never mind, I'll find someone like you"), None);
+    for uri in [
+      format!("/api/get/ids?ids={},{}", track_id, other_track_id),
+      format!("/api/get/{}/translations", track_id),
+      "/api/get?track_name=Hello&artist_name=Adele".to_owned(),
+      "/api/search?q=hello".to_owned(),
+      "/api/search?q=someone".to_owned(),
+    ] {
+      assert_eq!(app.get(&uri).await.status(), StatusCode::OK, "{}", uri);
+    }
+
+    // The track and its translations by id, its lookup and the search returning it
+    assert_eq!(evict_track(&app.state, track_id).await, 4);
+    assert!(!app.state.get_cache.contains_key(&track_cache_key(track_id)));
+    assert!(!app.state.get_cache.contains_key(&translations_cache_key(track_id)));
+    assert!(app.state.get_cache.contains_key(&track_cache_key(other_track_id)));
+    assert!(app.state.search_cache.contains_key("someone::::::::"));
+    assert_eq!(evict_track(&app.state, track_id).await, 0);
+  }
+
+  #[tokio::test]
+  async fn evicts_the_entries_of_the_tracks_with_the_same_names() {
+    let app = TestApp::new();
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    let other_track_id = app.add_track("Someone Like You", "Adele", Some("Never mind, I'll find someone like you"), None);
+    for uri in [format!("/api/get/ids?ids={},{}", track_id, other_track_id), "/api/search?q=hello".to_owned()] {
+      assert_eq!(app.get(&uri).await.status(), StatusCode::OK, "{}", uri);
+    }
+
+    let target = Target::Metadata { track_name: "hello".to_owned(), artist_name: "adele".to_owned(), album_name: None };
+    assert_eq!(evict(&app.state, target).await, 2);
+    assert!(app.state.get_cache.contains_key(&track_cache_key(other_track_id)));
+  }
+}
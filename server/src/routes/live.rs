@@ -0,0 +1,66 @@
+use axum::{
+  extract::{
+    ws::{Message, WebSocket, WebSocketUpgrade},
+    State,
+  },
+  response::Response,
+};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::sync::broadcast::error::RecvError;
+use crate::{errors::ApiError, AppState};
+
+/// Keeps a live feed connection counted for as long as it is alive
+struct ConnectionGuard {
+  state: Arc<AppState>,
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    self.state.live_connections.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+pub async fn route(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+  if state.live_connections.fetch_add(1, Ordering::Relaxed) >= state.live_max_connections {
+    state.live_connections.fetch_sub(1, Ordering::Relaxed);
+    return Err(ApiError::ServiceUnavailableError);
+  }
+  let guard = ConnectionGuard { state: state.clone() };
+
+  Ok(ws.on_upgrade(move |socket| stream_events(socket, guard)))
+}
+
+/// Forwards the published lyrics events to the client until it disconnects. A client that reads
+/// too slowly misses the events that were dropped from its channel, without slowing down publishes.
+async fn stream_events(mut socket: WebSocket, guard: ConnectionGuard) {
+  let mut receiver = guard.state.live_feed.subscribe();
+
+  loop {
+    tokio::select! {
+      event = receiver.recv() => {
+        let event = match event {
+          Ok(event) => event,
+          Err(RecvError::Lagged(skipped)) => {
+            tracing::debug!(message = "live feed subscriber lagged behind", skipped);
+            continue;
+          },
+          Err(RecvError::Closed) => break,
+        };
+
+        let Ok(text) = serde_json::to_string(&event) else {
+          continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+          break;
+        }
+      },
+      message = socket.recv() => {
+        // Messages from the client are ignored, except for the ones closing the connection
+        match message {
+          Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+          Some(Ok(_)) => {},
+        }
+      },
+    }
+  }
+}
@@ -1,25 +1,30 @@
 use anyhow::Result;
 use axum::{
-  extract::State,
+  extract::{ConnectInfo, Path, State},
   http::{
+    header,
     StatusCode,
     HeaderMap,
   },
   Json,
 };
-use rusqlite::Connection;
-use serde::Deserialize;
-use std::sync::Arc;
+use rusqlite::{Connection, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
 use crate::{
+  auth::bearer_claims,
+  entities::live_event::LiveEvent,
   errors::ApiError,
-  repositories::{lyrics_repository, track_repository},
-  utils::{strip_timestamp, is_valid_publish_token},
+  repositories::{lyrics_repository, retry_on_busy, track_repository},
+  routes::{get_lyrics_by_track_id::jsonld_cache_key, get_lyrics_by_track_ids::track_cache_key, get_preview::preview_cache_key},
+  utils::{language::{detect_language, is_language_tag}, lrc, lyrics_content_hash, client_id, lyrics_etag, matches_version, prepare_input, strip_timestamp, is_valid_publish_token},
   AppState
 };
 use axum_macros::debug_handler;
 use regex::Regex;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct PublishRequest {
     track_name: String,
@@ -28,50 +33,364 @@ pub struct PublishRequest {
     duration: f64,
     plain_lyrics: Option<String>,
     synced_lyrics: Option<String>,
+    /// Confirms that the track has no lyrics
+    #[serde(default)]
+    instrumental: bool,
+    /// ETag of the lyrics the edit is based on, like the `If-Match` header
+    base_version: Option<String>,
+    /// BCP-47 tag of the language of the lyrics, detected from the lyrics when not given
+    language: Option<String>,
+    /// URL of the page the lyrics were taken from, credited along with the lyrics
+    source: Option<String>,
+    /// Name to be credited under on the contributors leaderboard, anonymous when not given
+    contributor_handle: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchRequest {
+  edits: Vec<PatchEdit>,
+  /// ETag of the lyrics the edits are based on, like the `If-Match` header
+  base_version: Option<String>,
+  /// Like the handle of a publish
+  contributor_handle: Option<String>,
+}
+
+/// An edit of a line, located by `lineIndex` or `timestamp`. Without either, `newText` is inserted
+/// as a new line at `newTime`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PatchEdit {
+  /// 0-based index of the line among the synced lines, metadata and empty lines aside
+  line_index: Option<usize>,
+  /// Timestamp of the line, like `01:23.45`
+  timestamp: Option<String>,
+  new_text: Option<String>,
+  new_time: Option<String>,
+}
+
+impl PatchEdit {
+  fn to_lrc_edit(&self) -> Result<lrc::LrcEdit, String> {
+    let target = match (self.line_index, self.timestamp.as_deref()) {
+      (Some(_), Some(_)) => return Err("lineIndex and timestamp cannot be combined".to_owned()),
+      (Some(line_index), None) => lrc::LrcEditTarget::Index(line_index),
+      (None, Some(timestamp)) => lrc::LrcEditTarget::Timestamp(
+        lrc::parse_time(timestamp).ok_or_else(|| format!("timestamp: malformed timestamp {}", timestamp.trim()))?,
+      ),
+      (None, None) => lrc::LrcEditTarget::Insert,
+    };
+
+    Ok(lrc::LrcEdit {
+      target,
+      new_text: self.new_text.clone(),
+      new_time: self.new_time.clone(),
+    })
+  }
+}
+
+/// Most edits in a single patch, beyond which the lyrics are better published in full
+const MAX_PATCH_EDITS: usize = 500;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+const MAX_SOURCE_LENGTH: usize = 512;
+
+/// Track durations accepted, in seconds, like the lookups by metadata
+const MIN_DURATION: f64 = 1.0;
+const MAX_DURATION: f64 = 3600.0;
+
+const MAX_CONTRIBUTOR_HANDLE_LENGTH: usize = 32;
+
+/// Words that metadata echoes pad the names with, left out of the words counted by `check_metadata_echo`
+const METADATA_FILLER_WORDS: [&str; 7] = ["by", "lyrics", "song", "feat", "ft", "featuring", "official"];
+
+/// Who lyrics are credited to, see `lyrics_repository::get_leaderboard`
+struct Contributor<'a> {
+  /// Identifies the client like `client_id`, so that the publishes with the same API key or bearer
+  /// token, or else from the same IP, add up
+  id: String,
+  handle: Option<&'a str>,
+}
+
+impl<'a> Contributor<'a> {
+  fn new(id: String, handle: Option<&'a str>) -> Result<Self, ApiError> {
+    let handle = handle.map(str::trim);
+    if handle.is_some_and(|handle| !is_contributor_handle(handle)) {
+      return Err(ApiError::ValidationError(format!(
+        "contributorHandle: must be 2 to {} letters, digits, dots, dashes or underscores",
+        MAX_CONTRIBUTOR_HANDLE_LENGTH,
+      )));
+    }
+
+    Ok(Contributor { id, handle })
+  }
 }
 
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishResponse {
+  id: i64,
+  duplicate: bool,
+}
+
+enum PublishResult {
+  Created(i64),
+  Duplicate(i64),
+}
+
+impl PublishResult {
+  fn into_response(self) -> (StatusCode, Json<PublishResponse>) {
+    match self {
+      PublishResult::Created(id) => (StatusCode::CREATED, Json(PublishResponse { id, duplicate: false })),
+      PublishResult::Duplicate(id) => (StatusCode::OK, Json(PublishResponse { id, duplicate: true })),
+    }
+  }
+}
+
+/// A publish retried with the same `Idempotency-Key` header as a successful one gets the response of
+/// the first publish again, without storing anything. Keys are scoped to the client, identified like
+/// `client_id` by the API key or bearer token the server verified, or else by the IP. A key sent
+/// again with another request is refused, rather than answered with the response of the first one.
 #[debug_handler]
 pub async fn route(
   headers: HeaderMap,
   State(state): State<Arc<AppState>>,
+  // Not available on Unix domain sockets
+  connect_info: Option<ConnectInfo<SocketAddr>>,
   Json(payload): Json<PublishRequest>,
-) -> Result<StatusCode, ApiError> {
+) -> Result<(StatusCode, Json<PublishResponse>), ApiError> {
+  let client_id = client_id(&state, &headers, connect_info.map(|info| info.0)).await?;
+  let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+    Some(value) => {
+      let key = value
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+        .ok_or_else(|| ApiError::ValidationError(format!("Idempotency-Key: must be 1 to {} visible ASCII characters", MAX_IDEMPOTENCY_KEY_LENGTH)))?;
+      // Scoped to the client, so that clients can't replay each other's publishes by guessing their keys
+      Some(format!("{}:{}", client_id, key))
+    },
+    None => None,
+  };
+
+  // Replayed before anything else, as the proof-of-work token of the original request is used up
+  let request_hash = request_hash(&payload);
+  if let Some(idempotency_key) = &idempotency_key {
+    if let Some((first_request_hash, status, response)) = state.idempotency_cache.get(idempotency_key).await {
+      if first_request_hash != request_hash {
+        return Err(ApiError::IdempotencyKeyReusedError);
+      }
+      return Ok((status, Json(response)));
+    }
+  }
+
+  if !(MIN_DURATION..=MAX_DURATION).contains(&payload.duration) {
+    return Err(ApiError::ValidationError(format!("duration: must be between {} and {}", MIN_DURATION, MAX_DURATION)));
+  }
+
+  let has_lyrics = [&payload.plain_lyrics, &payload.synced_lyrics]
+    .iter()
+    .any(|lyrics| lyrics.as_deref().is_some_and(|lyrics| !lyrics.is_empty()));
+  if payload.instrumental && has_lyrics {
+    return Err(ApiError::ValidationError("instrumental: instrumental tracks cannot have lyrics".to_owned()));
+  }
+
+  if payload.language.as_deref().is_some_and(|language| !is_language_tag(language)) {
+    return Err(ApiError::ValidationError("language: must be a valid BCP-47 language tag".to_owned()));
+  }
+
+  if payload.source.as_deref().is_some_and(|source| !is_source_url(source.trim())) {
+    return Err(ApiError::ValidationError(format!("source: must be an http or https URL of at most {} characters", MAX_SOURCE_LENGTH)));
+  }
+
+  let contributor = Contributor::new(client_id, payload.contributor_handle.as_deref())?;
+
+  // Validated before the publish token is checked, so that the token is not used up by a failed publish
+  if let Some(synced_lyrics) = payload.synced_lyrics.as_deref().filter(|s| !s.is_empty()) {
+    lrc::validate(synced_lyrics, Some(payload.duration))
+      .map_err(|err| ApiError::ValidationError(format!("synced_lyrics: {}", err)))?;
+  }
+
+  // Placeholder and spam submissions are mostly a single short line, or just a URL
+  if !is_marked_instrumental(&payload) {
+    check_lyrics_length(&payload, state.publish_min_lines, state.publish_min_chars)?;
+    check_metadata_echo(&payload, state.publish_metadata_echo_threshold)?;
+  }
+
+  // Without a base version, the last publish wins
+  let base_version = headers
+    .get(header::IF_MATCH)
+    .and_then(|value| value.to_str().ok())
+    .or(payload.base_version.as_deref());
+
+  check_publish_token(&headers, &state).await?;
+  let result = publish(&payload, base_version, &contributor, &state).await?;
+
+  let (status, Json(response)) = result.into_response();
+  if let Some(idempotency_key) = idempotency_key {
+    state.idempotency_cache.insert(idempotency_key, (request_hash, status, response.clone())).await;
+  }
+
+  Ok((status, Json(response)))
+}
+
+/// Hashes the request, to tell a retry from another request sent with the same idempotency key. The
+/// parsed request is hashed, so that a retry encoded differently is still the same request.
+fn request_hash(payload: &PublishRequest) -> String {
+  let request = serde_json::to_vec(payload).expect("publish requests are serializable");
+  hex::encode(Sha256::digest(request))
+}
+
+/// Edits a few lines of the synced lyrics of a track, rather than publishing them again in full.
+/// The edits locate the lines in the current lyrics, so that version is required with `If-Match`
+/// (or `baseVersion`), and the edited lyrics are stored as a new version like any publish.
+#[debug_handler]
+pub async fn patch_route(
+  Path(track_id): Path<i64>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+  connect_info: Option<ConnectInfo<SocketAddr>>,
+  Json(payload): Json<PatchRequest>,
+) -> Result<(StatusCode, Json<PublishResponse>), ApiError> {
+  let base_version = headers
+    .get(header::IF_MATCH)
+    .and_then(|value| value.to_str().ok())
+    .or(payload.base_version.as_deref())
+    .ok_or_else(|| ApiError::ValidationError("If-Match: the version of the lyrics the edits are based on is required".to_owned()))?;
+
+  if payload.edits.is_empty() || payload.edits.len() > MAX_PATCH_EDITS {
+    return Err(ApiError::ValidationError(format!("edits: must have 1 to {} edits", MAX_PATCH_EDITS)));
+  }
+  let edits = payload.edits
+    .iter()
+    .enumerate()
+    .map(|(index, edit)| edit.to_lrc_edit().map_err(|message| ApiError::ValidationError(format!("edits[{}]: {}", index, message))))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let contributor = Contributor::new(
+    client_id(&state, &headers, connect_info.map(|info| info.0)).await?,
+    payload.contributor_handle.as_deref(),
+  )?;
+
+  let track = {
+    let mut conn = state.pool.get()?;
+    track_repository::get_track_by_id(track_id, &mut conn)?.ok_or(ApiError::TrackNotFoundError)?
+  };
+  if !matches_version(base_version, &lyrics_etag(track.id, track.last_lyrics.as_ref())) {
+    return Err(ApiError::VersionConflictError);
+  }
+  let synced_lyrics = track.last_lyrics
+    .as_ref()
+    .and_then(|lyrics| lyrics.synced_lyrics.as_deref())
+    .filter(|synced_lyrics| !synced_lyrics.is_empty())
+    .ok_or_else(|| ApiError::ValidationError("synced_lyrics: the track has no synced lyrics to edit".to_owned()))?;
+
+  // Validated before the publish token is checked, like a full publish
+  let synced_lyrics = lrc::apply_edits(synced_lyrics, &edits)
+    .map_err(|err| ApiError::ValidationError(err.to_string()))?;
+  let lines = lrc::validate(&synced_lyrics, track.duration)
+    .map_err(|err| ApiError::ValidationError(format!("synced_lyrics: {}", err)))?;
+  let plain_lyrics = lines.into_iter().map(|line| line.text).collect::<Vec<_>>().join("\n");
+
+  check_publish_token(&headers, &state).await?;
+
+  let result = {
+    let mut conn = state.pool.get()?;
+    retry_on_busy(|| patch_lyrics(track_id, base_version, &synced_lyrics, &plain_lyrics, &contributor, &mut conn)).await?
+  };
+  let track_name = track.name.as_deref().unwrap_or_default();
+  let artist_name = track.artist_name.as_deref().unwrap_or_default();
+  published(track_id, &result, track_name, artist_name, &state).await;
+
+  Ok(result.into_response())
+}
+
+async fn publish(payload: &PublishRequest, base_version: Option<&str>, contributor: &Contributor<'_>, state: &Arc<AppState>) -> Result<PublishResult, ApiError> {
+  let (track_id, result) = {
+    let mut conn = state.pool.get()?;
+    retry_on_busy(|| publish_lyrics(payload, base_version, contributor, &mut conn)).await?
+  };
+  state.track_filter.add_track(&prepare_input(&payload.track_name), &prepare_input(&payload.artist_name));
+  published(track_id, &result, payload.track_name.trim(), payload.artist_name.trim(), state).await;
+
+  Ok(result)
+}
+
+/// Trusted clients with a valid signed token skip the proof-of-work. An invalid or expired token is
+/// ignored, and the request then needs a solved challenge like any anonymous one.
+async fn check_publish_token(headers: &HeaderMap, state: &Arc<AppState>) -> Result<(), ApiError> {
+  if bearer_claims(headers, state.publish_token_secret.as_deref()).is_some() {
+    return Ok(());
+  }
+
   match headers.get("X-Publish-Token") {
     Some(publish_token) => {
-      let is_valid = is_valid_publish_token(publish_token.to_str()?, &state.challenge_cache).await;
+      let is_valid = is_valid_publish_token(publish_token.to_str()?, &state.challenge_cache, &state.challenge_cache_metrics).await;
 
-      if is_valid {
-        {
-          let mut conn = state.pool.get()?;
-          publish_lyrics(&payload, &mut conn)?;
-        }
-
-        Ok(StatusCode::CREATED)
-      } else {
-        Err(ApiError::IncorrectPublishTokenError)
+      match is_valid {
+        true => Ok(()),
+        false => Err(ApiError::IncorrectPublishTokenError),
       }
     },
-    None => Err(ApiError::IncorrectPublishTokenError)
+    None => Err(ApiError::IncorrectPublishTokenError),
+  }
+}
+
+/// Evicts the track from the caches, and announces new lyrics on the live feed
+async fn published(track_id: i64, result: &PublishResult, track_name: &str, artist_name: &str, state: &Arc<AppState>) {
+  // The track may be cached by id with its previous lyrics
+  state.get_cache.invalidate(&track_cache_key(track_id)).await;
+  state.get_cache.invalidate(&preview_cache_key(track_id)).await;
+  state.get_cache.invalidate(&jsonld_cache_key(track_id)).await;
+
+  if let PublishResult::Created(_) = result {
+    // Sending only fails when nobody is listening to the live feed
+    let _ = state.live_feed.send(LiveEvent {
+      id: track_id,
+      track_name: track_name.to_owned(),
+      artist_name: artist_name.to_owned(),
+    });
   }
 }
 
-fn publish_lyrics(payload: &PublishRequest, conn: &mut Connection) -> Result<()> {
+/// Stores the lyrics, returning the id of the track they were published to along with the result
+fn publish_lyrics(
+  payload: &PublishRequest,
+  base_version: Option<&str>,
+  contributor: &Contributor,
+  conn: &mut Connection,
+) -> Result<(i64, PublishResult), ApiError> {
   let mut tx = conn.transaction()?;
+  check_handle_owner(contributor, &mut tx)?;
 
   let existing_track = track_repository::get_track_id_by_metadata_tx(
-    &payload.track_name.trim(),
-    &payload.artist_name.trim(),
-    &payload.album_name.trim(),
+    payload.track_name.trim(),
+    payload.artist_name.trim(),
+    payload.album_name.trim(),
     payload.duration,
     &mut tx,
   )?;
 
+  // An edit based on other lyrics than the current ones would overwrite a newer correction
+  if let Some(base_version) = base_version {
+    let current_version = match existing_track {
+      Some(track_id) => Some(lyrics_etag(track_id, track_repository::get_last_lyrics_tx(track_id, &mut tx)?.as_ref())),
+      None => None,
+    };
+    if !current_version.is_some_and(|current_version| matches_version(base_version, &current_version)) {
+      return Err(ApiError::VersionConflictError);
+    }
+  }
+
   let track_id = match existing_track {
     Some(track_id) => track_id,
     None => track_repository::add_one_tx(
-      &payload.track_name.trim(),
-      &payload.artist_name.trim(),
-      &payload.album_name.trim(),
+      payload.track_name.trim(),
+      payload.artist_name.trim(),
+      payload.album_name.trim(),
       payload.duration,
       &mut tx,
     )?
@@ -82,35 +401,486 @@ fn publish_lyrics(payload: &PublishRequest, conn: &mut Connection) -> Result<()>
 
   // Generate plain_lyrics from synced_lyrics
   if plain_lyrics.is_none() && synced_lyrics.is_some() {
-    plain_lyrics = Some(strip_timestamp(synced_lyrics.as_deref().unwrap()));
+    plain_lyrics = Some(strip_timestamp(&lrc::strip_word_timings(synced_lyrics.as_deref().unwrap())));
+  }
+
+  let is_instrumental = is_marked_instrumental(payload);
+
+  // Instrumental tracks are stored without lyrics
+  let (plain_lyrics, synced_lyrics) = if is_instrumental { (None, None) } else { (plain_lyrics, synced_lyrics) };
+
+  // Publishing lyrics the track already has points the track to the existing lyrics instead of
+  // storing a duplicate
+  let content_hash = lyrics_content_hash(plain_lyrics.as_deref(), synced_lyrics.as_deref(), is_instrumental);
+  if let Some(lyrics_id) = lyrics_repository::get_id_by_content_hash_tx(track_id, &content_hash, &mut tx)? {
+    if track_repository::set_last_lyrics_id_tx(track_id, lyrics_id, &mut tx)? {
+      lyrics_repository::touch_tx(lyrics_id, &mut tx)?;
+    }
+    tx.commit()?;
+    return Ok((track_id, PublishResult::Duplicate(lyrics_id)));
+  }
+
+  let language = match is_instrumental {
+    true => None,
+    false => payload.language.clone().or_else(|| plain_lyrics.as_deref().and_then(detect_language)),
+  };
+
+  let lyrics_id = lyrics_repository::add_one_tx(
+    &plain_lyrics,
+    &synced_lyrics,
+    track_id,
+    is_instrumental,
+    &Some("lrclib".to_owned()),
+    language.as_deref(),
+    payload.source.as_deref().map(str::trim),
+    &mut tx,
+  )?;
+  lyrics_repository::set_contributor_tx(lyrics_id, &contributor.id, contributor.handle, &mut tx)?;
+
+  tx.commit()?;
+
+  Ok((track_id, PublishResult::Created(lyrics_id)))
+}
+
+/// Stores the edited synced lyrics as a new version of the lyrics of the track, unless they were
+/// changed since the version the edits are based on
+fn patch_lyrics(
+  track_id: i64,
+  base_version: &str,
+  synced_lyrics: &str,
+  plain_lyrics: &str,
+  contributor: &Contributor,
+  conn: &mut Connection,
+) -> Result<PublishResult, ApiError> {
+  let mut tx = conn.transaction()?;
+  check_handle_owner(contributor, &mut tx)?;
+
+  let current_lyrics = track_repository::get_last_lyrics_tx(track_id, &mut tx)?;
+  if !matches_version(base_version, &lyrics_etag(track_id, current_lyrics.as_ref())) {
+    return Err(ApiError::VersionConflictError);
+  }
+  let current_lyrics = current_lyrics.unwrap_or_default();
+
+  let plain_lyrics = Some(plain_lyrics.to_owned());
+  let synced_lyrics = Some(synced_lyrics.to_owned());
+
+  let content_hash = lyrics_content_hash(plain_lyrics.as_deref(), synced_lyrics.as_deref(), false);
+  if let Some(lyrics_id) = lyrics_repository::get_id_by_content_hash_tx(track_id, &content_hash, &mut tx)? {
+    if track_repository::set_last_lyrics_id_tx(track_id, lyrics_id, &mut tx)? {
+      lyrics_repository::touch_tx(lyrics_id, &mut tx)?;
+    }
+    tx.commit()?;
+    return Ok(PublishResult::Duplicate(lyrics_id));
+  }
+
+  // The edited lines don't change the language or where the lyrics were taken from
+  let lyrics_id = lyrics_repository::add_one_tx(
+    &plain_lyrics,
+    &synced_lyrics,
+    track_id,
+    false,
+    &Some("lrclib".to_owned()),
+    current_lyrics.language.as_deref(),
+    current_lyrics.attribution.as_deref(),
+    &mut tx,
+  )?;
+  lyrics_repository::set_contributor_tx(lyrics_id, &contributor.id, contributor.handle, &mut tx)?;
+
+  tx.commit()?;
+
+  Ok(PublishResult::Created(lyrics_id))
+}
+
+/// Rejects a handle another contributor published under first, so that a handle on the leaderboard
+/// is always the same contributor
+fn check_handle_owner(contributor: &Contributor, tx: &mut Transaction) -> Result<(), ApiError> {
+  let Some(handle) = contributor.handle else {
+    return Ok(());
+  };
+
+  match lyrics_repository::get_handle_owner_tx(handle, tx)? {
+    Some(owner) if owner != contributor.id => Err(ApiError::ValidationError("contributorHandle: already taken by another contributor".to_owned())),
+    _ => Ok(()),
   }
+}
+
+/// Whether the source is an absolute http(s) URL with a host, without whitespace or control characters
+fn is_source_url(source: &str) -> bool {
+  if source.len() > MAX_SOURCE_LENGTH || source.chars().any(|c| c.is_whitespace() || c.is_control()) {
+    return false;
+  }
+
+  let Some(rest) = source.strip_prefix("https://").or_else(|| source.strip_prefix("http://")) else {
+    return false;
+  };
+  let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+  let host = authority.rsplit('@').next().unwrap_or_default();
+  let host = match host.rsplit_once(':') {
+    Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+    _ => host,
+  };
 
+  !host.is_empty()
+    && !host.starts_with('.')
+    && !host.ends_with('.')
+    && host.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+}
+
+/// Whether the handle is short and only made of letters, digits, dots, dashes and underscores, so
+/// that it reads as a name and can't pass for a URL or markup
+fn is_contributor_handle(handle: &str) -> bool {
+  (2..=MAX_CONTRIBUTOR_HANDLE_LENGTH).contains(&handle.chars().count())
+    && handle.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+/// Whether the track is published as instrumental, with the flag or with a `[au: instrumental]` tag
+/// in the synced lyrics
+fn is_marked_instrumental(payload: &PublishRequest) -> bool {
   // Create a regex to match "[au: instrumental]" or "[au:instrumental]"
   let re = Regex::new(r"\[au:\s*instrumental\]").expect("Invalid regex");
-  let is_instrumental = synced_lyrics.as_ref().map_or(false, |lyrics| re.is_match(lyrics));
-
-  if is_instrumental {
-    // Mark the track as instrumental
-    lyrics_repository::add_one_tx(
-      &None,
-      &None,
-      track_id,
-      true,
-      &Some("lrclib".to_owned()),
-      &mut tx,
-    )?;
-  } else {
-    lyrics_repository::add_one_tx(
-      &plain_lyrics,
-      &synced_lyrics,
-      track_id,
-      false,
-      &Some("lrclib".to_owned()),
-      &mut tx,
-    )?;
+  payload.instrumental || payload.synced_lyrics.as_ref().is_some_and(|lyrics| re.is_match(lyrics))
+}
+
+/// Rejects lyrics with fewer lines or characters of text than the minimums. The plain lyrics are
+/// counted when given, otherwise the text of the synced lyrics, without their timestamps and tags.
+fn check_lyrics_length(payload: &PublishRequest, min_lines: usize, min_chars: usize) -> Result<(), ApiError> {
+  let (field, lines) = text_lines(payload);
+  let chars: usize = lines.iter().map(|line| line.chars().count()).sum();
+
+  if lines.len() < min_lines {
+    return Err(ApiError::ValidationError(format!(
+      "{}: lyrics must have at least {} lines of text, found {}. Publish the track as instrumental if it has no lyrics.",
+      field, min_lines, lines.len(),
+    )));
+  }
+  if chars < min_chars {
+    return Err(ApiError::ValidationError(format!(
+      "{}: lyrics must have at least {} characters of text, found {}. Publish the track as instrumental if it has no lyrics.",
+      field, min_chars, chars,
+    )));
   }
 
-  tx.commit()?;
+  Ok(())
+}
+
+/// Rejects lyrics made almost only of the words of the track and artist names, like the names
+/// repeated on every line. Songs whose lyrics genuinely repeat the title rarely mention the artist,
+/// so lyrics are only rejected when they also have a word of the artist name that isn't in the
+/// track name (or when the artist name has no such word).
+fn check_metadata_echo(payload: &PublishRequest, threshold: f64) -> Result<(), ApiError> {
+  if threshold == 0.0 {
+    return Ok(());
+  }
+
+  let (field, lines) = text_lines(payload);
+  let words: Vec<String> = lines
+    .iter()
+    .flat_map(|line| words_of(line))
+    .filter(|word| !METADATA_FILLER_WORDS.contains(&word.as_str()))
+    .collect();
+  if words.is_empty() {
+    return Ok(());
+  }
+
+  let track_words: HashSet<String> = words_of(&payload.track_name).into_iter().collect();
+  let artist_words: HashSet<String> = words_of(&payload.artist_name)
+    .into_iter()
+    .filter(|word| !track_words.contains(word))
+    .collect();
+
+  let echoed_words = words.iter().filter(|word| track_words.contains(*word) || artist_words.contains(*word)).count();
+  let mentions_artist = artist_words.is_empty() || words.iter().any(|word| artist_words.contains(word));
+
+  if mentions_artist && echoed_words as f64 >= threshold * words.len() as f64 {
+    return Err(ApiError::ValidationError(format!(
+      "{}: lyrics must be more than the track and artist names repeated. Publish the track as instrumental if it has no lyrics.",
+      field,
+    )));
+  }
 
   Ok(())
 }
+
+/// The non-empty lines of text of the lyrics, with the field they were taken from: the plain
+/// lyrics when given, otherwise the synced ones without their timestamps and tags
+fn text_lines(payload: &PublishRequest) -> (&'static str, Vec<String>) {
+  let plain_lyrics = payload.plain_lyrics.as_deref().filter(|s| !s.is_empty());
+  let synced_lyrics = payload.synced_lyrics.as_deref().filter(|s| !s.is_empty());
+
+  let (field, lines): (&str, Vec<String>) = match (plain_lyrics, synced_lyrics) {
+    (Some(plain_lyrics), _) => ("plain_lyrics", plain_lyrics.lines().map(|line| line.trim().to_owned()).collect()),
+    (None, Some(synced_lyrics)) => (
+      "synced_lyrics",
+      lrc::parse(synced_lyrics).unwrap_or_default().into_iter().map(|line| line.text.trim().to_owned()).collect(),
+    ),
+    (None, None) => ("plain_lyrics", Vec::new()),
+  };
+
+  (field, lines.into_iter().filter(|line| !line.is_empty()).collect())
+}
+
+/// The words of a text, normalized like the metadata of the lookups
+fn words_of(text: &str) -> Vec<String> {
+  prepare_input(text).split_whitespace().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::SocketAddr;
+  use axum::{body::Body, extract::ConnectInfo, http::{header, Request, StatusCode}, response::Response};
+  use serde_json::json;
+  use crate::test_utils::{body_json, TestApp};
+
+  #[tokio::test]
+  async fn rejects_durations_out_of_range_before_the_token_check() {
+    let app = TestApp::new();
+
+    for duration in [0.0, 3600.5, 1e300] {
+      let response = app.post_json("/api/publish", json!({
+        "trackName": "Hello",
+        "artistName": "Adele",
+        "albumName": "25",
+        "duration": duration,
+        "syncedLyrics": "[00:01.00]Hello, it's me\n[00:05.00]I was wondering",
+      })).await;
+
+      assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+      assert_eq!(body_json(response).await["message"], "duration: must be between 1 and 3600");
+    }
+  }
+
+  #[tokio::test]
+  async fn rejects_bodies_over_the_limit() {
+    let app = TestApp::with_config(|config| config.publish_body_limit = 1024);
+    let body = json!({
+      "trackName": "Hello",
+      "artistName": "Adele",
+      "albumName": "25",
+      "duration": 295.0,
+      "plainLyrics": "Hello, it's me\n".repeat(100),
+    }).to_string();
+
+    let with_length = Request::post("/api/publish")
+      .header(header::CONTENT_TYPE, "application/json")
+      .header(header::CONTENT_LENGTH, body.len())
+      .body(Body::from(body.clone()))
+      .unwrap();
+    assert_eq!(app.send(with_length).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // A body without a length is cut off while it's read
+    let streamed = Request::post("/api/publish")
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from_stream(futures::stream::iter([Ok::<_, std::io::Error>(body)])))
+      .unwrap();
+    assert_eq!(app.send(streamed).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Under the limit, the request goes on to the proof-of-work check
+    let response = app.post_json("/api/publish", json!({
+      "trackName": "Hello",
+      "artistName": "Adele",
+      "albumName": "25",
+      "duration": 295.0,
+      "plainLyrics": "Hello, it's me\nI was wondering",
+    })).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+  }
+
+  /// Publishes the lyrics without a token, returning the error: a failed validation, or else the
+  /// missing token
+  async fn publish_error(app: &TestApp, lyrics: serde_json::Value) -> String {
+    let mut payload = json!({
+      "trackName": "Skyfall",
+      "artistName": "Adele",
+      "albumName": "Skyfall",
+      "duration": 286.0,
+    });
+    payload.as_object_mut().unwrap().extend(lyrics.as_object().unwrap().clone());
+
+    let response = app.post_json("/api/publish", payload).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    body_json(response).await["message"].as_str().unwrap().to_owned()
+  }
+
+  const NO_TOKEN: &str = "The provided publish token is incorrect";
+
+  #[tokio::test]
+  async fn rejects_lyrics_under_the_minimum_lines_and_characters() {
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 2;
+      config.publish_min_chars = 20;
+    });
+
+    // Exactly at the minimums: 2 lines, 10 + 10 characters
+    assert_eq!(publish_error(&app, json!({ "plainLyrics": "This is th\ne end, hol" })).await, NO_TOKEN);
+    assert_eq!(
+      publish_error(&app, json!({ "plainLyrics": "This is the end, hold your breath and count to ten" })).await,
+      "plain_lyrics: lyrics must have at least 2 lines of text, found 1. Publish the track as instrumental if it has no lyrics.",
+    );
+    // Blank lines and the spacing around the text don't count
+    assert_eq!(
+      publish_error(&app, json!({ "plainLyrics": "  This is the end, hold your breath  \n\n   \n" })).await,
+      "plain_lyrics: lyrics must have at least 2 lines of text, found 1. Publish the track as instrumental if it has no lyrics.",
+    );
+    assert_eq!(
+      publish_error(&app, json!({ "plainLyrics": "This is th\ne end, ho" })).await,
+      "plain_lyrics: lyrics must have at least 20 characters of text, found 19. Publish the track as instrumental if it has no lyrics.",
+    );
+  }
+
+  #[tokio::test]
+  async fn counts_the_text_of_synced_lyrics_without_their_timestamps() {
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 2;
+      config.publish_min_chars = 20;
+    });
+
+    assert_eq!(publish_error(&app, json!({ "syncedLyrics": "[00:01.00]This is the end\n[00:04.00]Hold your breath" })).await, NO_TOKEN);
+    assert_eq!(
+      publish_error(&app, json!({ "syncedLyrics": "[ar:Adele]\n[00:01.00]This is\n[00:04.00]the end" })).await,
+      "synced_lyrics: lyrics must have at least 20 characters of text, found 14. Publish the track as instrumental if it has no lyrics.",
+    );
+  }
+
+  #[tokio::test]
+  async fn instrumental_tracks_and_disabled_checks_skip_the_minimums() {
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 2;
+      config.publish_min_chars = 20;
+    });
+    assert_eq!(publish_error(&app, json!({ "instrumental": true })).await, NO_TOKEN);
+    assert_eq!(publish_error(&app, json!({ "syncedLyrics": "[au: instrumental]" })).await, NO_TOKEN);
+
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 0;
+      config.publish_min_chars = 0;
+    });
+    assert_eq!(publish_error(&app, json!({ "plainLyrics": "Hush" })).await, NO_TOKEN);
+  }
+
+  const METADATA_ECHO: &str = "lyrics must be more than the track and artist names repeated. Publish the track as instrumental if it has no lyrics.";
+
+  #[tokio::test]
+  async fn rejects_lyrics_echoing_the_track_and_artist_names() {
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 0;
+      config.publish_min_chars = 0;
+      config.publish_metadata_echo_threshold = 0.5;
+    });
+
+    // The filler words around the names are not counted
+    assert_eq!(
+      publish_error(&app, json!({ "plainLyrics": "Skyfall by Adele\nSkyfall lyrics\nAdele Skyfall official song" })).await,
+      format!("plain_lyrics: {}", METADATA_ECHO),
+    );
+    assert_eq!(
+      publish_error(&app, json!({ "syncedLyrics": "[00:01.00]Skyfall\n[00:04.00]Adele" })).await,
+      format!("synced_lyrics: {}", METADATA_ECHO),
+    );
+
+    // 4 of the 8 words are the names, at the threshold, then 4 of 9 under it
+    assert_eq!(
+      publish_error(&app, json!({ "plainLyrics": "Skyfall Adele\nthis is the end\nSkyfall Adele" })).await,
+      format!("plain_lyrics: {}", METADATA_ECHO),
+    );
+    assert_eq!(publish_error(&app, json!({ "plainLyrics": "Skyfall Adele\nthis is the end now\nSkyfall Adele" })).await, NO_TOKEN);
+  }
+
+  #[tokio::test]
+  async fn accepts_lyrics_repeating_the_title_without_the_artist() {
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 0;
+      config.publish_min_chars = 0;
+      config.publish_metadata_echo_threshold = 0.5;
+    });
+
+    // Mostly the title, like a chorus, but the artist is never mentioned
+    assert_eq!(publish_error(&app, json!({ "plainLyrics": "Skyfall\nSkyfall\nLet the skyfall\nSkyfall" })).await, NO_TOKEN);
+
+    let app = TestApp::with_config(|config| {
+      config.publish_min_lines = 0;
+      config.publish_min_chars = 0;
+      config.publish_metadata_echo_threshold = 0.0;
+    });
+    assert_eq!(publish_error(&app, json!({ "plainLyrics": "Skyfall by Adele" })).await, NO_TOKEN);
+  }
+
+  /// Publishes lyrics to a track of its own from the IP, credited under the handle
+  async fn publish_from(app: &TestApp, ip: &str, track_name: &str, handle: &str) -> Response {
+    let mut request = Request::post("/api/publish")
+      .header(header::CONTENT_TYPE, "application/json")
+      .header("X-Publish-Token", app.publish_token().await)
+      .body(Body::from(json!({
+        "trackName": track_name,
+        "artistName": "Adele",
+        "albumName": "25",
+        "duration": 295.0,
+        "plainLyrics": "Hello, it's me\nI was wondering",
+        "contributorHandle": handle,
+      }).to_string()))
+      .unwrap();
+    request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 4000)));
+    app.send(request).await
+  }
+
+  #[tokio::test]
+  async fn reserves_a_handle_for_its_first_contributor() {
+    let app = TestApp::new();
+
+    assert_eq!(publish_from(&app, "203.0.113.1", "Hello", "alice").await.status(), StatusCode::CREATED);
+    let response = publish_from(&app, "203.0.113.2", "Skyfall", "Alice").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(body_json(response).await["message"], "contributorHandle: already taken by another contributor");
+
+    assert_eq!(publish_from(&app, "203.0.113.1", "Rolling in the Deep", "alice").await.status(), StatusCode::CREATED);
+    assert_eq!(publish_from(&app, "203.0.113.2", "Skyfall", "bob").await.status(), StatusCode::CREATED);
+
+    let leaderboard = body_json(app.get("/api/contributors/leaderboard?window=all").await).await;
+    assert_eq!(leaderboard["contributors"], json!([
+      { "rank": 1, "handle": "alice", "publishes": 2 },
+      { "rank": 2, "handle": "bob", "publishes": 1 },
+    ]));
+  }
+
+  /// Publishes the lyrics from the IP with the idempotency key and the `Authorization` header
+  async fn publish_with_key(app: &TestApp, ip: &str, authorization: &str, key: &str, plain_lyrics: &str) -> Response {
+    let mut request = Request::post("/api/publish")
+      .header(header::CONTENT_TYPE, "application/json")
+      .header(header::AUTHORIZATION, authorization)
+      .header("Idempotency-Key", key)
+      .header("X-Publish-Token", app.publish_token().await)
+      .body(Body::from(json!({
+        "trackName": "Hello",
+        "artistName": "Adele",
+        "albumName": "25",
+        "duration": 295.0,
+        "plainLyrics": plain_lyrics,
+      }).to_string()))
+      .unwrap();
+    request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 4000)));
+    app.send(request).await
+  }
+
+  #[tokio::test]
+  async fn replays_an_idempotency_key_to_the_same_request_only() {
+    let app = TestApp::new();
+    let lyrics = "Hello, it's me\nI was wondering";
+
+    let response = publish_with_key(&app, "203.0.113.1", "Bearer made-up", "key-1", lyrics).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let first = body_json(response).await;
+
+    // A made-up credential doesn't move the client to another scope
+    let response = publish_with_key(&app, "203.0.113.1", "Bearer made-up-again", "key-1", lyrics).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await, first);
+
+    let response = publish_with_key(&app, "203.0.113.1", "Bearer made-up", "key-1", "Hello from the other side\nI must have called").await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body_json(response).await["name"], "IdempotencyKeyReusedError");
+
+    // Another client's key is its own, so the same lyrics are published again as a duplicate
+    let response = publish_with_key(&app, "203.0.113.2", "Bearer made-up", "key-1", lyrics).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["duplicate"], true);
+  }
+}
@@ -3,14 +3,30 @@ use rand::{distributions::Alphanumeric, Rng};
 use serde::Serialize;
 use std::sync::{atomic::Ordering, Arc};
 use anyhow::Result;
-use crate::{errors::ApiError, AppState};
+use crate::{errors::ApiError, utils::pow::{Argon2Params, PowScheme}, AppState};
 use num_bigint::BigUint;
 
+/// A proof-of-work challenge. To solve it, find a nonce such that the digest of the solution, read
+/// as a big-endian number, is at most the target. With `sha256`, the digest is the SHA-256 of the
+/// prefix followed by the nonce. With `argon2id`, it's the 32-byte argon2id (version 0x13) hash of
+/// the nonce, salted with the prefix, using the given parameters. The solution is sent as the
+/// `X-Publish-Token` header, formatted as `{prefix}:{nonce}`.
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Challenge {
   prefix: String,
-  target: String
+  /// The largest accepted digest, as 64 uppercase hex digits
+  target: String,
+  /// `sha256` or `argon2id`
+  algorithm: &'static str,
+  /// Only for `argon2id`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  params: Option<Argon2Params>,
+  /// The number of leading zero bits of the target, which every accepted digest has too. The target
+  /// is the exact bound, a digest with this many leading zero bits can still be above it.
+  difficulty: u32,
+  /// Seconds before the challenge expires and its solution is rejected
+  expires_in: u64,
 }
 
 pub async fn route(
@@ -18,7 +34,12 @@ pub async fn route(
 ) -> Result<Json<Challenge>, ApiError> {
   let challenge = generate_challenge(&state).await?;
 
-  state.challenge_cache.insert(format!("challenge:{}", challenge.prefix), challenge.target.to_owned()).await;
+  // The scheme and target are stored alongside the prefix, so a solution is always verified against
+  // the algorithm and difficulty that were active when the challenge was issued
+  state.challenge_cache.insert(
+    format!("challenge:{}", challenge.prefix),
+    format!("{}:{}", state.pow_scheme.encode(), challenge.target),
+  ).await;
 
   Ok(Json(challenge))
 }
@@ -30,17 +51,29 @@ async fn generate_challenge(state: &Arc<AppState>) -> Result<Challenge> {
     .map(char::from)
     .collect();
   let last_10_mins_lyrics_count = state.recent_lyrics_count.load(Ordering::Relaxed);
-  let base_target = b"000000FF00000000000000000000000000000000000000000000000000000000".to_owned();
   let base_submit_count = 100;
-  let base_target_big_uint = BigUint::parse_bytes(&base_target, 16).unwrap();
+  // The least difficulty of argon2id challenges is set on its own, as its hashes are much slower
+  let max_target_big_uint = (BigUint::from(1u8) << (256 - state.min_pow_difficulty as usize)) - 1u8;
+  let base_target_big_uint = match state.pow_scheme {
+    PowScheme::Sha256 => BigUint::parse_bytes(b"000000FF00000000000000000000000000000000000000000000000000000000", 16).unwrap(),
+    PowScheme::Argon2id(_) => max_target_big_uint.clone(),
+  };
   let target_big_uint = if last_10_mins_lyrics_count > base_submit_count {
     base_target_big_uint * base_submit_count as u64 / last_10_mins_lyrics_count as u64
   } else {
     base_target_big_uint
   };
+  // Never hand out a target easier than the configured minimum difficulty
+  let target_big_uint = target_big_uint.min(max_target_big_uint);
   let target: String = format!("{:064X}", target_big_uint);
+  let difficulty = 256 - target_big_uint.bits() as u32;
+  let expires_in = state.challenge_cache.policy().time_to_live().map_or(0, |ttl| ttl.as_secs());
   Ok(Challenge {
     prefix,
-    target
+    target,
+    algorithm: state.pow_scheme.name(),
+    params: state.pow_scheme.argon2_params(),
+    difficulty,
+    expires_in,
   })
 }
@@ -1,12 +1,14 @@
-use axum::{extract::{Query, State}, Json};
+use axum::{extract::{Query, State}, http::{header, HeaderMap, HeaderValue}, response::Response};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::sync::Arc;
 use crate::{
   entities::track::SimpleTrack,
   errors::ApiError,
-  repositories::track_repository::get_tracks_by_keyword,
-  utils::process_param,
+  repositories::{lyrics_repository::search_fts, track_repository::{get_tracks_by_keyword, SearchFilters, SearchPage}},
+  utils::{bypasses_cache, cache_control, cache_status, fields::Fields, format::ResponseFormat, language::is_language_tag, process_param, quality::LyricsQuality, SEARCH_MAX_AGE, X_CACHE},
   AppState,
 };
 
@@ -17,6 +19,16 @@ pub struct QueryParams {
   track_name: Option<String>,
   artist_name: Option<String>,
   album_name: Option<String>,
+  /// Only return lyrics in this language, given as a BCP-47 tag
+  lang: Option<String>,
+  /// Only return tracks at least this long, in seconds
+  min_duration: Option<f64>,
+  /// Only return tracks at most this long, in seconds
+  max_duration: Option<f64>,
+  limit: Option<usize>,
+  cursor: Option<String>,
+  /// Comma-separated fields to return for each track, instead of the whole tracks
+  fields: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -31,77 +43,211 @@ pub struct TrackResponse {
   instrumental: bool,
   plain_lyrics: Option<String>,
   synced_lyrics: Option<String>,
+  language: Option<String>,
+  /// Where the lyrics were taken from, as given by the publisher or the provider
+  source: Option<String>,
+  #[serde(flatten)]
+  quality: LyricsQuality,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct CachedResult {
   tracks: Vec<TrackResponse>,
+  next_cursor: Option<String>,
   created_at: DateTime<Utc>,
 }
 
-pub async fn route(Query(params): Query<QueryParams>, State(state): State<Arc<AppState>>) -> Result<Json<Vec<TrackResponse>>, ApiError> {
-  let q = process_param(params.q.as_deref());
-  let track_name = process_param(params.track_name.as_deref());
-  let artist_name = process_param(params.artist_name.as_deref());
-  let album_name = process_param(params.album_name.as_deref());
+#[derive(Clone)]
+struct SearchQuery {
+  q: Option<String>,
+  track_name: Option<String>,
+  artist_name: Option<String>,
+  album_name: Option<String>,
+  lang: Option<String>,
+  min_duration: Option<f64>,
+  max_duration: Option<f64>,
+  cursor: Option<Cursor>,
+  limit: Option<usize>,
+}
+
+impl SearchQuery {
+  fn filters(&self) -> SearchFilters<'_> {
+    SearchFilters {
+      language: self.lang.as_deref(),
+      min_duration: self.min_duration,
+      max_duration: self.max_duration,
+    }
+  }
+}
+
+/// Keyword searches are ordered by relevance and paginated after the rank and id of the last
+/// returned track, while searches by track name are ordered by id and paginated after the last
+/// returned id
+#[derive(Clone, Copy)]
+enum Cursor {
+  AfterId(i64),
+  AfterRank(f64, i64),
+}
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+pub async fn route(Query(params): Query<QueryParams>, headers: HeaderMap, State(state): State<Arc<AppState>>) -> Result<(HeaderMap, Response), ApiError> {
+  for (name, value) in [
+    ("q", &params.q),
+    ("track_name", &params.track_name),
+    ("artist_name", &params.artist_name),
+    ("album_name", &params.album_name),
+  ] {
+    if value.as_deref().is_some_and(|value| value.trim().chars().count() > state.search_max_query_length) {
+      return Err(ApiError::ValidationError(
+        format!("{}: cannot be longer than {} characters", name, state.search_max_query_length),
+      ));
+    }
+  }
+
+  if params.lang.as_deref().is_some_and(|lang| !is_language_tag(lang)) {
+    return Err(ApiError::ValidationError("lang: must be a valid BCP-47 language tag".to_owned()));
+  }
+
+  for (name, value) in [("min_duration", params.min_duration), ("max_duration", params.max_duration)] {
+    if value.is_some_and(|value| !value.is_finite() || value < 0.0) {
+      return Err(ApiError::ValidationError(format!("{}: must be a positive number of seconds", name)));
+    }
+  }
+
+  if let (Some(min_duration), Some(max_duration)) = (params.min_duration, params.max_duration) {
+    if min_duration > max_duration {
+      return Err(ApiError::ValidationError("min_duration: cannot be greater than max_duration".to_owned()));
+    }
+  }
+
+  let fields = Fields::parse(params.fields.as_deref());
+  let format = ResponseFormat::negotiate_structured(&headers);
+  let is_paginated = params.limit.is_some() || params.cursor.is_some();
+  let cursor = params.cursor.as_deref().map(decode_cursor).transpose()?;
+
+  let search_query = SearchQuery {
+    q: process_param(params.q.as_deref()),
+    track_name: process_param(params.track_name.as_deref()),
+    artist_name: process_param(params.artist_name.as_deref()),
+    album_name: process_param(params.album_name.as_deref()),
+    lang: params.lang.as_deref().map(str::to_lowercase),
+    min_duration: params.min_duration,
+    max_duration: params.max_duration,
+    cursor,
+    limit: is_paginated.then(|| params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)),
+  };
+
+  // The parameters are already canonical (lowercased, trimmed, with collapsed whitespace), so equivalent
+  // queries share a cache key. A query left empty would match the whole table.
+  if search_query.q.is_none() && search_query.track_name.is_none() {
+    return Err(ApiError::ValidationError("q: either q or track_name must be given".to_owned()));
+  }
 
   // Generate a cache key based on query parameters
   let cache_key = format!(
-    "{}:{}:{}:{}",
-    q.as_deref().unwrap_or_default(),
-    track_name.as_deref().unwrap_or_default(),
-    artist_name.as_deref().unwrap_or_default(),
-    album_name.as_deref().unwrap_or_default()
+    "{}:{}:{}:{}:{}:{}:{}:{}:{}",
+    search_query.q.as_deref().unwrap_or_default(),
+    search_query.track_name.as_deref().unwrap_or_default(),
+    search_query.artist_name.as_deref().unwrap_or_default(),
+    search_query.album_name.as_deref().unwrap_or_default(),
+    search_query.lang.as_deref().unwrap_or_default(),
+    search_query.min_duration.map(|duration| duration.to_string()).unwrap_or_default(),
+    search_query.max_duration.map(|duration| duration.to_string()).unwrap_or_default(),
+    search_query.limit.map(|limit| limit.to_string()).unwrap_or_default(),
+    search_query.cursor.map(encode_cursor).unwrap_or_default(),
   );
 
-  let cached_result: Option<CachedResult> = {
-    let cached_result_str = state.search_cache.get(&cache_key).await;
-    if let Some(cached_result_str) = cached_result_str {
-      serde_json::from_str(&cached_result_str).ok()
-    } else {
-      None
-    }
+  let cached_result: Option<CachedResult> = if bypasses_cache(&headers) {
+    None
+  } else {
+    let cached_result = match state.search_cache.get(&cache_key).await {
+      Some(cached_result_str) => serde_json::from_str(&cached_result_str).ok(),
+      None => None,
+    };
+    state.search_cache_metrics.record(cached_result.is_some());
+    cached_result
   };
 
   if let Some(cached_result) = cached_result {
-    let tracks: Vec<TrackResponse> = cached_result.tracks.clone();
-
     let now = Utc::now();
     let created_at = cached_result.created_at;
 
     if (now - created_at).num_hours() >= 20 {
       let state_clone = Arc::clone(&state);
       let cache_key_clone = cache_key.clone();
-      let q_clone = q.clone();
-      let track_name_clone = track_name.clone();
-      let artist_name_clone = artist_name.clone();
-      let album_name_clone = album_name.clone();
+      let search_query_clone = search_query.clone();
 
       tokio::spawn(async move {
         let _ = fetch_and_cache_tracks(
           state_clone,
           cache_key_clone,
-          q_clone.as_deref(),
-          track_name_clone.as_deref(),
-          artist_name_clone.as_deref(),
-          album_name_clone.as_deref(),
+          &search_query_clone,
         ).await;
       });
     }
 
-    return Ok(Json(tracks));
+    return Ok((create_headers(cached_result.next_cursor.as_deref(), true), format.structured_body(select_fields(&cached_result.tracks, fields.as_ref())?)));
   }
 
-  let response = fetch_and_cache_tracks(
+  let (response, next_cursor) = fetch_and_cache_tracks(
     state,
     cache_key,
-    q.as_deref(),
-    track_name.as_deref(),
-    artist_name.as_deref(),
-    album_name.as_deref(),
+    &search_query,
   ).await?;
 
-  Ok(Json(response))
+  Ok((create_headers(next_cursor.as_deref(), false), format.structured_body(select_fields(&response, fields.as_ref())?)))
+}
+
+/// The tracks with only the requested fields, the cache keeps the whole tracks
+fn select_fields(tracks: &[TrackResponse], fields: Option<&Fields>) -> Result<Value, ApiError> {
+  let tracks = match fields {
+    Some(fields) => fields.select(tracks)?,
+    None => serde_json::to_value(tracks)?,
+  };
+  Ok(tracks)
+}
+
+fn encode_cursor(cursor: Cursor) -> String {
+  match cursor {
+    Cursor::AfterId(last_id) => URL_SAFE_NO_PAD.encode(format!("id:{}", last_id)),
+    // Floats are displayed with as many digits as parsing them back to the same rank takes
+    Cursor::AfterRank(score, last_id) => URL_SAFE_NO_PAD.encode(format!("rank:{}:{}", score, last_id)),
+  }
+}
+
+fn decode_cursor(cursor: &str) -> Result<Cursor, ApiError> {
+  URL_SAFE_NO_PAD.decode(cursor)
+    .ok()
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .and_then(|decoded| {
+      if let Some(id) = decoded.strip_prefix("id:") {
+        id.parse::<i64>().ok().map(Cursor::AfterId)
+      } else if let Some(rank) = decoded.strip_prefix("rank:") {
+        let (score, id) = rank.rsplit_once(':')?;
+        let score = score.parse::<f64>().ok().filter(|score| score.is_finite())?;
+        id.parse::<i64>().ok().map(|id| Cursor::AfterRank(score, id))
+      } else {
+        None
+      }
+    })
+    .ok_or_else(invalid_cursor)
+}
+
+fn invalid_cursor() -> ApiError {
+  ApiError::ValidationError("cursor: is invalid".to_owned())
+}
+
+fn create_headers(next_cursor: Option<&str>, cache_hit: bool) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  headers.insert(header::CACHE_CONTROL, cache_control(SEARCH_MAX_AGE));
+  headers.insert(X_CACHE, cache_status(cache_hit));
+  headers.insert(header::VARY, HeaderValue::from_static("accept"));
+  if let Some(value) = next_cursor.and_then(|next_cursor| HeaderValue::from_str(next_cursor).ok()) {
+    headers.insert("X-Next-Cursor", value);
+  }
+  headers
 }
 
 fn create_response(tracks: Vec<SimpleTrack>) -> Vec<TrackResponse> {
@@ -122,6 +268,16 @@ fn create_response(tracks: Vec<SimpleTrack>) -> Vec<TrackResponse> {
         None => false
       };
 
+      let language = match track.last_lyrics {
+        Some(ref lyrics) => lyrics.language.to_owned(),
+        None => None
+      };
+
+      let source = match track.last_lyrics {
+        Some(ref lyrics) => lyrics.attribution.to_owned(),
+        None => None
+      };
+
       TrackResponse {
         id: track.id,
         name: track.name.to_owned(),
@@ -129,9 +285,12 @@ fn create_response(tracks: Vec<SimpleTrack>) -> Vec<TrackResponse> {
         artist_name: track.artist_name.to_owned(),
         album_name: track.album_name.to_owned(),
         duration: track.duration,
+        quality: LyricsQuality::new(plain_lyrics.as_deref(), synced_lyrics.as_deref(), instrumental),
         instrumental,
         plain_lyrics,
         synced_lyrics,
+        language,
+        source,
       }
     }
   ).collect()
@@ -140,28 +299,140 @@ fn create_response(tracks: Vec<SimpleTrack>) -> Vec<TrackResponse> {
 async fn fetch_and_cache_tracks(
   state: Arc<AppState>,
   cache_key: String,
-  q: Option<&str>,
-  track_name: Option<&str>,
-  artist_name: Option<&str>,
-  album_name: Option<&str>,
-) -> Result<Vec<TrackResponse>, ApiError> {
+  search_query: &SearchQuery,
+) -> Result<(Vec<TrackResponse>, Option<String>), ApiError> {
   let mut conn = state.pool.get()?;
-  let tracks = get_tracks_by_keyword(
-      q,
-      track_name,
-      artist_name,
-      album_name,
-      &mut conn,
-  )?;
+
+  let (tracks, next_cursor) = match search_query.q.as_deref() {
+    Some(q) => {
+      let after = match search_query.cursor {
+        Some(Cursor::AfterRank(score, after_id)) => Some((score, after_id)),
+        Some(Cursor::AfterId(_)) => return Err(invalid_cursor()),
+        None => None,
+      };
+      let limit = search_query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+      // Fetch one extra row to find out whether there is a next page
+      let mut ranked_tracks = search_fts(q, &search_query.filters(), limit + 1, after, &mut conn)?;
+      let has_next_page = ranked_tracks.len() > limit;
+      ranked_tracks.truncate(limit);
+      let next_cursor = ranked_tracks.last()
+        .filter(|_| search_query.limit.is_some() && has_next_page)
+        .map(|(track, score)| encode_cursor(Cursor::AfterRank(*score, track.id)));
+      (ranked_tracks.into_iter().map(|(track, _)| track).collect(), next_cursor)
+    },
+    None => {
+      let after_id = match search_query.cursor {
+        Some(Cursor::AfterId(after_id)) => Some(after_id),
+        Some(Cursor::AfterRank(..)) => return Err(invalid_cursor()),
+        None => None,
+      };
+      let page = search_query.limit.map(|limit| SearchPage {
+        after_id,
+        // Fetch one extra row to find out whether there is a next page
+        limit: limit + 1,
+      });
+
+      let mut tracks = get_tracks_by_keyword(
+          None,
+          search_query.track_name.as_deref(),
+          search_query.artist_name.as_deref(),
+          search_query.album_name.as_deref(),
+          &search_query.filters(),
+          page.as_ref(),
+          &mut conn,
+      )?;
+
+      match search_query.limit {
+        Some(limit) if tracks.len() > limit => {
+          tracks.truncate(limit);
+          let next_cursor = tracks.last().map(|track| encode_cursor(Cursor::AfterId(track.id)));
+          (tracks, next_cursor)
+        },
+        _ => (tracks, None),
+      }
+    },
+  };
 
   let response = create_response(tracks);
 
   let cached_result = CachedResult {
       tracks: response.clone(),
+      next_cursor: next_cursor.clone(),
       created_at: Utc::now(),
   };
 
   state.search_cache.insert(cache_key, serde_json::to_string(&cached_result)?).await;
 
-  Ok(response)
+  Ok((response, next_cursor))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+  use axum::{body::Body, http::{header, Request, StatusCode}};
+  use crate::{test_utils::{body_json, body_msgpack, TestApp}, utils::format::MSGPACK_CONTENT_TYPE};
+
+  #[tokio::test]
+  async fn pages_through_keyword_searches_while_lyrics_are_published() {
+    let app = TestApp::new();
+    let mut expected_ids = HashSet::new();
+    for (index, name) in ["Cold Love", "Love Me Do", "Lovefool", "Love Story", "Crazy Little Thing", "Endless Love"].iter().enumerate() {
+      let lyrics = format!("Verse {} about love\nAnd more love", index);
+      expected_ids.insert(app.add_track(name, "Various", Some(&lyrics), None));
+    }
+
+    let mut seen_ids = Vec::new();
+    let mut uri = "/api/search?q=love&limit=2".to_owned();
+    loop {
+      let response = app.get(&uri).await;
+      assert_eq!(response.status(), StatusCode::OK);
+      let next_cursor = response.headers().get("X-Next-Cursor").map(|value| value.to_str().unwrap().to_owned());
+      let page = body_json(response).await;
+      seen_ids.extend(page.as_array().unwrap().iter().map(|track| track["id"].as_i64().unwrap()));
+
+      // A new match ranked first would shift every later page of an offset pagination
+      if seen_ids.len() == 2 {
+        app.add_track("Love Love Love", "Various", Some("Love, love, love"), None);
+      }
+
+      match next_cursor {
+        Some(next_cursor) => uri = format!("/api/search?q=love&limit=2&cursor={}", next_cursor),
+        None => break,
+      }
+    }
+
+    let unique_ids: HashSet<i64> = seen_ids.iter().copied().collect();
+    assert_eq!(unique_ids.len(), seen_ids.len(), "duplicated tracks in {:?}", seen_ids);
+    assert!(expected_ids.is_subset(&unique_ids), "skipped tracks in {:?}", seen_ids);
+  }
+
+  #[tokio::test]
+  async fn rejects_cursors_of_the_other_kind_of_search() {
+    let app = TestApp::new();
+    for name in ["Love Story", "Endless Love"] {
+      app.add_track(name, "Various", Some("love"), None);
+    }
+
+    let response = app.get("/api/search?q=love&limit=1").await;
+    let next_cursor = response.headers().get("X-Next-Cursor").unwrap().to_str().unwrap().to_owned();
+    let response = app.get(&format!("/api/search?track_name=love&limit=1&cursor={}", next_cursor)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[tokio::test]
+  async fn answers_in_msgpack_with_the_structure_of_the_json() {
+    let app = TestApp::new();
+    app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+    app.add_track("Hello", "Lionel Richie", None, Some("[00:01.00]Hello, is it me"));
+    let uri = "/api/search?track_name=Hello";
+
+    let json = body_json(app.get(uri).await).await;
+    assert_eq!(json.as_array().map(Vec::len), Some(2));
+
+    let response = app.send(Request::get(uri).header(header::ACCEPT, MSGPACK_CONTENT_TYPE).body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], MSGPACK_CONTENT_TYPE);
+    assert_eq!(body_msgpack(response).await, json);
+  }
 }
@@ -0,0 +1,122 @@
+use axum::{
+  body::{Body, Bytes},
+  extract::{Query, State},
+  http::{header, HeaderMap},
+  response::{IntoResponse, Response},
+};
+use futures::stream;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::{
+  auth::is_operator,
+  db::open_read_only,
+  errors::ApiError,
+  repositories::{lyrics_repository::search_fts_each, track_repository::SearchFilters},
+  routes::get_lyrics_by_track_id::create_response,
+  utils::{language::is_language_tag, process_param},
+  AppState,
+};
+
+/// Tracks serialized into each chunk of the response
+const STREAM_CHUNK_SIZE: usize = 100;
+/// Chunks buffered ahead of a slow client, after which reading the database waits for it
+const STREAM_BUFFERED_CHUNKS: usize = 4;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+  q: Option<String>,
+  /// Only return lyrics in this language, given as a BCP-47 tag
+  lang: Option<String>,
+  /// Only return tracks at least this long, in seconds
+  min_duration: Option<f64>,
+  /// Only return tracks at most this long, in seconds
+  max_duration: Option<f64>,
+}
+
+/// Streams every track matching the query as newline-delimited JSON, for internal tools that need
+/// all the matches of a broad query. The matches are returned in track id order rather than by
+/// relevance, and at most `search_stream_max_rows` of them.
+///
+/// Unlike `/api/search`, the results are never cached, and the query runs on a read-only
+/// connection opened for the stream alone, on a blocking thread. That connection stays open until
+/// the last match is sent, so the pool connections remain free for the other requests, and the
+/// stream reads a consistent snapshot of the database. The query is only read as fast as the client
+/// receives the response.
+pub async fn route(
+  Query(params): Query<QueryParams>,
+  headers: HeaderMap,
+  State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+  if !is_operator(&headers, state.publish_token_secret.as_deref()) {
+    return Err(ApiError::UnauthorizedError);
+  }
+
+  let q = process_param(params.q.as_deref())
+    .ok_or_else(|| ApiError::ValidationError("q: cannot be empty".to_owned()))?;
+  if q.chars().count() > state.search_max_query_length {
+    return Err(ApiError::ValidationError(format!("q: cannot be longer than {} characters", state.search_max_query_length)));
+  }
+  if params.lang.as_deref().is_some_and(|lang| !is_language_tag(lang)) {
+    return Err(ApiError::ValidationError("lang: must be a valid BCP-47 language tag".to_owned()));
+  }
+  for (name, value) in [("min_duration", params.min_duration), ("max_duration", params.max_duration)] {
+    if value.is_some_and(|value| !value.is_finite() || value < 0.0) {
+      return Err(ApiError::ValidationError(format!("{}: must be a positive number of seconds", name)));
+    }
+  }
+
+  // Opened before responding, so that a database that can't be opened is an error response
+  let mut conn = open_read_only(&state.database, state.db_busy_timeout)?;
+  let (sender, receiver) = mpsc::channel::<anyhow::Result<Bytes>>(STREAM_BUFFERED_CHUNKS);
+  let max_rows = state.search_stream_max_rows;
+  let lang = params.lang.map(|lang| lang.to_lowercase());
+
+  tokio::task::spawn_blocking(move || {
+    let filters = SearchFilters {
+      language: lang.as_deref(),
+      min_duration: params.min_duration,
+      max_duration: params.max_duration,
+    };
+    let mut chunk = Vec::new();
+    let mut lines = 0;
+
+    let mut serialize_error = None;
+
+    // Sending only fails once the client went away, which stops the query
+    let result = search_fts_each(&q, &filters, max_rows, &mut conn, |track| {
+      if let Err(err) = serde_json::to_writer(&mut chunk, &create_response(track)) {
+        serialize_error = Some(err);
+        return false;
+      }
+      chunk.push(b'\n');
+      lines += 1;
+
+      if lines < STREAM_CHUNK_SIZE {
+        return true;
+      }
+      lines = 0;
+      sender.blocking_send(Ok(Bytes::from(std::mem::take(&mut chunk)))).is_ok()
+    });
+
+    // An error aborts the response, so that the client doesn't take it for the complete results
+    let last_chunk = match (result, serialize_error) {
+      (Ok(_), None) => Ok(Bytes::from(chunk)),
+      (Err(err), _) => Err(err),
+      (_, Some(err)) => Err(err.into()),
+    };
+    if let Err(err) = &last_chunk {
+      tracing::error!(message = "search stream failed", error = err.to_string());
+    }
+    let _ = sender.blocking_send(last_chunk);
+  });
+
+  let chunks = stream::unfold(receiver, |mut receiver| async move {
+    receiver.recv().await.map(|chunk| (chunk, receiver))
+  });
+
+  Ok((
+    [(header::CONTENT_TYPE, "application/x-ndjson")],
+    Body::from_stream(chunks),
+  ).into_response())
+}
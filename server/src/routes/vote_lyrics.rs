@@ -0,0 +1,143 @@
+use axum::{
+  extract::{ConnectInfo, Path, State},
+  http::{HeaderMap, StatusCode},
+  Json,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use crate::{
+  entities::vote::Vote,
+  errors::ApiError,
+  repositories::{lyrics_repository, track_repository, vote_repository},
+  utils::{client_id, is_valid_publish_token},
+  AppState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoteRequest {
+  lyrics_id: Option<i64>,
+  /// Votes on the current lyrics of the track
+  track_id: Option<i64>,
+  vote: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VotesResponse {
+  lyrics_id: i64,
+  upvotes: i64,
+  downvotes: i64,
+  score: i64,
+}
+
+pub async fn route(
+  headers: HeaderMap,
+  connect_info: Option<ConnectInfo<SocketAddr>>,
+  State(state): State<Arc<AppState>>,
+  Json(payload): Json<VoteRequest>,
+) -> Result<(StatusCode, Json<VotesResponse>), ApiError> {
+  let vote = payload.vote.as_deref().and_then(Vote::parse)
+    .ok_or_else(|| ApiError::ValidationError("vote: must be one of up, down".to_owned()))?;
+
+  let publish_token = headers.get("X-Publish-Token").ok_or(ApiError::IncorrectPublishTokenError)?;
+  if !is_valid_publish_token(publish_token.to_str()?, &state.challenge_cache, &state.challenge_cache_metrics).await {
+    return Err(ApiError::IncorrectPublishTokenError);
+  }
+
+  let voter = client_id(&state, &headers, connect_info.map(|ConnectInfo(addr)| addr)).await?;
+
+  let mut conn = state.pool.get()?;
+  let lyrics_id = match (payload.lyrics_id, payload.track_id) {
+    (Some(lyrics_id), _) => {
+      if !lyrics_repository::exists(lyrics_id, &mut conn)? {
+        return Err(ApiError::TrackNotFoundError);
+      }
+      lyrics_id
+    },
+    (None, Some(track_id)) => track_repository::get_track_by_id(track_id, &mut conn)?
+      .and_then(|track| track.last_lyrics.and_then(|lyrics| lyrics.id))
+      .ok_or(ApiError::TrackNotFoundError)?,
+    (None, None) => return Err(ApiError::ValidationError("lyricsId: either lyricsId or trackId must be given".to_owned())),
+  };
+
+  // A client voting again on the same lyrics changes its vote
+  vote_repository::add_or_replace(lyrics_id, &voter, vote, &mut conn)?;
+
+  Ok((StatusCode::CREATED, Json(votes_response(lyrics_id, &mut conn)?)))
+}
+
+pub async fn get_route(
+  Path(lyrics_id): Path<i64>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<VotesResponse>, ApiError> {
+  let mut conn = state.pool.get()?;
+  if !lyrics_repository::exists(lyrics_id, &mut conn)? {
+    return Err(ApiError::TrackNotFoundError);
+  }
+
+  Ok(Json(votes_response(lyrics_id, &mut conn)?))
+}
+
+/// The votes of the current lyrics of a track
+pub async fn track_route(
+  Path(track_id): Path<i64>,
+  State(state): State<Arc<AppState>>,
+) -> Result<Json<VotesResponse>, ApiError> {
+  let mut conn = state.pool.get()?;
+  let lyrics_id = track_repository::get_track_by_id(track_id, &mut conn)?
+    .and_then(|track| track.last_lyrics.and_then(|lyrics| lyrics.id))
+    .ok_or(ApiError::TrackNotFoundError)?;
+
+  Ok(Json(votes_response(lyrics_id, &mut conn)?))
+}
+
+fn votes_response(lyrics_id: i64, conn: &mut Connection) -> Result<VotesResponse, ApiError> {
+  let counts = vote_repository::get_counts(lyrics_id, conn)?;
+  Ok(VotesResponse {
+    lyrics_id,
+    upvotes: counts.upvotes,
+    downvotes: counts.downvotes,
+    score: counts.score(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::SocketAddr, time::Duration};
+  use axum::{body::Body, extract::ConnectInfo, http::{header, Request, StatusCode}};
+  use serde_json::json;
+  use crate::{auth::{issue_token, RateClass}, test_utils::{body_json, TestApp}};
+
+  /// Upvotes the lyrics of the track from the same peer, with the given `Authorization` header,
+  /// returning the upvotes counted
+  async fn upvote(app: &TestApp, track_id: i64, authorization: &str) -> serde_json::Value {
+    let mut request = Request::post("/api/vote")
+      .header(header::CONTENT_TYPE, "application/json")
+      .header(header::AUTHORIZATION, authorization)
+      .header("X-Publish-Token", app.publish_token().await)
+      .body(Body::from(json!({ "trackId": track_id, "vote": "up" }).to_string()))
+      .unwrap();
+    request.extensions_mut().insert(ConnectInfo("203.0.113.7:4000".parse::<SocketAddr>().unwrap()));
+
+    let response = app.send(request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    body_json(response).await["upvotes"].clone()
+  }
+
+  #[tokio::test]
+  async fn unverified_credentials_dont_make_new_voters() {
+    let app = TestApp::with_config(|config| config.publish_token_secret = Some("secret".to_owned()));
+    let track_id = app.add_track("Hello", "Adele", Some("Hello, it's me"), None);
+
+    assert_eq!(upvote(&app, track_id, "Bearer made-up").await, 1);
+    assert_eq!(upvote(&app, track_id, "Bearer made-up-again").await, 1);
+    assert_eq!(upvote(&app, track_id, "Basic bm90OnZlcmlmaWVk").await, 1);
+
+    // A token the server issued identifies a client of its own, behind the same IP
+    let token = issue_token("secret", Duration::from_secs(60), RateClass::Standard);
+    assert_eq!(upvote(&app, track_id, &format!("Bearer {}", token)).await, 2);
+    assert_eq!(upvote(&app, track_id, &format!("Bearer {}", token)).await, 2);
+  }
+}
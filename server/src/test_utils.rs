@@ -0,0 +1,112 @@
+//! Helpers shared by the tests of the routes and the background tasks
+
+use axum::{
+  body::{to_bytes, Body},
+  http::{header, HeaderMap, Request},
+  response::Response,
+  Router,
+};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tower::ServiceExt;
+use crate::{
+  build_state,
+  config::Config,
+  providers::{noop::NoopProvider, LyricsProvider},
+  repositories::{lyrics_repository, track_repository},
+  router,
+  AppState,
+  REQUEST_ID_HEADER,
+};
+
+/// The server on a fresh database, answering requests without listening on a port
+pub(crate) struct TestApp {
+  pub state: Arc<AppState>,
+  pub router: Router,
+  /// Holds the database, removed when the test ends
+  _dir: TempDir,
+}
+
+impl TestApp {
+  pub fn new() -> Self {
+    Self::with_config(|_| {})
+  }
+
+  pub fn with_config(configure: impl FnOnce(&mut Config)) -> Self {
+    Self::with_providers(vec![Box::new(NoopProvider::new())], configure)
+  }
+
+  pub fn with_providers(providers: Vec<Box<dyn LyricsProvider>>, configure: impl FnOnce(&mut Config)) -> Self {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config {
+      database: Some(dir.path().join("lrclib.sqlite3")),
+      ..Config::default()
+    };
+    configure(&mut config);
+    config.validate().unwrap();
+
+    let state = build_state(&config, providers).unwrap();
+    let router = router(state.clone(), &config);
+    TestApp { state, router, _dir: dir }
+  }
+
+  pub async fn send(&self, request: Request<Body>) -> Response {
+    self.router.clone().oneshot(request).await.unwrap()
+  }
+
+  pub async fn get(&self, uri: &str) -> Response {
+    self.send(Request::get(uri).body(Body::empty()).unwrap()).await
+  }
+
+  /// Stores a track with its lyrics, returning the id of the track
+  pub fn add_track(&self, name: &str, artist_name: &str, plain_lyrics: Option<&str>, synced_lyrics: Option<&str>) -> i64 {
+    let mut conn = self.state.pool.get().unwrap();
+    let mut tx = conn.transaction().unwrap();
+    let track_id = track_repository::add_one_tx(name, artist_name, "Album", 200.0, &mut tx).unwrap();
+    lyrics_repository::add_one_tx(
+      &plain_lyrics.map(str::to_owned),
+      &synced_lyrics.map(str::to_owned),
+      track_id,
+      false,
+      &Some("lrclib".to_owned()),
+      None,
+      None,
+      &mut tx,
+    ).unwrap();
+    tx.commit().unwrap();
+    track_id
+  }
+
+  /// Issues a proof-of-work challenge that any nonce solves, returning the publish token of a solution
+  pub async fn publish_token(&self) -> String {
+    let prefix = hex::encode(rand::random::<[u8; 16]>());
+    self.state.challenge_cache.insert(format!("challenge:{}", prefix), format!("sha256:{}", "f".repeat(64))).await;
+    format!("{}:0", prefix)
+  }
+
+  pub async fn post_json(&self, uri: &str, body: serde_json::Value) -> Response {
+    let request = Request::post(uri)
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body.to_string()))
+      .unwrap();
+    self.send(request).await
+  }
+}
+
+pub(crate) async fn body_json(response: Response) -> serde_json::Value {
+  let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+  serde_json::from_slice(&body).unwrap()
+}
+
+/// Decodes a MessagePack body into the same values as a JSON one, to compare the two structures
+pub(crate) async fn body_msgpack(response: Response) -> serde_json::Value {
+  let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+  rmp_serde::from_slice(&body).unwrap()
+}
+
+/// The headers of a response, but the request id that differs for every request
+pub(crate) fn headers_of<B>(response: &axum::http::Response<B>) -> HeaderMap {
+  let mut headers = response.headers().clone();
+  headers.remove(REQUEST_ID_HEADER);
+  headers
+}
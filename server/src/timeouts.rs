@@ -0,0 +1,118 @@
+use axum::{
+  extract::{MatchedPath, Request, State},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use crate::{errors::ApiError, AppState};
+
+/// Routes answering with a stream or a WebSocket, which run for as long as the client reads them
+const UNTIMED_PATHS: [&str; 3] = ["/api/export", "/api/search/stream", "/api/live"];
+
+/// How long the handler of each route can take to respond
+pub struct RequestTimeouts {
+  /// `None` when requests have no time limit
+  pub default: Option<Duration>,
+  /// Overrides of the default by route path, like `/api/publish`, `None` for no limit
+  pub routes: HashMap<String, Option<Duration>>,
+}
+
+impl RequestTimeouts {
+  /// Builds the timeouts from the configured seconds, where 0 means no limit
+  pub fn new(default: u64, routes: &HashMap<String, u64>) -> Self {
+    let limit = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+
+    RequestTimeouts {
+      default: limit(default),
+      routes: routes.iter().map(|(path, seconds)| (path.to_owned(), limit(*seconds))).collect(),
+    }
+  }
+
+  fn for_path(&self, path: &str) -> Option<Duration> {
+    if UNTIMED_PATHS.contains(&path) {
+      return None;
+    }
+    self.routes.get(path).copied().unwrap_or(self.default)
+  }
+}
+
+/// Answers with a 504 when the handler of the route takes longer than its timeout. The handler is
+/// only stopped at its next await point: blocking work, like a database query, runs to completion
+/// first. Streaming routes only stop the time once they start sending their response, and the
+/// routes of `UNTIMED_PATHS` have no limit at all.
+pub async fn limit_duration(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+  let path = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_owned()).unwrap_or_default();
+
+  let Some(timeout) = state.request_timeouts.for_path(&path) else {
+    return next.run(request).await;
+  };
+
+  match tokio::time::timeout(timeout, next.run(request)).await {
+    Ok(response) => response,
+    Err(_) => {
+      tracing::warn!(message = "request timed out", path, timeout_secs = timeout.as_secs());
+      ApiError::TimeoutError.into_response()
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, time::Duration};
+  use axum::{body::Body, http::{Request, StatusCode}, middleware, routing::get, Router};
+  use tower::ServiceExt;
+  use crate::test_utils::{body_json, TestApp};
+  use super::limit_duration;
+
+  async fn slow() -> &'static str {
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    "done"
+  }
+
+  async fn fast() -> &'static str {
+    "done"
+  }
+
+  /// Handlers of the given durations, behind the timeouts of the app
+  fn router(app: &TestApp) -> Router {
+    Router::new()
+      .route("/api/slow", get(slow))
+      .route("/api/fast", get(fast))
+      .route("/api/unlimited", get(slow))
+      .route("/api/export", get(slow))
+      .route_layer(middleware::from_fn_with_state(app.state.clone(), limit_duration))
+      .with_state(app.state.clone())
+  }
+
+  async fn status(router: &Router, uri: &str) -> StatusCode {
+    router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap().status()
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn answers_504_when_the_handler_takes_too_long() {
+    let app = TestApp::with_config(|config| {
+      config.request_timeout = 5;
+      config.route_timeouts = HashMap::from([("/api/unlimited".to_owned(), 0)]);
+    });
+    let router = router(&app);
+
+    let response = router.clone().oneshot(Request::get("/api/slow").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body_json(response).await["name"], "TimeoutError");
+
+    assert_eq!(status(&router, "/api/fast").await, StatusCode::OK);
+    // Without a limit, by override or for a streaming route
+    assert_eq!(status(&router, "/api/unlimited").await, StatusCode::OK);
+    assert_eq!(status(&router, "/api/export").await, StatusCode::OK);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn route_timeouts_override_the_default() {
+    let app = TestApp::with_config(|config| {
+      config.request_timeout = 60;
+      config.route_timeouts = HashMap::from([("/api/slow".to_owned(), 10)]);
+    });
+
+    assert_eq!(status(&router(&app), "/api/slow").await, StatusCode::GATEWAY_TIMEOUT);
+  }
+}
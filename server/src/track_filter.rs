@@ -0,0 +1,230 @@
+use std::{
+  collections::{hash_map::DefaultHasher, HashSet},
+  hash::{Hash, Hasher},
+  sync::{atomic::{AtomicI64, AtomicUsize, Ordering}, Arc, Mutex, RwLock},
+  time::{Duration, Instant},
+};
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::Connection;
+use crate::{
+  repositories::{artist_alias_repository, track_repository},
+  utils::normalize::strip_featuring,
+  AppState,
+};
+
+/// Room for the tracks added between two rebuilds, on top of the tracks counted by the rebuild
+const GROWTH_HEADROOM: f64 = 0.1;
+
+/// A bloom filter of the names of the tracks in the database, so that lookups for tracks that are
+/// definitely missing skip the database.
+///
+/// The key is the track and artist names without their featured-artist suffix, which every step
+/// of the metadata lookup matches on (the fuzzy step included), so the filter never rules out a
+/// track that a lookup would find. Lookups for an artist alias always go to the database.
+pub struct TrackFilter {
+  false_positive_rate: f64,
+  snapshot: RwLock<Option<Snapshot>>,
+  /// The tracks and aliases added while a rebuild reads the database, to add to the rebuilt filter
+  pending: Mutex<Option<Vec<PendingKey>>>,
+  /// Lookups answered by the filter alone, exported by `/metrics`
+  pub negatives: AtomicUsize,
+  /// Unix timestamp of the last rebuild, 0 before the first one
+  pub last_rebuild_at: AtomicI64,
+}
+
+struct Snapshot {
+  bloom: BloomFilter,
+  aliases: HashSet<String>,
+}
+
+enum PendingKey {
+  Track(u64),
+  Alias(String),
+}
+
+/// The size and estimated false positive rate of the filter, exported by `/metrics`
+pub struct TrackFilterStats {
+  pub keys: usize,
+  pub size_bytes: usize,
+  pub false_positive_rate: f64,
+}
+
+impl TrackFilter {
+  pub fn new(false_positive_rate: f64) -> Self {
+    TrackFilter {
+      false_positive_rate,
+      snapshot: RwLock::new(None),
+      pending: Mutex::new(None),
+      negatives: AtomicUsize::new(0),
+      last_rebuild_at: AtomicI64::new(0),
+    }
+  }
+
+  /// Whether the database may have a track with these names, as prepared by `prepare_input`.
+  /// Always true until the first rebuild.
+  pub fn may_contain(&self, track_name_lower: &str, artist_name_lower: &str) -> bool {
+    let snapshot = self.snapshot.read().unwrap();
+    let Some(snapshot) = snapshot.as_ref() else {
+      return true;
+    };
+
+    let may_contain = snapshot.aliases.contains(artist_name_lower)
+      || snapshot.bloom.contains(key_hash(track_name_lower, artist_name_lower));
+    if !may_contain {
+      self.negatives.fetch_add(1, Ordering::Relaxed);
+    }
+    may_contain
+  }
+
+  /// Records a track added to the database, with its names as prepared by `prepare_input`
+  pub fn add_track(&self, track_name_lower: &str, artist_name_lower: &str) {
+    let hash = key_hash(track_name_lower, artist_name_lower);
+    if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+      pending.push(PendingKey::Track(hash));
+    }
+    if let Some(snapshot) = self.snapshot.write().unwrap().as_mut() {
+      snapshot.bloom.insert(hash);
+    }
+  }
+
+  /// Records an artist alias added to the database
+  pub fn add_alias(&self, alias_lower: &str) {
+    if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+      pending.push(PendingKey::Alias(alias_lower.to_owned()));
+    }
+    if let Some(snapshot) = self.snapshot.write().unwrap().as_mut() {
+      snapshot.aliases.insert(alias_lower.to_owned());
+    }
+  }
+
+  pub fn stats(&self) -> TrackFilterStats {
+    match self.snapshot.read().unwrap().as_ref() {
+      Some(snapshot) => TrackFilterStats {
+        keys: snapshot.bloom.keys,
+        size_bytes: snapshot.bloom.bits.len() * 8,
+        false_positive_rate: snapshot.bloom.false_positive_rate(),
+      },
+      None => TrackFilterStats { keys: 0, size_bytes: 0, false_positive_rate: 0.0 },
+    }
+  }
+
+  /// Builds the filter again from the tracks of the database. Deleted tracks only leave the
+  /// filter on a rebuild.
+  fn rebuild(&self, conn: &mut Connection) -> Result<usize> {
+    *self.pending.lock().unwrap() = Some(Vec::new());
+    let built = build_snapshot(self.false_positive_rate, conn);
+
+    let mut snapshot = self.snapshot.write().unwrap();
+    let pending = self.pending.lock().unwrap().take().unwrap_or_default();
+    let mut built = built?;
+    for key in pending {
+      match key {
+        PendingKey::Track(hash) => built.bloom.insert(hash),
+        PendingKey::Alias(alias_lower) => {
+          built.aliases.insert(alias_lower);
+        },
+      }
+    }
+    let keys = built.bloom.keys;
+    *snapshot = Some(built);
+
+    Ok(keys)
+  }
+}
+
+fn build_snapshot(false_positive_rate: f64, conn: &mut Connection) -> Result<Snapshot> {
+  let tracks_count = track_repository::count_live_tracks(conn)?;
+  let capacity = (tracks_count as f64 * (1.0 + GROWTH_HEADROOM)) as usize;
+  let mut bloom = BloomFilter::new(capacity, false_positive_rate);
+
+  track_repository::for_each_track_name(conn, |track_name_lower, artist_name_lower| {
+    bloom.insert(key_hash(track_name_lower, artist_name_lower));
+  })?;
+  let aliases = artist_alias_repository::get_all_aliases(conn)?.into_iter().collect();
+
+  Ok(Snapshot { bloom, aliases })
+}
+
+fn key_hash(track_name_lower: &str, artist_name_lower: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  strip_featuring(track_name_lower).hash(&mut hasher);
+  strip_featuring(artist_name_lower).hash(&mut hasher);
+  hasher.finish()
+}
+
+struct BloomFilter {
+  bits: Vec<u64>,
+  hashes: u32,
+  /// Keys inserted, counting the duplicates
+  keys: usize,
+}
+
+impl BloomFilter {
+  /// Sizes the filter for `capacity` keys at the given false positive rate
+  fn new(capacity: usize, false_positive_rate: f64) -> Self {
+    let capacity = capacity.max(1) as f64;
+    let bits_count = (-capacity * false_positive_rate.ln() / std::f64::consts::LN_2.powi(2)).ceil().max(64.0);
+    let hashes = (bits_count / capacity * std::f64::consts::LN_2).round().max(1.0) as u32;
+    BloomFilter {
+      bits: vec![0; (bits_count as usize).div_ceil(64)],
+      hashes,
+      keys: 0,
+    }
+  }
+
+  fn bits_count(&self) -> u64 {
+    self.bits.len() as u64 * 64
+  }
+
+  /// The bits of a key, derived from its hash by double hashing
+  fn positions(&self, hash: u64) -> impl Iterator<Item = u64> + '_ {
+    let step = hash.rotate_left(32) | 1;
+    (0..self.hashes as u64).map(move |i| hash.wrapping_add(i.wrapping_mul(step)) % self.bits_count())
+  }
+
+  fn insert(&mut self, hash: u64) {
+    let positions: Vec<u64> = self.positions(hash).collect();
+    for position in positions {
+      self.bits[(position / 64) as usize] |= 1 << (position % 64);
+    }
+    self.keys += 1;
+  }
+
+  fn contains(&self, hash: u64) -> bool {
+    self.positions(hash).all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+  }
+
+  /// The false positive rate expected for the keys inserted so far
+  fn false_positive_rate(&self) -> f64 {
+    let fill = -(self.hashes as f64) * self.keys as f64 / self.bits_count() as f64;
+    (1.0 - fill.exp()).powi(self.hashes as i32)
+  }
+}
+
+/// Rebuilds the track filter on every interval, the first time right away. The rebuild reads the
+/// tracks on a connection of its own, so it never holds the pooled connections.
+pub async fn run_scheduled_rebuild(state: Arc<AppState>, interval: Duration) {
+  let mut interval = tokio::time::interval(interval);
+
+  loop {
+    interval.tick().await;
+
+    let started_at = Instant::now();
+    let state_for_rebuild = state.clone();
+    let result = tokio::task::spawn_blocking(move || {
+      let mut conn = Connection::open(&state_for_rebuild.database)?;
+      conn.busy_timeout(state_for_rebuild.db_busy_timeout)?;
+      state_for_rebuild.track_filter.rebuild(&mut conn)
+    }).await;
+
+    match result {
+      Ok(Ok(keys)) => {
+        state.track_filter.last_rebuild_at.store(Utc::now().timestamp(), Ordering::Relaxed);
+        tracing::info!(message = "rebuilt the track filter", duration = started_at.elapsed().as_millis() as u64, keys);
+      },
+      Ok(Err(err)) => tracing::error!(message = "failed to rebuild the track filter", error = format!("{:#}", err)),
+      Err(err) => tracing::error!(message = "track filter rebuild panicked", error = err.to_string()),
+    }
+  }
+}
@@ -0,0 +1,76 @@
+use axum::{
+  extract::{Request, State},
+  http::{header, HeaderMap},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use crate::{config::{Config, MissingUserAgent}, errors::ApiError, AppState, PROBE_PATHS};
+
+/// The user agent of a client, which LRCLIB clients may send in `Lrclib-Client` or `X-User-Agent`
+/// when they can't set `User-Agent` (like browsers)
+pub fn user_agent(headers: &HeaderMap) -> Option<&str> {
+  headers
+    .get("Lrclib-Client")
+    .and_then(|value| value.to_str().ok())
+    .or_else(|| headers.get("X-User-Agent").and_then(|value| value.to_str().ok()))
+    .or_else(|| headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()))
+    .filter(|user_agent| !user_agent.trim().is_empty())
+}
+
+/// The configured user agent lists, with the patterns lowercased unless matching is case-sensitive
+pub struct UserAgentFilter {
+  denylist: Vec<String>,
+  allowlist: Option<Vec<String>>,
+  case_sensitive: bool,
+  missing: MissingUserAgent,
+}
+
+impl UserAgentFilter {
+  pub fn new(config: &Config) -> Self {
+    let case_sensitive = config.user_agent_case_sensitive;
+    let patterns = |patterns: &[String]| -> Vec<String> {
+      patterns.iter().map(|pattern| if case_sensitive { pattern.to_owned() } else { pattern.to_lowercase() }).collect()
+    };
+
+    UserAgentFilter {
+      denylist: patterns(&config.user_agent_denylist),
+      allowlist: config.user_agent_allowlist.as_deref().map(patterns),
+      case_sensitive,
+      missing: config.missing_user_agent,
+    }
+  }
+
+  /// Whether nothing is filtered, so that the headers don't need to be looked at
+  fn is_disabled(&self) -> bool {
+    self.denylist.is_empty() && self.allowlist.is_none() && self.missing == MissingUserAgent::Allow
+  }
+
+  pub fn allows(&self, user_agent: Option<&str>) -> bool {
+    let Some(user_agent) = user_agent else {
+      return self.missing == MissingUserAgent::Allow;
+    };
+
+    let user_agent = if self.case_sensitive { user_agent.to_owned() } else { user_agent.to_lowercase() };
+    let matches = |patterns: &[String]| patterns.iter().any(|pattern| user_agent.contains(pattern.as_str()));
+
+    !matches(&self.denylist) && self.allowlist.as_deref().map_or(true, matches)
+  }
+}
+
+/// Refuses the clients filtered out by their user agent with a 403, before they reach the routes.
+/// The health probes are always served.
+pub async fn filter_user_agent(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+  let filter = &state.user_agent_filter;
+  if filter.is_disabled() || PROBE_PATHS.contains(&request.uri().path()) {
+    return next.run(request).await;
+  }
+
+  let user_agent = user_agent(request.headers());
+  if !filter.allows(user_agent) {
+    tracing::debug!(message = "refused client by user agent", user_agent = user_agent.unwrap_or_default());
+    return ApiError::ForbiddenError.into_response();
+  }
+
+  next.run(request).await
+}
@@ -1,11 +1,38 @@
+use axum::{
+  http::{header, HeaderMap, HeaderValue, StatusCode},
+  response::{IntoResponse, Response},
+};
 use moka::future::Cache;
+use ipnet::IpNet;
+use std::{net::{IpAddr, SocketAddr}, sync::Arc};
+use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 use secular::lower_lay_string;
 use regex::Regex;
 use collapse::collapse;
+use chrono::{DateTime, Utc};
+use crate::{
+  api_keys::{find_api_key, hash_key, API_KEY_HEADER},
+  auth::bearer_claims,
+  entities::lyrics::SimpleLyrics,
+  errors::ApiError,
+  metrics::CacheMetrics,
+  AppState,
+};
+use pow::PowScheme;
+
+pub mod fields;
+pub mod format;
+pub mod jsonld;
+pub mod language;
+pub mod lrc;
+pub mod normalize;
+pub mod pow;
+pub mod quality;
+pub mod romanize;
 
 pub fn prepare_input(input: &str) -> String {
-  let mut prepared_input = lower_lay_string(&input);
+  let mut prepared_input = lower_lay_string(input);
 
   let re = Regex::new(r#"[`~!@#$%^&*()_|+\-=?;:",.<>\{\}\[\]\\\/]"#).unwrap();
   prepared_input = re.replace_all(&prepared_input, " ").to_string();
@@ -27,7 +54,11 @@ pub fn strip_timestamp(synced_lyrics: &str) -> String {
 
 // tokens
 
-pub async fn is_valid_publish_token(publish_token: &str, challenge_cache: &Cache<String, String>) -> bool {
+pub async fn is_valid_publish_token(
+  publish_token: &str,
+  challenge_cache: &Cache<String, String>,
+  challenge_cache_metrics: &CacheMetrics,
+) -> bool {
   let publish_token_parts = publish_token.split(":").collect::<Vec<&str>>();
 
   if publish_token_parts.len() != 2 {
@@ -36,11 +67,20 @@ pub async fn is_valid_publish_token(publish_token: &str, challenge_cache: &Cache
 
   let prefix = publish_token_parts[0];
   let nonce = publish_token_parts[1];
-  let target = challenge_cache.get(&format!("challenge:{}", prefix)).await;
+  let challenge = challenge_cache.get(&format!("challenge:{}", prefix)).await;
+  challenge_cache_metrics.record(challenge.is_some());
 
-  match target {
-    Some(target) => {
-      let result = verify_answer(prefix, &target, nonce);
+  match challenge {
+    Some(challenge) => {
+      let solution = (prefix.to_owned(), nonce.to_owned());
+      // Hashing with argon2id takes a while, and too much memory for the async workers
+      let result = tokio::task::spawn_blocking(move || {
+        let (prefix, nonce) = solution;
+        let Some((scheme, target)) = challenge.rsplit_once(':') else {
+          return false;
+        };
+        PowScheme::decode(scheme).is_some_and(|scheme| verify_answer(scheme, &prefix, target, &nonce))
+      }).await.unwrap_or(false);
 
       if result {
         challenge_cache.remove(&format!("challenge:{}", prefix)).await;
@@ -55,11 +95,10 @@ pub async fn is_valid_publish_token(publish_token: &str, challenge_cache: &Cache
   }
 }
 
-pub fn verify_answer(prefix: &str, target: &str, nonce: &str) -> bool {
-  let input = format!("{}{}", prefix, nonce);
-  let mut hasher = Sha256::new();
-  hasher.update(input);
-  let hashed_bytes = hasher.finalize();
+pub fn verify_answer(scheme: PowScheme, prefix: &str, target: &str, nonce: &str) -> bool {
+  let Some(hashed_bytes) = scheme.digest(prefix, nonce) else {
+    return false;
+  };
 
   let target_bytes = match hex::decode(target) {
     Ok(bytes) => bytes,
@@ -82,6 +121,123 @@ pub fn verify_answer(prefix: &str, target: &str, nonce: &str) -> bool {
   true
 }
 
+// client ip
+
+/// Networks of the reverse proxies whose `X-Forwarded-For` header is trusted
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+  /// Parses networks like `10.0.0.0/8`, or single addresses, returning the first invalid one as error
+  pub fn parse(networks: &[String]) -> Result<Self, String> {
+    networks
+      .iter()
+      .map(|network| {
+        let network = network.trim();
+        network.parse::<IpNet>()
+          .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+          .map_err(|_| network.to_owned())
+      })
+      .collect::<Result<Vec<IpNet>, String>>()
+      .map(TrustedProxies)
+  }
+
+  fn contains(&self, ip: IpAddr) -> bool {
+    self.0.iter().any(|network| network.contains(&ip))
+  }
+}
+
+/// Returns the client IP. `X-Forwarded-For` is only read when the socket peer is a trusted proxy,
+/// or the local reverse proxy of a Unix domain socket, as any client can send the header. Each proxy
+/// appends the address it got the request from, so the client is the last address of the header
+/// that isn't a trusted proxy.
+pub fn client_ip(request_headers: &HeaderMap, peer_addr: Option<SocketAddr>, trusted_proxies: &TrustedProxies) -> Option<IpAddr> {
+  let peer_ip = peer_addr.map(|addr| addr.ip().to_canonical());
+  if peer_ip.is_some_and(|ip| !trusted_proxies.contains(ip)) {
+    return peer_ip;
+  }
+
+  let forwarded_ips: Vec<&str> = request_headers
+    .get_all("X-Forwarded-For")
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .collect();
+
+  let mut client_ip = peer_ip;
+  for forwarded_ip in forwarded_ips.into_iter().rev() {
+    let Ok(ip) = forwarded_ip.trim().parse::<IpAddr>().map(|ip| ip.to_canonical()) else {
+      break;
+    };
+    client_ip = Some(ip);
+    if !trusted_proxies.contains(ip) {
+      break;
+    }
+  }
+
+  client_ip
+}
+
+/// Identifies the client of a request, for bookkeeping scoped to each client: by its API key or
+/// bearer token when the server can verify it, and by its IP otherwise, as any client can make up a
+/// credential to pass for a new client on every request. The identifier is keyed with the salt of
+/// the install, see `keyed_client_id`, so it can be stored without giving the credential or the IP
+/// away to whoever gets a copy of the database.
+pub async fn client_id(state: &Arc<AppState>, request_headers: &HeaderMap, peer_addr: Option<SocketAddr>) -> Result<String, ApiError> {
+  let api_key = request_headers
+    .get(API_KEY_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .map(str::trim);
+  let api_key = match api_key {
+    Some(api_key) => find_api_key(state, hash_key(api_key)).await?.is_some().then_some(api_key),
+    None => None,
+  };
+  let bearer_token = match bearer_claims(request_headers, state.publish_token_secret.as_deref()) {
+    Some(_) => request_headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).map(str::trim),
+    None => None,
+  };
+
+  let identity = match api_key.or(bearer_token) {
+    Some(credential) => credential.to_owned(),
+    None => client_ip(request_headers, peer_addr, &state.trusted_proxies).map(|ip| ip.to_string()).unwrap_or_default(),
+  };
+  Ok(keyed_client_id(&state.client_id_salt, &hex::encode(Sha256::digest(identity.as_bytes()))))
+}
+
+/// Keys the SHA-256 hash of a client identity with an HMAC of the salt. A plain hash of an IP is
+/// reversed by hashing all the addresses, which takes minutes for IPv4. Used by the migrations too,
+/// to key the plain hashes stored before.
+pub fn keyed_client_id(salt: &[u8], hashed_identity: &str) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(salt).expect("HMAC accepts keys of any size");
+  mac.update(hashed_identity.as_bytes());
+  hex::encode(mac.finalize().into_bytes())
+}
+
+// content hash
+
+/// Hashes the lyrics content, ignoring differences in whitespace, to detect duplicate submissions.
+/// Plain and synced lyrics are hashed separately, so plain-only lyrics never match lyrics that
+/// also have a synced version.
+pub fn lyrics_content_hash(plain_lyrics: Option<&str>, synced_lyrics: Option<&str>, instrumental: bool) -> String {
+  let normalize_whitespace = |lyrics: &str| {
+    lyrics
+      .lines()
+      .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+      .filter(|line| !line.is_empty())
+      .collect::<Vec<String>>()
+      .join("\n")
+  };
+
+  let mut hasher = Sha256::new();
+  hasher.update(format!(
+    "instrumental:{}\0plain:{}\0synced:{}",
+    instrumental,
+    plain_lyrics.map(normalize_whitespace).unwrap_or_default(),
+    synced_lyrics.map(normalize_whitespace).unwrap_or_default(),
+  ));
+  hex::encode(hasher.finalize())
+}
+
 pub fn process_param(param: Option<&str>) -> Option<String> {
   param
     .as_ref()
@@ -89,3 +245,225 @@ pub fn process_param(param: Option<&str>) -> Option<String> {
     .filter(|s| !s.trim().is_empty())
     .map(|s| s.to_owned())
 }
+
+// http caching
+
+/// Lyrics for a given track id are effectively immutable, so they can be cached for as long as `get_cache` keeps them
+pub const LYRICS_MAX_AGE: u64 = 60 * 60 * 24 * 7;
+/// Search results are cached for as long as `search_cache` keeps them
+pub const SEARCH_MAX_AGE: u64 = 60 * 60 * 24;
+
+pub fn lyrics_etag(track_id: i64, lyrics: Option<&SimpleLyrics>) -> String {
+  let lyrics_id = lyrics.and_then(|lyrics| lyrics.id).unwrap_or_default();
+  let updated_at = lyrics
+    .and_then(|lyrics| lyrics.updated_at)
+    .map(|updated_at| updated_at.timestamp_micros())
+    .unwrap_or_default();
+
+  let mut hasher = Sha256::new();
+  hasher.update(format!("{}:{}:{}", track_id, lyrics_id, updated_at));
+  let hashed_bytes = hasher.finalize();
+
+  // Weak, because the compression layer may change the representation
+  format!("W/\"{}\"", hex::encode(&hashed_bytes[..16]))
+}
+
+/// Derives a distinct entity tag for another representation of the same lyrics
+pub fn variant_etag(etag: &str, variant: &str) -> String {
+  match etag.strip_suffix('"') {
+    Some(stripped) => format!("{}-{}\"", stripped, variant),
+    None => format!("{}-{}", etag, variant),
+  }
+}
+
+pub fn is_etag_fresh(request_headers: &HeaderMap, etag: &str) -> bool {
+  request_headers
+    .get(header::IF_NONE_MATCH)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| {
+      value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"))
+    })
+}
+
+/// Whether an `If-Match` entity tag designates the same lyrics version as the current entity tag.
+/// The tags of all the representations (like `-lrc` or `-stripped`) of a version match it.
+pub fn matches_version(if_match: &str, etag: &str) -> bool {
+  let version = |tag: &str| {
+    tag.trim().trim_start_matches("W/").trim_matches('"').split('-').next().unwrap_or_default().to_owned()
+  };
+  let current_version = version(etag);
+
+  if_match.split(',').any(|tag| tag.trim() == "*" || version(tag) == current_version)
+}
+
+pub const X_CACHE: &str = "X-Cache";
+/// Set to `true` to skip reading the server-side caches, in `debug-cache` builds only
+#[cfg(feature = "debug-cache")]
+pub const X_CACHE_BYPASS: &str = "X-Cache-Bypass";
+/// Set to `full` on lookup misses while the in-memory queue is full, as the missing track is then
+/// only fetched once the backlog persisted in the database is reached
+pub const X_QUEUE_STATUS: &str = "X-Queue-Status";
+
+/// Whether the request asks to skip reading the server-side caches, the response still being cached.
+/// Always false, whatever the headers, unless the `debug-cache` feature is enabled.
+#[cfg(feature = "debug-cache")]
+pub fn bypasses_cache(request_headers: &HeaderMap) -> bool {
+  request_headers
+    .get(X_CACHE_BYPASS)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"))
+}
+
+#[cfg(not(feature = "debug-cache"))]
+pub fn bypasses_cache(_request_headers: &HeaderMap) -> bool {
+  false
+}
+
+/// Value of the `X-Cache` header, telling whether the response was served from the server-side cache
+pub fn cache_status(hit: bool) -> HeaderValue {
+  HeaderValue::from_static(if hit { "HIT" } else { "MISS" })
+}
+
+pub fn cache_control(max_age: u64) -> HeaderValue {
+  HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap()
+}
+
+/// Formats a timestamp as an HTTP-date, like `Sun, 06 Nov 1994 08:49:37 GMT` (RFC 7231)
+pub fn http_date(timestamp: DateTime<Utc>) -> String {
+  timestamp.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the resource was last modified at or before the `If-Modified-Since` date of the request.
+/// HTTP-dates have a resolution of a second, so the subsecond part of the timestamp is ignored.
+pub fn is_unmodified_since(request_headers: &HeaderMap, last_modified: DateTime<Utc>) -> bool {
+  request_headers
+    .get(header::IF_MODIFIED_SINCE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| DateTime::parse_from_rfc2822(value.trim()).ok())
+    .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+/// Builds a response carrying the Cache-Control, ETag and Last-Modified validators, or an empty 304
+/// response when the client already has the current version, as told by either validator.
+pub fn conditional_response(
+  request_headers: &HeaderMap,
+  etag: &str,
+  last_modified: Option<DateTime<Utc>>,
+  max_age: u64,
+  body: impl IntoResponse,
+) -> Response {
+  let mut headers = HeaderMap::new();
+  headers.insert(header::CACHE_CONTROL, cache_control(max_age));
+  if let Ok(value) = HeaderValue::from_str(etag) {
+    headers.insert(header::ETAG, value);
+  }
+  if let Some(last_modified) = last_modified {
+    if let Ok(value) = HeaderValue::from_str(&http_date(last_modified)) {
+      headers.insert(header::LAST_MODIFIED, value);
+    }
+  }
+
+  let unmodified = last_modified.is_some_and(|last_modified| is_unmodified_since(request_headers, last_modified));
+  if is_etag_fresh(request_headers, etag) || unmodified {
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+  } else {
+    (headers, body).into_response()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::{IpAddr, SocketAddr};
+  use axum::http::{header, HeaderMap, HeaderValue};
+  use chrono::{DateTime, Duration, Utc};
+  use super::{client_ip, http_date, is_unmodified_since, TrustedProxies};
+
+  fn forwarded_for(values: &[&str]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for value in values {
+      headers.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+    }
+    headers
+  }
+
+  fn peer(ip: &str) -> Option<SocketAddr> {
+    Some(SocketAddr::new(ip.parse().unwrap(), 40000))
+  }
+
+  fn ip(ip: &str) -> Option<IpAddr> {
+    Some(ip.parse().unwrap())
+  }
+
+  #[test]
+  fn ignores_forwarded_for_from_untrusted_peers() {
+    let trusted_proxies = TrustedProxies::parse(&["10.0.0.0/8".to_owned()]).unwrap();
+    let spoofed = forwarded_for(&["1.2.3.4"]);
+
+    assert_eq!(client_ip(&spoofed, peer("203.0.113.7"), &trusted_proxies), ip("203.0.113.7"));
+    // Nothing is trusted by default
+    assert_eq!(client_ip(&spoofed, peer("10.0.0.1"), &TrustedProxies::parse(&[]).unwrap()), ip("10.0.0.1"));
+  }
+
+  #[test]
+  fn reads_the_last_untrusted_address_behind_trusted_proxies() {
+    let trusted_proxies = TrustedProxies::parse(&["10.0.0.0/8".to_owned(), "192.168.1.1".to_owned()]).unwrap();
+
+    // The client prepended a spoofed address, which the proxies then appended the real one to
+    let headers = forwarded_for(&["1.2.3.4, 198.51.100.2", "192.168.1.1"]);
+    assert_eq!(client_ip(&headers, peer("10.1.2.3"), &trusted_proxies), ip("198.51.100.2"));
+
+    // An address that doesn't parse stops the walk at the last valid one
+    let headers = forwarded_for(&["1.2.3.4, not an ip, 192.168.1.1"]);
+    assert_eq!(client_ip(&headers, peer("10.1.2.3"), &trusted_proxies), ip("192.168.1.1"));
+
+    // IPv4 peers mapped to IPv6 are matched like IPv4 ones
+    let headers = forwarded_for(&["198.51.100.2"]);
+    assert_eq!(client_ip(&headers, peer("::ffff:10.1.2.3"), &trusted_proxies), ip("198.51.100.2"));
+  }
+
+  #[test]
+  fn trusts_forwarded_for_on_unix_sockets() {
+    let trusted_proxies = TrustedProxies::parse(&[]).unwrap();
+    assert_eq!(client_ip(&forwarded_for(&["198.51.100.2"]), None, &trusted_proxies), ip("198.51.100.2"));
+    assert_eq!(client_ip(&HeaderMap::new(), None, &trusted_proxies), None);
+  }
+
+  #[test]
+  fn rejects_invalid_trusted_proxies() {
+    assert_eq!(TrustedProxies::parse(&["10.0.0.0/8".to_owned(), "proxy.local".to_owned()]).err(), Some("proxy.local".to_owned()));
+  }
+
+  fn if_modified_since(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_str(value).unwrap());
+    headers
+  }
+
+  #[test]
+  fn formats_http_dates() {
+    let timestamp: DateTime<Utc> = "1994-11-06T08:49:37.250Z".parse().unwrap();
+    assert_eq!(http_date(timestamp), "Sun, 06 Nov 1994 08:49:37 GMT");
+  }
+
+  #[test]
+  fn compares_if_modified_since_to_the_second() {
+    let last_modified: DateTime<Utc> = "2024-05-01T12:00:00.750Z".parse().unwrap();
+
+    // The date sent back is the Last-Modified one, without its subsecond part
+    assert!(is_unmodified_since(&if_modified_since(&http_date(last_modified)), last_modified));
+    assert!(is_unmodified_since(&if_modified_since(&http_date(last_modified + Duration::days(1))), last_modified));
+    assert!(!is_unmodified_since(&if_modified_since(&http_date(last_modified - Duration::seconds(1))), last_modified));
+  }
+
+  #[test]
+  fn ignores_missing_and_invalid_if_modified_since() {
+    let last_modified: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+
+    assert!(!is_unmodified_since(&HeaderMap::new(), last_modified));
+    assert!(!is_unmodified_since(&if_modified_since("yesterday"), last_modified));
+    assert!(!is_unmodified_since(&if_modified_since("2030-01-01T00:00:00Z"), last_modified));
+  }
+}
@@ -0,0 +1,48 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// The response fields a client asked for with `?fields=syncedLyrics,id`, to trim the responses of
+/// the lookup routes down to what it uses
+pub struct Fields(BTreeSet<String>);
+
+impl Fields {
+  /// Returns `None` when no field is given, in which case the full response is returned
+  pub fn parse(fields: Option<&str>) -> Option<Self> {
+    let fields: BTreeSet<String> = fields?
+      .split(',')
+      .map(str::trim)
+      .filter(|field| !field.is_empty())
+      .map(str::to_owned)
+      .collect();
+
+    (!fields.is_empty()).then_some(Fields(fields))
+  }
+
+  /// Keeps only the requested fields of an object, or of every object of an array. Unknown fields
+  /// are ignored, and an object having none of the requested fields is kept whole.
+  pub fn select<T: Serialize + ?Sized>(&self, value: &T) -> serde_json::Result<Value> {
+    Ok(self.select_value(serde_json::to_value(value)?))
+  }
+
+  fn select_value(&self, value: Value) -> Value {
+    match value {
+      Value::Object(object) => {
+        if !object.keys().any(|key| self.0.contains(key)) {
+          return Value::Object(object);
+        }
+        Value::Object(object.into_iter().filter(|(key, _)| self.0.contains(key)).collect())
+      },
+      Value::Array(values) => Value::Array(values.into_iter().map(|value| self.select_value(value)).collect()),
+      value => value,
+    }
+  }
+
+  /// The variant passed to `variant_etag`, the same for every order the fields are given in. The
+  /// server-side caches keep the full responses, which are trimmed on the way out, so this is the
+  /// only key telling the trimmed variants apart.
+  pub fn etag_variant(&self) -> String {
+    // Commas would split the tag in If-None-Match headers
+    format!("fields:{}", self.0.iter().cloned().collect::<Vec<_>>().join("+"))
+  }
+}
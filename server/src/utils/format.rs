@@ -0,0 +1,296 @@
+use axum::{
+  http::{header, HeaderMap, HeaderValue},
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+use crate::{errors::ApiError, utils::{lrc::lrc_to_srt, variant_etag}};
+
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResponseFormat {
+  Json,
+  /// The same structure as the JSON, encoded as MessagePack for bandwidth-constrained clients
+  MsgPack,
+  Lrc,
+  Srt,
+}
+
+impl ResponseFormat {
+  /// Picks the response format from the `format` query parameter, falling back to the Accept header.
+  /// Without either, the response stays JSON so existing clients keep working.
+  pub fn negotiate(format: Option<&str>, request_headers: &HeaderMap) -> Result<Self, ApiError> {
+    if let Some(format) = format {
+      return match format {
+        "json" => Ok(ResponseFormat::Json),
+        "msgpack" => Ok(ResponseFormat::MsgPack),
+        "lrc" => Ok(ResponseFormat::Lrc),
+        "srt" => Ok(ResponseFormat::Srt),
+        _ => Err(ApiError::ValidationError(format!("format: unsupported format {}", format))),
+      };
+    }
+
+    let media_types = accepted_media_types(request_headers);
+
+    if accepts_msgpack(&media_types) {
+      Ok(ResponseFormat::MsgPack)
+    } else if media_types.contains(&"application/x-subrip") && !media_types.contains(&"application/json") {
+      Ok(ResponseFormat::Srt)
+    } else if media_types.contains(&"text/plain") && !media_types.contains(&"application/json") {
+      Ok(ResponseFormat::Lrc)
+    } else {
+      Ok(ResponseFormat::Json)
+    }
+  }
+
+  /// Picks JSON or MessagePack from the Accept header, for the responses that have no other format
+  pub fn negotiate_structured(request_headers: &HeaderMap) -> Self {
+    match accepts_msgpack(&accepted_media_types(request_headers)) {
+      true => ResponseFormat::MsgPack,
+      false => ResponseFormat::Json,
+    }
+  }
+
+  /// Whether the format is JSON or MessagePack, which carry the whole track
+  pub fn is_structured(&self) -> bool {
+    matches!(self, ResponseFormat::Json | ResponseFormat::MsgPack)
+  }
+
+  /// Encodes a structured response in this format, JSON unless it's MessagePack
+  pub fn structured_body<T: Serialize>(&self, value: T) -> Response {
+    match self {
+      ResponseFormat::MsgPack => MsgPack(value).into_response(),
+      _ => Json(value).into_response(),
+    }
+  }
+
+  /// Checks that the format can carry the preferred kind of lyrics
+  pub fn check_preference(&self, preference: LyricsPreference) -> Result<(), ApiError> {
+    if *self == ResponseFormat::Srt && preference == LyricsPreference::Plain {
+      return Err(ApiError::ValidationError("prefer: SRT subtitles are made from synced lyrics, plain lyrics cannot be preferred".to_owned()));
+    }
+    Ok(())
+  }
+
+  /// Derives a distinct entity tag for each representation of the same lyrics
+  pub fn etag(&self, etag: &str) -> String {
+    match self {
+      ResponseFormat::Json => etag.to_owned(),
+      ResponseFormat::MsgPack => variant_etag(etag, "msgpack"),
+      ResponseFormat::Lrc => variant_etag(etag, "lrc"),
+      ResponseFormat::Srt => variant_etag(etag, "srt"),
+    }
+  }
+}
+
+fn accepted_media_types(request_headers: &HeaderMap) -> Vec<&str> {
+  request_headers
+    .get(header::ACCEPT)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+    .split(',')
+    .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+    .collect()
+}
+
+fn accepts_msgpack(media_types: &[&str]) -> bool {
+  let msgpack = media_types.iter().any(|media_type| matches!(*media_type, MSGPACK_CONTENT_TYPE | "application/x-msgpack"));
+  msgpack && !media_types.contains(&"application/json")
+}
+
+/// A MessagePack body, keeping the field names of the JSON one so that the structures stay the same
+pub struct MsgPack<T>(pub T);
+
+impl<T: Serialize> IntoResponse for MsgPack<T> {
+  fn into_response(self) -> Response {
+    match rmp_serde::to_vec_named(&self.0) {
+      Ok(body) => ([(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK_CONTENT_TYPE))], body).into_response(),
+      Err(err) => ApiError::from(err).into_response(),
+    }
+  }
+}
+
+/// Header telling which kind of lyrics a lookup returned
+pub const X_LYRICS_KIND: &str = "X-Lyrics-Kind";
+
+/// The kind of lyrics a client asks for with `?prefer=`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LyricsPreference {
+  /// Both kinds, with synced lyrics first in the text formats
+  Any,
+  Synced,
+  Plain,
+}
+
+/// The kind of lyrics a lookup returned
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LyricsKind {
+  Synced,
+  Plain,
+  Instrumental,
+  /// The track has no lyrics yet
+  None,
+}
+
+impl LyricsPreference {
+  pub fn parse(prefer: Option<&str>) -> Result<Self, ApiError> {
+    match prefer {
+      None | Some("any") => Ok(LyricsPreference::Any),
+      Some("synced") => Ok(LyricsPreference::Synced),
+      Some("plain") => Ok(LyricsPreference::Plain),
+      Some(prefer) => Err(ApiError::ValidationError(format!("prefer: unsupported preference {}, expected synced, plain or any", prefer))),
+    }
+  }
+
+  /// Derives the entity tag of the lyrics trimmed to the preferred kind
+  pub fn etag(&self, etag: &str) -> String {
+    match self {
+      LyricsPreference::Any => etag.to_owned(),
+      LyricsPreference::Synced => variant_etag(etag, "prefer-synced"),
+      LyricsPreference::Plain => variant_etag(etag, "prefer-plain"),
+    }
+  }
+
+  /// Keeps only the preferred kind of lyrics, and returns the kind left. Without lyrics of the
+  /// preferred kind, whatever the track has is returned instead, or with `fallback` false the
+  /// lookup fails with a 404. Instrumental tracks are returned whatever the preference.
+  pub fn apply(
+    &self,
+    fallback: bool,
+    plain_lyrics: &mut Option<String>,
+    synced_lyrics: &mut Option<String>,
+    instrumental: bool,
+  ) -> Result<LyricsKind, ApiError> {
+    if instrumental {
+      return Ok(LyricsKind::Instrumental);
+    }
+
+    let has_plain = plain_lyrics.as_deref().is_some_and(|lyrics| !lyrics.is_empty());
+    let has_synced = synced_lyrics.as_deref().is_some_and(|lyrics| !lyrics.is_empty());
+
+    match (self, has_synced, has_plain) {
+      (LyricsPreference::Synced, true, _) => {
+        *plain_lyrics = None;
+        Ok(LyricsKind::Synced)
+      },
+      (LyricsPreference::Plain, _, true) => {
+        *synced_lyrics = None;
+        Ok(LyricsKind::Plain)
+      },
+      (LyricsPreference::Synced, false, _) if !fallback => Err(ApiError::PreferredLyricsNotFoundError("synced")),
+      (LyricsPreference::Plain, _, false) if !fallback => Err(ApiError::PreferredLyricsNotFoundError("plain")),
+      (_, true, _) => Ok(LyricsKind::Synced),
+      (_, false, true) => Ok(LyricsKind::Plain),
+      (_, false, false) => Ok(LyricsKind::None),
+    }
+  }
+}
+
+impl LyricsKind {
+  pub fn header_value(&self) -> HeaderValue {
+    HeaderValue::from_static(match self {
+      LyricsKind::Synced => "synced",
+      LyricsKind::Plain => "plain",
+      LyricsKind::Instrumental => "instrumental",
+      LyricsKind::None => "none",
+    })
+  }
+}
+
+/// Returns the raw lyrics text: synced lyrics when available, plain lyrics otherwise. Instrumental
+/// tracks get an empty body flagged with the `X-Instrumental` header.
+pub fn lyrics_text_response(synced_lyrics: Option<&str>, plain_lyrics: Option<&str>, instrumental: bool) -> Response {
+  let mut headers = HeaderMap::new();
+  headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+
+  if instrumental {
+    headers.insert("X-Instrumental", HeaderValue::from_static("true"));
+    return (headers, String::new()).into_response();
+  }
+
+  let body = synced_lyrics.or(plain_lyrics).unwrap_or_default().to_owned();
+  (headers, body).into_response()
+}
+
+/// Returns the synced lyrics converted to SRT subtitles. Instrumental tracks get an empty body
+/// flagged with the `X-Instrumental` header, like the raw lyrics text.
+pub fn subtitles_response(synced_lyrics: Option<&str>, instrumental: bool) -> Result<Response, ApiError> {
+  let mut headers = HeaderMap::new();
+  headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-subrip; charset=utf-8"));
+
+  if instrumental {
+    headers.insert("X-Instrumental", HeaderValue::from_static("true"));
+    return Ok((headers, String::new()).into_response());
+  }
+
+  let Some(synced_lyrics) = synced_lyrics.filter(|lyrics| !lyrics.is_empty()) else {
+    return Err(ApiError::ValidationError("format: the track has no synced lyrics to convert to SRT".to_owned()));
+  };
+  let body = lrc_to_srt(synced_lyrics)
+    .map_err(|err| ApiError::ValidationError(format!("format: the synced lyrics cannot be converted to SRT, {}", err)))?;
+
+  Ok((headers, body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{LyricsKind, LyricsPreference};
+
+  /// What a track has: plain lyrics, synced lyrics, and whether it's instrumental
+  type Track = (Option<&'static str>, Option<&'static str>, bool);
+
+  const BOTH: Track = (Some("Hello"), Some("[00:01.00]Hello"), false);
+  const PLAIN_ONLY: Track = (Some("Hello"), None, false);
+  const SYNCED_ONLY: Track = (None, Some("[00:01.00]Hello"), false);
+  const NO_LYRICS: Track = (Some(""), None, false);
+  const INSTRUMENTAL: Track = (None, None, true);
+
+  /// The kind returned along with whether plain and synced lyrics are left, or `None` for a 404
+  fn apply(preference: LyricsPreference, fallback: bool, track: Track) -> Option<(LyricsKind, bool, bool)> {
+    let (plain_lyrics, synced_lyrics, instrumental) = track;
+    let mut plain_lyrics = plain_lyrics.map(str::to_owned);
+    let mut synced_lyrics = synced_lyrics.map(str::to_owned);
+    let kind = preference.apply(fallback, &mut plain_lyrics, &mut synced_lyrics, instrumental).ok()?;
+    let is_present = |lyrics: Option<String>| lyrics.is_some_and(|lyrics| !lyrics.is_empty());
+    Some((kind, is_present(plain_lyrics), is_present(synced_lyrics)))
+  }
+
+  #[test]
+  fn keeps_the_preferred_kind_of_lyrics() {
+    use LyricsKind::{Instrumental, Plain, Synced};
+    use LyricsPreference::{Any, Plain as PreferPlain, Synced as PreferSynced};
+
+    let cases = [
+      (Any, BOTH, Some((Synced, true, true)), Some((Synced, true, true))),
+      (Any, PLAIN_ONLY, Some((Plain, true, false)), Some((Plain, true, false))),
+      (Any, SYNCED_ONLY, Some((Synced, false, true)), Some((Synced, false, true))),
+      (PreferSynced, BOTH, Some((Synced, false, true)), Some((Synced, false, true))),
+      (PreferSynced, SYNCED_ONLY, Some((Synced, false, true)), Some((Synced, false, true))),
+      (PreferSynced, PLAIN_ONLY, Some((Plain, true, false)), None),
+      (PreferPlain, BOTH, Some((Plain, true, false)), Some((Plain, true, false))),
+      (PreferPlain, PLAIN_ONLY, Some((Plain, true, false)), Some((Plain, true, false))),
+      (PreferPlain, SYNCED_ONLY, Some((Synced, false, true)), None),
+      (Any, NO_LYRICS, Some((LyricsKind::None, false, false)), Some((LyricsKind::None, false, false))),
+      (PreferSynced, NO_LYRICS, Some((LyricsKind::None, false, false)), None),
+      (PreferPlain, NO_LYRICS, Some((LyricsKind::None, false, false)), None),
+      (Any, INSTRUMENTAL, Some((Instrumental, false, false)), Some((Instrumental, false, false))),
+      (PreferSynced, INSTRUMENTAL, Some((Instrumental, false, false)), Some((Instrumental, false, false))),
+      (PreferPlain, INSTRUMENTAL, Some((Instrumental, false, false)), Some((Instrumental, false, false))),
+    ];
+
+    for (preference, track, with_fallback, without_fallback) in cases {
+      assert_eq!(apply(preference, true, track), with_fallback, "{:?} with fallback on {:?}", preference, track);
+      assert_eq!(apply(preference, false, track), without_fallback, "{:?} without fallback on {:?}", preference, track);
+    }
+  }
+
+  #[test]
+  fn parses_the_preferences() {
+    assert_eq!(LyricsPreference::parse(None).ok(), Some(LyricsPreference::Any));
+    assert_eq!(LyricsPreference::parse(Some("any")).ok(), Some(LyricsPreference::Any));
+    assert_eq!(LyricsPreference::parse(Some("synced")).ok(), Some(LyricsPreference::Synced));
+    assert_eq!(LyricsPreference::parse(Some("plain")).ok(), Some(LyricsPreference::Plain));
+    assert!(LyricsPreference::parse(Some("Synced")).is_err());
+  }
+}
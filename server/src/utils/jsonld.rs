@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use crate::{entities::track::SimpleTrack, utils::lrc::{self, strip_word_timings}};
+
+/// Media type of JSON-LD documents
+pub const JSON_LD_CONTENT_TYPE: &str = "application/ld+json";
+
+const SCHEMA_ORG_CONTEXT: &str = "https://schema.org";
+
+/// A schema.org `MusicComposition` of the track, with its lyrics and the recording they come with,
+/// for the structured data of web pages
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicComposition {
+  #[serde(rename = "@context")]
+  context: String,
+  #[serde(rename = "@type")]
+  kind: String,
+  name: Option<String>,
+  recorded_as: MusicRecording,
+  /// `None` for instrumental tracks and tracks without lyrics
+  #[serde(skip_serializing_if = "Option::is_none")]
+  lyrics: Option<CreativeWork>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MusicRecording {
+  #[serde(rename = "@type")]
+  kind: String,
+  name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  by_artist: Option<Thing>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  in_album: Option<Thing>,
+  /// ISO 8601 duration, like `PT3M21S`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  duration: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreativeWork {
+  #[serde(rename = "@type")]
+  kind: String,
+  text: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  in_language: Option<String>,
+  /// Where the lyrics were taken from
+  #[serde(skip_serializing_if = "Option::is_none")]
+  is_based_on: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Thing {
+  #[serde(rename = "@type")]
+  kind: String,
+  name: String,
+}
+
+impl Thing {
+  fn new(kind: &str, name: Option<&str>) -> Option<Self> {
+    name.map(|name| Thing { kind: kind.to_owned(), name: name.to_owned() })
+  }
+}
+
+impl MusicComposition {
+  pub fn new(track: &SimpleTrack) -> Self {
+    let lyrics = track.last_lyrics.as_ref().filter(|lyrics| !lyrics.instrumental);
+    // The text of the lyrics, without the timestamps when the track only has synced lyrics
+    let text = lyrics.and_then(|lyrics| match (&lyrics.plain_lyrics, &lyrics.synced_lyrics) {
+      (Some(plain_lyrics), _) => Some(plain_lyrics.to_owned()),
+      (None, Some(synced_lyrics)) => Some(synced_text(synced_lyrics)),
+      (None, None) => None,
+    });
+
+    MusicComposition {
+      context: SCHEMA_ORG_CONTEXT.to_owned(),
+      kind: "MusicComposition".to_owned(),
+      name: track.name.to_owned(),
+      recorded_as: MusicRecording {
+        kind: "MusicRecording".to_owned(),
+        name: track.name.to_owned(),
+        by_artist: Thing::new("MusicGroup", track.artist_name.as_deref()),
+        in_album: Thing::new("MusicAlbum", track.album_name.as_deref()),
+        duration: track.duration.map(iso_duration),
+      },
+      lyrics: text.filter(|text| !text.is_empty()).map(|text| CreativeWork {
+        kind: "CreativeWork".to_owned(),
+        text,
+        in_language: lyrics.and_then(|lyrics| lyrics.language.to_owned()),
+        is_based_on: lyrics.and_then(|lyrics| lyrics.attribution.to_owned()),
+      }),
+    }
+  }
+}
+
+/// The lines of synced lyrics, without their timestamps and word timings, and without the metadata
+/// lines. The stored synced lyrics were validated when published, so they parse.
+fn synced_text(synced_lyrics: &str) -> String {
+  let lines = lrc::parse(&strip_word_timings(synced_lyrics)).unwrap_or_default();
+  lines.into_iter().map(|line| line.text).collect::<Vec<_>>().join("\n")
+}
+
+fn iso_duration(seconds: f64) -> String {
+  let seconds = seconds.round() as u64;
+  format!("PT{}M{}S", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use crate::entities::{lyrics::SimpleLyrics, track::SimpleTrack};
+  use super::MusicComposition;
+
+  fn track(lyrics: SimpleLyrics) -> SimpleTrack {
+    SimpleTrack {
+      id: 1,
+      name: Some("Hello".to_owned()),
+      album_name: Some("25".to_owned()),
+      artist_name: Some("Adele".to_owned()),
+      duration: Some(295.4),
+      last_lyrics: Some(lyrics),
+    }
+  }
+
+  #[test]
+  fn describes_the_track_as_a_music_composition() {
+    let composition = MusicComposition::new(&track(SimpleLyrics {
+      synced_lyrics: Some("[ar:Adele]\n[00:01.00]<00:01.00>Hello, <00:01.50>it's me\n[00:05.00]I was wondering".to_owned()),
+      language: Some("en".to_owned()),
+      attribution: Some("https://example.com/hello".to_owned()),
+      ..Default::default()
+    }));
+
+    assert_eq!(serde_json::to_value(&composition).unwrap(), json!({
+      "@context": "https://schema.org",
+      "@type": "MusicComposition",
+      "name": "Hello",
+      "recordedAs": {
+        "@type": "MusicRecording",
+        "name": "Hello",
+        "byArtist": { "@type": "MusicGroup", "name": "Adele" },
+        "inAlbum": { "@type": "MusicAlbum", "name": "25" },
+        "duration": "PT4M55S",
+      },
+      "lyrics": {
+        "@type": "CreativeWork",
+        "text": "Hello, it's me\nI was wondering",
+        "inLanguage": "en",
+        "isBasedOn": "https://example.com/hello",
+      },
+    }));
+  }
+
+  #[test]
+  fn instrumental_tracks_have_no_lyrics() {
+    let composition = serde_json::to_value(MusicComposition::new(&track(SimpleLyrics {
+      instrumental: true,
+      ..Default::default()
+    }))).unwrap();
+
+    assert_eq!(composition["@type"], "MusicComposition");
+    assert!(composition.get("lyrics").is_none());
+  }
+}
@@ -0,0 +1,140 @@
+// Detection of the language lyrics are written in, stored as a BCP-47 tag next to the lyrics.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+  static ref LANGUAGE_TAG: Regex = Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{1,8})*$").unwrap();
+}
+
+/// Whether the text looks like a BCP-47 language tag, like `en`, `pt-BR` or `zh-Hant`
+pub fn is_language_tag(tag: &str) -> bool {
+  LANGUAGE_TAG.is_match(tag)
+}
+
+/// Detects the language of the lyrics text. Returns `None` when the text is too short or mixed
+/// to tell the language with confidence.
+pub fn detect_language(text: &str) -> Option<String> {
+  let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+  Some(language_tag(info.lang().code()).to_owned())
+}
+
+/// BCP-47 uses the two letter ISO 639-1 code of a language when there is one, and the three
+/// letter ISO 639-3 code, as returned by the detector, otherwise
+fn language_tag(code: &str) -> &str {
+  match code {
+    "afr" => "af",
+    "aka" => "ak",
+    "amh" => "am",
+    "ara" => "ar",
+    "aze" => "az",
+    "bel" => "be",
+    "ben" => "bn",
+    "bul" => "bg",
+    "cat" => "ca",
+    "ces" => "cs",
+    "cmn" => "zh",
+    "cym" => "cy",
+    "dan" => "da",
+    "deu" => "de",
+    "ell" => "el",
+    "eng" => "en",
+    "epo" => "eo",
+    "est" => "et",
+    "fin" => "fi",
+    "fra" => "fr",
+    "guj" => "gu",
+    "heb" => "he",
+    "hin" => "hi",
+    "hrv" => "hr",
+    "hun" => "hu",
+    "hye" => "hy",
+    "ind" => "id",
+    "ita" => "it",
+    "jav" => "jv",
+    "jpn" => "ja",
+    "kan" => "kn",
+    "kat" => "ka",
+    "khm" => "km",
+    "kor" => "ko",
+    "lat" => "la",
+    "lav" => "lv",
+    "lit" => "lt",
+    "mal" => "ml",
+    "mar" => "mr",
+    "mkd" => "mk",
+    "mya" => "my",
+    "nep" => "ne",
+    "nld" => "nl",
+    "nob" => "nb",
+    "ori" => "or",
+    "pan" => "pa",
+    "pes" => "fa",
+    "pol" => "pl",
+    "por" => "pt",
+    "ron" => "ro",
+    "rus" => "ru",
+    "sin" => "si",
+    "slk" => "sk",
+    "slv" => "sl",
+    "sna" => "sn",
+    "spa" => "es",
+    "srp" => "sr",
+    "swe" => "sv",
+    "tam" => "ta",
+    "tel" => "te",
+    "tgl" => "tl",
+    "tha" => "th",
+    "tuk" => "tk",
+    "tur" => "tr",
+    "ukr" => "uk",
+    "urd" => "ur",
+    "uzb" => "uz",
+    "vie" => "vi",
+    "yid" => "yi",
+    "zul" => "zu",
+    code => code,
+  }
+}
+
+/// The language tags of an `Accept-Language` header, most preferred first. The `*` wildcard, tags
+/// with a zero quality and malformed entries are left out.
+pub fn accepted_languages(header: &str) -> Vec<String> {
+  let mut languages: Vec<(String, f32)> = header
+    .split(',')
+    .filter_map(|entry| {
+      let mut parts = entry.split(';');
+      let tag = parts.next()?.trim();
+      if !is_language_tag(tag) {
+        return None;
+      }
+
+      let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+        Some(quality) => quality.trim().parse::<f32>().ok()?,
+        None => 1.0,
+      };
+      (quality > 0.0).then(|| (tag.to_owned(), quality))
+    })
+    .collect();
+
+  // Stable, so that tags of the same quality keep the order of the header
+  languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+  languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Picks the available language tag best matching the accepted ones, which are the most preferred
+/// first. An accepted tag matches the same tag, or else a tag of the same language, so that `pt`
+/// matches `pt-BR` and the other way around. Available tags listed first win ties.
+pub fn best_language<'a>(accepted: &[String], available: &[&'a str]) -> Option<&'a str> {
+  accepted.iter().find_map(|tag| {
+    available
+      .iter()
+      .find(|language| language.eq_ignore_ascii_case(tag))
+      .or_else(|| available.iter().find(|language| primary_subtag(language).eq_ignore_ascii_case(primary_subtag(tag))))
+      .copied()
+  })
+}
+
+fn primary_subtag(tag: &str) -> &str {
+  tag.split('-').next().unwrap_or(tag)
+}
@@ -0,0 +1,696 @@
+// Parsing and validation of LRC synced lyrics.
+// Every non-empty line must either be a metadata tag like `[ar: Adele]`, or start with one or more
+// timestamps like `[01:23.45]`. Timestamps may omit the fraction, or use 1 to 3 fractional digits.
+// Lines may also carry the word timings of enhanced LRC (A2), like `[00:01.00]<00:01.00>Hello <00:01.50>world`.
+
+use std::{fmt, time::Duration};
+
+/// How far past the end of the track a timestamp can be before it is considered wrong
+const DURATION_GRACE: Duration = Duration::from_secs(10);
+/// How long the last SRT cue stays on screen, since LRC lines have no end time
+const LAST_CUE_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrcLine {
+  /// 1-based line number in the original text
+  pub line: usize,
+  /// Several timestamps on a single line, like `[00:12.00][01:15.00]`, repeat the same text
+  pub timestamps: Vec<Duration>,
+  /// The text with its word timings removed
+  pub text: String,
+  /// Word timings of enhanced LRC, empty for regular lines
+  pub words: Vec<LrcWord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrcWord {
+  pub timestamp: Duration,
+  pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrcError {
+  /// 1-based line number
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for LrcError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+/// How much of some LRC text is synchronized
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncCoverage {
+  /// Lines with text, metadata lines aside
+  pub lines: usize,
+  /// Lines with text and a valid timestamp
+  pub synced_lines: usize,
+  /// Between the earliest and the latest line timestamps, zero without any
+  pub span: Duration,
+}
+
+/// Parses the synchronized lines of LRC text. Metadata lines and empty lines are skipped.
+pub fn parse(input: &str) -> Result<Vec<LrcLine>, LrcError> {
+  let mut lines = Vec::new();
+
+  for (index, raw_line) in input.lines().enumerate() {
+    if let Some(line) = parse_line(index + 1, raw_line)? {
+      lines.push(line);
+    }
+  }
+
+  Ok(lines)
+}
+
+/// Measures the synchronized part of LRC text. Unlike `parse`, lines without a valid timestamp are
+/// counted instead of failing, and timed lines without text (pauses) are left out.
+pub fn sync_coverage(input: &str) -> SyncCoverage {
+  let mut lines = 0;
+  let mut synced_lines = 0;
+  let mut first: Option<Duration> = None;
+  let mut last: Option<Duration> = None;
+
+  for (index, raw_line) in input.lines().enumerate() {
+    match parse_line(index + 1, raw_line) {
+      Ok(Some(line)) => {
+        if line.text.trim().is_empty() {
+          continue;
+        }
+        lines += 1;
+        synced_lines += 1;
+        for timestamp in line.timestamps {
+          first = Some(first.map_or(timestamp, |first| first.min(timestamp)));
+          last = Some(last.map_or(timestamp, |last| last.max(timestamp)));
+        }
+      },
+      Ok(None) => {},
+      Err(_) => lines += 1,
+    }
+  }
+
+  let span = match (first, last) {
+    (Some(first), Some(last)) => last - first,
+    _ => Duration::ZERO,
+  };
+
+  SyncCoverage { lines, synced_lines, span }
+}
+
+/// Parses a single line, returning `None` for metadata lines and empty lines
+fn parse_line(line_number: usize, raw_line: &str) -> Result<Option<LrcLine>, LrcError> {
+  let line = raw_line.trim();
+
+  if line.is_empty() || is_metadata(line) {
+    return Ok(None);
+  }
+
+  let mut timestamps = Vec::new();
+  let mut rest = line;
+
+  while let Some(tag) = rest.strip_prefix('[') {
+    let Some(end) = tag.find(']') else {
+      return Err(LrcError { line: line_number, message: "unclosed bracket".to_owned() });
+    };
+
+    let content = &tag[..end];
+    let timestamp = parse_timestamp(content).ok_or_else(|| LrcError {
+      line: line_number,
+      message: format!("malformed timestamp [{}]", content),
+    })?;
+
+    timestamps.push(timestamp);
+    rest = tag[end + 1..].trim_start();
+  }
+
+  if timestamps.is_empty() {
+    return Err(LrcError { line: line_number, message: "missing timestamp".to_owned() });
+  }
+
+  let (leading, words) = split_words(rest);
+  let text = if words.is_empty() { leading } else { join_words(&leading, &words) };
+
+  Ok(Some(LrcLine { line: line_number, timestamps, text, words }))
+}
+
+/// Parses the LRC text and checks that the lines are in chronological order. When the track
+/// duration is known, timestamps that are well past the end of the track are also rejected.
+pub fn validate(input: &str, duration: Option<f64>) -> Result<Vec<LrcLine>, LrcError> {
+  let lines = parse(input)?;
+  let max_timestamp = duration
+    .filter(|duration| *duration > 0.0)
+    // Converting a duration too large for a `Duration` panics, and such a track has no end to check
+    .and_then(|duration| Duration::try_from_secs_f64(duration).ok())
+    .map(|duration| duration + DURATION_GRACE);
+
+  let mut previous = Duration::ZERO;
+
+  for line in &lines {
+    // Only the first timestamp is checked for ordering, since lines repeated through several
+    // timestamps naturally point back and forth in time
+    let first = line.timestamps[0];
+    if first < previous {
+      return Err(LrcError {
+        line: line.line,
+        message: format!("timestamp [{}] is earlier than the previous line", format_timestamp(first)),
+      });
+    }
+    previous = first;
+
+    if let Some(pair) = line.words.windows(2).find(|pair| pair[1].timestamp < pair[0].timestamp) {
+      return Err(LrcError {
+        line: line.line,
+        message: format!("word timing <{}> is earlier than the previous word", format_timestamp(pair[1].timestamp)),
+      });
+    }
+
+    if let Some(max_timestamp) = max_timestamp {
+      let word_timestamps = line.words.iter().map(|word| &word.timestamp);
+      if let Some(timestamp) = line.timestamps.iter().chain(word_timestamps).find(|timestamp| **timestamp > max_timestamp) {
+        return Err(LrcError {
+          line: line.line,
+          message: format!("timestamp [{}] is past the end of the track", format_timestamp(*timestamp)),
+        });
+      }
+    }
+  }
+
+  Ok(lines)
+}
+
+/// Removes the enhanced LRC word timings, turning the text into regular line-level LRC. Lines
+/// without word timings are kept byte-for-byte.
+pub fn strip_word_timings(input: &str) -> String {
+  input
+    .split_inclusive('\n')
+    .map(|raw_line| {
+      let line = raw_line.trim_end_matches(['\r', '\n']);
+
+      // Keep the line timestamps as they are, only the text after them is rewritten
+      let mut text_start = 0;
+      while let Some(end) = line[text_start..].strip_prefix('[').and_then(|tag| tag.find(']')) {
+        text_start += end + 2;
+      }
+
+      let (leading, words) = split_words(&line[text_start..]);
+      if words.is_empty() {
+        return raw_line.to_owned();
+      }
+
+      let line_ending = &raw_line[line.len()..];
+      format!("{}{}{}", &line[..text_start], join_words(&leading, &words), line_ending)
+    })
+    .collect()
+}
+
+/// Shifts every line timestamp and word timing of LRC text by `offset_ms`, later for a positive
+/// offset, clamping the timestamps that would be negative to zero. Metadata lines and lines without
+/// a valid timestamp are kept as they are.
+pub fn shift(input: &str, offset_ms: i64) -> String {
+  input
+    .split_inclusive('\n')
+    .map(|raw_line| {
+      let line = raw_line.trim_end_matches(['\r', '\n']);
+      let line_ending = &raw_line[line.len()..];
+      if line.trim().is_empty() || is_metadata(line.trim()) {
+        return raw_line.to_owned();
+      }
+
+      let mut shifted = String::with_capacity(raw_line.len());
+      let mut rest = line.trim_start();
+      let mut timestamps = 0;
+      while let Some((content, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+        let Some(timestamp) = parse_timestamp(content) else {
+          break;
+        };
+        shifted.push_str(&format!("[{}]", shift_timestamp(content, timestamp, offset_ms)));
+        timestamps += 1;
+        // The spacing before the text is kept, only the one between the tags is dropped
+        rest = match after.trim_start() {
+          next if next.starts_with('[') => next,
+          _ => after,
+        };
+      }
+      if timestamps == 0 {
+        return raw_line.to_owned();
+      }
+
+      // Word timings, anything else between angle brackets is regular text
+      while let Some(start) = rest.find('<') {
+        let tag = &rest[start + 1..];
+        match tag.find('>').and_then(|end| Some((end, parse_timestamp(&tag[..end])?))) {
+          Some((end, timestamp)) => {
+            shifted.push_str(&rest[..start]);
+            shifted.push_str(&format!("<{}>", shift_timestamp(&tag[..end], timestamp, offset_ms)));
+            rest = &tag[end + 1..];
+          },
+          None => {
+            shifted.push_str(&rest[..=start]);
+            rest = tag;
+          },
+        }
+      }
+      shifted.push_str(rest);
+      shifted.push_str(line_ending);
+      shifted
+    })
+    .collect()
+}
+
+/// Formats a shifted timestamp with centiseconds, or with milliseconds when the original had them
+/// or the offset needs them
+fn shift_timestamp(original: &str, timestamp: Duration, offset_ms: i64) -> String {
+  let millis = (timestamp.as_millis() as i64 + offset_ms).max(0) as u64;
+  let has_millis = original.split_once('.').is_some_and(|(_, fraction)| fraction.len() == 3);
+  match has_millis || offset_ms % 10 != 0 {
+    true => format!("{:02}:{:02}.{:03}", millis / 60_000, (millis / 1000) % 60, millis % 1000),
+    false => format_timestamp(Duration::from_millis(millis)),
+  }
+}
+
+/// Where an edit of `apply_edits` applies
+#[derive(Debug, Clone, PartialEq)]
+pub enum LrcEditTarget {
+  /// 0-based index of the line among the synchronized lines, as returned by `parse`
+  Index(usize),
+  /// The line with this timestamp
+  Timestamp(Duration),
+  /// A new line, inserted after the last line starting at or before `new_time`
+  Insert,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrcEdit {
+  pub target: LrcEditTarget,
+  /// The new text of the line, word timings included
+  pub new_text: Option<String>,
+  /// The new timestamp of the line, in LRC notation like `01:23.45`. With several timestamps on the
+  /// line, it replaces the one of `LrcEditTarget::Timestamp`, or else the first one.
+  pub new_time: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrcEditError {
+  /// 0-based index of the edit, `None` when the text to edit is invalid
+  pub edit: Option<usize>,
+  pub message: String,
+}
+
+impl fmt::Display for LrcEditError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.edit {
+      Some(edit) => write!(f, "edits[{}]: {}", edit, self.message),
+      None => write!(f, "{}", self.message),
+    }
+  }
+}
+
+/// Applies edits to LRC text, all of them located in the original text. Metadata lines and the
+/// lines that are not edited are kept byte-for-byte. The result is not validated, use `validate`
+/// to check that the edits kept the lines in chronological order.
+pub fn apply_edits(input: &str, edits: &[LrcEdit]) -> Result<String, LrcEditError> {
+  let lines = parse(input).map_err(|err| LrcEditError { edit: None, message: format!("the lyrics to edit are invalid, {}", err) })?;
+  let mut raw_lines: Vec<String> = input.lines().map(str::to_owned).collect();
+  // New lines by the line number they follow, 0 for the lines before the first synchronized line
+  let mut inserts: Vec<(usize, String)> = Vec::new();
+  let mut edited_lines: Vec<(usize, usize)> = Vec::new();
+
+  for (index, edit) in edits.iter().enumerate() {
+    let error = |message: String| LrcEditError { edit: Some(index), message };
+
+    if let Some(text) = &edit.new_text {
+      if text.contains(['\n', '\r']) {
+        return Err(error("newText: must be a single line".to_owned()));
+      }
+    }
+    let new_time = match &edit.new_time {
+      Some(time) => {
+        let time = time.trim().trim_start_matches('[').trim_end_matches(']');
+        let timestamp = parse_timestamp(time).ok_or_else(|| error(format!("newTime: malformed timestamp {}", time)))?;
+        Some((time, timestamp))
+      },
+      None => None,
+    };
+
+    let (line, timestamp) = match edit.target {
+      LrcEditTarget::Insert => {
+        let (Some((new_time, time)), Some(new_text)) = (new_time, &edit.new_text) else {
+          return Err(error("a new line needs both newTime and newText".to_owned()));
+        };
+        let after = lines.iter().filter(|line| line.timestamps[0] <= time).map(|line| line.line).next_back().unwrap_or(0);
+        inserts.push((after, format!("[{}]{}", new_time, new_text.trim())));
+        continue;
+      },
+      LrcEditTarget::Index(line_index) => {
+        let line = lines.get(line_index).ok_or_else(|| error(format!("lineIndex: there are only {} synchronized lines", lines.len())))?;
+        (line, 0)
+      },
+      LrcEditTarget::Timestamp(timestamp) => {
+        lines
+          .iter()
+          .find_map(|line| line.timestamps.iter().position(|t| *t == timestamp).map(|position| (line, position)))
+          .ok_or_else(|| error(format!("timestamp: no line at [{}]", format_timestamp(timestamp))))?
+      },
+    };
+
+    if edit.new_text.is_none() && new_time.is_none() {
+      return Err(error("an edit needs newText, newTime or both".to_owned()));
+    }
+    if let Some((_, other)) = edited_lines.iter().find(|(edited_line, _)| *edited_line == line.line) {
+      return Err(error(format!("the line is already edited by edits[{}]", other)));
+    }
+    edited_lines.push((line.line, index));
+
+    let raw_line = raw_lines[line.line - 1].trim();
+    let (mut tags, rest) = split_timestamp_tags(raw_line);
+    if let Some((new_time, _)) = new_time {
+      tags[timestamp] = new_time;
+    }
+    let text = edit.new_text.as_deref().map(str::trim).unwrap_or(rest);
+    let tags: String = tags.iter().map(|tag| format!("[{}]", tag)).collect();
+    raw_lines[line.line - 1] = format!("{}{}", tags, text);
+  }
+
+  let first_line = lines.first().map(|line| line.line).unwrap_or(raw_lines.len() + 1);
+  let mut output: Vec<String> = Vec::with_capacity(raw_lines.len() + inserts.len());
+  for (position, raw_line) in raw_lines.into_iter().enumerate() {
+    let line_number = position + 1;
+    if line_number == first_line {
+      output.extend(inserts.iter().filter(|(after, _)| *after == 0).map(|(_, line)| line.to_owned()));
+    }
+    output.push(raw_line);
+    output.extend(inserts.iter().filter(|(after, _)| *after == line_number).map(|(_, line)| line.to_owned()));
+  }
+  if lines.is_empty() {
+    output.extend(inserts.into_iter().map(|(_, line)| line));
+  }
+
+  let mut result = output.join("\n");
+  if input.ends_with('\n') {
+    result.push('\n');
+  }
+  Ok(result)
+}
+
+/// Splits the leading `[mm:ss.xx]` tags of a line, returned without their brackets, from its text
+fn split_timestamp_tags(line: &str) -> (Vec<&str>, &str) {
+  let mut tags = Vec::new();
+  let mut rest = line;
+  while let Some((content, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+    tags.push(content);
+    rest = after.trim_start();
+  }
+  (tags, rest)
+}
+
+/// Parses a timestamp of LRC notation, like `01:23.45`
+pub fn parse_time(input: &str) -> Option<Duration> {
+  parse_timestamp(input.trim().trim_start_matches('[').trim_end_matches(']'))
+}
+
+/// Converts LRC text to SRT subtitles. Each line becomes a cue lasting until the next line starts,
+/// lines with several timestamps become several cues, and empty lines only end the previous cue.
+pub fn lrc_to_srt(input: &str) -> Result<String, LrcError> {
+  let lines = parse(input)?;
+
+  let mut entries: Vec<(Duration, &str)> = lines
+    .iter()
+    .flat_map(|line| line.timestamps.iter().map(|timestamp| (*timestamp, line.text.as_str())))
+    .collect();
+  entries.sort_by_key(|(timestamp, _)| *timestamp);
+
+  let mut srt = String::new();
+  let mut index = 0;
+
+  for (position, (start, text)) in entries.iter().enumerate() {
+    if text.is_empty() {
+      continue;
+    }
+
+    let end = entries[position + 1..]
+      .iter()
+      .map(|(timestamp, _)| *timestamp)
+      .find(|timestamp| timestamp > start)
+      .unwrap_or(*start + LAST_CUE_DURATION);
+
+    index += 1;
+    srt.push_str(&format!(
+      "{}\n{} --> {}\n{}\n\n",
+      index,
+      format_srt_timestamp(*start),
+      format_srt_timestamp(end),
+      text,
+    ));
+  }
+
+  Ok(srt)
+}
+
+/// Splits the text of a line at its `<mm:ss.xx>` word timings, returning the text before the first
+/// word timing along with the timed words. Anything else between angle brackets is regular text.
+fn split_words(text: &str) -> (String, Vec<LrcWord>) {
+  let mut leading = String::new();
+  let mut words: Vec<LrcWord> = Vec::new();
+  let mut rest = text;
+
+  while let Some(start) = rest.find('<') {
+    let tag = &rest[start + 1..];
+    let word_timing = tag.find('>').and_then(|end| Some((end, parse_timestamp(&tag[..end])?)));
+    let current = words.last_mut().map_or(&mut leading, |word| &mut word.text);
+
+    match word_timing {
+      Some((end, timestamp)) => {
+        current.push_str(&rest[..start]);
+        words.push(LrcWord { timestamp, text: String::new() });
+        rest = &tag[end + 1..];
+      },
+      None => {
+        current.push_str(&rest[..=start]);
+        rest = tag;
+      },
+    }
+  }
+
+  words.last_mut().map_or(&mut leading, |word| &mut word.text).push_str(rest);
+
+  (leading, words)
+}
+
+/// Joins the text around the word timings, without the spacing that was left around them
+fn join_words(leading: &str, words: &[LrcWord]) -> String {
+  std::iter::once(leading)
+    .chain(words.iter().map(|word| word.text.as_str()))
+    .flat_map(str::split_whitespace)
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Metadata tags are a single `[key: value]` tag on their own line, with an alphabetic key
+fn is_metadata(line: &str) -> bool {
+  let Some(content) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) else {
+    return false;
+  };
+
+  match content.split_once(':') {
+    Some((key, _)) => !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic() || c == '#') && !content.contains(']'),
+    None => false,
+  }
+}
+
+/// Parses `mm:ss`, `mm:ss.x`, `mm:ss.xx` and `mm:ss.xxx`
+fn parse_timestamp(content: &str) -> Option<Duration> {
+  let (minutes, seconds) = content.split_once(':')?;
+  let (seconds, fraction) = match seconds.split_once('.') {
+    Some((seconds, fraction)) => (seconds, Some(fraction)),
+    None => (seconds, None),
+  };
+
+  if minutes.is_empty() || minutes.len() > 3 || !minutes.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  if seconds.len() != 2 || !seconds.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+
+  let minutes: u64 = minutes.parse().ok()?;
+  let seconds: u64 = seconds.parse().ok()?;
+  if seconds >= 60 {
+    return None;
+  }
+
+  let millis = match fraction {
+    Some(fraction) => {
+      if fraction.is_empty() || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+      }
+      // Scale to milliseconds, so that e.g. `.5`, `.50` and `.500` are equal
+      fraction.parse::<u64>().ok()? * 10_u64.pow(3 - fraction.len() as u32)
+    },
+    None => 0,
+  };
+
+  Some(Duration::from_millis((minutes * 60 + seconds) * 1000 + millis))
+}
+
+/// Formats `HH:MM:SS,mmm`
+fn format_srt_timestamp(timestamp: Duration) -> String {
+  let millis = timestamp.as_millis();
+  format!(
+    "{:02}:{:02}:{:02},{:03}",
+    millis / 3_600_000,
+    (millis / 60_000) % 60,
+    (millis / 1000) % 60,
+    millis % 1000,
+  )
+}
+
+fn format_timestamp(timestamp: Duration) -> String {
+  let centis = timestamp.as_millis() / 10;
+  format!("{:02}:{:02}.{:02}", centis / 6000, (centis / 100) % 60, centis % 100)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use super::{format_srt_timestamp, lrc_to_srt, parse, validate, LrcError, LrcWord};
+
+  fn millis(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+  }
+
+  fn error(input: &str) -> LrcError {
+    parse(input).unwrap_err()
+  }
+
+  #[test]
+  fn parses_timestamps_with_and_without_fractions() {
+    let lines = parse("[00:01]One\n[00:02.5]Two\n[00:03.25]Three\n[01:04.125]Four\n[100:00.00]Five").unwrap();
+
+    let timestamps: Vec<Duration> = lines.iter().map(|line| line.timestamps[0]).collect();
+    assert_eq!(timestamps, [millis(1000), millis(2500), millis(3250), millis(64125), millis(6_000_000)]);
+    assert_eq!(lines[0].text, "One");
+  }
+
+  #[test]
+  fn repeats_the_text_of_multi_bracket_lines() {
+    let lines = parse("[00:12.00][01:15.00] Chorus").unwrap();
+
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].timestamps, [millis(12000), millis(75000)]);
+    assert_eq!(lines[0].text, "Chorus");
+  }
+
+  #[test]
+  fn skips_metadata_and_empty_lines() {
+    let lines = parse("[ar: Adele]\n[ti:Hello]\n\n  \n[#: comment]\n[00:01.00]Hello").unwrap();
+
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].line, 6);
+  }
+
+  #[test]
+  fn keeps_timed_lines_without_text() {
+    let lines = parse("[00:01.00]\n[00:02.00]After the pause").unwrap();
+
+    assert_eq!(lines[0].text, "");
+    assert_eq!(lines[1].text, "After the pause");
+  }
+
+  #[test]
+  fn parses_enhanced_lrc_word_timings() {
+    let lines = parse("[00:01.00]<00:01.00>Hello <00:01.50>world").unwrap();
+
+    assert_eq!(lines[0].text, "Hello world");
+    assert_eq!(lines[0].words, [
+      LrcWord { timestamp: millis(1000), text: "Hello ".to_owned() },
+      LrcWord { timestamp: millis(1500), text: "world".to_owned() },
+    ]);
+  }
+
+  #[test]
+  fn rejects_malformed_lines() {
+    assert_eq!(error("[00:01.00]Fine\nNo timestamp"), LrcError { line: 2, message: "missing timestamp".to_owned() });
+    assert_eq!(error("[00:01.00 Unclosed").message, "unclosed bracket");
+    assert_eq!(error("[00:60.00]Too many seconds").message, "malformed timestamp [00:60.00]");
+    assert_eq!(error("[0:1.00]One digit seconds").message, "malformed timestamp [0:1.00]");
+    assert_eq!(error("[00:01.0000]Four digit fraction").message, "malformed timestamp [00:01.0000]");
+    assert_eq!(error("[00:01.]Empty fraction").message, "malformed timestamp [00:01.]");
+    assert_eq!(error("[00:01.00][chorus]Bad second tag").message, "malformed timestamp [chorus]");
+  }
+
+  #[test]
+  fn accepts_lines_in_chronological_order() {
+    assert!(validate("[00:01.00]One\n[00:01.00]Same time\n[00:02.00]Two", None).is_ok());
+    // Only the first timestamp of a repeated line has to follow the previous line
+    assert!(validate("[00:10.00][00:30.00]Chorus\n[00:20.00]Verse", None).is_ok());
+  }
+
+  #[test]
+  fn rejects_lines_out_of_order() {
+    let err = validate("[00:02.00]Two\n[00:01.00]One", None).unwrap_err();
+
+    assert_eq!(err, LrcError { line: 2, message: "timestamp [00:01.00] is earlier than the previous line".to_owned() });
+  }
+
+  #[test]
+  fn rejects_word_timings_out_of_order() {
+    let err = validate("[00:01.00]<00:02.00>Hello <00:01.50>world", None).unwrap_err();
+
+    assert_eq!(err.message, "word timing <00:01.50> is earlier than the previous word");
+  }
+
+  #[test]
+  fn rejects_timestamps_past_the_end_of_the_track() {
+    assert!(validate("[03:29.00]Last line", Some(200.0)).is_ok());
+
+    let err = validate("[00:01.00]First\n[03:31.00]Too late", Some(200.0)).unwrap_err();
+    assert_eq!(err, LrcError { line: 2, message: "timestamp [03:31.00] is past the end of the track".to_owned() });
+  }
+
+  #[test]
+  fn ignores_durations_that_dont_fit_a_duration() {
+    assert!(validate("[00:01.00]Hello", Some(1e300)).is_ok());
+    assert!(validate("[00:01.00]Hello", Some(f64::INFINITY)).is_ok());
+  }
+
+  #[test]
+  fn formats_srt_timestamps_with_hours_and_milliseconds() {
+    assert_eq!(format_srt_timestamp(millis(0)), "00:00:00,000");
+    assert_eq!(format_srt_timestamp(millis(5_070)), "00:00:05,070");
+    assert_eq!(format_srt_timestamp(millis(754_321)), "00:12:34,321");
+    assert_eq!(format_srt_timestamp(millis(3_723_004)), "01:02:03,004");
+  }
+
+  #[test]
+  fn converts_fractional_seconds_to_milliseconds() {
+    let srt = lrc_to_srt("[00:01.5]Tenths\n[00:02.25]Hundredths\n[00:03.125]Thousandths").unwrap();
+
+    assert_eq!(srt, concat!(
+      "1\n00:00:01,500 --> 00:00:02,250\nTenths\n\n",
+      "2\n00:00:02,250 --> 00:00:03,125\nHundredths\n\n",
+      "3\n00:00:03,125 --> 00:00:08,125\nThousandths\n\n",
+    ));
+  }
+
+  #[test]
+  fn numbers_the_cues_of_repeated_lines_in_time_order() {
+    let srt = lrc_to_srt("[ar:Adele]\n[00:01.00][00:05.00]Chorus\n[00:03.00]Verse\n[00:04.00]\n[00:07.00]End").unwrap();
+
+    // The empty line ends the verse without a cue of its own
+    assert_eq!(srt, concat!(
+      "1\n00:00:01,000 --> 00:00:03,000\nChorus\n\n",
+      "2\n00:00:03,000 --> 00:00:04,000\nVerse\n\n",
+      "3\n00:00:05,000 --> 00:00:07,000\nChorus\n\n",
+      "4\n00:00:07,000 --> 00:00:12,000\nEnd\n\n",
+    ));
+  }
+
+  #[test]
+  fn ends_a_cue_at_the_next_later_timestamp() {
+    let srt = lrc_to_srt("[00:01.00]First\n[00:01.00]Same time\n[00:02.00]Next").unwrap();
+
+    assert!(srt.starts_with("1\n00:00:01,000 --> 00:00:02,000\nFirst\n\n2\n00:00:01,000 --> 00:00:02,000\nSame time\n\n"), "{}", srt);
+  }
+}
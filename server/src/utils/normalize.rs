@@ -0,0 +1,34 @@
+use crate::utils::prepare_input;
+
+/// Words that introduce a featured artist, e.g. "feat.", "ft." or "featuring"
+const FEATURING_WORDS: [&str; 3] = ["feat", "ft", "featuring"];
+
+/// Normalizes a track or artist name for fuzzy matching: on top of `prepare_input` (lowercasing,
+/// stripping diacritics and punctuation, collapsing whitespace), drops any featured-artist suffix.
+pub fn normalize(input: &str) -> String {
+  strip_featuring(&prepare_input(input))
+}
+
+/// Drops the featured-artist suffix of a name already prepared by `prepare_input`, like the
+/// `_lower` columns
+pub fn strip_featuring(prepared_input: &str) -> String {
+  let words: Vec<&str> = prepared_input.split_whitespace().collect();
+
+  // The first word is never treated as a featuring word
+  let end = words
+    .iter()
+    .skip(1)
+    .position(|word| FEATURING_WORDS.contains(word))
+    .map(|position| position + 1)
+    .unwrap_or(words.len());
+
+  words[..end].join(" ")
+}
+
+/// The LIKE patterns matching a normalized name followed by a featured-artist suffix
+pub fn featuring_patterns(normalized: &str) -> Vec<String> {
+  FEATURING_WORDS
+    .iter()
+    .map(|word| format!("{} {} %", normalized, word))
+    .collect()
+}
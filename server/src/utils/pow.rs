@@ -0,0 +1,101 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::config::{Config, PowAlgorithm};
+
+/// The hash function of a proof-of-work challenge, with its parameters. It's cached along with the
+/// target of every issued challenge, so that a solution is verified with the scheme it was solved
+/// with, even if the config changed in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowScheme {
+  Sha256,
+  Argon2id(Argon2Params),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Argon2Params {
+  /// In KiB
+  pub memory_cost: u32,
+  pub time_cost: u32,
+  pub parallelism: u32,
+}
+
+impl PowScheme {
+  pub fn from_config(config: &Config) -> Self {
+    match config.pow_algorithm {
+      PowAlgorithm::Sha256 => PowScheme::Sha256,
+      PowAlgorithm::Argon2id => PowScheme::Argon2id(Argon2Params {
+        memory_cost: config.pow_argon2_memory_cost,
+        time_cost: config.pow_argon2_time_cost,
+        parallelism: config.pow_argon2_parallelism,
+      }),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      PowScheme::Sha256 => "sha256",
+      PowScheme::Argon2id(_) => "argon2id",
+    }
+  }
+
+  pub fn argon2_params(&self) -> Option<Argon2Params> {
+    match self {
+      PowScheme::Sha256 => None,
+      PowScheme::Argon2id(params) => Some(*params),
+    }
+  }
+
+  /// The 32-byte digest of a solution. With SHA-256, the prefix followed by the nonce is hashed. With
+  /// argon2id, the nonce is the password and the prefix the salt. `None` for invalid argon2id
+  /// parameters or a salt that is too short.
+  pub fn digest(&self, prefix: &str, nonce: &str) -> Option<[u8; 32]> {
+    match self {
+      PowScheme::Sha256 => Some(Sha256::digest(format!("{}{}", prefix, nonce).as_bytes()).into()),
+      PowScheme::Argon2id(params) => {
+        let params = Params::new(params.memory_cost, params.time_cost, params.parallelism, Some(32)).ok()?;
+        let mut digest = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+          .hash_password_into(nonce.as_bytes(), prefix.as_bytes(), &mut digest)
+          .ok()?;
+        Some(digest)
+      },
+    }
+  }
+
+  /// Formats the scheme as `sha256`, or `argon2id:{memory_cost}:{time_cost}:{parallelism}`
+  pub fn encode(&self) -> String {
+    match self {
+      PowScheme::Sha256 => self.name().to_owned(),
+      PowScheme::Argon2id(params) => format!("{}:{}:{}:{}", self.name(), params.memory_cost, params.time_cost, params.parallelism),
+    }
+  }
+
+  pub fn decode(value: &str) -> Option<Self> {
+    let mut parts = value.split(':');
+    let scheme = match parts.next()? {
+      "sha256" => PowScheme::Sha256,
+      "argon2id" => PowScheme::Argon2id(Argon2Params {
+        memory_cost: parts.next()?.parse().ok()?,
+        time_cost: parts.next()?.parse().ok()?,
+        parallelism: parts.next()?.parse().ok()?,
+      }),
+      _ => return None,
+    };
+
+    match parts.next() {
+      Some(_) => None,
+      None => Some(scheme),
+    }
+  }
+}
+
+/// The least difficulty of the challenges, in leading zero bits of the target, for the configured
+/// algorithm
+pub fn min_difficulty(config: &Config) -> u8 {
+  match config.pow_algorithm {
+    PowAlgorithm::Sha256 => config.min_pow_difficulty,
+    PowAlgorithm::Argon2id => config.pow_argon2_min_difficulty,
+  }
+}
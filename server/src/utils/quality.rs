@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use crate::utils::lrc::sync_coverage;
+
+/// Quality signals computed from the lyrics, so that clients can rank lyrics without parsing them.
+/// Flattened into the track responses.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LyricsQuality {
+  /// Lines with text, zero for instrumental tracks
+  pub line_count: usize,
+  /// Whether every line of the lyrics has a timestamp, `None` without synced lyrics
+  pub fully_synced: Option<bool>,
+  /// Seconds between the first and the last synced line, `None` without synced lyrics
+  pub synced_span: Option<f64>,
+}
+
+impl LyricsQuality {
+  pub fn new(plain_lyrics: Option<&str>, synced_lyrics: Option<&str>, instrumental: bool) -> Self {
+    if instrumental {
+      return LyricsQuality::default();
+    }
+
+    let plain_line_count = plain_lyrics.map(|lyrics| lyrics.lines().filter(|line| !line.trim().is_empty()).count());
+
+    match synced_lyrics {
+      Some(synced_lyrics) => {
+        let coverage = sync_coverage(synced_lyrics);
+        // Plain lyrics with more lines than the synced ones are only partially synced
+        let line_count = plain_line_count.unwrap_or_default().max(coverage.lines);
+
+        LyricsQuality {
+          line_count,
+          fully_synced: Some(line_count > 0 && coverage.synced_lines >= line_count),
+          synced_span: Some(coverage.span.as_secs_f64()),
+        }
+      },
+      None => LyricsQuality {
+        line_count: plain_line_count.unwrap_or_default(),
+        fully_synced: None,
+        synced_span: None,
+      },
+    }
+  }
+}
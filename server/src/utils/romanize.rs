@@ -0,0 +1,172 @@
+// Transliteration of Japanese kana (Hepburn) and Korean hangul (Revised Romanization).
+// Everything else, including kanji and the LRC timestamp tags, passes through unchanged.
+
+const HANGUL_BASE: u32 = 0xAC00;
+const HANGUL_LAST: u32 = 0xD7A3;
+const SILENT_INITIAL: usize = 11;
+
+const INITIALS: [&str; 19] = [
+  "g", "kk", "n", "d", "tt", "r", "m", "b", "pp", "s", "ss", "", "j", "jj", "ch", "k", "t", "p", "h",
+];
+
+const MEDIALS: [&str; 21] = [
+  "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo", "we", "wi", "yu", "eu", "ui", "i",
+];
+
+const FINALS: [&str; 28] = [
+  "", "k", "k", "k", "n", "n", "n", "t", "l", "k", "m", "l", "l", "l", "p", "l", "m", "p", "p", "t", "t", "ng", "t", "t", "k", "t", "p", "t",
+];
+
+// Finals followed by a syllable starting with a silent ㅇ are pronounced as the next syllable's initial
+const LIAISON_FINALS: [&str; 28] = [
+  "", "g", "kk", "ks", "n", "nj", "n", "d", "r", "lg", "lm", "lb", "ls", "lt", "lp", "r", "m", "b", "bs", "s", "ss", "ng", "j", "ch", "k", "t", "p", "",
+];
+
+pub fn romanize(input: &str) -> String {
+  romanize_kana(&romanize_hangul(input))
+}
+
+fn decompose_hangul(c: char) -> Option<(usize, usize, usize)> {
+  let code = c as u32;
+  if !(HANGUL_BASE..=HANGUL_LAST).contains(&code) {
+    return None;
+  }
+
+  let index = (code - HANGUL_BASE) as usize;
+  Some((index / 588, (index % 588) / 28, index % 28))
+}
+
+fn romanize_hangul(input: &str) -> String {
+  let mut output = String::with_capacity(input.len());
+  let mut chars = input.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match decompose_hangul(c) {
+      Some((initial, medial, last)) => {
+        output.push_str(INITIALS[initial]);
+        output.push_str(MEDIALS[medial]);
+
+        let next_is_silent = chars
+          .peek()
+          .and_then(|next| decompose_hangul(*next))
+          .is_some_and(|(next_initial, _, _)| next_initial == SILENT_INITIAL);
+
+        if next_is_silent {
+          output.push_str(LIAISON_FINALS[last]);
+        } else {
+          output.push_str(FINALS[last]);
+        }
+      },
+      None => output.push(c),
+    }
+  }
+
+  output
+}
+
+fn katakana_to_hiragana(c: char) -> char {
+  match c {
+    '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+    _ => c,
+  }
+}
+
+fn kana_romaji(c: char) -> Option<&'static str> {
+  let romaji = match c {
+    'ぁ' | 'あ' => "a", 'ぃ' | 'い' => "i", 'ぅ' | 'う' => "u", 'ぇ' | 'え' => "e", 'ぉ' | 'お' => "o",
+    'か' | 'ゕ' => "ka", 'が' => "ga", 'き' => "ki", 'ぎ' => "gi", 'く' => "ku",
+    'ぐ' => "gu", 'け' | 'ゖ' => "ke", 'げ' => "ge", 'こ' => "ko", 'ご' => "go",
+    'さ' => "sa", 'ざ' => "za", 'し' => "shi", 'じ' => "ji", 'す' => "su",
+    'ず' => "zu", 'せ' => "se", 'ぜ' => "ze", 'そ' => "so", 'ぞ' => "zo",
+    'た' => "ta", 'だ' => "da", 'ち' => "chi", 'ぢ' => "ji", 'つ' => "tsu",
+    'づ' => "zu", 'て' => "te", 'で' => "de", 'と' => "to", 'ど' => "do",
+    'な' => "na", 'に' => "ni", 'ぬ' => "nu", 'ね' => "ne", 'の' => "no",
+    'は' => "ha", 'ば' => "ba", 'ぱ' => "pa", 'ひ' => "hi", 'び' => "bi",
+    'ぴ' => "pi", 'ふ' => "fu", 'ぶ' => "bu", 'ぷ' => "pu", 'へ' => "he",
+    'べ' => "be", 'ぺ' => "pe", 'ほ' => "ho", 'ぼ' => "bo", 'ぽ' => "po",
+    'ま' => "ma", 'み' => "mi", 'む' => "mu", 'め' => "me", 'も' => "mo",
+    'ゃ' | 'や' => "ya", 'ゅ' | 'ゆ' => "yu", 'ょ' | 'よ' => "yo",
+    'ら' => "ra", 'り' => "ri", 'る' => "ru", 'れ' => "re", 'ろ' => "ro",
+    'ゎ' | 'わ' => "wa", 'ゐ' => "i", 'ゑ' => "e", 'を' => "o", 'ん' => "n", 'ゔ' => "vu",
+    _ => return None,
+  };
+  Some(romaji)
+}
+
+fn is_vowel(c: char) -> bool {
+  matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+fn romanize_kana(input: &str) -> String {
+  let mut output = String::with_capacity(input.len());
+  let mut chars = input.chars().map(katakana_to_hiragana).peekable();
+  let mut last_kana: Option<&'static str> = None;
+  let mut geminate = false;
+
+  while let Some(c) = chars.next() {
+    match c {
+      // Small tsu doubles the consonant of the following kana
+      'っ' => {
+        geminate = true;
+        last_kana = None;
+        continue;
+      },
+      // Prolonged sound mark repeats the previous vowel
+      'ー' => {
+        if let Some(vowel) = output.chars().last().filter(|last| is_vowel(*last)) {
+          output.push(vowel);
+        }
+        continue;
+      },
+      // Contracted sounds like きゃ (kya) and しゃ (sha)
+      'ゃ' | 'ゅ' | 'ょ' if last_kana.is_some_and(|kana| kana.len() > 1 && kana.ends_with('i')) => {
+        output.pop();
+        if !(output.ends_with("sh") || output.ends_with("ch") || output.ends_with('j')) {
+          output.push('y');
+        }
+        output.push_str(&kana_romaji(c).unwrap_or_default()[1..]);
+        last_kana = None;
+        continue;
+      },
+      // Extended katakana like ファ (fa) and ウィ (wi)
+      'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' if last_kana.is_some() => {
+        output.pop();
+        if last_kana == Some("u") {
+          output.push('w');
+        }
+        output.push_str(kana_romaji(c).unwrap_or_default());
+        last_kana = None;
+        continue;
+      },
+      _ => {},
+    }
+
+    match kana_romaji(c) {
+      Some(romaji) => {
+        if geminate {
+          if let Some(consonant) = romaji.chars().next().filter(|first| !is_vowel(*first)) {
+            output.push(if consonant == 'c' { 't' } else { consonant });
+          }
+          geminate = false;
+        }
+
+        output.push_str(romaji);
+
+        // Separate ん from a following vowel or y, so that e.g. きんえん reads kin'en
+        let next_romaji = chars.peek().and_then(|next| kana_romaji(*next));
+        if romaji == "n" && next_romaji.is_some_and(|next| next.starts_with(|first: char| is_vowel(first) || first == 'y')) {
+          output.push('\'');
+        }
+
+        last_kana = Some(romaji);
+      },
+      None => {
+        geminate = false;
+        last_kana = None;
+        output.push(c);
+      },
+    }
+  }
+
+  output
+}
@@ -0,0 +1,172 @@
+use std::{
+  sync::{atomic::{AtomicI64, AtomicU64, Ordering}, Arc},
+  time::{Duration, Instant},
+};
+use anyhow::Result;
+use chrono::{Timelike, Utc};
+use rusqlite::Connection;
+use crate::{config::{Config, VacuumMode}, AppState};
+
+/// Value of `PRAGMA auto_vacuum` for incremental auto-vacuum
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+pub struct VacuumSettings {
+  pub mode: VacuumMode,
+  pub window_start: u8,
+  pub window_end: u8,
+  pub max_recent_lyrics: usize,
+  pub batch_pages: u32,
+}
+
+impl VacuumSettings {
+  pub fn new(config: &Config) -> Self {
+    VacuumSettings {
+      mode: config.vacuum_mode,
+      window_start: config.vacuum_window_start,
+      window_end: config.vacuum_window_end,
+      max_recent_lyrics: config.vacuum_max_recent_lyrics,
+      batch_pages: config.vacuum_batch_pages,
+    }
+  }
+
+  fn is_in_window(&self) -> bool {
+    let hour = Utc::now().hour() as u8;
+    match self.window_start < self.window_end {
+      true => hour >= self.window_start && hour < self.window_end,
+      // The window wraps around midnight
+      false => hour >= self.window_start || hour < self.window_end,
+    }
+  }
+
+  fn window_length(&self) -> Duration {
+    let hours = (self.window_end as u64 + 24 - self.window_start as u64) % 24;
+    Duration::from_secs(hours * 60 * 60)
+  }
+
+  /// Whether the vacuum can run now, in the window and with few enough recent publishes
+  fn can_run(&self, state: &AppState) -> bool {
+    self.is_in_window() && state.recent_lyrics_count.load(Ordering::Relaxed) <= self.max_recent_lyrics
+  }
+}
+
+/// The last completed vacuum, exported by `/metrics`
+#[derive(Default)]
+pub struct VacuumMetrics {
+  /// Unix timestamp, 0 before the first vacuum
+  pub last_run_at: AtomicI64,
+  pub last_duration_micros: AtomicU64,
+  pub last_reclaimed_pages: AtomicU64,
+}
+
+struct VacuumOutcome {
+  reclaimed_pages: u64,
+  /// Whether an incremental vacuum stopped before reclaiming every free page
+  interrupted: bool,
+}
+
+/// Vacuums the database at most once per window. The vacuum runs on a connection of its own, so
+/// it never holds the pooled connections, and the requests keep reading from the WAL meanwhile.
+pub async fn run_scheduled_vacuum(state: Arc<AppState>, settings: VacuumSettings) {
+  let settings = Arc::new(settings);
+  let mut interval = tokio::time::interval(Duration::from_secs(60));
+  let mut last_started_at: Option<Instant> = None;
+
+  loop {
+    interval.tick().await;
+    if last_started_at.is_some_and(|at| at.elapsed() < settings.window_length()) {
+      continue;
+    }
+    if !settings.is_in_window() {
+      continue;
+    }
+    if state.recent_lyrics_count.load(Ordering::Relaxed) > settings.max_recent_lyrics {
+      tracing::debug!(message = "vacuum postponed, too many recent publishes");
+      continue;
+    }
+
+    let started_at = Instant::now();
+    last_started_at = Some(started_at);
+    let state_for_vacuum = state.clone();
+    let settings_for_vacuum = settings.clone();
+    let result = tokio::task::spawn_blocking(move || vacuum(&state_for_vacuum, &settings_for_vacuum)).await;
+    let duration = started_at.elapsed();
+
+    match result {
+      Ok(Ok(outcome)) => {
+        let metrics = &state.vacuum_metrics;
+        metrics.last_run_at.store(Utc::now().timestamp(), Ordering::Relaxed);
+        metrics.last_duration_micros.store(duration.as_micros() as u64, Ordering::Relaxed);
+        metrics.last_reclaimed_pages.store(outcome.reclaimed_pages, Ordering::Relaxed);
+        tracing::info!(
+          message = "vacuumed the database",
+          duration = duration.as_millis() as u64,
+          reclaimed_pages = outcome.reclaimed_pages,
+          interrupted = outcome.interrupted,
+        );
+      },
+      Ok(Err(err)) => tracing::error!(message = "failed to vacuum the database", error = format!("{:#}", err)),
+      Err(err) => tracing::error!(message = "vacuum task panicked", error = err.to_string()),
+    }
+  }
+}
+
+fn vacuum(state: &AppState, settings: &VacuumSettings) -> Result<VacuumOutcome> {
+  let conn = Connection::open(&state.database)?;
+  conn.busy_timeout(state.db_busy_timeout)?;
+  let free_pages_before = free_pages(&conn)?;
+
+  let outcome = match settings.mode {
+    VacuumMode::Off => VacuumOutcome { reclaimed_pages: 0, interrupted: false },
+    VacuumMode::Full => {
+      conn.execute_batch("VACUUM")?;
+      VacuumOutcome { reclaimed_pages: free_pages_before, interrupted: false }
+    },
+    VacuumMode::Incremental => {
+      let auto_vacuum: i64 = conn.pragma_query_value(None, "auto_vacuum", |row| row.get(0))?;
+      if auto_vacuum != AUTO_VACUUM_INCREMENTAL {
+        // The new auto-vacuum mode only applies to an existing database after a full vacuum
+        tracing::info!(message = "switching the database to incremental auto-vacuum, with a full vacuum");
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        conn.execute_batch("VACUUM")?;
+        VacuumOutcome { reclaimed_pages: free_pages_before, interrupted: false }
+      } else {
+        incremental_vacuum(&conn, state, settings)?
+      }
+    },
+  };
+
+  // Moves the vacuumed pages from the WAL to the database file and shrinks the WAL back. If readers
+  // still use the WAL after the busy timeout, it's left for the regular checkpoints.
+  conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+
+  Ok(outcome)
+}
+
+/// Reclaims the free pages in batches of their own write transaction, so that publishes only wait
+/// for one batch at a time. Stops early when the window ends or publishes pick up.
+fn incremental_vacuum(conn: &Connection, state: &AppState, settings: &VacuumSettings) -> Result<VacuumOutcome> {
+  let mut reclaimed_pages = 0;
+
+  loop {
+    let before = free_pages(conn)?;
+    if before == 0 {
+      return Ok(VacuumOutcome { reclaimed_pages, interrupted: false });
+    }
+    if !settings.can_run(state) {
+      return Ok(VacuumOutcome { reclaimed_pages, interrupted: true });
+    }
+
+    conn.execute_batch(&format!("PRAGMA incremental_vacuum({})", settings.batch_pages))?;
+    let after = free_pages(conn)?;
+    // Pages freed by concurrent writes can outnumber the reclaimed ones, don't chase them forever
+    if after >= before {
+      return Ok(VacuumOutcome { reclaimed_pages, interrupted: true });
+    }
+    reclaimed_pages += before - after;
+  }
+}
+
+fn free_pages(conn: &Connection) -> Result<u64> {
+  let count: i64 = conn.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+  Ok(count as u64)
+}
@@ -1,6 +1,15 @@
-use std::path::PathBuf;
-use clap::{Parser, Subcommand};
-use server::serve;
+use std::{net::IpAddr, path::PathBuf, process, time::Duration};
+use clap::{Args, Parser, Subcommand};
+use server::{
+  api_keys::{create_api_key, revoke_api_key},
+  auth::{issue_token, RateClass},
+  config::{Config, LogFormat, PowAlgorithm, WorkersCount},
+  reindex::{reindex, ReindexOptions},
+  serve,
+};
+
+/// How long the API key commands wait for a server holding the database lock
+const API_KEY_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -12,11 +21,32 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
   /// Start the LRCLIB server
-  Serve {
-    /// The port you want the server to bind to
-    #[arg(short, long, value_name = "PORT", default_value_t = 3300)]
-    port: u16,
+  Serve(Box<ServeArgs>),
+  /// Issue a signed publish token for a trusted client
+  IssueToken {
+    /// The secret the server is configured with
+    #[arg(
+      long,
+      value_name = "SECRET",
+      env = "LRCLIB_PUBLISH_TOKEN_SECRET",
+      hide_env_values = true
+    )]
+    secret: String,
+
+    /// How long the token stays valid, in days
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    expires_in_days: u64,
 
+    /// Lift the publish rate limit for this token
+    #[arg(long)]
+    unlimited: bool,
+
+    /// Allow this token to use the operator endpoints, implies --unlimited
+    #[arg(long, conflicts_with = "unlimited")]
+    operator: bool,
+  },
+  /// Create an API key for a client, printing the key
+  CreateApiKey {
     /// Path to the database file
     #[arg(
       short,
@@ -26,26 +56,376 @@ enum Commands {
     )]
     database: PathBuf,
 
-    /// The number of queue processing workers
+    /// A name identifying the client
+    #[arg(long, value_name = "NAME")]
+    name: String,
+
+    /// The number of requests allowed per UTC day (0 for no quota)
+    #[arg(long, value_name = "REQUESTS")]
+    daily_quota: u64,
+  },
+  /// Revoke the API keys of a client
+  RevokeApiKey {
+    /// Path to the database file
     #[arg(
       short,
       long,
-      value_name = "WORKERS_COUNT",
-      env = "LRCLIB_WORKERS_COUNT",
-      default_value_t = 0
+      value_name = "FILE",
+      env = "LRCLIB_DATABASE_FILE"
     )]
-    workers_count: u8,
+    database: PathBuf,
+
+    /// The name the keys were created with
+    #[arg(long, value_name = "NAME")]
+    name: String,
+  },
+  /// Recompute the content hash, language and search index of all lyrics
+  Reindex {
+    /// Path to the database file
+    #[arg(
+      short,
+      long,
+      value_name = "FILE",
+      env = "LRCLIB_DATABASE_FILE"
+    )]
+    database: PathBuf,
+
+    /// The number of lyrics processed in each transaction
+    #[arg(long, value_name = "ROWS", default_value_t = 1000)]
+    batch_size: usize,
+
+    /// Only report how many lyrics would change
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Start over instead of resuming an interrupted reindex
+    #[arg(long)]
+    restart: bool,
+
+    /// How long to wait for a server holding the database lock, in milliseconds
+    #[arg(long, value_name = "MILLISECONDS", default_value_t = 5000)]
+    db_busy_timeout: u64,
   },
 }
 
+/// Options given on the command line override the ones from the config file
+#[derive(Args)]
+struct ServeArgs {
+  /// Path to a TOML config file
+  #[arg(
+    short,
+    long,
+    value_name = "FILE",
+    env = "LRCLIB_CONFIG_FILE"
+  )]
+  config: Option<PathBuf>,
+
+  /// The log output format, compact or json [default: compact]
+  #[arg(
+    long,
+    value_name = "FORMAT",
+    env = "LRCLIB_LOG_FORMAT"
+  )]
+  log_format: Option<LogFormat>,
+
+  /// The IPv4 or IPv6 address you want the server to bind to [default: 0.0.0.0]
+  #[arg(
+    short,
+    long,
+    value_name = "ADDRESS",
+    env = "LRCLIB_BIND_ADDRESS"
+  )]
+  bind_address: Option<IpAddr>,
+
+  /// The port you want the server to bind to [default: 3300]
+  #[arg(short, long, value_name = "PORT")]
+  port: Option<u16>,
+
+  /// Path of a Unix domain socket to listen on instead of the address and port
+  #[arg(
+    long,
+    value_name = "PATH",
+    env = "LRCLIB_UNIX_SOCKET"
+  )]
+  unix_socket: Option<PathBuf>,
+
+  /// Path to the database file
+  #[arg(
+    short,
+    long,
+    value_name = "FILE",
+    env = "LRCLIB_DATABASE_FILE"
+  )]
+  database: Option<PathBuf>,
+
+  /// The number of queue processing workers, or auto for one per CPU core [default: auto]
+  #[arg(
+    short,
+    long,
+    value_name = "WORKERS_COUNT",
+    env = "LRCLIB_WORKERS_COUNT"
+  )]
+  workers_count: Option<WorkersCount>,
+
+  /// The minimum proof-of-work difficulty, in leading zero bits of the target [default: 24]
+  #[arg(
+    long,
+    value_name = "BITS",
+    env = "LRCLIB_MIN_POW_DIFFICULTY"
+  )]
+  min_pow_difficulty: Option<u8>,
+
+  /// The proof-of-work hash function, sha256 or argon2id [default: sha256]
+  #[arg(
+    long,
+    value_name = "ALGORITHM",
+    env = "LRCLIB_POW_ALGORITHM"
+  )]
+  pow_algorithm: Option<PowAlgorithm>,
+
+  /// The number of challenges a single IP can request per minute (0 to disable) [default: 30]
+  #[arg(
+    long,
+    value_name = "REQUESTS",
+    env = "LRCLIB_CHALLENGE_RATE_LIMIT"
+  )]
+  challenge_rate_limit: Option<u32>,
+
+  /// The number of lyrics a single IP can publish per minute (0 to disable) [default: 10]
+  #[arg(
+    long,
+    value_name = "REQUESTS",
+    env = "LRCLIB_PUBLISH_RATE_LIMIT"
+  )]
+  publish_rate_limit: Option<u32>,
+
+  /// The longest search parameter accepted, in characters [default: 200]
+  #[arg(
+    long,
+    value_name = "CHARACTERS",
+    env = "LRCLIB_SEARCH_MAX_QUERY_LENGTH"
+  )]
+  search_max_query_length: Option<usize>,
+
+  /// The most tracks a single search stream returns [default: 100000]
+  #[arg(
+    long,
+    value_name = "TRACKS",
+    env = "LRCLIB_SEARCH_STREAM_MAX_ROWS"
+  )]
+  search_stream_max_rows: Option<usize>,
+
+  /// The largest publish request body accepted, in bytes [default: 262144]
+  #[arg(
+    long,
+    value_name = "BYTES",
+    env = "LRCLIB_PUBLISH_BODY_LIMIT"
+  )]
+  publish_body_limit: Option<usize>,
+
+  /// Whether to queue missing tracks to be fetched from the providers [default: true]
+  #[arg(
+    long,
+    value_name = "BOOL",
+    env = "LRCLIB_ENABLE_QUEUE"
+  )]
+  enable_queue: Option<bool>,
+
+  /// How long to wait for in-flight queue jobs on shutdown, in seconds [default: 30]
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    env = "LRCLIB_QUEUE_GRACE_PERIOD"
+  )]
+  queue_grace_period: Option<u64>,
+
+  /// How long a connection waits for a locked database before failing, in milliseconds [default: 5000]
+  #[arg(
+    long,
+    value_name = "MILLISECONDS",
+    env = "LRCLIB_DB_BUSY_TIMEOUT"
+  )]
+  db_busy_timeout: Option<u64>,
+
+  /// The number of pooled database connections [default: one per queue worker plus four per CPU core, at least 8]
+  #[arg(
+    long,
+    value_name = "CONNECTIONS",
+    env = "LRCLIB_DB_POOL_SIZE"
+  )]
+  db_pool_size: Option<u32>,
+
+  /// How long a request waits for a free database connection before failing with 503, in milliseconds [default: 5000]
+  #[arg(
+    long,
+    value_name = "MILLISECONDS",
+    env = "LRCLIB_DB_POOL_TIMEOUT"
+  )]
+  db_pool_timeout: Option<u64>,
+
+  /// The page cache size of each database connection, in KiB [default: 65536]
+  #[arg(
+    long,
+    value_name = "KIB",
+    env = "LRCLIB_DB_CACHE_SIZE"
+  )]
+  db_cache_size: Option<u32>,
+
+  /// Secret used to verify signed publish tokens, which let trusted clients skip the proof-of-work
+  #[arg(
+    long,
+    value_name = "SECRET",
+    env = "LRCLIB_PUBLISH_TOKEN_SECRET",
+    hide_env_values = true
+  )]
+  publish_token_secret: Option<String>,
+
+  /// The number of unreviewed flags after which lyrics stop being served from the cache (0 to disable) [default: 3]
+  #[arg(
+    long,
+    value_name = "FLAGS",
+    env = "LRCLIB_FLAG_EVICTION_THRESHOLD"
+  )]
+  flag_eviction_threshold: Option<u32>,
+
+  /// The number of clients that can be connected to the live feed at once [default: 1000]
+  #[arg(
+    long,
+    value_name = "CONNECTIONS",
+    env = "LRCLIB_LIVE_MAX_CONNECTIONS"
+  )]
+  live_max_connections: Option<usize>,
+
+  /// Require a valid API key on every API request but the health probes [default: false]
+  #[arg(
+    long,
+    value_name = "BOOL",
+    env = "LRCLIB_API_KEYS_ENABLED"
+  )]
+  api_keys_enabled: Option<bool>,
+}
+
+impl ServeArgs {
+  fn into_config(self) -> Config {
+    let mut config = match &self.config {
+      Some(path) => Config::load(path).unwrap_or_else(|err| {
+        eprintln!("{:#}", err);
+        process::exit(1);
+      }),
+      None => Config::default(),
+    };
+
+    if let Some(log_format) = self.log_format { config.log_format = log_format; }
+    if let Some(bind_address) = self.bind_address { config.bind_address = bind_address; }
+    if let Some(port) = self.port { config.port = port; }
+    if let Some(unix_socket) = self.unix_socket { config.unix_socket = Some(unix_socket); }
+    if let Some(database) = self.database { config.database = Some(database); }
+    if let Some(workers_count) = self.workers_count { config.workers_count = workers_count; }
+    if let Some(min_pow_difficulty) = self.min_pow_difficulty { config.min_pow_difficulty = min_pow_difficulty; }
+    if let Some(pow_algorithm) = self.pow_algorithm { config.pow_algorithm = pow_algorithm; }
+    if let Some(challenge_rate_limit) = self.challenge_rate_limit { config.challenge_rate_limit = challenge_rate_limit; }
+    if let Some(publish_rate_limit) = self.publish_rate_limit { config.publish_rate_limit = publish_rate_limit; }
+    if let Some(search_max_query_length) = self.search_max_query_length { config.search_max_query_length = search_max_query_length; }
+    if let Some(search_stream_max_rows) = self.search_stream_max_rows { config.search_stream_max_rows = search_stream_max_rows; }
+    if let Some(publish_body_limit) = self.publish_body_limit { config.publish_body_limit = publish_body_limit; }
+    if let Some(enable_queue) = self.enable_queue { config.enable_queue = enable_queue; }
+    if let Some(queue_grace_period) = self.queue_grace_period { config.queue_grace_period = queue_grace_period; }
+    if let Some(db_busy_timeout) = self.db_busy_timeout { config.db_busy_timeout = db_busy_timeout; }
+    if let Some(db_pool_size) = self.db_pool_size { config.db_pool_size = Some(db_pool_size); }
+    if let Some(db_pool_timeout) = self.db_pool_timeout { config.db_pool_timeout = db_pool_timeout; }
+    if let Some(db_cache_size) = self.db_cache_size { config.db_cache_size = db_cache_size; }
+    if let Some(publish_token_secret) = self.publish_token_secret { config.publish_token_secret = Some(publish_token_secret); }
+    if let Some(flag_eviction_threshold) = self.flag_eviction_threshold { config.flag_eviction_threshold = flag_eviction_threshold; }
+    if let Some(live_max_connections) = self.live_max_connections { config.live_max_connections = live_max_connections; }
+    if let Some(api_keys_enabled) = self.api_keys_enabled { config.api_keys_enabled = api_keys_enabled; }
+
+    config
+  }
+}
 
 #[tokio::main]
 async fn main() {
   let cli = Cli::parse();
 
-  match &cli.command {
-    Some(Commands::Serve { port, database, workers_count }) => {
-      serve(port.to_owned(), database, workers_count.to_owned()).await;
+  match cli.command {
+    Some(Commands::Serve(args)) => {
+      let config = args.into_config();
+
+      if config.database.is_none() {
+        eprintln!("The database file must be set with --database, LRCLIB_DATABASE_FILE or the config file");
+        process::exit(2);
+      }
+
+      if let Err(err) = config.validate() {
+        eprintln!("Invalid configuration: {:#}", err);
+        process::exit(1);
+      }
+
+      serve(config).await;
+    },
+    Some(Commands::IssueToken {
+      secret,
+      expires_in_days,
+      unlimited,
+      operator,
+    }) => {
+      let class = if operator {
+        RateClass::Operator
+      } else if unlimited {
+        RateClass::Unlimited
+      } else {
+        RateClass::Standard
+      };
+      println!("{}", issue_token(&secret, Duration::from_secs(expires_in_days * 60 * 60 * 24), class));
+    },
+    Some(Commands::CreateApiKey { database, name, daily_quota }) => {
+      match create_api_key(&database, &name, daily_quota, API_KEY_BUSY_TIMEOUT) {
+        Ok(key) => println!("{}", key),
+        Err(err) => {
+          eprintln!("Failed to create the API key: {:#}", err);
+          process::exit(1);
+        },
+      }
+    },
+    Some(Commands::RevokeApiKey { database, name }) => {
+      match revoke_api_key(&database, &name, API_KEY_BUSY_TIMEOUT) {
+        Ok(true) => println!("Revoked the API keys of {}", name),
+        Ok(false) => {
+          eprintln!("No API key named {}", name);
+          process::exit(1);
+        },
+        Err(err) => {
+          eprintln!("Failed to revoke the API keys: {:#}", err);
+          process::exit(1);
+        },
+      }
+    },
+    Some(Commands::Reindex {
+      database,
+      batch_size,
+      dry_run,
+      restart,
+      db_busy_timeout,
+    }) => {
+      let options = ReindexOptions {
+        batch_size: batch_size.max(1),
+        dry_run,
+        restart,
+        busy_timeout: Duration::from_millis(db_busy_timeout),
+      };
+      let verb = if dry_run { "would change" } else { "changed" };
+
+      let result = reindex(&database, &options, |progress| {
+        eprintln!("Processed {} lyrics up to id {}, {} {}", progress.processed, progress.last_lyrics_id, verb, progress.changed);
+      });
+
+      match result {
+        Ok(progress) => println!("Done: {} of {} lyrics {}", progress.changed, progress.processed, verb),
+        Err(err) => {
+          eprintln!("Reindex failed: {:#}", err);
+          process::exit(1);
+        },
+      }
     },
     None => {}
   }